@@ -0,0 +1,4 @@
+DROP TRIGGER IF EXISTS account_balances_audit_trigger ON account_balances;
+CREATE TRIGGER account_balances_audit_trigger
+    AFTER INSERT OR UPDATE OR DELETE ON account_balances
+    FOR EACH ROW EXECUTE FUNCTION audit_trigger_function();
@@ -0,0 +1,28 @@
+-- account_balances is keyed by account_id rather than id, so the audit trigger
+-- takes the name of the key column as an argument instead of assuming NEW.id
+CREATE OR REPLACE FUNCTION audit_trigger_function()
+RETURNS TRIGGER AS $$
+DECLARE
+    key_column TEXT := COALESCE(TG_ARGV[0], 'id');
+BEGIN
+    IF (TG_OP = 'DELETE') THEN
+        INSERT INTO audit_logs (entity_type, entity_id, operation, before_state, after_state)
+        VALUES (TG_TABLE_NAME, (to_jsonb(OLD) ->> key_column)::uuid, TG_OP, row_to_json(OLD), NULL);
+        RETURN OLD;
+    ELSIF (TG_OP = 'UPDATE') THEN
+        INSERT INTO audit_logs (entity_type, entity_id, operation, before_state, after_state)
+        VALUES (TG_TABLE_NAME, (to_jsonb(NEW) ->> key_column)::uuid, TG_OP, row_to_json(OLD), row_to_json(NEW));
+        RETURN NEW;
+    ELSIF (TG_OP = 'INSERT') THEN
+        INSERT INTO audit_logs (entity_type, entity_id, operation, before_state, after_state)
+        VALUES (TG_TABLE_NAME, (to_jsonb(NEW) ->> key_column)::uuid, TG_OP, NULL, row_to_json(NEW));
+        RETURN NEW;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS account_balances_audit_trigger ON account_balances;
+CREATE TRIGGER account_balances_audit_trigger
+    AFTER INSERT OR UPDATE OR DELETE ON account_balances
+    FOR EACH ROW EXECUTE FUNCTION audit_trigger_function('account_id');
@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS transactions_from_account_created_idx;
+DROP TABLE IF EXISTS budget_alerts;
+DROP TABLE IF EXISTS budgets;
+ALTER TABLE transactions DROP COLUMN category;
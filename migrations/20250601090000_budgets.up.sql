@@ -0,0 +1,29 @@
+-- Spending categories on transactions
+ALTER TABLE transactions ADD COLUMN category TEXT;
+
+-- Monthly budgets, either for a whole account or for one category on it
+CREATE TABLE budgets (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    account_id UUID NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    category TEXT,                        -- NULL means all spending on the account
+    amount NUMERIC(20, 4) NOT NULL CHECK (amount > 0),
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX budgets_account_category_idx ON budgets (account_id, COALESCE(category, ''));
+
+-- Alerts raised when spending crosses a budget threshold, at most once per month
+CREATE TABLE budget_alerts (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    budget_id UUID NOT NULL REFERENCES budgets(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    threshold INTEGER NOT NULL,           -- percentage of the budget, e.g. 80 or 100
+    period_start DATE NOT NULL,
+    spent NUMERIC(20, 4) NOT NULL,
+    read_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (budget_id, period_start, threshold)
+);
+
+CREATE INDEX transactions_from_account_created_idx ON transactions (from_account_id, created_at);
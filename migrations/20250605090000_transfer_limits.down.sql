@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS limit_overrides;
+DROP TABLE IF EXISTS transfer_limits;
+ALTER TABLE users DROP COLUMN role;
@@ -0,0 +1,30 @@
+-- Roles, so that limits and overrides can be managed by admins
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'customer' CHECK (role IN ('customer', 'admin'));
+
+-- Transfer limits, set either for a single account or for every account of a type.
+-- A NULL limit is not enforced; account-specific limits take precedence over type-wide ones.
+CREATE TABLE transfer_limits (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    account_id UUID UNIQUE REFERENCES accounts(id) ON DELETE CASCADE,
+    account_type TEXT UNIQUE,
+    per_transaction NUMERIC(20, 4) CHECK (per_transaction > 0),
+    daily_amount NUMERIC(20, 4) CHECK (daily_amount > 0),
+    weekly_amount NUMERIC(20, 4) CHECK (weekly_amount > 0),
+    hourly_count INTEGER CHECK (hourly_count >= 0),
+    updated_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    CHECK ((account_id IS NULL) <> (account_type IS NULL))
+);
+
+-- Temporary admin overrides of a single limit on an account. A NULL value lifts the
+-- limit entirely until the override expires.
+CREATE TABLE limit_overrides (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    account_id UUID NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    limit_kind TEXT NOT NULL CHECK (limit_kind IN ('per_transaction', 'daily_amount', 'weekly_amount', 'hourly_count')),
+    value NUMERIC(20, 4) CHECK (value >= 0),
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_by UUID REFERENCES users(id),
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX limit_overrides_account_idx ON limit_overrides (account_id, expires_at);
@@ -0,0 +1,18 @@
+-- Maker-checker approvals: operations that only take effect once a second,
+-- different user has approved them
+CREATE TABLE pending_approvals (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    kind TEXT NOT NULL CHECK (kind IN ('transfer', 'balance_adjustment')),
+    payload JSONB NOT NULL,               -- the request to execute once approved
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'approved', 'rejected', 'expired')),
+    requested_by UUID NOT NULL REFERENCES users(id),
+    decided_by UUID REFERENCES users(id),
+    decision_note TEXT,
+    transaction_id UUID REFERENCES transactions(id),
+    expires_at TIMESTAMPTZ NOT NULL,
+    decided_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    CHECK (decided_by IS NULL OR decided_by <> requested_by)
+);
+
+CREATE INDEX pending_approvals_status_idx ON pending_approvals (status, expires_at);
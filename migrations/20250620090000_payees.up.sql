@@ -0,0 +1,12 @@
+-- Saved counterparties for each user
+CREATE TABLE payees (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    account_id UUID NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    nickname TEXT NOT NULL,
+    -- whether the name the user gave matched the account holder's name
+    verification_status TEXT NOT NULL CHECK (verification_status IN ('verified', 'mismatch', 'unverified')),
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (user_id, account_id)
+);
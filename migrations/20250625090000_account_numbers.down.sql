@@ -0,0 +1,2 @@
+ALTER TABLE accounts DROP COLUMN account_number;
+DROP SEQUENCE IF EXISTS account_number_seq;
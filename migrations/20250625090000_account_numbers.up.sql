@@ -0,0 +1,40 @@
+-- Human-friendly account numbers: bank prefix, 10 digit sequence and a Luhn check digit.
+-- New numbers are generated by the application from account_number_seq.
+CREATE SEQUENCE account_number_seq;
+
+ALTER TABLE accounts ADD COLUMN account_number TEXT UNIQUE;
+
+-- Backfill existing accounts under the default prefix
+CREATE FUNCTION luhn_check_digit(digits TEXT) RETURNS INTEGER AS $$
+DECLARE
+    total INTEGER := 0;
+    d INTEGER;
+    double BOOLEAN := TRUE;
+BEGIN
+    FOR i IN REVERSE length(digits)..1 LOOP
+        d := substr(digits, i, 1)::INTEGER;
+        IF double THEN
+            d := d * 2;
+            IF d > 9 THEN
+                d := d - 9;
+            END IF;
+        END IF;
+        total := total + d;
+        double := NOT double;
+    END LOOP;
+    RETURN (10 - total % 10) % 10;
+END;
+$$ LANGUAGE plpgsql IMMUTABLE;
+
+WITH numbered AS (
+    SELECT id, '7001' || lpad(nextval('account_number_seq')::TEXT, 10, '0') AS base
+    FROM (SELECT id FROM accounts ORDER BY created_at, id) ordered
+)
+UPDATE accounts a
+SET account_number = n.base || luhn_check_digit(n.base)
+FROM numbered n
+WHERE a.id = n.id;
+
+DROP FUNCTION luhn_check_digit(TEXT);
+
+ALTER TABLE accounts ALTER COLUMN account_number SET NOT NULL;
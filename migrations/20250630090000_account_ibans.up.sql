@@ -0,0 +1,3 @@
+-- IBANs depend on the configured country and bank code, so they are generated by the
+-- application: at account creation, and for existing accounts at startup.
+ALTER TABLE accounts ADD COLUMN iban TEXT UNIQUE;
@@ -0,0 +1,20 @@
+-- Optional TOTP two-factor authentication. The secret is set at enrollment and only
+-- takes effect once a first code confirms it (totp_enabled_at). totp_last_step is the
+-- last time step accepted, so a code cannot be replayed.
+ALTER TABLE users
+    ADD COLUMN totp_secret TEXT,
+    ADD COLUMN totp_enabled_at TIMESTAMPTZ,
+    ADD COLUMN totp_last_step BIGINT;
+
+-- One-time codes for when the authenticator is lost, stored as SHA-256 hashes
+CREATE TABLE recovery_codes (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (user_id, code_hash)
+);
+
+-- When the session last re-entered a TOTP code, for step-up on large transfers
+ALTER TABLE sessions ADD COLUMN step_up_at TIMESTAMPTZ;
@@ -0,0 +1,11 @@
+-- Failed login tracking, per email address and per client IP. Rows exist for unknown
+-- emails too, so throttling does not reveal which emails have accounts.
+CREATE TABLE login_throttles (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    scope TEXT NOT NULL CHECK (scope IN ('email', 'ip')),
+    key TEXT NOT NULL,
+    failures INTEGER NOT NULL DEFAULT 0,
+    last_failure_at TIMESTAMPTZ,
+    locked_until TIMESTAMPTZ,
+    UNIQUE (scope, key)
+);
@@ -0,0 +1,22 @@
+-- Email verification. Accounts opened before verification existed are treated as verified.
+ALTER TABLE users
+    ADD COLUMN email_verified_at TIMESTAMPTZ,
+    -- A requested new address, which replaces email once verified
+    ADD COLUMN pending_email TEXT;
+
+UPDATE users SET email_verified_at = COALESCE(created_at, now());
+
+-- Single-use links sent by email, stored as SHA-256 hashes
+CREATE TABLE email_tokens (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    purpose TEXT NOT NULL CHECK (purpose IN ('verify_email', 'reset_password')),
+    -- The address the token was sent to, and that it verifies
+    email TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX email_tokens_user_id_idx ON email_tokens(user_id);
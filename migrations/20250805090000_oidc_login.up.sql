@@ -0,0 +1,18 @@
+-- Staff who sign in through the company identity provider, by the provider's id for them
+CREATE TABLE oidc_identities (
+    issuer TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ DEFAULT now(),
+    PRIMARY KEY (issuer, subject)
+);
+
+CREATE INDEX oidc_identities_user_id_idx ON oidc_identities(user_id);
+
+-- Logins sent to the provider and not yet back, keyed by their state parameter
+CREATE TABLE oidc_logins (
+    state TEXT PRIMARY KEY,
+    code_verifier TEXT NOT NULL,
+    nonce TEXT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
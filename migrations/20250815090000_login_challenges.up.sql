@@ -0,0 +1,10 @@
+-- Login challenges issued after the password step, by the challenge token's jti. Each
+-- is used up by a successful code, or after a few wrong ones.
+CREATE TABLE login_challenges (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    failures INTEGER NOT NULL DEFAULT 0,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX login_challenges_user_id_idx ON login_challenges(user_id);
@@ -0,0 +1 @@
+ALTER TABLE pending_approvals DROP COLUMN review_id;
@@ -0,0 +1,3 @@
+-- Approved transfers are still screened by the risk checks; a flagged one waits in the
+-- review queue, under this review
+ALTER TABLE pending_approvals ADD COLUMN review_id UUID REFERENCES review_queue(id);
@@ -0,0 +1,10 @@
+-- Each time a user checked an account holder's name when adding a payee, so the check
+-- cannot be used to guess names
+CREATE TABLE payee_name_checks (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    account_id UUID NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    checked_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX payee_name_checks_user_id_idx ON payee_name_checks(user_id, checked_at);
@@ -0,0 +1,5 @@
+DROP TABLE transactions;
+DROP TABLE account_balances;
+DROP TABLE accounts;
+DROP TABLE account_number_seq;
+DROP TABLE users;
@@ -0,0 +1,54 @@
+-- The ledger's domain in SQLite: users, accounts, balances and transactions. The tables of
+-- the HTTP api (sessions, limits, payees, reviews, ...) exist only in PostgreSQL.
+--
+-- UUIDs are stored as 16 byte blobs. SQLite has no decimal type and REAL would round, so
+-- amounts are TEXT holding NUMERIC(20, 4) values exactly as the ledger writes them, always
+-- with four decimal places, e.g. '12.5000'. All arithmetic on them happens in the ledger.
+
+CREATE TABLE users (
+    id BLOB PRIMARY KEY NOT NULL,
+    full_name TEXT NOT NULL,
+    email TEXT UNIQUE NOT NULL,
+    pending_email TEXT,
+    email_verified_at TEXT,
+    password_hash TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+-- The sequence account numbers and IBANs are derived from, as a single row
+CREATE TABLE account_number_seq (
+    value INTEGER NOT NULL
+);
+
+INSERT INTO account_number_seq (value) VALUES (0);
+
+CREATE TABLE accounts (
+    id BLOB PRIMARY KEY NOT NULL,
+    user_id BLOB NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    account_type TEXT NOT NULL,
+    account_number TEXT UNIQUE NOT NULL,
+    iban TEXT UNIQUE,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE INDEX accounts_user_id_idx ON accounts (user_id);
+
+CREATE TABLE account_balances (
+    account_id BLOB PRIMARY KEY NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    balance TEXT NOT NULL DEFAULT '0.0000'
+        CHECK (balance GLOB '[0-9]*.[0-9][0-9][0-9][0-9]' OR balance GLOB '-[0-9]*.[0-9][0-9][0-9][0-9]'),
+    updated_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE TABLE transactions (
+    id BLOB PRIMARY KEY NOT NULL,
+    from_account_id BLOB NOT NULL REFERENCES accounts(id),
+    to_account_id BLOB NOT NULL REFERENCES accounts(id),
+    -- Positive: no sign, and not zero
+    amount TEXT NOT NULL CHECK (amount GLOB '[0-9]*.[0-9][0-9][0-9][0-9]' AND amount <> '0.0000'),
+    category TEXT,
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX transactions_from_account_id_idx ON transactions (from_account_id);
+CREATE INDEX transactions_to_account_id_idx ON transactions (to_account_id);
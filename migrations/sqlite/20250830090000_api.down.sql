@@ -0,0 +1,27 @@
+DROP TABLE oidc_logins;
+DROP TABLE oidc_identities;
+DROP TABLE api_keys;
+DROP TABLE service_accounts;
+DROP TABLE email_tokens;
+DROP TABLE login_throttles;
+DROP TABLE login_challenges;
+DROP TABLE recovery_codes;
+DROP TABLE revoked_tokens;
+DROP TABLE refresh_tokens;
+DROP TABLE sessions;
+DROP TABLE payee_name_checks;
+DROP TABLE payees;
+DROP TABLE pending_approvals;
+DROP TABLE review_queue;
+DROP TABLE limit_overrides;
+DROP TABLE transfer_limits;
+DROP TABLE budget_alerts;
+DROP TABLE budgets;
+DROP TABLE audit_logs;
+
+ALTER TABLE users DROP COLUMN totp_last_step;
+ALTER TABLE users DROP COLUMN totp_enabled_at;
+ALTER TABLE users DROP COLUMN totp_secret;
+ALTER TABLE users DROP COLUMN role;
+
+DROP INDEX transactions_from_account_created_idx;
@@ -0,0 +1,243 @@
+-- What the HTTP api keeps next to the ledger: roles, two-factor, sessions, throttles,
+-- emailed tokens, limits, reviews, approvals, payees, budgets, service accounts, staff
+-- logins and the audit log, as in the PostgreSQL migrations up to 20250825090000.
+--
+-- As in the ledger's tables, UUIDs are 16 byte blobs and amounts TEXT with exactly four
+-- decimal places. Timestamps are TEXT in one fixed-width UTC format, e.g.
+-- '2025-08-30T09:00:00.000000Z', so that they compare as text. JSON is stored as TEXT,
+-- and so are the scopes of an API key, as a JSON array.
+--
+-- The audit log only holds the entries the server writes itself: there are no triggers
+-- recording every change to users, accounts, balances and transactions as in PostgreSQL.
+
+-- Timestamps written before they had a fixed width
+UPDATE transactions SET created_at = strftime('%Y-%m-%dT%H:%M:%f000Z', created_at);
+
+CREATE INDEX transactions_from_account_created_idx ON transactions (from_account_id, created_at);
+
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'customer' CHECK (role IN ('customer', 'admin'));
+ALTER TABLE users ADD COLUMN totp_secret TEXT;
+ALTER TABLE users ADD COLUMN totp_enabled_at TEXT;
+ALTER TABLE users ADD COLUMN totp_last_step INTEGER;
+
+CREATE TABLE audit_logs (
+    id BLOB PRIMARY KEY NOT NULL,
+    entity_type TEXT NOT NULL,
+    entity_id BLOB NOT NULL,
+    operation TEXT NOT NULL,
+    performed_by BLOB REFERENCES users(id),
+    before_state TEXT,
+    after_state TEXT,
+    created_at TEXT DEFAULT (strftime('%Y-%m-%dT%H:%M:%f000Z', 'now'))
+);
+
+CREATE TABLE budgets (
+    id BLOB PRIMARY KEY NOT NULL,
+    user_id BLOB NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    account_id BLOB NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    category TEXT,
+    amount TEXT NOT NULL CHECK (amount GLOB '[0-9]*.[0-9][0-9][0-9][0-9]' AND amount <> '0.0000'),
+    created_at TEXT DEFAULT (strftime('%Y-%m-%dT%H:%M:%f000Z', 'now'))
+);
+
+CREATE UNIQUE INDEX budgets_account_category_idx ON budgets (account_id, COALESCE(category, ''));
+
+CREATE TABLE budget_alerts (
+    id BLOB PRIMARY KEY NOT NULL,
+    budget_id BLOB NOT NULL REFERENCES budgets(id) ON DELETE CASCADE,
+    user_id BLOB NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    threshold INTEGER NOT NULL,
+    period_start TEXT NOT NULL,
+    spent TEXT NOT NULL,
+    read_at TEXT,
+    created_at TEXT DEFAULT (strftime('%Y-%m-%dT%H:%M:%f000Z', 'now')),
+    UNIQUE (budget_id, period_start, threshold)
+);
+
+CREATE TABLE transfer_limits (
+    id BLOB PRIMARY KEY NOT NULL,
+    account_id BLOB UNIQUE REFERENCES accounts(id) ON DELETE CASCADE,
+    account_type TEXT UNIQUE,
+    per_transaction TEXT CHECK (per_transaction GLOB '[0-9]*.[0-9][0-9][0-9][0-9]' AND per_transaction <> '0.0000'),
+    daily_amount TEXT CHECK (daily_amount GLOB '[0-9]*.[0-9][0-9][0-9][0-9]' AND daily_amount <> '0.0000'),
+    weekly_amount TEXT CHECK (weekly_amount GLOB '[0-9]*.[0-9][0-9][0-9][0-9]' AND weekly_amount <> '0.0000'),
+    hourly_count INTEGER CHECK (hourly_count >= 0),
+    updated_at TEXT DEFAULT (strftime('%Y-%m-%dT%H:%M:%f000Z', 'now')),
+    CHECK ((account_id IS NULL) <> (account_type IS NULL))
+);
+
+CREATE TABLE limit_overrides (
+    id BLOB PRIMARY KEY NOT NULL,
+    account_id BLOB NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    limit_kind TEXT NOT NULL CHECK (limit_kind IN ('per_transaction', 'daily_amount', 'weekly_amount', 'hourly_count')),
+    value TEXT CHECK (value GLOB '[0-9]*.[0-9][0-9][0-9][0-9]'),
+    expires_at TEXT NOT NULL,
+    created_by BLOB REFERENCES users(id),
+    created_at TEXT DEFAULT (strftime('%Y-%m-%dT%H:%M:%f000Z', 'now'))
+);
+
+CREATE INDEX limit_overrides_account_idx ON limit_overrides (account_id, expires_at);
+
+CREATE TABLE review_queue (
+    id BLOB PRIMARY KEY NOT NULL,
+    from_account_id BLOB NOT NULL REFERENCES accounts(id),
+    to_account_id BLOB NOT NULL REFERENCES accounts(id),
+    amount TEXT NOT NULL CHECK (amount GLOB '[0-9]*.[0-9][0-9][0-9][0-9]' AND amount <> '0.0000'),
+    category TEXT,
+    flags TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'approved', 'rejected')),
+    requested_by BLOB REFERENCES users(id),
+    reviewed_by BLOB REFERENCES users(id),
+    review_note TEXT,
+    transaction_id BLOB REFERENCES transactions(id),
+    reviewed_at TEXT,
+    created_at TEXT DEFAULT (strftime('%Y-%m-%dT%H:%M:%f000Z', 'now'))
+);
+
+CREATE INDEX review_queue_status_idx ON review_queue (status, created_at);
+
+CREATE TABLE pending_approvals (
+    id BLOB PRIMARY KEY NOT NULL,
+    kind TEXT NOT NULL CHECK (kind IN ('transfer', 'balance_adjustment')),
+    payload TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'approved', 'rejected', 'expired')),
+    requested_by BLOB NOT NULL REFERENCES users(id),
+    decided_by BLOB REFERENCES users(id),
+    decision_note TEXT,
+    transaction_id BLOB REFERENCES transactions(id),
+    expires_at TEXT NOT NULL,
+    decided_at TEXT,
+    created_at TEXT DEFAULT (strftime('%Y-%m-%dT%H:%M:%f000Z', 'now')),
+    review_id BLOB REFERENCES review_queue(id),
+    CHECK (decided_by IS NULL OR decided_by <> requested_by)
+);
+
+CREATE INDEX pending_approvals_status_idx ON pending_approvals (status, expires_at);
+
+CREATE TABLE payees (
+    id BLOB PRIMARY KEY NOT NULL,
+    user_id BLOB NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    account_id BLOB NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    nickname TEXT NOT NULL,
+    verification_status TEXT NOT NULL CHECK (verification_status IN ('verified', 'mismatch', 'unverified')),
+    created_at TEXT DEFAULT (strftime('%Y-%m-%dT%H:%M:%f000Z', 'now')),
+    updated_at TEXT DEFAULT (strftime('%Y-%m-%dT%H:%M:%f000Z', 'now')),
+    UNIQUE (user_id, account_id)
+);
+
+CREATE TABLE payee_name_checks (
+    id BLOB PRIMARY KEY NOT NULL,
+    user_id BLOB NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    account_id BLOB NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    checked_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f000Z', 'now'))
+);
+
+CREATE INDEX payee_name_checks_user_id_idx ON payee_name_checks (user_id, checked_at);
+
+CREATE TABLE sessions (
+    id BLOB PRIMARY KEY NOT NULL,
+    user_id BLOB NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    expires_at TEXT NOT NULL,
+    revoked_at TEXT,
+    revoked_reason TEXT,
+    created_at TEXT DEFAULT (strftime('%Y-%m-%dT%H:%M:%f000Z', 'now')),
+    user_agent TEXT,
+    ip_address TEXT,
+    last_seen_at TEXT DEFAULT (strftime('%Y-%m-%dT%H:%M:%f000Z', 'now')),
+    step_up_at TEXT
+);
+
+CREATE INDEX sessions_user_id_idx ON sessions (user_id);
+
+CREATE TABLE refresh_tokens (
+    id BLOB PRIMARY KEY NOT NULL,
+    session_id BLOB NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    token_hash TEXT NOT NULL UNIQUE,
+    used_at TEXT,
+    created_at TEXT DEFAULT (strftime('%Y-%m-%dT%H:%M:%f000Z', 'now'))
+);
+
+CREATE INDEX refresh_tokens_session_id_idx ON refresh_tokens (session_id);
+
+CREATE TABLE revoked_tokens (
+    jti BLOB PRIMARY KEY NOT NULL,
+    expires_at TEXT NOT NULL,
+    revoked_at TEXT DEFAULT (strftime('%Y-%m-%dT%H:%M:%f000Z', 'now'))
+);
+
+CREATE TABLE recovery_codes (
+    id BLOB PRIMARY KEY NOT NULL,
+    user_id BLOB NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    used_at TEXT,
+    created_at TEXT DEFAULT (strftime('%Y-%m-%dT%H:%M:%f000Z', 'now')),
+    UNIQUE (user_id, code_hash)
+);
+
+CREATE TABLE login_challenges (
+    id BLOB PRIMARY KEY NOT NULL,
+    user_id BLOB NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    failures INTEGER NOT NULL DEFAULT 0,
+    expires_at TEXT NOT NULL
+);
+
+CREATE INDEX login_challenges_user_id_idx ON login_challenges (user_id);
+
+CREATE TABLE login_throttles (
+    id BLOB PRIMARY KEY NOT NULL,
+    scope TEXT NOT NULL CHECK (scope IN ('email', 'ip')),
+    key TEXT NOT NULL,
+    failures INTEGER NOT NULL DEFAULT 0,
+    last_failure_at TEXT,
+    locked_until TEXT,
+    UNIQUE (scope, key)
+);
+
+CREATE TABLE email_tokens (
+    id BLOB PRIMARY KEY NOT NULL,
+    user_id BLOB NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    purpose TEXT NOT NULL CHECK (purpose IN ('verify_email', 'reset_password')),
+    email TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    expires_at TEXT NOT NULL,
+    used_at TEXT,
+    created_at TEXT DEFAULT (strftime('%Y-%m-%dT%H:%M:%f000Z', 'now'))
+);
+
+CREATE INDEX email_tokens_user_id_idx ON email_tokens (user_id);
+
+CREATE TABLE service_accounts (
+    id BLOB PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL UNIQUE,
+    owner_id BLOB NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TEXT DEFAULT (strftime('%Y-%m-%dT%H:%M:%f000Z', 'now'))
+);
+
+CREATE TABLE api_keys (
+    id BLOB PRIMARY KEY NOT NULL,
+    service_account_id BLOB NOT NULL REFERENCES service_accounts(id) ON DELETE CASCADE,
+    prefix TEXT NOT NULL UNIQUE,
+    key_hash TEXT NOT NULL UNIQUE,
+    scopes TEXT NOT NULL,
+    expires_at TEXT,
+    last_used_at TEXT,
+    revoked_at TEXT,
+    created_at TEXT DEFAULT (strftime('%Y-%m-%dT%H:%M:%f000Z', 'now'))
+);
+
+CREATE TABLE oidc_identities (
+    issuer TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    user_id BLOB NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TEXT DEFAULT (strftime('%Y-%m-%dT%H:%M:%f000Z', 'now')),
+    PRIMARY KEY (issuer, subject)
+);
+
+CREATE INDEX oidc_identities_user_id_idx ON oidc_identities (user_id);
+
+CREATE TABLE oidc_logins (
+    state TEXT PRIMARY KEY NOT NULL,
+    code_verifier TEXT NOT NULL,
+    nonce TEXT NOT NULL,
+    expires_at TEXT NOT NULL
+);
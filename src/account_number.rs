@@ -0,0 +1,133 @@
+//! Human-friendly account numbers: a bank prefix, a zero-padded sequence number and a
+//! trailing Luhn check digit, e.g. `7001 0000000042 3`.
+
+use std::fmt;
+
+/// Digits in the sequence part of an account number.
+const SEQUENCE_DIGITS: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccountNumberError {
+    /// Contains something other than digits, spaces and dashes
+    InvalidCharacters,
+    InvalidLength,
+    /// Well formed, but the check digit does not match, most likely a typo
+    CheckDigitMismatch,
+}
+
+impl fmt::Display for AccountNumberError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccountNumberError::InvalidCharacters => write!(f, "Account number may only contain digits"),
+            AccountNumberError::InvalidLength => write!(f, "Account number has the wrong number of digits"),
+            AccountNumberError::CheckDigitMismatch => write!(f, "Account number check digit does not match"),
+        }
+    }
+}
+
+impl std::error::Error for AccountNumberError {}
+
+/// Computes the Luhn check digit for a string of ASCII digits.
+pub fn luhn_check_digit(digits: &str) -> u32 {
+    let sum: u32 = digits
+        .chars()
+        .rev()
+        .filter_map(|c| c.to_digit(10))
+        .enumerate()
+        .map(|(i, d)| {
+            // Every second digit from the right, starting with the rightmost, is doubled
+            if i % 2 == 0 {
+                let doubled = d * 2;
+                if doubled > 9 { doubled - 9 } else { doubled }
+            } else {
+                d
+            }
+        })
+        .sum();
+
+    (10 - sum % 10) % 10
+}
+
+/// Builds the account number for the `sequence`th account under `prefix`.
+pub fn generate(prefix: &str, sequence: i64) -> String {
+    let base = format!("{}{:0width$}", prefix, sequence, width = SEQUENCE_DIGITS);
+    let check = luhn_check_digit(&base);
+    format!("{}{}", base, check)
+}
+
+/// Normalizes a typed account number, dropping spaces and dashes, and verifies its
+/// check digit. Catches most typos without touching the database.
+pub fn validate(input: &str, prefix_len: usize) -> Result<String, AccountNumberError> {
+    let number: String = input.chars().filter(|c| !c.is_whitespace() && *c != '-').collect();
+
+    if !number.chars().all(|c| c.is_ascii_digit()) {
+        return Err(AccountNumberError::InvalidCharacters);
+    }
+
+    if number.len() != prefix_len + SEQUENCE_DIGITS + 1 {
+        return Err(AccountNumberError::InvalidLength);
+    }
+
+    let (base, check) = number.split_at(number.len() - 1);
+    if check.parse::<u32>().ok() != Some(luhn_check_digit(base)) {
+        return Err(AccountNumberError::CheckDigitMismatch);
+    }
+
+    Ok(number)
+}
+
+/// The sequence an account number was generated from.
+pub fn sequence(number: &str) -> Option<i64> {
+    let end = number.len().checked_sub(1)?;
+    let start = end.checked_sub(SEQUENCE_DIGITS)?;
+    number.get(start..end)?.parse().ok()
+}
+
+/// Formats an account number for display as prefix, sequence and check digit groups.
+pub fn format(number: &str) -> String {
+    if number.len() <= SEQUENCE_DIGITS + 1 {
+        return number.to_string();
+    }
+    let (prefix, rest) = number.split_at(number.len() - SEQUENCE_DIGITS - 1);
+    let (sequence, check) = rest.split_at(SEQUENCE_DIGITS);
+    format!("{} {} {}", prefix, sequence, check)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn luhn_matches_known_values() {
+        assert_eq!(luhn_check_digit("7992739871"), 3);
+        assert_eq!(luhn_check_digit("453957876362148"), 6);
+    }
+
+    #[test]
+    fn generated_numbers_validate() {
+        let number = generate("7001", 42);
+        assert_eq!(number.len(), 15);
+        assert!(number.starts_with("70010000000042"));
+        assert_eq!(validate(&number, 4), Ok(number.clone()));
+        assert_eq!(validate(&format(&number), 4), Ok(number.clone()));
+        assert_eq!(sequence(&number), Some(42));
+    }
+
+    #[test]
+    fn typos_are_caught() {
+        let number = generate("7001", 42);
+
+        // A single changed digit
+        let mut typo: Vec<char> = number.chars().collect();
+        typo[8] = if typo[8] == '1' { '2' } else { '1' };
+        let typo: String = typo.into_iter().collect();
+        assert_eq!(validate(&typo, 4), Err(AccountNumberError::CheckDigitMismatch));
+
+        // Two adjacent digits swapped
+        let swapped = format!("{}{}{}", &number[..12], &number[13..14], &number[12..13]) + &number[14..];
+        assert_eq!(validate(&swapped, 4), Err(AccountNumberError::CheckDigitMismatch));
+
+        assert_eq!(validate("7001-ABC", 4), Err(AccountNumberError::InvalidCharacters));
+        assert_eq!(validate("7001", 4), Err(AccountNumberError::InvalidLength));
+    }
+}
@@ -1,107 +1,99 @@
-use std::fmt;
-
-use axum::{extract::{Query, State}, Json, http::StatusCode};
+use axum::{extract::State, Json, http::StatusCode};
 use bigdecimal::BigDecimal;
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
+use validator::Validate;
 
+use crate::error::LedgerError;
+use crate::ledger::accounts::{AccountBalance, AccountLookup};
+use crate::middleware::auth::AdminUser;
+use crate::middleware::validate::{self, ValidatedJson, ValidatedQuery};
 use crate::state;
 
-#[derive(Clone, Serialize, Deserialize)]
-pub enum Types {
-    Savings,
-    Current,
-    Salary,
-    FD, // Fixed Deposit
-    RD, // Recurring Deposit
-}
-
+use super::approval;
 
-impl fmt::Display for Types {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result  {
-        match self {
-            Types::Savings => write!(f, "savings"),
-            Types::Current => write!(f, "current"),
-            Types::Salary => write!(f, "salary"),
-            Types::FD => write!(f, "fd"),
-            Types::RD => write!(f, "rd"),
-        }
-    }
-}
-
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct AccountBalanceReq {
     account_id: Uuid,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
-pub struct AccountBalance {
-    account_id: Uuid,
-    balance: BigDecimal,
+#[derive(Clone, Serialize, Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LookupReq {
+    #[validate(length(min = 1, max = 34))]
+    account_number: String,
 }
 
+/// A manual correction to an account's balance, by a signed amount.
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct AdjustBalanceReq {
+    pub(crate) account_id: Uuid,
+    #[validate(custom(function = "validate::adjustment"))]
+    #[schema(value_type = String)]
+    pub(crate) amount: BigDecimal,
+    #[validate(length(max = 500), custom(function = "validate::not_blank"))]
+    reason: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/account/checkBalance",
+    tag = "account",
+    params(AccountBalanceReq),
+    responses((status = 200, description = "The balance", body = AccountBalance)),
+)]
 pub async fn check_balance(
-    State(state): State<state::AppState>, 
-    Query(req): Query<AccountBalanceReq>
-) -> Result<Json<AccountBalance>, (StatusCode, String)> {
-    let pool = state.db;
+    State(state): State<state::AppState>,
+    ValidatedQuery(req): ValidatedQuery<AccountBalanceReq>
+) -> Result<Json<AccountBalance>, LedgerError> {
+    let balance = state.accounts().balance(req.account_id).await?;
+
+    Ok(Json(balance))
+}
+
+/// Resolves an account number to its account and masked holder name.
+#[utoipa::path(
+    get,
+    path = "/api/v1/account/lookup",
+    tag = "account",
+    params(LookupReq),
+    responses((status = 200, description = "The account, as far as a payer may see it", body = AccountLookup)),
+)]
+pub async fn lookup(
+    State(state): State<state::AppState>,
+    ValidatedQuery(req): ValidatedQuery<LookupReq>
+) -> Result<Json<AccountLookup>, LedgerError> {
+    let account = state.accounts().lookup(&req.account_number).await?;
 
-    let user = sqlx::query_as!(
-        AccountBalance,
-        "SELECT account_id, balance FROM account_balances where account_id = $1",
-        req.account_id
-    ).fetch_one(&pool).await
-     .map_err(|e| {
-        match e {
-            sqlx::Error::RowNotFound => (
-                StatusCode::NOT_FOUND, 
-                format!("Account with ID {} not found", req.account_id)
-            ),
-            _ => (
-                StatusCode::INTERNAL_SERVER_ERROR, 
-                format!("Failed to fetch account balance: {}", e)
-            )
-        }
-     })?;
-    
-    Ok(Json(user))
+    Ok(Json(account))
 }
 
-pub async fn update_balance(
-    State(state): State<state::AppState>, 
-    Json(req): Json<AccountBalance>
-) -> Result<Json<AccountBalance>, (StatusCode, String)> {
-    let pool = state.db;
-    
-    // First check if the account exists
-    let exists = sqlx::query_scalar!(
-        "SELECT EXISTS(SELECT 1 FROM account_balances WHERE account_id = $1)",
-        req.account_id
-    )
-    .fetch_one(&pool)
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e)))?;
-    
-    if !exists.unwrap_or(false) {
-        return Err((StatusCode::NOT_FOUND, format!("Account with ID {} not found", req.account_id)));
-    }
-    
-    // Don't allow negative balances
-    if req.balance < BigDecimal::from(0) {
-        return Err((StatusCode::BAD_REQUEST, "Account balance cannot be negative".to_string()));
-    }
-    
-    let balance = sqlx::query_scalar!(
-        "UPDATE account_balances SET balance = $1 where account_id = $2 returning balance",
-        req.balance,
-        req.account_id
-    ).fetch_one(&pool).await
-     .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to update account balance: {}", e)))?;
-    
-    let res = AccountBalance {
-        account_id: req.account_id,
-        balance: balance
-    };
+/// Requests a manual balance adjustment. Adjustments always need a second admin's
+/// approval before they are applied, see [`super::approval`].
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/account/adjustBalance",
+    tag = "admin",
+    request_body = AdjustBalanceReq,
+    responses((status = 202, description = "Waiting for a second admin's approval", body = approval::PendingApproval)),
+)]
+pub async fn adjust_balance(
+    State(state): State<state::AppState>,
+    AdminUser(admin_id): AdminUser,
+    ValidatedJson(req): ValidatedJson<AdjustBalanceReq>
+) -> Result<(StatusCode, Json<approval::PendingApproval>), LedgerError> {
+    // Fails for unknown accounts before anyone is asked to approve
+    state.accounts().balance(req.account_id).await?;
+
+    let pending = approval::request(
+        &state.db,
+        approval::Kind::BalanceAdjustment,
+        &req,
+        admin_id,
+        state.config.approval_ttl
+    ).await?;
 
-    Ok(Json(res))
+    Ok((StatusCode::ACCEPTED, Json(pending)))
 }
@@ -0,0 +1,281 @@
+use std::fmt;
+
+use anyhow::{anyhow, Context};
+use axum::{extract::State, Json, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+use validator::{Validate, ValidationError};
+
+use crate::error::{LedgerError, Problem};
+use crate::ledger::Repository;
+use crate::middleware::auth::AdminUser;
+use crate::middleware::validate::{self, ValidatedJson, ValidatedQuery};
+use crate::openapi;
+use crate::state;
+use crate::store::approvals::{ApprovalDecision, NewApproval};
+use crate::store::{Database, Storage};
+
+use crate::ledger::transfers::Transfer;
+
+use super::account::AdjustBalanceReq;
+use super::budget;
+use super::transaction::{self, TransferOutcome};
+
+/// The kinds of operation that go through maker-checker approval.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    Transfer,
+    BalanceAdjustment,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kind::Transfer => write!(f, "transfer"),
+            Kind::BalanceAdjustment => write!(f, "balance_adjustment"),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct PendingApproval {
+    pub(crate) id: Uuid,
+    pub(crate) kind: String,
+    pub(crate) payload: serde_json::Value,
+    pub(crate) status: String,
+    pub(crate) requested_by: Uuid,
+    pub(crate) decided_by: Option<Uuid>,
+    pub(crate) decision_note: Option<String>,
+    pub(crate) transaction_id: Option<Uuid>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub(crate) expires_at: OffsetDateTime,
+    #[schema(value_type = Option<openapi::CompactTimestamp>)]
+    pub(crate) decided_at: Option<OffsetDateTime>,
+    #[schema(value_type = Option<openapi::CompactTimestamp>)]
+    pub(crate) created_at: Option<OffsetDateTime>,
+    /// Set instead of `transaction_id` when an approved transfer was flagged by a risk check
+    pub(crate) review_id: Option<Uuid>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GetApprovalsReq {
+    #[validate(custom(function = "known_status"))]
+    status: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct DecideApprovalReq {
+    approval_id: Uuid,
+    #[validate(length(max = 500))]
+    note: Option<String>,
+}
+
+fn known_status(status: &str) -> Result<(), ValidationError> {
+    validate::one_of(status, &["pending", "approved", "rejected", "expired"])
+}
+
+/// Records an operation that will only be executed once a different user approves it.
+pub(crate) async fn request(
+    db: &Database,
+    kind: Kind,
+    payload: &impl Serialize,
+    requested_by: Uuid,
+    ttl: Duration
+) -> Result<PendingApproval, LedgerError> {
+    let payload = serde_json::to_value(payload)
+        .context("Failed to serialize request")?;
+
+    let mut conn = db.acquire().await
+        .context("Database error")?;
+
+    let pending = conn.insert_approval(&NewApproval {
+        kind: &kind.to_string(),
+        payload,
+        requested_by,
+        expires_at: OffsetDateTime::now_utc() + ttl,
+    }).await
+        .context("Failed to create approval request")?;
+
+    Ok(pending)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/approval/all",
+    tag = "admin",
+    params(GetApprovalsReq),
+    responses((status = 200, description = "Approvals with the status asked for", body = Vec<PendingApproval>)),
+)]
+pub async fn get_all(
+    State(state): State<state::AppState>,
+    _admin: AdminUser,
+    ValidatedQuery(req): ValidatedQuery<GetApprovalsReq>
+) -> Result<Json<Vec<PendingApproval>>, LedgerError> {
+    let status = req.status.unwrap_or_else(|| "pending".to_string());
+
+    let mut conn = state.db.acquire().await
+        .context("Database error")?;
+
+    // Report lapsed requests as expired rather than pending
+    conn.expire_approvals().await
+        .context("Failed to expire approvals")?;
+
+    let res = conn.approvals_with_status(&status).await
+        .context("Failed to fetch approvals")?;
+
+    Ok(Json(res))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/approval/approve",
+    tag = "admin",
+    request_body = DecideApprovalReq,
+    responses((status = 200, description = "Approved and executed, or for a flagged transfer, sent on for review", body = PendingApproval)),
+)]
+pub async fn approve(
+    State(state): State<state::AppState>,
+    AdminUser(admin_id): AdminUser,
+    ValidatedJson(req): ValidatedJson<DecideApprovalReq>
+) -> Result<Json<PendingApproval>, LedgerError> {
+    let mut tx = state.db.begin().await
+        .context("Failed to start transaction")?;
+
+    let pending = match lock_pending(&mut *tx, req.approval_id, admin_id).await {
+        Ok(pending) => pending,
+        Err(Decision::Expired) => {
+            // Persist the expiry even though the approval itself fails
+            Database::commit(tx).await
+                .context("Failed to commit transaction")?;
+            return Err(expired(req.approval_id));
+        }
+        Err(Decision::Refused(res)) => return Err(res),
+    };
+
+    let mut transaction_id = None;
+    let mut review_id = None;
+    let mut from_account_id = None;
+
+    match pending.kind.as_str() {
+        "transfer" => {
+            let transfer: Transfer = serde_json::from_value(pending.payload)
+                .context("Invalid transfer payload")?;
+
+            // Approving vouches for the request, not for the risk checks, which screen it now
+            // as they would any other transfer and send a flagged one on for review
+            match transaction::execute(&mut *tx, &state, &transfer, Some(pending.requested_by), Some(&state.risk)).await? {
+                TransferOutcome::Posted(id) => {
+                    transaction_id = Some(id);
+                    from_account_id = Some(transfer.from_account_id);
+                }
+                TransferOutcome::HeldForReview { review_id: id, .. } => review_id = Some(id),
+            }
+        }
+        "balance_adjustment" => {
+            let adjustment: AdjustBalanceReq = serde_json::from_value(pending.payload)
+                .context("Invalid adjustment payload")?;
+
+            state.accounts().adjust(&mut *tx, adjustment.account_id, &adjustment.amount).await?;
+        }
+        kind => {
+            return Err(anyhow!("Unknown approval kind {}", kind).into());
+        }
+    }
+
+    let approved = tx.decide_approval(req.approval_id, &ApprovalDecision {
+        status: "approved",
+        decided_by: admin_id,
+        note: req.note.as_deref(),
+        transaction_id,
+        review_id,
+    }).await
+        .context("Failed to update approval")?;
+
+    Database::commit(tx).await
+        .context("Failed to commit transaction")?;
+
+    if let Some(account_id) = from_account_id {
+        budget::spawn_evaluation(state.db.clone(), account_id);
+    }
+
+    Ok(Json(approved))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/approval/reject",
+    tag = "admin",
+    request_body = DecideApprovalReq,
+    responses((status = 200, description = "Rejected", body = PendingApproval)),
+)]
+pub async fn reject(
+    State(state): State<state::AppState>,
+    AdminUser(admin_id): AdminUser,
+    ValidatedJson(req): ValidatedJson<DecideApprovalReq>
+) -> Result<Json<PendingApproval>, LedgerError> {
+    let mut tx = state.db.begin().await
+        .context("Failed to start transaction")?;
+
+    match lock_pending(&mut *tx, req.approval_id, admin_id).await {
+        Ok(_) => {}
+        Err(Decision::Expired) => {
+            Database::commit(tx).await
+                .context("Failed to commit transaction")?;
+            return Err(expired(req.approval_id));
+        }
+        Err(Decision::Refused(res)) => return Err(res),
+    }
+
+    let rejected = tx.decide_approval(req.approval_id, &ApprovalDecision {
+        status: "rejected",
+        decided_by: admin_id,
+        note: req.note.as_deref(),
+        transaction_id: None,
+        review_id: None,
+    }).await
+        .context("Failed to update approval")?;
+
+    Database::commit(tx).await
+        .context("Failed to commit transaction")?;
+
+    Ok(Json(rejected))
+}
+
+/// Why an approval cannot be decided.
+enum Decision {
+    /// The approval lapsed and has just been marked expired
+    Expired,
+    Refused(LedgerError),
+}
+
+fn expired(approval_id: Uuid) -> LedgerError {
+    Problem::new(StatusCode::GONE, "approval_expired", format!("Approval {} has expired", approval_id)).into()
+}
+
+/// Locks a pending approval for a decision by `decider`, who must not be the requester.
+async fn lock_pending(conn: &mut dyn Storage, approval_id: Uuid, decider: Uuid) -> Result<PendingApproval, Decision> {
+    let pending = conn.lock_approval(approval_id).await
+        .map_err(|e| Decision::Refused(e.context("Failed to fetch approval").into()))?
+    .ok_or_else(|| Decision::Refused(LedgerError::NotFound(format!("Approval with ID {} not found", approval_id))))?;
+
+    if pending.status != "pending" {
+        return Err(Decision::Refused(LedgerError::Conflict(format!("Approval {} is already {}", approval_id, pending.status))));
+    }
+
+    if pending.requested_by == decider {
+        return Err(Decision::Refused(LedgerError::Forbidden("Approvals must be decided by a different user than the requester".to_string())));
+    }
+
+    if pending.expires_at <= OffsetDateTime::now_utc() {
+        conn.expire_approval(approval_id).await
+            .map_err(|e| Decision::Refused(e.context("Failed to expire approval").into()))?;
+
+        return Err(Decision::Expired);
+    }
+
+    Ok(pending)
+}
@@ -0,0 +1,161 @@
+use anyhow::Context;
+use axum::{extract::State, Json};
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+use time::{Date, OffsetDateTime};
+use utoipa::ToSchema;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::error::LedgerError;
+use crate::ledger::Repository;
+use crate::middleware::auth::AuthUser;
+use crate::middleware::validate::{self, ValidatedJson};
+use crate::openapi;
+use crate::state;
+use crate::store::budgets::{NewAlert, NewBudget};
+use crate::store::Database;
+
+/// Percentages of a budget at which an alert is raised.
+const ALERT_THRESHOLDS: [i32; 2] = [80, 100];
+
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct CreateBudgetReq {
+    account_id: Uuid,
+    #[validate(length(max = 50), custom(function = "validate::not_blank"))]
+    category: Option<String>,
+    #[validate(custom(function = "validate::amount"))]
+    #[schema(value_type = String)]
+    amount: BigDecimal,
+}
+
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct DeleteBudgetReq {
+    budget_id: Uuid,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct Budget {
+    pub(crate) id: Uuid,
+    pub(crate) account_id: Uuid,
+    pub(crate) category: Option<String>,
+    #[schema(value_type = String)]
+    pub(crate) amount: BigDecimal,
+    #[schema(value_type = Option<openapi::CompactTimestamp>)]
+    pub(crate) created_at: Option<OffsetDateTime>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/budget/create",
+    tag = "budget",
+    request_body = CreateBudgetReq,
+    responses((status = 200, description = "The budget", body = Budget)),
+)]
+pub async fn create(
+    State(state): State<state::AppState>,
+    AuthUser(user_id): AuthUser,
+    ValidatedJson(req): ValidatedJson<CreateBudgetReq>
+) -> Result<Json<Budget>, LedgerError> {
+    // Budgets can only be set on the caller's own accounts
+    if !state.accounts().is_owner(req.account_id, user_id).await? {
+        return Err(LedgerError::NotFound(format!("Account with ID {} not found", req.account_id)));
+    }
+
+    let mut conn = state.db.acquire().await
+        .context("Database error")?;
+
+    let budget = conn.upsert_budget(&NewBudget {
+        user_id,
+        account_id: req.account_id,
+        category: req.category.as_deref(),
+        amount: &req.amount,
+    }).await
+        .context("Failed to create budget")?;
+
+    Ok(Json(budget))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/budget/all",
+    tag = "budget",
+    responses((status = 200, description = "The user's budgets", body = Vec<Budget>)),
+)]
+pub async fn get_all(
+    State(state): State<state::AppState>,
+    AuthUser(user_id): AuthUser
+) -> Result<Json<Vec<Budget>>, LedgerError> {
+    let mut conn = state.db.acquire().await
+        .context("Database error")?;
+
+    let res = conn.budgets_of(user_id).await
+        .context("Failed to fetch budgets")?;
+
+    Ok(Json(res))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/budget/delete",
+    tag = "budget",
+    request_body = DeleteBudgetReq,
+    responses((status = 200, description = "Deleted", body = String)),
+)]
+pub async fn delete(
+    State(state): State<state::AppState>,
+    AuthUser(user_id): AuthUser,
+    ValidatedJson(req): ValidatedJson<DeleteBudgetReq>
+) -> Result<Json<String>, LedgerError> {
+    let mut conn = state.db.acquire().await
+        .context("Database error")?;
+
+    let deleted = conn.delete_budget(user_id, req.budget_id).await
+        .context("Failed to delete budget")?;
+
+    if !deleted {
+        return Err(LedgerError::NotFound(format!("Budget with ID {} not found", req.budget_id)));
+    }
+
+    Ok(Json(format!("Budget {} deleted", req.budget_id)))
+}
+
+/// Evaluates the budgets on `account_id` in a background task, so that budget
+/// alerts never slow down the transfer that triggered them.
+pub fn spawn_evaluation(db: Database, account_id: Uuid) {
+    tokio::spawn(async move {
+        if let Err(e) = evaluate(db, account_id).await {
+            eprintln!("Failed to evaluate budgets for account {}: {}", account_id, e);
+        }
+    });
+}
+
+/// Checks every budget on `account_id` against this month's spending and records
+/// an alert for each threshold that has been crossed. Alerts are only raised once
+/// per budget, threshold and month.
+pub async fn evaluate(db: Database, account_id: Uuid) -> anyhow::Result<()> {
+    let today = OffsetDateTime::now_utc().date();
+    let period_start = Date::from_calendar_date(today.year(), today.month(), 1)?;
+
+    let mut conn = db.acquire().await?;
+
+    let budgets = conn.budget_spending(account_id, period_start).await?;
+
+    for budget in budgets {
+        for threshold in ALERT_THRESHOLDS {
+            if budget.spent.clone() * BigDecimal::from(100) < budget.amount.clone() * BigDecimal::from(threshold) {
+                continue;
+            }
+
+            conn.insert_alert(&NewAlert {
+                budget_id: budget.id,
+                user_id: budget.user_id,
+                threshold,
+                period_start,
+                spent: &budget.spent,
+            }).await?;
+        }
+    }
+
+    Ok(())
+}
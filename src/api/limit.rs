@@ -0,0 +1,352 @@
+use std::fmt;
+
+use anyhow::Context;
+use axum::{
+    extract::State,
+    http::StatusCode,
+    Json,
+};
+use bigdecimal::{BigDecimal, ToPrimitive};
+use serde::{Deserialize, Serialize};
+use sqlx::error::ErrorKind;
+use time::{Duration, OffsetDateTime, Time};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+use validator::{Validate, ValidationError};
+
+use crate::error::{LedgerError, Problem};
+use crate::ledger::Repository;
+use crate::ledger::accounts::Types;
+use crate::middleware::auth::{AdminUser, AuthUser};
+use crate::middleware::validate::{self, ValidatedJson, ValidatedQuery};
+use crate::state;
+use crate::store::limits::{LimitTarget, NewOverride};
+use crate::store::{self, Storage};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitKind {
+    PerTransaction,
+    DailyAmount,
+    WeeklyAmount,
+    HourlyCount,
+}
+
+impl fmt::Display for LimitKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LimitKind::PerTransaction => write!(f, "per_transaction"),
+            LimitKind::DailyAmount => write!(f, "daily_amount"),
+            LimitKind::WeeklyAmount => write!(f, "weekly_amount"),
+            LimitKind::HourlyCount => write!(f, "hourly_count"),
+        }
+    }
+}
+
+/// The limits in force on an account, after account, account type and override
+/// precedence has been applied. `None` means the limit is not enforced.
+#[derive(Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct Limits {
+    #[schema(value_type = Option<String>)]
+    pub(crate) per_transaction: Option<BigDecimal>,
+    #[schema(value_type = Option<String>)]
+    pub(crate) daily_amount: Option<BigDecimal>,
+    #[schema(value_type = Option<String>)]
+    pub(crate) weekly_amount: Option<BigDecimal>,
+    pub(crate) hourly_count: Option<i32>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
+#[validate(schema(function = "one_target", skip_on_field_errors = false))]
+pub struct SetLimitsReq {
+    account_id: Option<Uuid>,
+    account_type: Option<Types>,
+    #[validate(custom(function = "validate::amount"))]
+    #[schema(value_type = Option<String>)]
+    per_transaction: Option<BigDecimal>,
+    #[validate(custom(function = "validate::amount"))]
+    #[schema(value_type = Option<String>)]
+    daily_amount: Option<BigDecimal>,
+    #[validate(custom(function = "validate::amount"))]
+    #[schema(value_type = Option<String>)]
+    weekly_amount: Option<BigDecimal>,
+    #[validate(range(min = 0))]
+    hourly_count: Option<i32>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
+#[validate(schema(function = "value_fits_limit", skip_on_field_errors = false))]
+pub struct OverrideLimitReq {
+    account_id: Uuid,
+    limit: LimitKind,
+    /// Replacement value for the limit, or `None` to lift it entirely. A whole
+    /// number of transfers for `hourly_count`, an amount for the others
+    #[schema(value_type = Option<String>)]
+    value: Option<BigDecimal>,
+    /// At most a year; overrides are for exceptions, not standing limits
+    #[validate(range(min = 1, max = 525_600))]
+    duration_minutes: i64,
+}
+
+/// Limits are set either for one account or for every account of a type.
+fn one_target(req: &SetLimitsReq) -> Result<(), ValidationError> {
+    if req.account_id.is_some() == req.account_type.is_some() {
+        return Err(ValidationError::new("one_target")
+            .with_message("Exactly one of account_id or account_type must be provided".into()));
+    }
+    Ok(())
+}
+
+/// Count limits are replaced by a number of transfers, the others by an amount.
+fn value_fits_limit(req: &OverrideLimitReq) -> Result<(), ValidationError> {
+    match (&req.value, req.limit) {
+        (None, _) => Ok(()),
+        (Some(value), LimitKind::HourlyCount) => validate::count(value),
+        (Some(value), _) => validate::amount(value),
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct LimitOverride {
+    pub(crate) id: Uuid,
+    pub(crate) account_id: Uuid,
+    pub(crate) limit_kind: String,
+    #[schema(value_type = Option<String>)]
+    pub(crate) value: Option<BigDecimal>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub(crate) expires_at: OffsetDateTime,
+}
+
+#[derive(Clone, Serialize, Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GetLimitsReq {
+    account_id: Uuid,
+}
+
+/// A transfer that would exceed one of the sender's limits.
+#[derive(Debug, Clone)]
+pub struct LimitBreach {
+    limit: LimitKind,
+    limit_value: String,
+    /// When the window the limit applies to rolls over, `None` for per-transaction limits
+    resets_at: Option<OffsetDateTime>,
+}
+
+impl From<LimitBreach> for LedgerError {
+    fn from(breach: LimitBreach) -> Self {
+        // Count limits are rate limits and can simply be retried later, amount limits
+        // reject this particular transfer
+        let status = match breach.limit {
+            LimitKind::HourlyCount => StatusCode::TOO_MANY_REQUESTS,
+            _ => StatusCode::UNPROCESSABLE_ENTITY,
+        };
+
+        let mut problem = Problem::new(
+            status,
+            "transfer_limit_exceeded",
+            format!("Transfer exceeds the {} limit of {}", breach.limit, breach.limit_value),
+        )
+        .with("limit", breach.limit)
+        .with("limit_value", &breach.limit_value)
+        .with("resets_at", breach.resets_at.and_then(|t| t.format(&time::format_description::well_known::Rfc3339).ok()));
+
+        if let Some(resets_at) = breach.resets_at && status == StatusCode::TOO_MANY_REQUESTS {
+            problem = problem.retry_after((resets_at - OffsetDateTime::now_utc()).whole_seconds());
+        }
+
+        problem.into()
+    }
+}
+
+/// Errors from [`check`]: either a breached limit or a failure to evaluate them.
+pub enum CheckError {
+    Breach(LimitBreach),
+    Database(anyhow::Error),
+}
+
+impl From<anyhow::Error> for CheckError {
+    fn from(e: anyhow::Error) -> Self {
+        CheckError::Database(e)
+    }
+}
+
+impl From<CheckError> for LedgerError {
+    fn from(e: CheckError) -> Self {
+        match e {
+            CheckError::Breach(breach) => breach.into(),
+            CheckError::Database(e) => e.context("Failed to check transfer limits").into(),
+        }
+    }
+}
+
+/// Loads the limits in force on `account_id`, including any active overrides.
+pub async fn effective_limits(conn: &mut dyn Storage, account_id: Uuid) -> anyhow::Result<Limits> {
+    let Some(mut limits) = conn.limits_of(account_id).await? else {
+        return Ok(Limits::default());
+    };
+
+    // The most recent active override of each kind wins
+    let overrides = conn.active_overrides(account_id).await?;
+
+    for o in overrides {
+        match o.limit_kind.as_str() {
+            "per_transaction" => limits.per_transaction = o.value,
+            "daily_amount" => limits.daily_amount = o.value,
+            "weekly_amount" => limits.weekly_amount = o.value,
+            "hourly_count" => limits.hourly_count = o.value.and_then(|v| v.to_i32()),
+            _ => {}
+        }
+    }
+
+    Ok(limits)
+}
+
+/// Checks a transfer of `amount` out of `account_id` against the account's limits.
+///
+/// This must run inside the transaction that posts the transfer, after the sender's
+/// balance row has been locked, so that concurrent transfers are counted against
+/// the same history.
+pub async fn check(conn: &mut dyn Storage, account_id: Uuid, amount: &BigDecimal) -> Result<(), CheckError> {
+    let limits = effective_limits(conn, account_id).await?;
+
+    if let Some(max) = &limits.per_transaction && amount > max {
+        return Err(CheckError::Breach(LimitBreach {
+            limit: LimitKind::PerTransaction,
+            limit_value: max.to_string(),
+            resets_at: None,
+        }));
+    }
+
+    if limits.daily_amount.is_none() && limits.weekly_amount.is_none() && limits.hourly_count.is_none() {
+        return Ok(());
+    }
+
+    // Limits apply to calendar windows in UTC, weeks starting on Monday
+    let now = OffsetDateTime::now_utc();
+    let hour_start = now.replace_time(Time::from_hms(now.hour(), 0, 0).unwrap_or(Time::MIDNIGHT));
+    let day_start = now.replace_time(Time::MIDNIGHT);
+    let week_start = day_start - Duration::days(now.weekday().number_days_from_monday() as i64);
+
+    let usage = conn.usage(account_id, hour_start, day_start, week_start).await?;
+
+    if let Some(max) = limits.hourly_count && usage.hourly + 1 > max as i64 {
+        return Err(CheckError::Breach(LimitBreach {
+            limit: LimitKind::HourlyCount,
+            limit_value: max.to_string(),
+            resets_at: Some(hour_start + Duration::hours(1)),
+        }));
+    }
+
+    if let Some(max) = &limits.daily_amount && usage.daily + amount > *max {
+        return Err(CheckError::Breach(LimitBreach {
+            limit: LimitKind::DailyAmount,
+            limit_value: max.to_string(),
+            resets_at: Some(day_start + Duration::days(1)),
+        }));
+    }
+
+    if let Some(max) = &limits.weekly_amount && usage.weekly + amount > *max {
+        return Err(CheckError::Breach(LimitBreach {
+            limit: LimitKind::WeeklyAmount,
+            limit_value: max.to_string(),
+            resets_at: Some(week_start + Duration::weeks(1)),
+        }));
+    }
+
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/account/limits",
+    tag = "limit",
+    params(GetLimitsReq),
+    responses((status = 200, description = "The limits in force for one of the user's accounts", body = Limits)),
+)]
+pub async fn get(
+    State(state): State<state::AppState>,
+    AuthUser(user_id): AuthUser,
+    ValidatedQuery(req): ValidatedQuery<GetLimitsReq>
+) -> Result<Json<Limits>, LedgerError> {
+    state.accounts().owned(req.account_id, user_id).await?;
+
+    let mut conn = state.db.acquire().await
+        .context("Database error")?;
+
+    let limits = effective_limits(&mut *conn, req.account_id).await
+        .context("Failed to fetch limits")?;
+
+    Ok(Json(limits))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/limit/set",
+    tag = "admin",
+    request_body = SetLimitsReq,
+    responses((status = 200, description = "The limits now set", body = Limits)),
+)]
+pub async fn set(
+    State(state): State<state::AppState>,
+    _admin: AdminUser,
+    ValidatedJson(req): ValidatedJson<SetLimitsReq>
+) -> Result<Json<Limits>, LedgerError> {
+    let account_type = req.account_type.map(|t| t.to_string());
+
+    let target = match (req.account_id, &account_type) {
+        (Some(account_id), _) => LimitTarget::Account(account_id),
+        (None, account_type) => LimitTarget::AccountType(account_type.as_deref().unwrap_or_default()),
+    };
+
+    let limits = Limits {
+        per_transaction: req.per_transaction,
+        daily_amount: req.daily_amount,
+        weekly_amount: req.weekly_amount,
+        hourly_count: req.hourly_count,
+    };
+
+    let mut conn = state.db.acquire().await
+        .context("Database error")?;
+
+    conn.set_limits(&target, &limits).await
+        .map_err(|e| match e {
+            e if store::violates(&e, ErrorKind::CheckViolation) || store::violates(&e, ErrorKind::ForeignKeyViolation) => LedgerError::BadRequest(
+                "Invalid limits: amounts must be positive, counts not negative, and the account must exist".to_string()
+            ),
+            e => e.context("Failed to set limits").into(),
+        })?;
+
+    Ok(Json(limits))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/limit/override",
+    tag = "admin",
+    request_body = OverrideLimitReq,
+    responses((status = 200, description = "The override", body = LimitOverride)),
+)]
+pub async fn create_override(
+    State(state): State<state::AppState>,
+    AdminUser(admin_id): AdminUser,
+    ValidatedJson(req): ValidatedJson<OverrideLimitReq>
+) -> Result<Json<LimitOverride>, LedgerError> {
+    let expires_at = OffsetDateTime::now_utc() + Duration::minutes(req.duration_minutes);
+
+    let mut conn = state.db.acquire().await
+        .context("Database error")?;
+
+    let res = conn.insert_override(&NewOverride {
+        account_id: req.account_id,
+        limit_kind: &req.limit.to_string(),
+        value: req.value.as_ref(),
+        expires_at,
+        created_by: admin_id,
+    }).await
+        .map_err(|e| match e {
+            e if store::violates(&e, ErrorKind::ForeignKeyViolation) => LedgerError::AccountNotFound(format!("Account with ID {} not found", req.account_id)),
+            e => e.context("Failed to create limit override").into(),
+        })?;
+
+    Ok(Json(res))
+}
@@ -0,0 +1,272 @@
+use anyhow::Context;
+use axum::{extract::State, Json, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use time::{Duration, OffsetDateTime};
+use utoipa::ToSchema;
+use uuid::Uuid;
+use validator::{Validate, ValidationError};
+
+use crate::config::Config;
+use crate::error::{LedgerError, Problem};
+use crate::ledger::Repository;
+use crate::ledger::repository::now;
+use crate::middleware::auth::AdminUser;
+use crate::middleware::validate::ValidatedJson;
+use crate::state;
+use crate::store::audit::AuditEntry;
+use crate::store::{Database, Storage};
+
+/// Failures allowed before each further attempt has to wait.
+const FREE_ATTEMPTS: i32 = 2;
+const MAX_BACKOFF: Duration = Duration::minutes(1);
+/// An IP address is shared by many users, so it gets this many times the email limit.
+const IP_FAILURE_MULTIPLIER: i32 = 4;
+
+/// The only answer a throttled login gets, whether or not the email exists.
+const THROTTLED: &str = "Too many login attempts, try again later";
+
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
+#[validate(schema(function = "some_target", skip_on_field_errors = false))]
+pub struct UnlockReq {
+    #[validate(email)]
+    email: Option<String>,
+    #[validate(ip)]
+    ip_address: Option<String>,
+}
+
+fn some_target(req: &UnlockReq) -> Result<(), ValidationError> {
+    if req.email.is_none() && req.ip_address.is_none() {
+        return Err(ValidationError::new("no_target")
+            .with_message("One of email or ip_address must be provided".into()));
+    }
+    Ok(())
+}
+
+/// The throttle rows a login attempt counts against.
+fn keys(email: &str, ip_address: Option<&str>) -> Vec<(&'static str, String)> {
+    let mut keys = vec![("email", email.trim().to_lowercase())];
+    if let Some(ip) = ip_address {
+        keys.push(("ip", ip.to_string()));
+    }
+    keys
+}
+
+/// How long to wait after the latest of `failures` consecutive failures: nothing for the
+/// first few, then doubling from one second.
+fn backoff(failures: i32) -> Duration {
+    if failures < FREE_ATTEMPTS {
+        return Duration::ZERO;
+    }
+    let exponent = (failures - FREE_ATTEMPTS).min(16) as u32;
+    Duration::seconds(2i64.pow(exponent)).min(MAX_BACKOFF)
+}
+
+/// An attempt counted against the email and IP by [`attempt`]. Dropped, it stays counted as
+/// a failure; one that was not is taken back with [`record_success`] or [`withdraw`].
+pub(crate) struct Attempt {
+    counted: Vec<Counted>,
+}
+
+/// A throttle row as an attempt found it, with forgotten failures already left out.
+struct Throttle {
+    id: Uuid,
+    scope: &'static str,
+    key: String,
+    failures: i32,
+    last_failure_at: Option<OffsetDateTime>,
+    locked_until: Option<OffsetDateTime>,
+}
+
+struct Counted {
+    throttle_id: Uuid,
+    scope: &'static str,
+    counted_at: OffsetDateTime,
+    /// When the failure before this attempt happened, put back if it is taken back
+    previous_failure_at: Option<OffsetDateTime>,
+}
+
+/// Counts an attempt against the email and IP before the password or code is checked, or
+/// refuses it if either is locked out or still backing off. The rows stay locked while this
+/// is decided, so guesses sent in parallel each see the ones before them.
+pub(crate) async fn attempt(db: &Database, config: &Config, email: &str, ip_address: Option<&str>) -> Result<Attempt, LedgerError> {
+    let now = now();
+    let forget_before = now - config.login_lockout;
+
+    let mut tx = db.begin().await
+        .context("Failed to start transaction")?;
+
+    let mut throttles = Vec::new();
+    for (scope, key) in keys(email, ip_address) {
+        let throttle = tx.lock_throttle(scope, &key).await
+            .context("Database error")?;
+
+        // Failures older than the lockout period, or from before a lockout that has ended, are forgotten
+        let forgotten = throttle.last_failure_at.is_none_or(|at| at < forget_before)
+            || throttle.locked_until.is_some_and(|until| until <= now);
+
+        throttles.push(Throttle {
+            id: throttle.id,
+            scope,
+            key,
+            failures: if forgotten { 0 } else { throttle.failures },
+            last_failure_at: if forgotten { None } else { throttle.last_failure_at },
+            locked_until: throttle.locked_until,
+        });
+    }
+
+    let mut throttled = false;
+    for throttle in &throttles {
+        let limit = match throttle.scope {
+            "ip" => config.login_max_failures * IP_FAILURE_MULTIPLIER,
+            _ => config.login_max_failures,
+        };
+
+        if throttle.locked_until.is_some_and(|until| until > now) {
+            throttled = true;
+        } else if throttle.failures >= limit {
+            throttled = true;
+
+            let locked_until = now + config.login_lockout;
+
+            tx.lock_out(throttle.id, locked_until).await
+                .context("Failed to lock out login")?;
+
+            audit(&mut *tx, throttle.id, "LOCKOUT", None, json!({
+                "scope": throttle.scope,
+                "key": throttle.key,
+                "failures": throttle.failures,
+                "locked_until": locked_until.unix_timestamp(),
+            })).await?;
+        } else if throttle.last_failure_at.is_some_and(|at| at + backoff(throttle.failures) > now) {
+            throttled = true;
+        }
+    }
+
+    if throttled {
+        // A refused attempt is not counted, and leaves no row behind for a new email or IP
+        for throttle in throttles.iter().filter(|throttle| throttle.failures == 0) {
+            tx.delete_throttle(throttle.id, true).await
+                .context("Database error")?;
+        }
+
+        Database::commit(tx).await
+            .context("Failed to commit transaction")?;
+
+        return Err(Problem::new(StatusCode::TOO_MANY_REQUESTS, "login_throttled", THROTTLED).into());
+    }
+
+    let mut counted = Vec::new();
+    for throttle in throttles {
+        tx.count_failures(throttle.id, throttle.failures + 1, now).await
+            .context("Failed to record login attempt")?;
+
+        counted.push(Counted {
+            throttle_id: throttle.id,
+            scope: throttle.scope,
+            counted_at: now,
+            previous_failure_at: throttle.last_failure_at,
+        });
+    }
+
+    Database::commit(tx).await
+        .context("Failed to commit transaction")?;
+
+    Ok(Attempt { counted })
+}
+
+/// Takes back an attempt that was not a failure, such as a right password still waiting
+/// for its second factor, leaving earlier failures counted.
+pub(crate) async fn withdraw(db: &Database, attempt: Attempt) -> Result<(), LedgerError> {
+    let mut conn = db.acquire().await
+        .context("Database error")?;
+
+    for counted in &attempt.counted {
+        uncount(&mut *conn, counted).await?;
+    }
+
+    Ok(())
+}
+
+/// Takes back a successful login and clears the email's failures. The IP's are kept, so
+/// one valid account cannot be used to reset guessing against others.
+pub(crate) async fn record_success(db: &Database, attempt: Attempt) -> Result<(), LedgerError> {
+    let mut conn = db.acquire().await
+        .context("Database error")?;
+
+    for counted in &attempt.counted {
+        if counted.scope == "email" {
+            conn.delete_throttle(counted.throttle_id, false).await
+                .context("Failed to reset login failures")?;
+        } else {
+            uncount(&mut *conn, counted).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn uncount(conn: &mut dyn Storage, counted: &Counted) -> Result<(), LedgerError> {
+    conn.uncount_failure(counted.throttle_id, counted.counted_at, counted.previous_failure_at).await
+        .context("Failed to take back login attempt")?;
+
+    Ok(())
+}
+
+async fn audit(conn: &mut dyn Storage, throttle_id: Uuid, operation: &str, performed_by: Option<Uuid>, state: serde_json::Value) -> Result<(), LedgerError> {
+    conn.insert_audit_log(&AuditEntry {
+        entity_type: "login_throttle",
+        entity_id: throttle_id,
+        operation,
+        performed_by,
+        after_state: state,
+    }).await
+        .context("Failed to write audit log")?;
+
+    Ok(())
+}
+
+/// Lifts a lockout early and clears the failure count, for an email, an IP or both.
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/user/unlock",
+    tag = "admin",
+    request_body = UnlockReq,
+    responses((status = 200, description = "Unlocked", body = String)),
+)]
+pub async fn unlock(
+    State(state): State<state::AppState>,
+    AdminUser(admin_id): AdminUser,
+    ValidatedJson(req): ValidatedJson<UnlockReq>
+) -> Result<Json<String>, LedgerError> {
+    let mut targets = Vec::new();
+    if let Some(email) = &req.email {
+        targets.push(("email", email.trim().to_lowercase()));
+    }
+    if let Some(ip) = &req.ip_address {
+        targets.push(("ip", ip.to_string()));
+    }
+
+    let mut tx = state.db.begin().await
+        .context("Failed to start transaction")?;
+
+    let mut unlocked = 0;
+    for (scope, key) in targets {
+        let throttle = tx.remove_throttle(scope, &key).await
+            .context("Failed to unlock login")?;
+
+        if let Some(throttle_id) = throttle {
+            audit(&mut *tx, throttle_id, "UNLOCK", Some(admin_id), json!({ "scope": scope, "key": key })).await?;
+            unlocked += 1;
+        }
+    }
+
+    Database::commit(tx).await
+        .context("Failed to commit transaction")?;
+
+    if unlocked == 0 {
+        return Err(LedgerError::NotFound("No failed logins recorded for that email or IP address".to_string()));
+    }
+
+    Ok(Json("Login unlocked".to_string()))
+}
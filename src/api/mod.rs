@@ -1,3 +1,15 @@
 pub mod account;
+pub mod approval;
+pub mod budget;
+pub mod limit;
+pub mod login_throttle;
+pub mod notification;
+pub mod oidc;
+pub mod payee;
+pub mod review;
+pub mod session;
 pub mod transaction;
+pub mod two_factor;
 pub mod user;
+pub mod v2;
+pub mod verification;
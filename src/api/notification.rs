@@ -0,0 +1,83 @@
+use anyhow::Context;
+use axum::{extract::State, Json};
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+use time::{Date, OffsetDateTime};
+use utoipa::ToSchema;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::error::LedgerError;
+use crate::ledger::Repository;
+use crate::middleware::auth::AuthUser;
+use crate::middleware::validate::ValidatedJson;
+use crate::openapi;
+use crate::state;
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct Notification {
+    pub(crate) id: Uuid,
+    pub(crate) budget_id: Uuid,
+    pub(crate) account_id: Uuid,
+    pub(crate) category: Option<String>,
+    #[schema(value_type = String)]
+    pub(crate) budget_amount: BigDecimal,
+    pub(crate) threshold: i32,
+    #[schema(value_type = String)]
+    pub(crate) spent: BigDecimal,
+    #[schema(value_type = openapi::CompactDate)]
+    pub(crate) period_start: Date,
+    #[schema(value_type = Option<openapi::CompactTimestamp>)]
+    pub(crate) read_at: Option<OffsetDateTime>,
+    #[schema(value_type = Option<openapi::CompactTimestamp>)]
+    pub(crate) created_at: Option<OffsetDateTime>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct MarkReadReq {
+    notification_id: Uuid,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/notification/all",
+    tag = "notification",
+    responses((status = 200, description = "The user's notifications", body = Vec<Notification>)),
+)]
+pub async fn get_all(
+    State(state): State<state::AppState>,
+    AuthUser(user_id): AuthUser
+) -> Result<Json<Vec<Notification>>, LedgerError> {
+    let mut conn = state.db.acquire().await
+        .context("Database error")?;
+
+    let res = conn.notifications_of(user_id).await
+        .context("Failed to fetch notifications")?;
+
+    Ok(Json(res))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/notification/markRead",
+    tag = "notification",
+    request_body = MarkReadReq,
+    responses((status = 200, description = "Marked as read", body = String)),
+)]
+pub async fn mark_read(
+    State(state): State<state::AppState>,
+    AuthUser(user_id): AuthUser,
+    ValidatedJson(req): ValidatedJson<MarkReadReq>
+) -> Result<Json<String>, LedgerError> {
+    let mut conn = state.db.acquire().await
+        .context("Database error")?;
+
+    let updated = conn.mark_notification_read(user_id, req.notification_id).await
+        .context("Failed to update notification")?;
+
+    if !updated {
+        return Err(LedgerError::NotFound(format!("Notification with ID {} not found", req.notification_id)));
+    }
+
+    Ok(Json(format!("Notification {} marked as read", req.notification_id)))
+}
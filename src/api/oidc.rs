@@ -0,0 +1,212 @@
+use anyhow::Context;
+use axum::{extract::State, http::StatusCode, response::Redirect, Json};
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::error::{LedgerError, Problem};
+use crate::ledger::repository::{NewUser, UserChanges};
+use crate::ledger::Repository;
+use crate::middleware::client::ClientInfo;
+use crate::middleware::correlation;
+use crate::middleware::validate::ValidatedQuery;
+use crate::oidc::{Identity, LoginError, OidcClient};
+use crate::state::{self, AppState};
+use crate::store::staff::NewOidcLogin;
+use crate::store::{Database, Storage};
+
+use super::session::{self, random_token};
+
+/// How long a staff member has to get through the identity provider.
+const LOGIN_TTL: Duration = Duration::minutes(10);
+
+#[derive(Clone, Serialize, Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CallbackQuery {
+    code: Option<String>,
+    state: String,
+    /// Set instead of `code` when the provider refused the login
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct StaffLoginRes {
+    /// Short-lived access token
+    token: String,
+    refresh_token: String,
+    expires_in: i64,
+    user_id: Uuid,
+    full_name: String,
+    role: String,
+}
+
+impl From<LoginError> for LedgerError {
+    fn from(e: LoginError) -> Self {
+        match e {
+            LoginError::Provider(_) => {
+                eprintln!("[{}] {}", correlation::current(), e);
+                Problem::new(StatusCode::BAD_GATEWAY, "identity_provider_unavailable", "The identity provider could not be reached").into()
+            }
+            LoginError::Rejected(_) => LedgerError::Unauthorized(e.to_string()),
+        }
+    }
+}
+
+fn client(state: &AppState) -> Result<&OidcClient, LedgerError> {
+    state.oidc.as_deref()
+        .ok_or(LedgerError::NotFound("Staff login is not configured".to_string()))
+}
+
+/// Starts a staff login, sending the browser to the identity provider.
+#[utoipa::path(
+    get,
+    path = "/api/v1/oidc/login",
+    tag = "oidc",
+    responses((status = 303, description = "Sent to the identity provider", headers(("location" = String, description = "The provider's authorization URL")))),
+    security(()),
+)]
+pub async fn login(State(state): State<state::AppState>) -> Result<Redirect, LedgerError> {
+    let oidc = client(&state)?;
+    let pending = oidc.start_login().await?;
+
+    let mut conn = state.db.acquire().await
+        .context("Database error")?;
+
+    conn.insert_oidc_login(&NewOidcLogin {
+        state: &pending.state,
+        code_verifier: &pending.code_verifier,
+        nonce: &pending.nonce,
+        expires_at: OffsetDateTime::now_utc() + LOGIN_TTL,
+    }).await
+        .context("Failed to start login")?;
+
+    Ok(Redirect::to(&pending.authorization_url))
+}
+
+/// Where the identity provider sends the browser back to. Starts a ledger session for
+/// the staff member, with the role their groups map to.
+#[utoipa::path(
+    get,
+    path = "/api/v1/oidc/callback",
+    tag = "oidc",
+    params(CallbackQuery),
+    responses((status = 200, description = "Signed in, with the role the staff member's groups map to", body = StaffLoginRes)),
+    security(()),
+)]
+pub async fn callback(
+    State(state): State<state::AppState>,
+    client_info: ClientInfo,
+    ValidatedQuery(query): ValidatedQuery<CallbackQuery>
+) -> Result<Json<StaffLoginRes>, LedgerError> {
+    let oidc = client(&state)?;
+
+    // Taken whatever happens next, so each state is good for one attempt
+    let mut conn = state.db.acquire().await
+        .context("Database error")?;
+
+    let pending = conn.take_oidc_login(&query.state).await
+        .context("Database error")?
+    .ok_or(LedgerError::BadRequest("Unknown or expired login, start again".to_string()))?;
+
+    if let Some(error) = query.error {
+        let description = query.error_description.unwrap_or_default();
+        return Err(LedgerError::Unauthorized(format!("Identity provider refused the login: {} {}", error, description).trim_end().to_string()));
+    }
+    let code = query.code
+        .ok_or(LedgerError::BadRequest("Missing authorization code".to_string()))?;
+
+    drop(conn);
+
+    let identity = oidc.finish_login(&code, &pending.code_verifier, &pending.nonce).await?;
+
+    let role = oidc.role_for(&identity)
+        .ok_or(LedgerError::Forbidden("Not in any group with access to the ledger".to_string()))?;
+
+    let (user_id, full_name) = link_identity(&state, oidc.issuer(), &identity, role).await?;
+
+    let tokens = session::start(&state, user_id, &client_info).await?;
+
+    Ok(Json(StaffLoginRes {
+        token: tokens.access_token,
+        refresh_token: tokens.refresh_token,
+        expires_in: tokens.expires_in,
+        user_id,
+        full_name,
+        role: role.to_string(),
+    }))
+}
+
+/// The ledger user behind `identity`, linked on first login to the user with its verified
+/// email or to a new one, and given `role`, so changes at the provider apply at the next login.
+async fn link_identity(state: &AppState, issuer: &str, identity: &Identity, role: &str) -> Result<(Uuid, String), LedgerError> {
+    let mut conn = state.db.acquire().await
+        .context("Database error")?;
+
+    let linked = conn.linked_user(issuer, &identity.sub).await
+        .context("Database error")?;
+
+    let user_id = match linked {
+        Some(user_id) => user_id,
+        None => {
+            // An unverified email could claim anyone's ledger user
+            let email = identity.email.as_deref()
+                .filter(|_| identity.email_verified)
+                .ok_or(LedgerError::Forbidden("The identity provider did not share a verified email".to_string()))?;
+            let user_id = find_or_create_user(state, &mut *conn, email, identity.name.as_deref()).await?;
+
+            conn.link_identity(issuer, &identity.sub, user_id).await
+                .context("Failed to link identity")?;
+
+            user_id
+        }
+    };
+
+    conn.set_role(user_id, role).await
+        .context("Failed to update role")?;
+
+    let user = conn.find_user(user_id).await
+        .context("Database error")?
+        .ok_or_else(|| LedgerError::NotFound(format!("User with ID {} not found", user_id)))?;
+
+    Ok((user_id, user.full_name))
+}
+
+async fn find_or_create_user(state: &AppState, conn: &mut dyn Storage, email: &str, name: Option<&str>) -> Result<Uuid, LedgerError> {
+    let existing = conn.find_user_by_email(email).await
+        .context("Database error")?
+        .map(|user| user.id);
+
+    // Staff never sign in with a password, so theirs is one nobody knows
+    let password_hash = state.config.password_hasher().hash_blocking(&random_token()).await
+        .context("Failed to hash password")?;
+
+    // A customer becoming staff gives up their password, and the sessions it started, so
+    // that staff rights are only ever granted by the identity provider
+    if let Some(user_id) = existing {
+        let mut tx = state.db.begin().await
+            .context("Failed to start transaction")?;
+
+        tx.update_user(user_id, &UserChanges { password_hash: Some(password_hash), ..Default::default() }).await
+            .context("Failed to disable password")?;
+
+        tx.revoke_sessions(user_id, None, "linked_to_identity_provider").await
+            .context("Failed to revoke sessions")?;
+
+        Database::commit(tx).await
+            .context("Failed to commit transaction")?;
+
+        return Ok(user_id);
+    }
+
+    let user_id = conn.insert_verified_user(&NewUser {
+        full_name: name.unwrap_or(email).to_string(),
+        email: email.to_string(),
+        password_hash,
+    }).await
+        .context("Failed to create user")?;
+
+    Ok(user_id)
+}
@@ -0,0 +1,244 @@
+use anyhow::Context;
+use axum::{extract::State, Json, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use sqlx::error::ErrorKind;
+use time::{Duration, OffsetDateTime};
+use utoipa::ToSchema;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::config::Config;
+use crate::error::{LedgerError, Problem};
+use crate::ledger::Repository;
+use crate::ledger::transfers::Transfer;
+use crate::middleware::auth::{AuthUser, VerifiedUser};
+use crate::middleware::validate::{self, ValidatedJson};
+use crate::openapi;
+use crate::state;
+use crate::store::payees::NewPayee;
+use crate::store::{self, Database, Storage};
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct Payee {
+    pub(crate) id: Uuid,
+    pub(crate) account_id: Uuid,
+    pub(crate) nickname: String,
+    pub(crate) verification_status: String,
+    #[schema(value_type = Option<openapi::CompactTimestamp>)]
+    pub(crate) created_at: Option<OffsetDateTime>,
+    #[schema(value_type = Option<openapi::CompactTimestamp>)]
+    pub(crate) updated_at: Option<OffsetDateTime>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct CreatePayeeReq {
+    account_id: Uuid,
+    #[validate(length(max = 100), custom(function = "validate::not_blank"))]
+    nickname: String,
+    /// The name the user expects the account to be held under, checked against the
+    /// real holder's name without revealing it
+    #[validate(length(max = 200))]
+    owner_name: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct UpdatePayeeReq {
+    payee_id: Uuid,
+    #[validate(length(max = 100), custom(function = "validate::not_blank"))]
+    nickname: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct DeletePayeeReq {
+    payee_id: Uuid,
+}
+
+/// Compares names ignoring case and whitespace differences.
+fn names_match(expected: &str, actual: &str) -> bool {
+    let normalize = |s: &str| s.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+    normalize(expected) == normalize(actual)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/payee/create",
+    tag = "payee",
+    request_body = CreatePayeeReq,
+    responses((status = 200, description = "The payee", body = Payee)),
+)]
+pub async fn create(
+    State(state): State<state::AppState>,
+    VerifiedUser(user_id): VerifiedUser,
+    ValidatedJson(req): ValidatedJson<CreatePayeeReq>
+) -> Result<Json<Payee>, LedgerError> {
+    let mut conn = state.db.acquire().await
+        .context("Database error")?;
+
+    let holder = conn.find_account(req.account_id).await
+        .context("Database error")?
+        .ok_or(LedgerError::NotFound(format!("Account with ID {} not found", req.account_id)))?
+        .owner_name;
+
+    if req.owner_name.is_some() {
+        check_name_limit(&mut *conn, &state.config, user_id, req.account_id).await?;
+    }
+
+    let verification_status = match &req.owner_name {
+        Some(name) if names_match(name, &holder) => "verified",
+        Some(_) => "mismatch",
+        None => "unverified",
+    };
+
+    let payee = conn.insert_payee(&NewPayee {
+        user_id,
+        account_id: req.account_id,
+        nickname: req.nickname.trim(),
+        verification_status,
+    }).await
+        .map_err(|e| match e {
+            e if store::violates(&e, ErrorKind::UniqueViolation) => LedgerError::Conflict(format!("Account {} is already a payee", req.account_id)),
+            e => e.context("Failed to create payee").into(),
+        })?;
+
+    Ok(Json(payee))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/payee/all",
+    tag = "payee",
+    responses((status = 200, description = "The user's payees", body = Vec<Payee>)),
+)]
+pub async fn get_all(
+    State(state): State<state::AppState>,
+    AuthUser(user_id): AuthUser
+) -> Result<Json<Vec<Payee>>, LedgerError> {
+    let mut conn = state.db.acquire().await
+        .context("Database error")?;
+
+    let res = conn.payees_of(user_id).await
+        .context("Failed to fetch payees")?;
+
+    Ok(Json(res))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/payee/update",
+    tag = "payee",
+    request_body = UpdatePayeeReq,
+    responses((status = 200, description = "The payee", body = Payee)),
+)]
+pub async fn update(
+    State(state): State<state::AppState>,
+    AuthUser(user_id): AuthUser,
+    ValidatedJson(req): ValidatedJson<UpdatePayeeReq>
+) -> Result<Json<Payee>, LedgerError> {
+    let mut conn = state.db.acquire().await
+        .context("Database error")?;
+
+    let payee = conn.rename_payee(user_id, req.payee_id, req.nickname.trim()).await
+        .context("Failed to update payee")?
+    .ok_or(LedgerError::NotFound(format!("Payee with ID {} not found", req.payee_id)))?;
+
+    Ok(Json(payee))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/payee/delete",
+    tag = "payee",
+    request_body = DeletePayeeReq,
+    responses((status = 200, description = "Deleted", body = String)),
+)]
+pub async fn delete(
+    State(state): State<state::AppState>,
+    AuthUser(user_id): AuthUser,
+    ValidatedJson(req): ValidatedJson<DeletePayeeReq>
+) -> Result<Json<String>, LedgerError> {
+    let mut conn = state.db.acquire().await
+        .context("Database error")?;
+
+    let deleted = conn.delete_payee(user_id, req.payee_id).await
+        .context("Failed to delete payee")?;
+
+    if !deleted {
+        return Err(LedgerError::NotFound(format!("Payee with ID {} not found", req.payee_id)));
+    }
+
+    Ok(Json(format!("Payee {} deleted", req.payee_id)))
+}
+
+/// Records a check of an account holder's name by `user_id`, refusing it once they have
+/// made too many in the last day. Otherwise the result would let them guess names.
+async fn check_name_limit(conn: &mut dyn Storage, config: &Config, user_id: Uuid, account_id: Uuid) -> Result<(), LedgerError> {
+    // Recorded before counting, so checks made in parallel count against each other
+    conn.record_name_check(user_id, account_id).await
+        .context("Failed to record name check")?;
+
+    let checks = conn.name_checks_since(user_id, OffsetDateTime::now_utc() - Duration::days(1)).await
+        .context("Failed to count name checks")?;
+
+    if checks > config.payee_name_checks_per_day {
+        return Err(Problem::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "payee_name_check_limit",
+            format!("At most {} account holder names can be checked a day", config.payee_name_checks_per_day),
+        ).into());
+    }
+
+    Ok(())
+}
+
+/// Looks up the account behind one of `user_id`'s payees.
+pub(crate) async fn account_for(db: &Database, user_id: Uuid, payee_id: Uuid) -> Result<Uuid, LedgerError> {
+    let mut conn = db.acquire().await
+        .context("Database error")?;
+
+    let account_id = conn.payee_account(user_id, payee_id).await
+        .context("Failed to fetch payee")?
+    .ok_or_else(|| Problem::new(StatusCode::NOT_FOUND, "payee_not_found", format!("Payee with ID {} not found", payee_id)))?;
+
+    Ok(account_id)
+}
+
+/// Caps how much `user_id` can send to a recipient they first added as a payee, or first
+/// paid, recently. A recipient without either is new as of this transfer, so deleting the
+/// payee, or never saving one, does not get around the limit. The user's own accounts are
+/// not recipients.
+pub(crate) async fn check_cooling_off(conn: &mut dyn Storage, config: &Config, user_id: Uuid, transfer: &Transfer) -> Result<(), LedgerError> {
+    let now = OffsetDateTime::now_utc();
+
+    let history = conn.recipient_history(user_id, transfer.to_account_id).await
+        .context("Failed to check payee cooling-off")?;
+
+    if history.own {
+        return Ok(());
+    }
+
+    let first_seen_at = [history.added_at, history.first_paid_at]
+        .into_iter()
+        .flatten()
+        .min()
+        .unwrap_or(now);
+
+    if first_seen_at + config.payee_cooling_off <= now {
+        return Ok(());
+    }
+
+    if &history.sent + &transfer.amount > config.payee_cooling_off_limit {
+        let ends_at = first_seen_at + config.payee_cooling_off;
+        return Err(Problem::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "payee_cooling_off",
+            format!(
+                "At most {} can be sent to a new recipient until {}; {} has been sent so far",
+                config.payee_cooling_off_limit,
+                ends_at,
+                history.sent
+            ),
+        ).into());
+    }
+
+    Ok(())
+}
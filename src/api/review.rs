@@ -0,0 +1,194 @@
+use anyhow::{anyhow, Context};
+use axum::{extract::State, Json};
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+use validator::{Validate, ValidationError};
+
+use crate::error::LedgerError;
+use crate::ledger::Repository;
+use crate::ledger::transfers::Transfer;
+use crate::middleware::auth::AdminUser;
+use crate::middleware::validate::{self, ValidatedJson, ValidatedQuery};
+use crate::openapi;
+use crate::risk::RiskFlag;
+use crate::state;
+use crate::store::reviews::ReviewDecision;
+use crate::store::{Database, Storage};
+
+use super::budget;
+use super::transaction::{self, TransferOutcome};
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct Review {
+    pub(crate) id: Uuid,
+    pub(crate) from_account_id: Uuid,
+    pub(crate) to_account_id: Uuid,
+    #[schema(value_type = String)]
+    pub(crate) amount: BigDecimal,
+    pub(crate) category: Option<String>,
+    pub(crate) flags: serde_json::Value,
+    pub(crate) status: String,
+    pub(crate) requested_by: Option<Uuid>,
+    pub(crate) reviewed_by: Option<Uuid>,
+    pub(crate) review_note: Option<String>,
+    pub(crate) transaction_id: Option<Uuid>,
+    #[schema(value_type = Option<openapi::CompactTimestamp>)]
+    pub(crate) reviewed_at: Option<OffsetDateTime>,
+    #[schema(value_type = Option<openapi::CompactTimestamp>)]
+    pub(crate) created_at: Option<OffsetDateTime>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GetReviewsReq {
+    #[validate(custom(function = "known_status"))]
+    status: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct DecideReviewReq {
+    review_id: Uuid,
+    #[validate(length(max = 500))]
+    note: Option<String>,
+}
+
+fn known_status(status: &str) -> Result<(), ValidationError> {
+    validate::one_of(status, &["pending", "approved", "rejected"])
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/review/all",
+    tag = "admin",
+    params(GetReviewsReq),
+    responses((status = 200, description = "Reviews with the status asked for", body = Vec<Review>)),
+)]
+pub async fn get_all(
+    State(state): State<state::AppState>,
+    _admin: AdminUser,
+    ValidatedQuery(req): ValidatedQuery<GetReviewsReq>
+) -> Result<Json<Vec<Review>>, LedgerError> {
+    let status = req.status.unwrap_or_else(|| "pending".to_string());
+
+    let mut conn = state.db.acquire().await
+        .context("Database error")?;
+
+    let res = conn.reviews_with_status(&status).await
+        .context("Failed to fetch review queue")?;
+
+    Ok(Json(res))
+}
+
+/// Posts a held transfer. Limits and balances are checked again, but the transfer is
+/// not screened a second time.
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/review/approve",
+    tag = "admin",
+    request_body = DecideReviewReq,
+    responses((status = 200, description = "Approved and posted", body = Review)),
+)]
+pub async fn approve(
+    State(state): State<state::AppState>,
+    AdminUser(admin_id): AdminUser,
+    ValidatedJson(req): ValidatedJson<DecideReviewReq>
+) -> Result<Json<Review>, LedgerError> {
+    let mut tx = state.db.begin().await
+        .context("Failed to start transaction")?;
+
+    let review = lock_pending(&mut *tx, req.review_id, admin_id).await?;
+
+    let transfer = Transfer {
+        from_account_id: review.from_account_id,
+        to_account_id: review.to_account_id,
+        amount: review.amount,
+        category: review.category,
+    };
+
+    let transaction_id = match transaction::execute(&mut *tx, &state, &transfer, review.requested_by, None).await? {
+        TransferOutcome::Posted(transaction_id) => transaction_id,
+        TransferOutcome::HeldForReview { .. } => {
+            return Err(anyhow!("Approved transaction was held again").into());
+        }
+    };
+
+    let review = tx.decide_review(req.review_id, &ReviewDecision {
+        status: "approved",
+        reviewed_by: admin_id,
+        note: req.note.as_deref(),
+        transaction_id: Some(transaction_id),
+    }).await
+        .context("Failed to update review")?;
+
+    Database::commit(tx).await
+        .context("Failed to commit transaction")?;
+
+    budget::spawn_evaluation(state.db.clone(), transfer.from_account_id);
+
+    Ok(Json(review))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/review/reject",
+    tag = "admin",
+    request_body = DecideReviewReq,
+    responses((status = 200, description = "Rejected", body = Review)),
+)]
+pub async fn reject(
+    State(state): State<state::AppState>,
+    AdminUser(admin_id): AdminUser,
+    ValidatedJson(req): ValidatedJson<DecideReviewReq>
+) -> Result<Json<Review>, LedgerError> {
+    let mut tx = state.db.begin().await
+        .context("Failed to start transaction")?;
+
+    lock_pending(&mut *tx, req.review_id, admin_id).await?;
+
+    let review = tx.decide_review(req.review_id, &ReviewDecision {
+        status: "rejected",
+        reviewed_by: admin_id,
+        note: req.note.as_deref(),
+        transaction_id: None,
+    }).await
+        .context("Failed to update review")?;
+
+    Database::commit(tx).await
+        .context("Failed to commit transaction")?;
+
+    Ok(Json(review))
+}
+
+/// Queues a transfer flagged by a risk check for review, in the caller's transaction.
+pub(crate) async fn enqueue(
+    conn: &mut dyn Storage,
+    transfer: &Transfer,
+    flags: &[RiskFlag],
+    requested_by: Option<Uuid>
+) -> Result<Uuid, LedgerError> {
+    let review_id = conn.insert_review(transfer, serde_json::to_value(flags).unwrap_or_default(), requested_by).await
+        .context("Failed to queue transaction for review")?;
+
+    Ok(review_id)
+}
+
+/// Locks a review for a decision, failing if it does not exist, was already decided
+/// or would be decided by the user who requested the transfer.
+async fn lock_pending(conn: &mut dyn Storage, review_id: Uuid, decider: Uuid) -> Result<Review, LedgerError> {
+    let review = conn.lock_review(review_id).await
+        .context("Failed to fetch review")?
+    .ok_or_else(|| LedgerError::NotFound(format!("Review with ID {} not found", review_id)))?;
+
+    if review.status != "pending" {
+        return Err(LedgerError::Conflict(format!("Review {} is already {}", review_id, review.status)));
+    }
+
+    if review.requested_by == Some(decider) {
+        return Err(LedgerError::Forbidden("Reviews must be decided by a different user than the requester".to_string()));
+    }
+
+    Ok(review)
+}
@@ -0,0 +1,272 @@
+use anyhow::Context;
+use axum::{extract::State, Json};
+use jsonwebtoken::jwk::JwkSet;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use utoipa::ToSchema;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::error::LedgerError;
+use crate::ledger::Repository;
+use crate::middleware::auth::{AuthUser, Claims, CurrentSession};
+use crate::middleware::client::ClientInfo;
+use crate::middleware::validate::{self, ValidatedJson};
+use crate::openapi;
+use crate::state::{self, AppState};
+use crate::store::sessions::NewSession;
+use crate::store::{Database, Storage};
+
+/// The tokens handed out when a session starts or is refreshed.
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct Tokens {
+    pub(crate) access_token: String,
+    pub(crate) refresh_token: String,
+    /// Seconds until the access token expires
+    pub(crate) expires_in: i64,
+}
+
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct RefreshReq {
+    #[validate(custom(function = "validate::not_blank"))]
+    refresh_token: String,
+}
+
+/// A signed-in device, as shown to its owner.
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct ActiveSession {
+    pub(crate) id: Uuid,
+    pub(crate) user_agent: Option<String>,
+    pub(crate) ip_address: Option<String>,
+    #[schema(value_type = Option<openapi::CompactTimestamp>)]
+    pub(crate) created_at: Option<OffsetDateTime>,
+    #[schema(value_type = Option<openapi::CompactTimestamp>)]
+    pub(crate) last_seen_at: Option<OffsetDateTime>,
+    /// Whether this is the session making the request
+    pub(crate) current: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct RevokeSessionReq {
+    session_id: Uuid,
+}
+
+/// Refresh tokens are random and high-entropy, so a plain SHA-256 is enough to keep
+/// them useless if the table leaks.
+pub(crate) fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// A random 256 bit token, hex encoded.
+pub(crate) fn random_token() -> String {
+    hex::encode(rand::rng().random::<[u8; 32]>())
+}
+
+fn issue_access_token(state: &AppState, user_id: Uuid, session_id: Uuid) -> Result<String, LedgerError> {
+    let expiration = OffsetDateTime::now_utc() + state.config.access_token_ttl;
+    let claims = Claims {
+        sub: user_id.to_string(),
+        exp: expiration.unix_timestamp() as usize,
+        jti: Uuid::new_v4(),
+        sid: session_id,
+    };
+
+    let token = state.keys.sign(&claims)
+        .context("Failed to sign access token")?;
+
+    Ok(token)
+}
+
+/// Stores a fresh refresh token for a session and returns it.
+async fn issue_refresh_token(conn: &mut dyn Storage, session_id: Uuid) -> Result<String, LedgerError> {
+    let token = random_token();
+
+    conn.insert_refresh_token(session_id, &hash_token(&token)).await
+        .context("Failed to store refresh token")?;
+
+    Ok(token)
+}
+
+/// The public keys that verify access tokens, so other services can check them locally.
+pub async fn jwks(State(state): State<state::AppState>) -> Json<JwkSet> {
+    Json(state.keys.jwks())
+}
+
+/// Starts a new session for a user who has just authenticated.
+pub(crate) async fn start(state: &AppState, user_id: Uuid, client: &ClientInfo) -> Result<Tokens, LedgerError> {
+    let mut tx = state.db.begin().await
+        .context("Failed to start transaction")?;
+
+    let session_id = tx.insert_session(&NewSession {
+        user_id,
+        expires_at: OffsetDateTime::now_utc() + state.config.refresh_token_ttl,
+        user_agent: client.user_agent.as_deref(),
+        ip_address: client.ip_address.as_deref(),
+    }).await
+        .context("Failed to create session")?;
+
+    let refresh_token = issue_refresh_token(&mut *tx, session_id).await?;
+
+    Database::commit(tx).await
+        .context("Failed to commit transaction")?;
+
+    Ok(Tokens {
+        access_token: issue_access_token(state, user_id, session_id)?,
+        refresh_token,
+        expires_in: state.config.access_token_ttl.whole_seconds(),
+    })
+}
+
+/// Trades a refresh token for a new access token and a new refresh token. Each refresh
+/// token works once; presenting a used one revokes its whole session, since either the
+/// client or an attacker holds a stolen copy.
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/refresh",
+    tag = "session",
+    request_body = RefreshReq,
+    responses((status = 200, description = "New tokens; the refresh token sent is used up", body = Tokens)),
+    security(()),
+)]
+pub async fn refresh(
+    State(state): State<state::AppState>,
+    ValidatedJson(req): ValidatedJson<RefreshReq>
+) -> Result<Json<Tokens>, LedgerError> {
+    let token_hash = hash_token(&req.refresh_token);
+
+    let mut tx = state.db.begin().await
+        .context("Failed to start transaction")?;
+
+    let session_id = tx.use_refresh_token(&token_hash).await
+        .context("Failed to use refresh token")?;
+
+    let Some(session_id) = session_id else {
+        let reused = tx.revoke_session_of_token(&token_hash, "refresh_token_reuse").await
+            .context("Failed to revoke session")?;
+
+        Database::commit(tx).await
+            .context("Failed to commit transaction")?;
+
+        if let Some(session_id) = reused {
+            eprintln!("Refresh token reused, revoked session {}", session_id);
+        }
+        return Err(LedgerError::Unauthorized("Invalid refresh token".to_string()));
+    };
+
+    let user_id = tx.extend_session(session_id, OffsetDateTime::now_utc() + state.config.refresh_token_ttl).await
+        .context("Failed to extend session")?
+        .ok_or(LedgerError::Unauthorized("Session has expired or been revoked".to_string()))?;
+
+    let refresh_token = issue_refresh_token(&mut *tx, session_id).await?;
+
+    Database::commit(tx).await
+        .context("Failed to commit transaction")?;
+
+    Ok(Json(Tokens {
+        access_token: issue_access_token(&state, user_id, session_id)?,
+        refresh_token,
+        expires_in: state.config.access_token_ttl.whole_seconds(),
+    }))
+}
+
+/// Ends the caller's session. The access token used for the call stops working
+/// immediately, as do the session's refresh tokens.
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/logout",
+    tag = "session",
+    responses((status = 200, description = "Signed out", body = String)),
+)]
+pub async fn logout(
+    State(state): State<state::AppState>,
+    AuthUser(user_id): AuthUser,
+    session: CurrentSession
+) -> Result<Json<String>, LedgerError> {
+    let mut tx = state.db.begin().await
+        .context("Failed to start transaction")?;
+
+    tx.revoke_session(session.id, user_id, "logout").await
+        .context("Failed to revoke session")?;
+
+    tx.revoke_access_token(session.jti, session.expires_at).await
+        .context("Failed to revoke token")?;
+
+    Database::commit(tx).await
+        .context("Failed to commit transaction")?;
+
+    Ok(Json("Logged out".to_string()))
+}
+
+/// Revokes every live session of `user_id` except `keep`, returning how many were revoked.
+pub(crate) async fn revoke_others(conn: &mut dyn Storage, user_id: Uuid, keep: Uuid, reason: &str) -> anyhow::Result<u64> {
+    conn.revoke_sessions(user_id, Some(keep), reason).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/sessions",
+    tag = "session",
+    responses((status = 200, description = "The user's active sessions", body = Vec<ActiveSession>)),
+)]
+pub async fn get_all(
+    State(state): State<state::AppState>,
+    AuthUser(user_id): AuthUser,
+    session: CurrentSession
+) -> Result<Json<Vec<ActiveSession>>, LedgerError> {
+    let mut conn = state.db.acquire().await
+        .context("Failed to acquire connection")?;
+
+    let res = conn.live_sessions(user_id, session.id).await
+        .context("Failed to fetch sessions")?;
+
+    Ok(Json(res))
+}
+
+/// Signs out one of the caller's devices, which may be the current one.
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/sessions/revoke",
+    tag = "session",
+    request_body = RevokeSessionReq,
+    responses((status = 200, description = "Revoked", body = String)),
+)]
+pub async fn revoke(
+    State(state): State<state::AppState>,
+    AuthUser(user_id): AuthUser,
+    ValidatedJson(req): ValidatedJson<RevokeSessionReq>
+) -> Result<Json<String>, LedgerError> {
+    let mut conn = state.db.acquire().await
+        .context("Failed to acquire connection")?;
+
+    let revoked = conn.revoke_session(req.session_id, user_id, "revoked_by_user").await
+        .context("Failed to revoke session")?;
+
+    if !revoked {
+        return Err(LedgerError::NotFound(format!("Session with ID {} not found", req.session_id)));
+    }
+
+    Ok(Json(format!("Session {} revoked", req.session_id)))
+}
+
+/// Signs out every device except the one making the request.
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/sessions/revokeOthers",
+    tag = "session",
+    responses((status = 200, description = "Every other session revoked", body = String)),
+)]
+pub async fn revoke_all_others(
+    State(state): State<state::AppState>,
+    AuthUser(user_id): AuthUser,
+    session: CurrentSession
+) -> Result<Json<String>, LedgerError> {
+    let mut conn = state.db.acquire().await
+        .context("Failed to acquire connection")?;
+
+    let revoked = revoke_others(&mut *conn, user_id, session.id, "revoked_by_user").await
+        .context("Failed to revoke sessions")?;
+
+    Ok(Json(format!("{} other sessions revoked", revoked)))
+}
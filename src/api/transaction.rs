@@ -1,137 +1,229 @@
-use axum::{extract::{Query, State}, Json, http::StatusCode};
+use anyhow::Context;
+use axum::{extract::State, Json, http::StatusCode, response::{IntoResponse, Response}};
 use serde::{Deserialize, Serialize};
-use sqlx::{types::{BigDecimal, Uuid}, Pool, Postgres, Error as SqlxError};
-use time::OffsetDateTime;
-
+use sqlx::types::{BigDecimal, Uuid};
+use utoipa::{IntoParams, ToSchema};
+use validator::{Validate, ValidationError};
+
+use crate::error::LedgerError;
+use crate::ledger::Repository;
+use crate::ledger::transfers::{same_account, Counterparty, Transaction, Transfer};
+use crate::middleware::auth::{CurrentSession, VerifiedUser};
+use crate::middleware::validate::{self, ValidatedJson, ValidatedQuery};
+use crate::risk::{RiskEngine, RiskFlag, TransferContext};
 use crate::state;
+use crate::store::{Database, Storage};
+
+use super::{approval, budget, limit, payee, review, two_factor};
 
-#[derive(Clone, Serialize, Deserialize)]
+/// A transfer request. The counterparty is given as a raw account id, an account
+/// number, an IBAN, or one of the caller's saved payees.
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
+#[validate(schema(function = "distinct_accounts", skip_on_field_errors = false))]
 pub struct CreateTransReq {
     from_account_id: Uuid,
-    to_account_id: Uuid,
+    to_account_id: Option<Uuid>,
+    #[validate(length(max = 34))]
+    to_account_number: Option<String>,
+    #[validate(length(max = 42))]
+    to_iban: Option<String>,
+    payee_id: Option<Uuid>,
+    #[validate(custom(function = "validate::amount"))]
+    #[schema(value_type = String)]
     amount: BigDecimal,
+    #[validate(length(max = 50), custom(function = "validate::not_blank"))]
+    category: Option<String>,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct GetTransReq {
     account_id: Uuid
 }
 
-#[derive(Clone, Serialize, Deserialize)]
-struct BalanceResult {
-    account_id: Uuid,
-    balance: BigDecimal
+/// A transfer that was accepted but not posted yet.
+#[derive(Clone, Serialize, ToSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum HeldTransfer {
+    /// Above the approval threshold, so waiting for a second user
+    PendingApproval { approval: approval::PendingApproval },
+    /// Flagged by a risk check, so waiting for a reviewer
+    PendingReview { review_id: Uuid, flags: Vec<RiskFlag> },
 }
 
-#[derive(Clone, Serialize, Deserialize)]
-pub struct Transaction {
-    id: Uuid,
-    from_account_id: Uuid,
-    to_account_id: Uuid,
-    amount: BigDecimal,
-    created_at: Option<OffsetDateTime>
+/// What happened to a transfer passed to [`execute`].
+pub enum TransferOutcome {
+    Posted(Uuid),
+    HeldForReview { review_id: Uuid, flags: Vec<RiskFlag> },
 }
 
-async fn update_balance(trans: CreateTransReq, pool: Pool<Postgres>) -> anyhow::Result<()> {
-    let from_balance = sqlx::query_as!(
-        BalanceResult,
-        "SELECT account_id, balance FROM account_balances where account_id = $1",
-        trans.from_account_id
-    ).fetch_one(&pool).await
-     .map_err(|e| anyhow::anyhow!("Failed to fetch source account balance: {}", e))?;
-    
-    let to_balance = sqlx::query_as!(
-        BalanceResult,
-        "SELECT account_id, balance FROM account_balances where account_id = $1",
-        trans.to_account_id
-    ).fetch_one(&pool).await
-     .map_err(|e| anyhow::anyhow!("Failed to fetch destination account balance: {}", e))?;
-
-    let new_from_acc_balance = from_balance.balance - trans.clone().amount;
-    let new_to_acc_balance = to_balance.balance + trans.clone().amount;
-
-    if (new_from_acc_balance > BigDecimal::from(0)) && (new_to_acc_balance > BigDecimal::from(0)) {
-        let new_from_balance = sqlx::query_scalar!(
-            "UPDATE account_balances SET balance = $1 where account_id = $2 returning balance",
-            new_from_acc_balance,
-            from_balance.account_id
-        ).fetch_one(&pool).await
-         .map_err(|e| anyhow::anyhow!("Failed to update source account balance: {}", e))?;
-        
-        let new_to_balance = sqlx::query_scalar!(
-            "UPDATE account_balances SET balance = $1 where account_id = $2 returning balance",
-            new_to_acc_balance,
-            to_balance.account_id
-        ).fetch_one(&pool).await
-         .map_err(|e| anyhow::anyhow!("Failed to update destination account balance: {}", e))?;
-        
-        println!("from_balance = {new_from_balance}, to_balance = {new_to_balance}, amount = {}", trans.amount.to_string());
-        Ok(())
-    } else {
-        Err(anyhow::anyhow!("Insufficient balance for transaction. From account balance would be {new_from_acc_balance}, to account balance would be {new_to_acc_balance}"))
+/// Posts a transfer inside the caller's database transaction: locks both balances,
+/// checks the sender's limits, screens the transfer with `risk` if given, then records
+/// it and moves the money. Flagged transfers are queued for review instead.
+///
+/// Nothing is visible to others until the caller commits.
+pub(crate) async fn execute(
+    conn: &mut (dyn Storage + 'static),
+    state: &state::AppState,
+    req: &Transfer,
+    requested_by: Option<Uuid>,
+    risk: Option<&RiskEngine>
+) -> Result<TransferOutcome, LedgerError> {
+    let transfers = state.transfers();
+
+    // Holding the balance locks while checking limits means concurrent transfers out of
+    // the same account are checked against each other's history one at a time
+    let locked = transfers.lock(conn, req).await?;
+
+    limit::check(conn, req.from_account_id, &req.amount).await?;
+
+    if let Some(user_id) = requested_by {
+        payee::check_cooling_off(conn, &state.config, user_id, req).await?;
+    }
+
+    if let Some(risk) = risk {
+        let context = TransferContext {
+            from_account_id: req.from_account_id,
+            to_account_id: req.to_account_id,
+            amount: req.amount.clone(),
+        };
+
+        let flags = risk.screen(conn, &context).await
+            .context("Failed to screen transaction")?;
+
+        if !flags.is_empty() {
+            let review_id = review::enqueue(conn, req, &flags, requested_by).await?;
+
+            return Ok(TransferOutcome::HeldForReview { review_id, flags });
+        }
     }
+
+    let transaction_id = transfers.post(conn, locked, req).await?;
+
+    Ok(TransferOutcome::Posted(transaction_id))
 }
 
-#[axum::debug_handler]
-pub async fn create(State(state): State<state::AppState>, Json(req): Json<CreateTransReq>) -> Result<Json<String>, (StatusCode, String)> {
-    let pool = state.db;
-
-    let transaction_id = sqlx::query_scalar!(
-        "INSERT INTO transactions (from_account_id, to_account_id, amount ) values ($1, $2, $3) returning id",
-        req.from_account_id,
-        req.to_account_id,
-        req.amount,
-    ).fetch_one(&pool).await
-     .map_err(|e| {
-        let error_msg = format!("Failed to create transaction: {}", e);
-        (StatusCode::INTERNAL_SERVER_ERROR, error_msg)
-     })?;
-
-    match update_balance(req, pool).await {
-        Ok(_) => Ok(Json(format!("Transaction created successfully with ID: {}", transaction_id))),
-        Err(e) => Err((StatusCode::BAD_REQUEST, e.to_string()))
+fn distinct_accounts(req: &CreateTransReq) -> Result<(), ValidationError> {
+    if req.to_account_id == Some(req.from_account_id) {
+        return Err(same_account());
     }
+    Ok(())
 }
 
-pub async fn get_all(State(state): State<state::AppState>) -> Result<Json<Vec<Transaction>>, (StatusCode, String)> {
-    let pool = state.db;
+/// Resolves the counterparty of a transfer request to an account.
+async fn resolve(state: &state::AppState, user_id: Uuid, req: CreateTransReq) -> Result<Transfer, LedgerError> {
+    let to = match (req.to_account_id, req.to_account_number, req.to_iban, req.payee_id) {
+        (Some(to_account_id), None, None, None) => Counterparty::Account(to_account_id),
+        (None, Some(number), None, None) => Counterparty::AccountNumber(number),
+        (None, None, Some(iban), None) => Counterparty::Iban(iban),
+        (None, None, None, Some(payee_id)) => Counterparty::Account(payee::account_for(&state.db, user_id, payee_id).await?),
+        _ => return Err(LedgerError::BadRequest(
+            "Exactly one of to_account_id, to_account_number, to_iban or payee_id must be provided".to_string()
+        )),
+    };
+
+    state.transfers().prepare(req.from_account_id, to, req.amount, req.category).await
+}
 
-    let res = sqlx::query_as!(
-        Transaction,
-        "SELECT * FROM transactions;"
-    ).fetch_all(&pool)
-     .await
-     .map_err(|e| {
-        let error_msg = format!("Failed to fetch transactions: {}", e);
-        (StatusCode::INTERNAL_SERVER_ERROR, error_msg)
-     })?;
+/// What became of a transfer request passed to [`submit`].
+pub(crate) enum Submitted {
+    Posted(Uuid),
+    Held(Box<HeldTransfer>),
+}
 
-    Ok(Json(res))
+/// Resolves, checks and posts a transfer out of one of `user_id`'s accounts, or holds it
+/// for approval or review. This is what both versions of the API do when asked for a transfer.
+pub(crate) async fn submit(
+    state: &state::AppState,
+    user_id: Uuid,
+    session_id: Option<Uuid>,
+    req: CreateTransReq
+) -> Result<Submitted, LedgerError> {
+    // Money only leaves the caller's own accounts, so the checks below are about its owner
+    let from = state.accounts().owned(req.from_account_id, user_id).await?;
+
+    let req = resolve(state, user_id, req).await?;
+
+    let mut conn = state.db.acquire().await
+        .context("Database error")?;
+
+    two_factor::check_step_up(&mut *conn, &state.config, from.user_id, session_id, &req.amount).await?;
+
+    // Large transfers wait for a second user's approval before anything is checked or posted
+    if req.amount > state.config.approval_threshold {
+        let pending = approval::request(&state.db, approval::Kind::Transfer, &req, user_id, state.config.approval_ttl).await?;
+
+        return Ok(Submitted::Held(Box::new(HeldTransfer::PendingApproval { approval: pending })));
+    }
+
+    drop(conn);
+
+    let mut tx = state.db.begin().await
+        .context("Failed to start transaction")?;
+
+    let outcome = execute(&mut *tx, state, &req, Some(user_id), Some(&state.risk)).await?;
+
+    Database::commit(tx).await
+        .context("Failed to commit transaction")?;
+
+    match outcome {
+        TransferOutcome::Posted(transaction_id) => {
+            budget::spawn_evaluation(state.db.clone(), req.from_account_id);
+            Ok(Submitted::Posted(transaction_id))
+        }
+        TransferOutcome::HeldForReview { review_id, flags } => Ok(Submitted::Held(Box::new(HeldTransfer::PendingReview { review_id, flags }))),
+    }
 }
 
-pub async fn query(State(state): State<state::AppState>, Query(req): Query<GetTransReq>) -> Result<Json<Vec<Transaction>>, (StatusCode, String)> {
-    let pool = state.db;
-
-    let res = sqlx::query_as!(
-        Transaction,
-        "SELECT * FROM transactions where from_account_id = $1 OR to_account_id = $1",
-        req.account_id
-    ).fetch_all(&pool).await
-     .map_err(|e| {
-        match e {
-            SqlxError::RowNotFound => (
-                StatusCode::NOT_FOUND, 
-                format!("No transactions found for account ID: {}", req.account_id)
-            ),
-            _ => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to fetch transactions: {}", e)
-            )
+#[utoipa::path(
+    post,
+    path = "/api/v1/transaction/create",
+    tag = "transaction",
+    request_body = CreateTransReq,
+    responses(
+        (status = 200, description = "Posted", body = String),
+        (status = 202, description = "Held for approval or review", body = HeldTransfer),
+    ),
+)]
+#[axum::debug_handler]
+pub async fn create(
+    State(state): State<state::AppState>,
+    VerifiedUser(user_id): VerifiedUser,
+    session: Option<CurrentSession>,
+    ValidatedJson(req): ValidatedJson<CreateTransReq>
+) -> Result<Response, LedgerError> {
+    match submit(&state, user_id, session.map(|session| session.id), req).await? {
+        Submitted::Posted(transaction_id) => {
+            Ok(Json(format!("Transaction created successfully with ID: {}", transaction_id)).into_response())
         }
-     })?;
+        Submitted::Held(held) => Ok((StatusCode::ACCEPTED, Json(held)).into_response()),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/transaction/all",
+    tag = "transaction",
+    responses((status = 200, description = "Every transaction", body = Vec<Transaction>)),
+)]
+pub async fn get_all(State(state): State<state::AppState>) -> Result<Json<Vec<Transaction>>, LedgerError> {
+    let res = state.transfers().all().await?;
+
+    Ok(Json(res))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/transaction/query",
+    tag = "transaction",
+    params(GetTransReq),
+    responses((status = 200, description = "The account's transactions", body = Vec<Transaction>)),
+)]
+pub async fn query(State(state): State<state::AppState>, ValidatedQuery(req): ValidatedQuery<GetTransReq>) -> Result<Json<Vec<Transaction>>, LedgerError> {
+    let res = state.transfers().for_account(req.account_id).await?;
 
     if res.is_empty() {
-        return Err((StatusCode::NOT_FOUND, format!("No transactions found for account ID: {}", req.account_id)));
+        return Err(LedgerError::NotFound(format!("No transactions found for account ID: {}", req.account_id)));
     }
 
     Ok(Json(res))
@@ -0,0 +1,440 @@
+use anyhow::{anyhow, Context};
+use axum::{extract::State, Json, http::StatusCode};
+use bigdecimal::BigDecimal;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+use utoipa::ToSchema;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::config::Config;
+use crate::error::{LedgerError, Problem};
+use crate::ledger::Repository;
+use crate::middleware::auth::{AuthUser, CurrentSession};
+use crate::middleware::client::ClientInfo;
+use crate::middleware::validate::{self, ValidatedJson};
+use crate::state::{self, AppState};
+use crate::store::{Database, Storage};
+use crate::totp;
+
+use super::{login_throttle, session};
+
+/// Audience of login challenge tokens, so they can never pass as access tokens.
+const CHALLENGE_AUDIENCE: &str = "login_2fa";
+/// How long a user has to enter their code after the password step.
+const CHALLENGE_TTL: Duration = Duration::minutes(5);
+/// Wrong codes a login challenge takes before it is used up and the password is needed again.
+const CHALLENGE_MAX_FAILURES: i32 = 3;
+const RECOVERY_CODE_COUNT: usize = 10;
+/// Recovery code alphabet, without characters that are easily confused when copied by hand.
+const RECOVERY_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct EnrollRes {
+    secret: String,
+    otpauth_uri: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct CodeReq {
+    #[validate(length(max = 32), custom(function = "validate::not_blank"))]
+    code: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct ConfirmRes {
+    /// Shown once; only their hashes are kept
+    recovery_codes: Vec<String>,
+}
+
+/// Returned by login instead of tokens when the user has 2FA enabled.
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct LoginChallenge {
+    two_factor_required: bool,
+    challenge_token: String,
+    expires_in: i64,
+}
+
+/// The second login step: the challenge plus either a TOTP code or a recovery code.
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct CompleteLoginReq {
+    #[validate(custom(function = "validate::not_blank"))]
+    challenge_token: String,
+    #[validate(length(max = 32))]
+    code: Option<String>,
+    #[validate(length(max = 32))]
+    recovery_code: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ChallengeClaims {
+    sub: String,
+    exp: usize,
+    aud: String,
+    jti: Uuid,
+}
+
+fn new_recovery_code() -> String {
+    let mut rng = rand::rng();
+    let code: String = (0..10)
+        .map(|_| RECOVERY_ALPHABET[rng.random_range(0..RECOVERY_ALPHABET.len())] as char)
+        .collect();
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+/// Normalizes a typed recovery code so case and the dash do not matter.
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+fn invalid_code() -> LedgerError {
+    Problem::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_two_factor_code", "Invalid two-factor code").into()
+}
+
+/// Checks a TOTP code for `user_id` and records its time step, so each code only
+/// works once.
+async fn verify_code(conn: &mut dyn Storage, user_id: Uuid, secret: &str, code: &str) -> Result<bool, LedgerError> {
+    let secret = totp::decode_secret(secret)
+        .ok_or_else(|| anyhow!("Stored TOTP secret of user {} is invalid", user_id))?;
+
+    let Some(step) = totp::verify(&secret, code, OffsetDateTime::now_utc().unix_timestamp()) else {
+        return Ok(false);
+    };
+
+    let recorded = conn.record_totp_step(user_id, step).await
+        .context("Failed to record TOTP use")?;
+
+    Ok(recorded)
+}
+
+/// The confirmed TOTP secret of `user_id`, if 2FA is enabled.
+async fn enabled_secret(conn: &mut dyn Storage, user_id: Uuid) -> Result<Option<String>, LedgerError> {
+    let totp = conn.totp(user_id).await
+        .context("Database error")?;
+
+    Ok(totp.filter(|totp| totp.enabled).and_then(|totp| totp.secret))
+}
+
+/// Starts enrollment with a new secret. 2FA only takes effect once [`confirm`]ed.
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/2fa/enroll",
+    tag = "two_factor",
+    responses((status = 200, description = "A new secret, to confirm with a code", body = EnrollRes)),
+)]
+pub async fn enroll(
+    State(state): State<state::AppState>,
+    AuthUser(user_id): AuthUser
+) -> Result<Json<EnrollRes>, LedgerError> {
+    let mut conn = state.db.acquire().await
+        .context("Failed to acquire connection")?;
+
+    let user = conn.totp(user_id).await
+        .context("Database error")?
+        .context("User not found")?;
+
+    if user.enabled {
+        return Err(LedgerError::Conflict("Two-factor authentication is already enabled".to_string()));
+    }
+
+    let secret = totp::generate_secret();
+    let encoded = totp::encode_secret(&secret);
+
+    conn.set_totp_secret(user_id, &encoded).await
+        .context("Failed to store TOTP secret")?;
+
+    Ok(Json(EnrollRes {
+        otpauth_uri: totp::otpauth_uri(&state.config.totp_issuer, &user.email, &secret),
+        secret: encoded,
+    }))
+}
+
+/// Turns 2FA on with a first code from the authenticator, and hands out recovery codes.
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/2fa/confirm",
+    tag = "two_factor",
+    request_body = CodeReq,
+    responses((status = 200, description = "Enabled; the recovery codes are shown once", body = ConfirmRes)),
+)]
+pub async fn confirm(
+    State(state): State<state::AppState>,
+    AuthUser(user_id): AuthUser,
+    ValidatedJson(req): ValidatedJson<CodeReq>
+) -> Result<Json<ConfirmRes>, LedgerError> {
+    let mut conn = state.db.acquire().await
+        .context("Failed to acquire connection")?;
+
+    let user = conn.totp(user_id).await
+        .context("Database error")?
+        .context("User not found")?;
+
+    if user.enabled {
+        return Err(LedgerError::Conflict("Two-factor authentication is already enabled".to_string()));
+    }
+
+    let secret = user.secret
+        .ok_or(LedgerError::BadRequest("Start enrollment before confirming".to_string()))?;
+
+    if !verify_code(&mut *conn, user_id, &secret, &req.code).await? {
+        return Err(invalid_code());
+    }
+
+    let recovery_codes: Vec<String> = (0..RECOVERY_CODE_COUNT).map(|_| new_recovery_code()).collect();
+    let hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|code| session::hash_token(&normalize_recovery_code(code)))
+        .collect();
+
+    drop(conn);
+
+    let mut tx = state.db.begin().await
+        .context("Failed to start transaction")?;
+
+    tx.enable_totp(user_id).await
+        .context("Failed to enable two-factor authentication")?;
+
+    tx.set_recovery_codes(user_id, &hashes).await
+        .context("Failed to store recovery codes")?;
+
+    Database::commit(tx).await
+        .context("Failed to commit transaction")?;
+
+    Ok(Json(ConfirmRes { recovery_codes }))
+}
+
+/// Turns 2FA off. Needs a current code, so a stolen session alone cannot do it, and wrong
+/// codes count against the user's login throttle.
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/2fa/disable",
+    tag = "two_factor",
+    request_body = CodeReq,
+    responses((status = 200, description = "Disabled", body = String)),
+)]
+pub async fn disable(
+    State(state): State<state::AppState>,
+    AuthUser(user_id): AuthUser,
+    client: ClientInfo,
+    ValidatedJson(req): ValidatedJson<CodeReq>
+) -> Result<Json<String>, LedgerError> {
+    let secret = {
+        let mut conn = state.db.acquire().await
+            .context("Failed to acquire connection")?;
+
+        enabled_secret(&mut *conn, user_id).await?
+            .ok_or(LedgerError::BadRequest("Two-factor authentication is not enabled".to_string()))?
+    };
+
+    check_signed_in_code(&state, user_id, &secret, &req.code, &client).await?;
+
+    let mut tx = state.db.begin().await
+        .context("Failed to start transaction")?;
+
+    tx.clear_totp(user_id).await
+        .context("Failed to disable two-factor authentication")?;
+
+    tx.set_recovery_codes(user_id, &[]).await
+        .context("Failed to clear recovery codes")?;
+
+    Database::commit(tx).await
+        .context("Failed to commit transaction")?;
+
+    Ok(Json("Two-factor authentication disabled".to_string()))
+}
+
+/// Re-confirms the current session with a fresh code, for operations that need one. Wrong
+/// codes count against the user's login throttle.
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/2fa/stepUp",
+    tag = "two_factor",
+    request_body = CodeReq,
+    responses((status = 200, description = "The session may make large transfers for a while", body = String)),
+)]
+pub async fn step_up(
+    State(state): State<state::AppState>,
+    AuthUser(user_id): AuthUser,
+    session: CurrentSession,
+    client: ClientInfo,
+    ValidatedJson(req): ValidatedJson<CodeReq>
+) -> Result<Json<String>, LedgerError> {
+    let secret = {
+        let mut conn = state.db.acquire().await
+            .context("Failed to acquire connection")?;
+
+        enabled_secret(&mut *conn, user_id).await?
+            .ok_or(LedgerError::BadRequest("Two-factor authentication is not enabled".to_string()))?
+    };
+
+    check_signed_in_code(&state, user_id, &secret, &req.code, &client).await?;
+
+    let mut conn = state.db.acquire().await
+        .context("Failed to acquire connection")?;
+
+    conn.record_step_up(session.id).await
+        .context("Failed to record step-up")?;
+
+    Ok(Json(format!("Step-up valid for {} minutes", state.config.step_up_ttl.whole_minutes())))
+}
+
+/// Checks a code from a signed-in user. A stolen access token must not be enough to guess
+/// codes, so they count against the user's login throttle as wrong passwords do.
+async fn check_signed_in_code(state: &AppState, user_id: Uuid, secret: &str, code: &str, client: &ClientInfo) -> Result<(), LedgerError> {
+    let user = state.users().find(user_id).await?;
+
+    let attempt = login_throttle::attempt(&state.db, &state.config, &user.email, client.ip_address.as_deref()).await?;
+
+    let mut conn = state.db.acquire().await
+        .context("Failed to acquire connection")?;
+
+    if !verify_code(&mut *conn, user_id, secret, code).await? {
+        return Err(invalid_code());
+    }
+
+    login_throttle::withdraw(&state.db, attempt).await
+}
+
+/// Issues the token that carries a user from the password step to the code step, if
+/// they have 2FA enabled.
+pub(crate) async fn challenge(state: &AppState, user_id: Uuid) -> Result<Option<LoginChallenge>, LedgerError> {
+    let mut conn = state.db.acquire().await
+        .context("Failed to acquire connection")?;
+
+    if enabled_secret(&mut *conn, user_id).await?.is_none() {
+        return Ok(None);
+    }
+
+    let expires_at = OffsetDateTime::now_utc() + CHALLENGE_TTL;
+    let challenge_id = Uuid::new_v4();
+
+    conn.insert_login_challenge(challenge_id, user_id, expires_at).await
+        .context("Failed to issue login challenge")?;
+
+    let claims = ChallengeClaims {
+        sub: user_id.to_string(),
+        exp: expires_at.unix_timestamp() as usize,
+        aud: CHALLENGE_AUDIENCE.to_string(),
+        jti: challenge_id,
+    };
+
+    let challenge_token = state.keys.sign(&claims)
+        .context("Failed to issue login challenge")?;
+
+    Ok(Some(LoginChallenge {
+        two_factor_required: true,
+        challenge_token,
+        expires_in: CHALLENGE_TTL.whole_seconds(),
+    }))
+}
+
+/// Checks the second login step and returns the user it authenticates. Wrong codes count
+/// against the user's login throttle as wrong passwords do, and use the challenge up after
+/// a few; a right one uses it up at once.
+pub(crate) async fn redeem_challenge(state: &AppState, req: &CompleteLoginReq, ip_address: Option<&str>) -> Result<Uuid, LedgerError> {
+    let invalid_challenge = || LedgerError::Unauthorized("Invalid or expired login challenge".to_string());
+
+    let claims: ChallengeClaims = state.keys.verify(&req.challenge_token, Some(CHALLENGE_AUDIENCE))
+        .map_err(|_| invalid_challenge())?;
+
+    let user_id = Uuid::parse_str(&claims.sub)
+        .map_err(|_| invalid_challenge())?;
+
+    let mut conn = state.db.acquire().await
+        .context("Failed to acquire connection")?;
+
+    let email = conn.login_challenge_email(claims.jti, user_id).await
+        .context("Database error")?
+        .ok_or_else(invalid_challenge)?;
+
+    drop(conn);
+
+    let attempt = login_throttle::attempt(&state.db, &state.config, &email, ip_address).await?;
+
+    let mut conn = state.db.acquire().await
+        .context("Failed to acquire connection")?;
+
+    let secret = enabled_secret(&mut *conn, user_id).await?
+        .ok_or_else(invalid_challenge)?;
+
+    let verified = match (&req.code, &req.recovery_code) {
+        (Some(code), None) => verify_code(&mut *conn, user_id, &secret, code).await?,
+        (None, Some(recovery_code)) => {
+            conn.use_recovery_code(user_id, &session::hash_token(&normalize_recovery_code(recovery_code))).await
+                .context("Failed to use recovery code")?
+        }
+        _ => {
+            login_throttle::withdraw(&state.db, attempt).await?;
+            return Err(LedgerError::BadRequest("Exactly one of code or recovery_code must be provided".to_string()));
+        }
+    };
+
+    if !verified {
+        let failures = conn.fail_login_challenge(claims.jti).await
+            .context("Failed to record login failure")?;
+
+        if failures.is_some_and(|failures| failures >= CHALLENGE_MAX_FAILURES) {
+            burn_challenge(&mut *conn, claims.jti).await?;
+        }
+
+        return Err(LedgerError::Unauthorized("Invalid two-factor code".to_string()));
+    }
+
+    // Each challenge signs in once
+    if !burn_challenge(&mut *conn, claims.jti).await? {
+        return Err(invalid_challenge());
+    }
+
+    drop(conn);
+
+    login_throttle::record_success(&state.db, attempt).await?;
+
+    Ok(user_id)
+}
+
+/// Deletes a login challenge, returning whether it was still there.
+async fn burn_challenge(conn: &mut dyn Storage, challenge_id: Uuid) -> Result<bool, LedgerError> {
+    let burned = conn.delete_login_challenge(challenge_id).await
+        .context("Failed to use up login challenge")?;
+
+    Ok(burned)
+}
+
+/// Requires users with 2FA to have stepped up recently before sending more than the
+/// step-up threshold. Without a session, as for service accounts, there is no step-up,
+/// so such transfers are refused.
+pub(crate) async fn check_step_up(
+    conn: &mut dyn Storage,
+    config: &Config,
+    user_id: Uuid,
+    session_id: Option<Uuid>,
+    amount: &BigDecimal
+) -> Result<(), LedgerError> {
+    if *amount <= config.step_up_threshold {
+        return Ok(());
+    }
+
+    let status = conn.step_up(user_id, session_id).await
+        .context("Failed to check step-up")?;
+
+    let fresh = status.step_up_at
+        .is_some_and(|at| OffsetDateTime::now_utc() - at <= config.step_up_ttl);
+
+    if status.totp_enabled && !fresh {
+        return Err(Problem::new(
+            StatusCode::FORBIDDEN,
+            "step_up_required",
+            format!(
+                "Transfers above {} need a two-factor code entered in the last {} minutes",
+                config.step_up_threshold,
+                config.step_up_ttl.whole_minutes()
+            ),
+        ).into());
+    }
+
+    Ok(())
+}
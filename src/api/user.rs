@@ -1,305 +1,295 @@
-use axum::{extract::State, Json, http::StatusCode};
+use anyhow::Context;
+use axum::{extract::State, Json};
 use serde::{Deserialize, Serialize};
-use time::OffsetDateTime;
+use utoipa::ToSchema;
 use uuid::Uuid;
+use validator::{Validate, ValidationError};
+use crate::error::LedgerError;
+use crate::ledger::Repository;
+use crate::ledger::accounts::Types;
+use crate::ledger::users::{Profile, ProfileUpdate, Registration};
+use crate::middleware::auth::{AuthUser, CurrentSession};
+use crate::middleware::client::ClientInfo;
+use crate::middleware::validate::{self, ValidatedJson};
 use crate::state;
-use jsonwebtoken::{encode, Header, EncodingKey};
-use bcrypt::{hash, verify, DEFAULT_COST};
+use crate::store::Database;
 
-use super::account::Types;
+use super::{login_throttle, session, two_factor, verification};
 
-#[derive(Clone, Serialize, Deserialize)]
-struct User {
-    id: Uuid,
-    full_name: String,
-    email: String,
-    created_at: Option<OffsetDateTime>
-}
-
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
 pub struct UserReq {
+    #[validate(length(max = 200), custom(function = "validate::not_blank"))]
     full_name: String,
+    #[validate(email)]
     email: String,
+    #[validate(length(max = 256))]
     password: String,
     account_type: Types,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
-pub struct UpdateProfileReq {
+/// Changes to the signed-in user. Changing the email or password needs the current password.
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
+#[validate(schema(function = "some_change", skip_on_field_errors = false))]
+pub struct UpdateMeReq {
+    #[validate(length(max = 200), custom(function = "validate::not_blank"))]
     full_name: Option<String>,
+    /// Takes effect once the link sent to the new address is followed
+    #[validate(email)]
     email: Option<String>,
+    #[validate(length(max = 256))]
     password: Option<String>,
-    account_type: Option<Types>
+    current_password: Option<String>,
+}
+
+fn some_change(req: &UpdateMeReq) -> Result<(), ValidationError> {
+    if req.full_name.is_none() && req.email.is_none() && req.password.is_none() {
+        return Err(ValidationError::new("no_change")
+            .with_message("At least one of full_name, email or password must be provided".into()));
+    }
+    Ok(())
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct CreateUserRes {
     account_id: Uuid,
+    account_number: String,
+    iban: Option<String>,
     full_name: String,
     email: String,
     account_type: Types,
-    token: String
+    token: String,
+    refresh_token: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Validate, ToSchema)]
 pub struct LoginReq {
+    #[validate(email)]
     email: String,
+    #[validate(length(min = 1, max = 256))]
     password: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct LoginRes {
+    /// Short-lived access token
     token: String,
+    refresh_token: String,
+    expires_in: i64,
     account_id: String,
     full_name: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-    sub: String,  // user id
-    exp: usize,   // expiration time
+/// A login either signs the user in or, with 2FA enabled, asks for a code.
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum LoginOutcome {
+    SignedIn(LoginRes),
+    TwoFactorRequired(two_factor::LoginChallenge),
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/register",
+    tag = "user",
+    request_body = UserReq,
+    responses((status = 200, description = "Registered, and signed in to a new session", body = CreateUserRes)),
+    security(()),
+)]
 #[axum::debug_handler]
 pub async fn register(
-    State(state): State<state::AppState>, 
-    Json(req): Json<UserReq>
-) -> Result<Json<CreateUserRes>, (StatusCode, String)> {
-    let pool = state.db;
-
-    // Check if the email already exists
-    let existing_user = sqlx::query_scalar!(
-        "SELECT EXISTS(SELECT 1 FROM users WHERE email = $1)",
-        req.email
-    )
-    .fetch_one(&pool)
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e)))?;
-
-    if existing_user.unwrap_or(false) {
-        return Err((StatusCode::CONFLICT, format!("User with email {} already exists", req.email)));
-    }
+    State(state): State<state::AppState>,
+    client: ClientInfo,
+    ValidatedJson(req): ValidatedJson<UserReq>
+) -> Result<Json<CreateUserRes>, LedgerError> {
+    let registered = state.users().register(Registration {
+        full_name: req.full_name.clone(),
+        email: req.email.clone(),
+        password: req.password,
+        account_type: req.account_type.clone(),
+    }).await?;
+
+    verification::send_verification(&state, registered.user_id, &req.email).await?;
+
+    let tokens = session::start(&state, registered.user_id, &client).await?;
 
-    let password_hash = hash(req.password.as_bytes(), DEFAULT_COST)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to hash password: {}", e)))?;
-
-    let user_id = sqlx::query_scalar!(
-        r#"INSERT INTO users (full_name, email, password_hash) VALUES ($1, $2, $3) returning id"#,
-        req.full_name,
-        req.email,
-        password_hash
-    ).fetch_one(&pool).await
-     .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create user: {}", e)))?;
-    
-    println!("user_id = {user_id}");
-    
-    let account_id = sqlx::query_scalar!(
-        "INSERT INTO accounts (user_id, account_type) VALUES ($1, $2) returning id",
-        user_id,
-        req.account_type.to_string()
-    ).fetch_one(&pool).await
-     .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create account: {}", e)))?;
-    
-    println!("account_id = {account_id}");
-    
-    let balance = sqlx::query_scalar!(
-        "INSERT INTO account_balances (account_id) VALUES ($1) returning balance",
-        account_id
-    ).fetch_one(&pool).await
-     .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create account balance: {}", e)))?;
-    
-    println!("balance = {balance}");
-
-    let token = generate_jwt_token(user_id)
-        .map_err(|e| (e, "Failed to generate authentication token".to_string()))?;
-    
     let res = CreateUserRes {
-        account_id,
+        account_id: registered.account.account_id,
+        account_number: registered.account.account_number,
+        iban: Some(registered.account.iban),
         full_name: req.full_name,
         email: req.email,
         account_type: req.account_type,
-        token
+        token: tokens.access_token,
+        refresh_token: tokens.refresh_token,
     };
 
     Ok(Json(res))
 }
 
-fn generate_jwt_token(user_id: Uuid) -> Result<String, StatusCode> {
-    let expiration = time::OffsetDateTime::now_utc() + time::Duration::days(7);
-    let claims = Claims {
-        sub: user_id.to_string(),
-        exp: expiration.unix_timestamp() as usize,
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/login",
+    tag = "user",
+    request_body = LoginReq,
+    responses((status = 200, description = "Signed in, or asked for a two-factor code", body = LoginOutcome)),
+    security(()),
+)]
+#[axum::debug_handler]
+pub async fn login(
+    State(state): State<state::AppState>,
+    client: ClientInfo,
+    ValidatedJson(req): ValidatedJson<LoginReq>
+) -> Result<Json<LoginOutcome>, LedgerError> {
+    let attempt = login_throttle::attempt(&state.db, &state.config, &req.email, client.ip_address.as_deref()).await?;
+
+    let Some(user) = state.users().authenticate(&req.email, &req.password).await? else {
+        return Err(LedgerError::Unauthorized("Invalid email or password".to_string()));
     };
 
-    let secret_key = std::env::var("JWT_SECRET_KEY")
-        .map_err(|_| {
-            eprintln!("JWT_SECRET_KEY environment variable not set");
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
-
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret_key.as_bytes())
-    ).map_err(|e| {
-        eprintln!("Failed to encode JWT token: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    Ok(token)
-}
+    // Staff access is granted and withdrawn at the identity provider, so they sign in there
+    if user.staff {
+        login_throttle::withdraw(&state.db, attempt).await?;
+        return Err(LedgerError::Forbidden("Sign in through the company identity provider".to_string()));
+    }
 
+    // With 2FA, failures are only cleared once the code has been entered too
+    if let Some(challenge) = two_factor::challenge(&state, user.user_id).await? {
+        login_throttle::withdraw(&state.db, attempt).await?;
+        return Ok(Json(LoginOutcome::TwoFactorRequired(challenge)));
+    }
 
-#[axum::debug_handler]
-pub async fn login(
+    login_throttle::record_success(&state.db, attempt).await?;
+
+    let res = sign_in(&state, user.user_id, &client).await?;
+
+    Ok(Json(LoginOutcome::SignedIn(res)))
+}
+
+/// Completes a login for a user with 2FA enabled, using the challenge from [`login`].
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/login/2fa",
+    tag = "user",
+    request_body = two_factor::CompleteLoginReq,
+    responses((status = 200, description = "Signed in", body = LoginRes)),
+    security(()),
+)]
+pub async fn login_2fa(
     State(state): State<state::AppState>,
-    Json(req): Json<LoginReq>
-) -> Result<Json<LoginRes>, (StatusCode, String)> {
-    let pool = state.db;
-
-    // Get user from database
-    let user = sqlx::query!(
-        r#"SELECT id, full_name, email, created_at, password_hash FROM users WHERE email = $1"#,
-        req.email
-    )
-    .fetch_optional(&pool)
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e)))?
-    .ok_or((StatusCode::UNAUTHORIZED, "Invalid email or password".to_string()))?;
-
-    // Verify password
-    if !verify(&req.password, &user.password_hash)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to verify password: {}", e)))? {
-        return Err((StatusCode::UNAUTHORIZED, "Invalid email or password".to_string()));
-    }
+    client: ClientInfo,
+    ValidatedJson(req): ValidatedJson<two_factor::CompleteLoginReq>
+) -> Result<Json<LoginRes>, LedgerError> {
+    let user_id = two_factor::redeem_challenge(&state, &req, client.ip_address.as_deref()).await?;
 
-    // Create JWT token
-    let token = generate_jwt_token(user.id)
-        .map_err(|e| (e, "Failed to generate authentication token".to_string()))?;
+    let res = sign_in(&state, user_id, &client).await?;
 
-    let user = User {
-        id: user.id,
-        full_name: user.full_name,
-        email: user.email,
-        created_at: user.created_at,
-    };
+    Ok(Json(res))
+}
+
+/// Starts a session for a user who has passed every login step.
+async fn sign_in(state: &state::AppState, user_id: Uuid, client: &ClientInfo) -> Result<LoginRes, LedgerError> {
+    let tokens = session::start(state, user_id, client).await?;
 
-    let account_id = sqlx::query_scalar!(
-        "SELECT id FROM accounts WHERE user_id = $1",
-        user.id
-    ).fetch_one(&pool).await
-     .map_err(|e| match e {
-        sqlx::Error::RowNotFound => (StatusCode::NOT_FOUND, format!("No account found for user {}", user.id)),
-        _ => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to fetch account: {}", e))
-     })?;
-
-    Ok(Json(LoginRes { 
-        token, 
-        account_id: account_id.to_string(), 
-        full_name: user.full_name 
-    }))
+    let account = state.accounts().primary(user_id).await?;
+
+    Ok(LoginRes {
+        token: tokens.access_token,
+        refresh_token: tokens.refresh_token,
+        expires_in: tokens.expires_in,
+        account_id: account.id.to_string(),
+        full_name: account.owner_name
+    })
 }
 
-pub async fn update_profile(
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/me",
+    tag = "user",
+    responses((status = 200, description = "The signed-in user", body = Profile)),
+)]
+pub async fn get_me(
     State(state): State<state::AppState>,
-    Json(req): Json<UpdateProfileReq>
-) -> Result<Json<CreateUserRes>, (StatusCode, String)> {
-    let pool = state.db;
+    AuthUser(user_id): AuthUser
+) -> Result<Json<Profile>, LedgerError> {
+    let res = state.users().profile(user_id).await?;
+
+    Ok(Json(res))
+}
+
+/// Updates a user, who is signed in to `session_id`. A new email is only sent a
+/// verification link here; it replaces the current one when the link is followed. A new
+/// password signs out every other session.
+pub(crate) async fn update_profile(
+    state: &state::AppState,
+    user_id: Uuid,
+    session_id: Uuid,
+    client: &ClientInfo,
+    req: UpdateMeReq
+) -> Result<Profile, LedgerError> {
+    let users = state.users();
+
+    let user = users.find(user_id).await?;
+
+    let new_email = req.email.filter(|email| *email != user.email);
+
+    // Someone holding a stolen token must not be able to take the account over
+    if new_email.is_some() || req.password.is_some() {
+        let Some(current_password) = &req.current_password else {
+            return Err(LedgerError::BadRequest("current_password is required to change the email or password".to_string()));
+        };
+
+        let attempt = login_throttle::attempt(&state.db, &state.config, &user.email, client.ip_address.as_deref()).await?;
+
+        if !users.verify_password(&user, current_password).await? {
+            return Err(LedgerError::Forbidden("Current password is incorrect".to_string()));
+        }
+
+        login_throttle::withdraw(&state.db, attempt).await?;
+    }
+
+    let changes = users.prepare_update(&user, ProfileUpdate {
+        full_name: req.full_name,
+        new_email: new_email.clone(),
+        password: req.password,
+    }).await?;
+
+    let mut tx = state.db.begin().await
+        .context("Failed to start transaction")?;
 
-    // At least one field must be provided for update
-    if req.full_name.is_none() && req.email.is_none() && req.password.is_none() && req.account_type.is_none() {
-        return Err((StatusCode::BAD_REQUEST, "At least one field must be provided for update".to_string()));
+    users.apply_update(&mut *tx, user_id, &changes).await?;
+
+    // A new password signs out every other device, which may be using the old one
+    if changes.password_hash.is_some() {
+        session::revoke_others(&mut *tx, user_id, session_id, "password_changed").await
+            .context("Failed to revoke sessions")?;
     }
 
-    // Check if email exists
-    if req.email.is_none() {
-        return Err((StatusCode::BAD_REQUEST, "Email is required to identify the user".to_string()));
+    Database::commit(tx).await
+        .context("Failed to commit transaction")?;
+
+    if let Some(email) = &new_email {
+        verification::send_verification(state, user_id, email).await?;
     }
 
-    // Get current user data
-    let current_user = sqlx::query!(
-        r#"SELECT id, full_name, email, password_hash FROM users WHERE email = $1"#,
-        req.email
-    )
-    .fetch_one(&pool)
-    .await
-    .map_err(|e| match e {
-        sqlx::Error::RowNotFound => (StatusCode::NOT_FOUND, format!("User with email {} not found", req.email.as_ref().unwrap())),
-        _ => (StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e))
-    })?;
-
-    // Update only provided fields
-    let new_name = req.full_name.unwrap_or(current_user.full_name);
-    let new_email = req.email.unwrap_or(current_user.email);
-    let new_password_hash = if let Some(new_password) = req.password {
-        hash(&new_password, DEFAULT_COST)
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to hash password: {}", e)))?
-    } else {
-        current_user.password_hash
-    };
+    users.profile(user_id).await
+}
 
-    let updated_user = sqlx::query_as!(
-        User,
-        r#"
-        UPDATE users 
-        SET full_name = $1,
-            email = $2,
-            password_hash = $3
-        WHERE id = $4
-        RETURNING id, full_name, email, created_at
-        "#,
-        new_name,
-        new_email,
-        new_password_hash,
-        current_user.id
-    )
-    .fetch_one(&pool)
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to update user: {}", e)))?;
-
-    let token = generate_jwt_token(current_user.id)
-        .map_err(|e| (e, "Failed to generate authentication token".to_string()))?;
-
-    // Get account type
-    let account_type = if let Some(new_type) = req.account_type {
-        // Update account type if provided
-        sqlx::query!(
-            "UPDATE accounts SET account_type = $1 WHERE user_id = $2",
-            new_type.to_string(),
-            current_user.id
-        )
-        .execute(&pool)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to update account type: {}", e)))?;
-        
-        new_type
-    } else {
-        // Get current account type
-        let account_type_str = sqlx::query_scalar!(
-            "SELECT account_type FROM accounts WHERE user_id = $1",
-            current_user.id
-        )
-        .fetch_one(&pool)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to fetch account type: {}", e)))?;
-        
-        match account_type_str.as_str() {
-            "savings" => Types::Savings,
-            "current" => Types::Current,
-            "salary" => Types::Salary,
-            "fd" => Types::FD,
-            "rd" => Types::RD,
-            _ => Types::Savings // Default to Savings if unknown
-        }
-    };
+/// Updates the signed-in user, see [`update_profile`].
+#[utoipa::path(
+    patch,
+    path = "/api/v1/user/me",
+    tag = "user",
+    request_body = UpdateMeReq,
+    responses((status = 200, description = "The updated user", body = Profile)),
+)]
+pub async fn update_me(
+    State(state): State<state::AppState>,
+    AuthUser(user_id): AuthUser,
+    session: CurrentSession,
+    client: ClientInfo,
+    ValidatedJson(req): ValidatedJson<UpdateMeReq>
+) -> Result<Json<Profile>, LedgerError> {
+    let res = update_profile(&state, user_id, session.id, &client, req).await?;
 
-    let res = CreateUserRes {
-        account_id: current_user.id,
-        full_name: updated_user.full_name,
-        email: updated_user.email,
-        account_type,
-        token
-    };
-    
     Ok(Json(res))
-}
\ No newline at end of file
+}
@@ -0,0 +1,150 @@
+//! Version 2 of the API: the same ledger as version 1, with resource-oriented paths and
+//! status codes. Each handler shares what it does with its version 1 counterpart, so the
+//! two versions can be served side by side while clients migrate.
+
+use anyhow::Context;
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use uuid::Uuid;
+
+use crate::error::LedgerError;
+use crate::middleware::auth::{AuthUser, CurrentSession, VerifiedUser};
+use crate::middleware::client::ClientInfo;
+use crate::middleware::validate::{PathParams, ValidatedJson};
+use crate::state;
+
+use crate::ledger::accounts::Account;
+use crate::ledger::transfers::Transaction;
+use crate::ledger::users::Profile;
+
+use super::transaction::{self, CreateTransReq, HeldTransfer, Submitted};
+use super::user::{self, UpdateMeReq};
+
+/// Where a posted transfer can be found.
+fn transfer_location(id: Uuid) -> String {
+    format!("/api/v2/transfers/{}", id)
+}
+
+/// Requests a transfer, which is posted at once unless it needs approval or review first.
+#[utoipa::path(
+    post,
+    path = "/api/v2/transfers",
+    tag = "transaction",
+    request_body = CreateTransReq,
+    responses(
+        (status = 201, description = "Posted", body = Transaction, headers(("location" = String, description = "The posted transfer"))),
+        (status = 202, description = "Held for approval or review", body = HeldTransfer),
+    ),
+)]
+pub async fn create_transfer(
+    State(state): State<state::AppState>,
+    VerifiedUser(user_id): VerifiedUser,
+    session: Option<CurrentSession>,
+    ValidatedJson(req): ValidatedJson<CreateTransReq>
+) -> Result<Response, LedgerError> {
+    match transaction::submit(&state, user_id, session.map(|session| session.id), req).await? {
+        Submitted::Posted(transaction_id) => {
+            let posted = state.transfers().find(transaction_id).await?
+                .context("Posted transaction disappeared")?;
+
+            Ok((
+                StatusCode::CREATED,
+                [(header::LOCATION, transfer_location(transaction_id))],
+                Json(posted),
+            ).into_response())
+        }
+        Submitted::Held(held) => Ok((StatusCode::ACCEPTED, Json(held)).into_response()),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v2/transfers/{id}",
+    tag = "transaction",
+    params(("id" = Uuid, Path, description = "The transfer's id")),
+    responses((status = 200, description = "The transfer, to or from one of the user's accounts", body = Transaction)),
+)]
+pub async fn get_transfer(
+    State(state): State<state::AppState>,
+    AuthUser(user_id): AuthUser,
+    PathParams(id): PathParams<Uuid>
+) -> Result<Json<Transaction>, LedgerError> {
+    let not_found = || LedgerError::NotFound(format!("Transfer {} not found", id));
+
+    let transfer = state.transfers().find(id).await?
+        .ok_or_else(not_found)?;
+
+    // Only the payer and the payee see a transfer
+    let accounts = state.accounts();
+    if !accounts.is_owner(transfer.from_account_id, user_id).await?
+        && !accounts.is_owner(transfer.to_account_id, user_id).await? {
+        return Err(not_found());
+    }
+
+    Ok(Json(transfer))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v2/accounts/{id}",
+    tag = "account",
+    params(("id" = Uuid, Path, description = "The account's id")),
+    responses((status = 200, description = "One of the user's accounts, and its balance", body = Account)),
+)]
+pub async fn get_account(
+    State(state): State<state::AppState>,
+    AuthUser(user_id): AuthUser,
+    PathParams(id): PathParams<Uuid>
+) -> Result<Json<Account>, LedgerError> {
+    let accounts = state.accounts();
+    accounts.owned(id, user_id).await?;
+
+    let account = accounts.find(id).await?;
+
+    Ok(Json(account))
+}
+
+/// Lists the transactions of one of the user's accounts, which is an empty list for an
+/// account without any.
+#[utoipa::path(
+    get,
+    path = "/api/v2/accounts/{id}/transactions",
+    tag = "transaction",
+    params(("id" = Uuid, Path, description = "The account's id")),
+    responses((status = 200, description = "Transactions into and out of the account", body = Vec<Transaction>)),
+)]
+pub async fn list_account_transactions(
+    State(state): State<state::AppState>,
+    AuthUser(user_id): AuthUser,
+    PathParams(id): PathParams<Uuid>
+) -> Result<Json<Vec<Transaction>>, LedgerError> {
+    state.accounts().owned(id, user_id).await?;
+
+    let transactions = state.transfers().for_account(id).await?;
+
+    Ok(Json(transactions))
+}
+
+/// Updates the signed-in user, see [`user::update_profile`].
+#[utoipa::path(
+    patch,
+    path = "/api/v2/users/me",
+    tag = "user",
+    request_body = UpdateMeReq,
+    responses((status = 200, description = "The updated user", body = Profile)),
+)]
+pub async fn update_me(
+    State(state): State<state::AppState>,
+    AuthUser(user_id): AuthUser,
+    session: CurrentSession,
+    client: ClientInfo,
+    ValidatedJson(req): ValidatedJson<UpdateMeReq>
+) -> Result<Json<Profile>, LedgerError> {
+    let res = user::update_profile(&state, user_id, session.id, &client, req).await?;
+
+    Ok(Json(res))
+}
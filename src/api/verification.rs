@@ -0,0 +1,286 @@
+use std::fmt;
+
+use anyhow::Context;
+use axum::{extract::State, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::error::ErrorKind;
+use time::{Duration, OffsetDateTime};
+use utoipa::ToSchema;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::error::LedgerError;
+use crate::ledger::users::check_password;
+use crate::ledger::Repository;
+use crate::mailer::Email;
+use crate::middleware::auth::AuthUser;
+use crate::middleware::validate::{self, ValidatedJson};
+use crate::state::{self, AppState};
+use crate::store::email_tokens::NewEmailToken;
+use crate::store::{self, Database};
+
+use super::session;
+
+/// What an emailed token lets its holder do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Purpose {
+    VerifyEmail,
+    ResetPassword,
+}
+
+impl Purpose {
+    fn ttl(self) -> Duration {
+        match self {
+            Purpose::VerifyEmail => Duration::hours(24),
+            Purpose::ResetPassword => Duration::hours(1),
+        }
+    }
+}
+
+impl fmt::Display for Purpose {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Purpose::VerifyEmail => write!(f, "verify_email"),
+            Purpose::ResetPassword => write!(f, "reset_password"),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct ForgotPasswordReq {
+    #[validate(email)]
+    email: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct ResetPasswordReq {
+    #[validate(custom(function = "validate::not_blank"))]
+    token: String,
+    #[validate(length(max = 256))]
+    new_password: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct VerifyEmailReq {
+    #[validate(custom(function = "validate::not_blank"))]
+    token: String,
+}
+
+/// Creates a token for `email`, replacing any earlier unused token with the same purpose.
+async fn issue(db: &Database, user_id: Uuid, purpose: Purpose, email: &str) -> Result<String, LedgerError> {
+    let token = session::random_token();
+
+    let mut tx = db.begin().await
+        .context("Failed to start transaction")?;
+
+    tx.insert_email_token(&NewEmailToken {
+        user_id,
+        purpose: &purpose.to_string(),
+        email,
+        token_hash: &session::hash_token(&token),
+        expires_at: OffsetDateTime::now_utc() + purpose.ttl(),
+    }).await
+        .context("Failed to store token")?;
+
+    Database::commit(tx).await
+        .context("Failed to commit transaction")?;
+
+    Ok(token)
+}
+
+/// Uses up a token, returning the user and address it was issued for.
+async fn redeem(db: &Database, purpose: Purpose, token: &str) -> Result<(Uuid, String), LedgerError> {
+    let mut conn = db.acquire().await
+        .context("Database error")?;
+
+    let redeemed = conn.redeem_email_token(&session::hash_token(token), &purpose.to_string()).await
+        .context("Failed to use token")?
+    .ok_or(LedgerError::BadRequest("Invalid or expired token".to_string()))?;
+
+    Ok((redeemed.user_id, redeemed.email))
+}
+
+/// Sends an email, logging rather than failing the request if delivery fails. The
+/// user can always ask for another.
+async fn deliver(state: &AppState, email: Email) {
+    let to = email.to.clone();
+    if let Err(e) = state.mailer.send(email).await {
+        eprintln!("Failed to send email to {}: {}", to, e);
+    }
+}
+
+/// Emails a verification link for `email`, which is either the user's address or the
+/// one they asked to change to.
+pub(crate) async fn send_verification(state: &AppState, user_id: Uuid, email: &str) -> Result<(), LedgerError> {
+    let token = issue(&state.db, user_id, Purpose::VerifyEmail, email).await?;
+
+    deliver(state, Email {
+        to: email.to_string(),
+        subject: "Verify your email address".to_string(),
+        body: format!(
+            "Confirm this is your email address by opening the link below within 24 hours:\n\n{}/verify-email?token={}",
+            state.config.app_base_url,
+            token
+        ),
+    }).await;
+
+    Ok(())
+}
+
+/// Emails a password reset link. Always answers the same way, so it cannot be used to
+/// find out which emails are registered.
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/forgotPassword",
+    tag = "user",
+    request_body = ForgotPasswordReq,
+    responses((status = 200, description = "A reset link is on its way if the email is known", body = String)),
+    security(()),
+)]
+pub async fn forgot_password(
+    State(state): State<state::AppState>,
+    ValidatedJson(req): ValidatedJson<ForgotPasswordReq>
+) -> Result<Json<String>, LedgerError> {
+    let mut conn = state.db.acquire().await
+        .context("Database error")?;
+
+    let user = conn.find_user_by_email(&req.email).await
+        .context("Database error")?;
+
+    drop(conn);
+
+    // Sent in the background, so that known emails take no longer to answer than unknown ones
+    if let Some(user) = user {
+        tokio::spawn(async move {
+            if let Err(e) = send_reset(&state, user.id, &user.email).await {
+                eprintln!("Failed to send password reset to {}: {}", user.email, e);
+            }
+        });
+    }
+
+    Ok(Json("If that email is registered, a password reset link has been sent".to_string()))
+}
+
+async fn send_reset(state: &AppState, user_id: Uuid, email: &str) -> Result<(), LedgerError> {
+    let token = issue(&state.db, user_id, Purpose::ResetPassword, email).await?;
+
+    deliver(state, Email {
+        to: email.to_string(),
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "Choose a new password by opening the link below within an hour:\n\n{}/reset-password?token={}\n\nIf you did not ask for this, you can ignore this email.",
+            state.config.app_base_url,
+            token
+        ),
+    }).await;
+
+    Ok(())
+}
+
+/// Sets a new password from a reset link and signs out every session.
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/resetPassword",
+    tag = "user",
+    request_body = ResetPasswordReq,
+    responses((status = 200, description = "Password changed, and every session signed out", body = String)),
+    security(()),
+)]
+pub async fn reset_password(
+    State(state): State<state::AppState>,
+    ValidatedJson(req): ValidatedJson<ResetPasswordReq>
+) -> Result<Json<String>, LedgerError> {
+    let invalid_token = || LedgerError::BadRequest("Invalid or expired token".to_string());
+
+    // Check the password before using the token up, so a rejected one can be retried
+    let user = {
+        let mut conn = state.db.acquire().await
+            .context("Database error")?;
+
+        let user_id = conn.email_token_user(&session::hash_token(&req.token), &Purpose::ResetPassword.to_string()).await
+            .context("Database error")?
+            .ok_or_else(invalid_token)?;
+
+        conn.find_user(user_id).await
+            .context("Database error")?
+            .ok_or_else(invalid_token)?
+    };
+
+    check_password(&state.config, &req.new_password, &[&user.full_name, &user.email]).await?;
+
+    let (user_id, email) = redeem(&state.db, Purpose::ResetPassword, &req.token).await?;
+
+    let password_hash = state.config.password_hasher().hash_blocking(&req.new_password).await
+        .context("Failed to hash password")?;
+
+    let mut tx = state.db.begin().await
+        .context("Failed to start transaction")?;
+
+    // Following the link also proves the address works
+    tx.reset_password(user_id, &password_hash, &email).await
+        .context("Failed to update password")?;
+
+    tx.revoke_sessions(user_id, None, "password_reset").await
+        .context("Failed to revoke sessions")?;
+
+    Database::commit(tx).await
+        .context("Failed to commit transaction")?;
+
+    Ok(Json("Password has been reset".to_string()))
+}
+
+/// Confirms an address from a verification link. For an email change, this is when
+/// the new address replaces the old one.
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/verifyEmail",
+    tag = "user",
+    request_body = VerifyEmailReq,
+    responses((status = 200, description = "Email verified", body = String)),
+    security(()),
+)]
+pub async fn verify_email(
+    State(state): State<state::AppState>,
+    ValidatedJson(req): ValidatedJson<VerifyEmailReq>
+) -> Result<Json<String>, LedgerError> {
+    let (user_id, email) = redeem(&state.db, Purpose::VerifyEmail, &req.token).await?;
+
+    let mut conn = state.db.acquire().await
+        .context("Database error")?;
+
+    let verified = conn.verify_email(user_id, &email).await
+        .map_err(|e| match e {
+            e if store::violates(&e, ErrorKind::UniqueViolation) => LedgerError::Conflict(format!("{} is already in use by another account", email)),
+            e => e.context("Failed to verify email").into(),
+        })?;
+
+    if !verified {
+        return Err(LedgerError::BadRequest("This address is no longer waiting to be verified".to_string()));
+    }
+
+    Ok(Json(format!("{} verified", email)))
+}
+
+/// Sends a fresh verification link for the pending new address, or the current one.
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/resendVerification",
+    tag = "user",
+    responses((status = 200, description = "A new link is on its way", body = String)),
+)]
+pub async fn resend_verification(
+    State(state): State<state::AppState>,
+    AuthUser(user_id): AuthUser
+) -> Result<Json<String>, LedgerError> {
+    let user = state.users().find(user_id).await?;
+
+    let email = match (user.pending_email, user.email_verified) {
+        (Some(pending), _) => pending,
+        (None, false) => user.email,
+        (None, true) => return Err(LedgerError::Conflict("Email address is already verified".to_string())),
+    };
+
+    send_verification(&state, user_id, &email).await?;
+
+    Ok(Json(format!("Verification email sent to {}", email)))
+}
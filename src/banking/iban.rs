@@ -0,0 +1,260 @@
+//! International Bank Account Numbers (ISO 13616).
+//!
+//! An IBAN is a two letter country code, two check digits and a country-specific
+//! Basic Bank Account Number (BBAN). The check digits make the whole IBAN, rearranged
+//! with the country code and check digits moved to the end and letters replaced by
+//! numbers (A = 10 ... Z = 35), leave a remainder of 1 when divided by 97.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Total IBAN length for each country in the SWIFT IBAN registry.
+const COUNTRY_LENGTHS: &[(&str, usize)] = &[
+    ("AD", 24), ("AE", 23), ("AL", 28), ("AT", 20), ("AZ", 28), ("BA", 20), ("BE", 16),
+    ("BG", 22), ("BH", 22), ("BI", 27), ("BR", 29), ("BY", 28), ("CH", 21), ("CR", 22),
+    ("CY", 28), ("CZ", 24), ("DE", 22), ("DJ", 27), ("DK", 18), ("DO", 28), ("EE", 20),
+    ("EG", 29), ("ES", 24), ("FI", 18), ("FK", 18), ("FO", 18), ("FR", 27), ("GB", 22),
+    ("GE", 22), ("GI", 23), ("GL", 18), ("GR", 27), ("GT", 28), ("HR", 21), ("HU", 28),
+    ("IE", 22), ("IL", 23), ("IQ", 23), ("IS", 26), ("IT", 27), ("JO", 30), ("KW", 30),
+    ("KZ", 20), ("LB", 28), ("LC", 32), ("LI", 21), ("LT", 20), ("LU", 20), ("LV", 21),
+    ("LY", 25), ("MC", 27), ("MD", 24), ("ME", 22), ("MK", 19), ("MN", 20), ("MR", 27),
+    ("MT", 31), ("MU", 30), ("NI", 28), ("NL", 18), ("NO", 15), ("OM", 23), ("PK", 24),
+    ("PL", 28), ("PS", 29), ("PT", 25), ("QA", 29), ("RO", 24), ("RS", 22), ("RU", 33),
+    ("SA", 24), ("SC", 31), ("SD", 18), ("SE", 24), ("SI", 19), ("SK", 24), ("SM", 27),
+    ("SO", 23), ("ST", 25), ("SV", 28), ("TL", 23), ("TN", 24), ("TR", 26), ("UA", 29),
+    ("VA", 22), ("VG", 24), ("XK", 20), ("YE", 30),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IbanError {
+    /// Contains something other than letters, digits and spaces
+    InvalidCharacters,
+    UnknownCountry(String),
+    InvalidLength { expected: usize, actual: usize },
+    /// Well formed, but the mod-97 check fails, most likely a typo
+    ChecksumMismatch,
+    /// The bank code and account digits do not fit the country's BBAN
+    BbanTooLong { max: usize },
+}
+
+impl fmt::Display for IbanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IbanError::InvalidCharacters => write!(f, "IBAN may only contain letters and digits"),
+            IbanError::UnknownCountry(code) => write!(f, "Unknown IBAN country code {}", code),
+            IbanError::InvalidLength { expected, actual } => {
+                write!(f, "IBAN should have {} characters but has {}", expected, actual)
+            }
+            IbanError::ChecksumMismatch => write!(f, "IBAN check digits do not match"),
+            IbanError::BbanTooLong { max } => write!(f, "Bank code and account number exceed the {} character BBAN", max),
+        }
+    }
+}
+
+impl std::error::Error for IbanError {}
+
+/// A validated IBAN, stored in its electronic form (no spaces, upper case).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Iban(String);
+
+/// The expected IBAN length for a country, if it uses IBANs.
+pub fn country_length(country: &str) -> Option<usize> {
+    COUNTRY_LENGTHS.iter().find(|(code, _)| *code == country).map(|(_, len)| *len)
+}
+
+/// Remainder of the number formed by `input`, with letters expanded to two digits,
+/// divided by 97. Computed piecewise so it works for arbitrarily long inputs.
+fn mod97(input: &str) -> u32 {
+    input.chars().fold(0, |acc, c| {
+        let value = c.to_digit(36).unwrap_or(0);
+        if value >= 10 {
+            (acc * 100 + value) % 97
+        } else {
+            (acc * 10 + value) % 97
+        }
+    })
+}
+
+fn check_digits(country: &str, bban: &str) -> u32 {
+    98 - mod97(&format!("{}{}00", bban, country))
+}
+
+impl Iban {
+    /// Parses and fully validates an IBAN in either electronic or print format.
+    pub fn parse(input: &str) -> Result<Iban, IbanError> {
+        let iban: String = input
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+
+        if !iban.chars().all(|c| c.is_ascii_alphanumeric()) || iban.len() < 4 {
+            return Err(IbanError::InvalidCharacters);
+        }
+
+        let country = &iban[..2];
+        if !country.chars().all(|c| c.is_ascii_alphabetic()) || !iban[2..4].chars().all(|c| c.is_ascii_digit()) {
+            return Err(IbanError::InvalidCharacters);
+        }
+
+        let expected = country_length(country).ok_or_else(|| IbanError::UnknownCountry(country.to_string()))?;
+        if iban.len() != expected {
+            return Err(IbanError::InvalidLength { expected, actual: iban.len() });
+        }
+
+        let rearranged = format!("{}{}", &iban[4..], &iban[..4]);
+        if mod97(&rearranged) != 1 {
+            return Err(IbanError::ChecksumMismatch);
+        }
+
+        Ok(Iban(iban))
+    }
+
+    /// Builds the IBAN for a BBAN, computing its check digits.
+    pub fn from_bban(country: &str, bban: &str) -> Result<Iban, IbanError> {
+        let country = country.to_ascii_uppercase();
+        let bban = bban.to_ascii_uppercase();
+
+        if !bban.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(IbanError::InvalidCharacters);
+        }
+
+        let expected = country_length(&country).ok_or_else(|| IbanError::UnknownCountry(country.clone()))?;
+        if bban.len() + 4 != expected {
+            return Err(IbanError::InvalidLength { expected, actual: bban.len() + 4 });
+        }
+
+        Ok(Iban(format!("{}{:02}{}", country, check_digits(&country, &bban), bban)))
+    }
+
+    /// Builds the IBAN for an account at `bank_code`, with the account digits
+    /// zero-padded to fill the rest of the country's BBAN.
+    pub fn for_account(country: &str, bank_code: &str, account: &str) -> Result<Iban, IbanError> {
+        let expected = country_length(&country.to_ascii_uppercase())
+            .ok_or_else(|| IbanError::UnknownCountry(country.to_string()))?;
+        let bban_len = expected - 4;
+
+        if bank_code.len() + account.len() > bban_len {
+            return Err(IbanError::BbanTooLong { max: bban_len });
+        }
+
+        let bban = format!("{}{:0>width$}", bank_code, account, width = bban_len - bank_code.len());
+        Iban::from_bban(country, &bban)
+    }
+
+    /// The IBAN without spaces, as used in electronic messages and storage.
+    pub fn electronic(&self) -> &str {
+        &self.0
+    }
+
+    pub fn country_code(&self) -> &str {
+        &self.0[..2]
+    }
+
+    pub fn check_digits(&self) -> &str {
+        &self.0[2..4]
+    }
+
+    pub fn bban(&self) -> &str {
+        &self.0[4..]
+    }
+
+    /// The IBAN in groups of four characters, as printed for people to read.
+    pub fn print_format(&self) -> String {
+        self.0
+            .as_bytes()
+            .chunks(4)
+            .map(|chunk| std::str::from_utf8(chunk).unwrap_or_default())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl fmt::Display for Iban {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.print_format())
+    }
+}
+
+impl FromStr for Iban {
+    type Err = IbanError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Iban::parse(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Examples from the SWIFT IBAN registry.
+    const REGISTRY_EXAMPLES: &[&str] = &[
+        "AT611904300234573201",
+        "BE68539007547034",
+        "CH9300762011623852957",
+        "DE89370400440532013000",
+        "ES9121000418450200051332",
+        "FR1420041010050500013M02606",
+        "GB29NWBK60161331926819",
+        "IE29AIBK93115212345678",
+        "IT60X0542811101000000123456",
+        "MT84MALT011000012345MTLCAST001S",
+        "NL91ABNA0417164300",
+        "NO9386011117947",
+        "PL61109010140000071219812874",
+        "SA0380000000608010167519",
+        "SE4550000000058398257466",
+    ];
+
+    #[test]
+    fn registry_examples_are_valid() {
+        for example in REGISTRY_EXAMPLES {
+            let iban = Iban::parse(example).unwrap_or_else(|e| panic!("{}: {}", example, e));
+            assert_eq!(iban.electronic(), *example);
+        }
+    }
+
+    #[test]
+    fn generated_check_digits_match_registry() {
+        for example in REGISTRY_EXAMPLES {
+            let generated = Iban::from_bban(&example[..2], &example[4..]).unwrap();
+            assert_eq!(generated.electronic(), *example);
+        }
+    }
+
+    #[test]
+    fn print_format_is_accepted() {
+        let iban = Iban::parse("gb82 west 1234 5698 7654 32").unwrap();
+        assert_eq!(iban.electronic(), "GB82WEST12345698765432");
+        assert_eq!(iban.print_format(), "GB82 WEST 1234 5698 7654 32");
+        assert_eq!(iban.country_code(), "GB");
+        assert_eq!(iban.check_digits(), "82");
+        assert_eq!(iban.bban(), "WEST12345698765432");
+    }
+
+    #[test]
+    fn invalid_ibans_are_rejected() {
+        // Check digits altered
+        assert_eq!(Iban::parse("GB82WEST12345698765431"), Err(IbanError::ChecksumMismatch));
+        // Two characters transposed
+        assert_eq!(Iban::parse("DE89370400440532010300"), Err(IbanError::ChecksumMismatch));
+        assert_eq!(
+            Iban::parse("DE8937040044053201300"),
+            Err(IbanError::InvalidLength { expected: 22, actual: 21 })
+        );
+        assert_eq!(Iban::parse("XX89370400440532013000"), Err(IbanError::UnknownCountry("XX".to_string())));
+        assert_eq!(Iban::parse("DE89-3704-0044"), Err(IbanError::InvalidCharacters));
+    }
+
+    #[test]
+    fn account_ibans_pad_the_account_number() {
+        let iban = Iban::for_account("DE", "37040044", "532013000").unwrap();
+        assert_eq!(iban.electronic(), "DE89370400440532013000");
+
+        assert_eq!(
+            Iban::for_account("DE", "37040044", "12345678901"),
+            Err(IbanError::BbanTooLong { max: 18 })
+        );
+    }
+}
@@ -0,0 +1,3 @@
+//! Banking identifiers and the helpers to generate, validate and format them.
+
+pub mod iban;
@@ -0,0 +1,90 @@
+//! The command line: serving the API, the default, and administering service accounts.
+
+use clap::{Parser, Subcommand};
+use time::Duration;
+
+use crate::service_accounts::{self, Scope};
+use crate::store::Database;
+
+#[derive(Parser)]
+#[command(name = "rusty_ledger", about = "Rusty Ledger banking API")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Serve the API (the default)
+    Serve,
+    /// Manage service accounts
+    #[command(subcommand)]
+    ServiceAccount(ServiceAccountCommand),
+    /// Mint, list and revoke service account API keys
+    #[command(subcommand)]
+    ApiKey(ApiKeyCommand),
+}
+
+#[derive(Subcommand)]
+pub enum ServiceAccountCommand {
+    /// Create a service account acting on a user's accounts
+    Create {
+        name: String,
+        /// Email of the user whose accounts the service acts on
+        #[arg(long)]
+        owner: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ApiKeyCommand {
+    /// Mint a key and print it; it cannot be shown again
+    Create {
+        /// Name of the service account
+        service_account: String,
+        /// A scope to grant, e.g. transactions:write; repeat for more
+        #[arg(long = "scope", required = true)]
+        scopes: Vec<Scope>,
+        /// Days until the key expires; it never does without this
+        #[arg(long)]
+        expires_in_days: Option<i64>,
+    },
+    /// Revoke a key by its prefix, the part after `rl_`
+    Revoke { prefix: String },
+    /// List every key, without the secrets
+    List,
+}
+
+/// Runs an administrative command; serving is left to the caller.
+pub async fn run(command: Command, db: &Database) -> anyhow::Result<()> {
+    match command {
+        Command::Serve => unreachable!("serving is handled by main"),
+        Command::ServiceAccount(ServiceAccountCommand::Create { name, owner }) => {
+            let id = service_accounts::create(db, &name, &owner).await?;
+            println!("Created service account {} ({})", name, id);
+        }
+        Command::ApiKey(ApiKeyCommand::Create { service_account, scopes, expires_in_days }) => {
+            let key = service_accounts::mint_key(db, &service_account, &scopes, expires_in_days.map(Duration::days)).await?;
+            println!("{}", key);
+            eprintln!("Store this key now; only its hash is kept.");
+        }
+        Command::ApiKey(ApiKeyCommand::Revoke { prefix }) => {
+            service_accounts::revoke_key(db, &prefix).await?;
+            println!("Revoked API key {}", prefix);
+        }
+        Command::ApiKey(ApiKeyCommand::List) => {
+            for key in service_accounts::list_keys(db).await? {
+                let status = match (key.revoked_at, key.expires_at) {
+                    (Some(_), _) => "revoked".to_string(),
+                    (None, Some(expires_at)) if expires_at <= time::OffsetDateTime::now_utc() => "expired".to_string(),
+                    (None, Some(expires_at)) => format!("expires {}", expires_at.date()),
+                    (None, None) => "active".to_string(),
+                };
+                let last_used = key.last_used_at.map_or("never used".to_string(), |at| format!("last used {}", at.date()));
+                println!("{}\t{}\t{}\t{}\t{}", key.prefix, key.service_account, key.scopes.join(","), status, last_used);
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,210 @@
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+use time::Duration;
+
+use crate::banking::iban::Iban;
+use crate::oidc;
+use crate::password::hash::Hasher;
+use crate::password::policy::PasswordPolicy;
+
+/// Settings read from the environment at startup.
+#[derive(Clone)]
+pub struct Config {
+    /// Leading digits of every account number issued by this ledger (`BANK_PREFIX`)
+    pub bank_prefix: String,
+    /// Country of the IBANs issued by this ledger (`IBAN_COUNTRY_CODE`)
+    pub iban_country_code: String,
+    /// Bank code that starts the BBAN of every IBAN issued by this ledger (`IBAN_BANK_CODE`)
+    pub iban_bank_code: String,
+    /// Transfers above this amount need a second user's approval (`APPROVAL_THRESHOLD`)
+    pub approval_threshold: BigDecimal,
+    /// How long a pending approval stays valid (`APPROVAL_TTL_HOURS`)
+    pub approval_ttl: Duration,
+    /// Most that can be sent to a new recipient during its cooling-off period (`PAYEE_COOLING_OFF_LIMIT`)
+    pub payee_cooling_off_limit: BigDecimal,
+    /// How long a recipient stays in its cooling-off period after it is first added as a payee
+    /// or first paid (`PAYEE_COOLING_OFF_HOURS`)
+    pub payee_cooling_off: Duration,
+    /// Account holder names a user can check when adding payees, per day (`PAYEE_NAME_CHECKS_PER_DAY`)
+    pub payee_name_checks_per_day: i64,
+    /// Directory of `<kid>.pem` keys that sign access tokens; without one, a key is
+    /// generated at startup (`JWT_KEYS_DIR`)
+    pub jwt_keys_dir: Option<PathBuf>,
+    /// Key in `jwt_keys_dir` that signs new tokens, by default the last by name (`JWT_SIGNING_KID`)
+    pub jwt_signing_kid: Option<String>,
+    /// Lifetime of an access token (`ACCESS_TOKEN_TTL_MINUTES`)
+    pub access_token_ttl: Duration,
+    /// How long a session stays alive without being refreshed (`REFRESH_TOKEN_TTL_DAYS`)
+    pub refresh_token_ttl: Duration,
+    /// Issuer shown in authenticator apps (`TOTP_ISSUER`)
+    pub totp_issuer: String,
+    /// Transfers above this amount need a recent TOTP step-up from users with 2FA (`STEP_UP_THRESHOLD`)
+    pub step_up_threshold: BigDecimal,
+    /// How long a TOTP step-up stays fresh (`STEP_UP_TTL_MINUTES`)
+    pub step_up_ttl: Duration,
+    /// Failed logins for one email before it is locked out; an IP gets four times as many (`LOGIN_MAX_FAILURES`)
+    pub login_max_failures: i32,
+    /// How long a lockout lasts, and how long failures are remembered (`LOGIN_LOCKOUT_MINUTES`)
+    pub login_lockout: Duration,
+    /// Reverse proxies whose `X-Forwarded-For` is believed, as comma-separated IPs (`TRUSTED_PROXIES`)
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Base URL of the web app, used for links in emails (`APP_BASE_URL`)
+    pub app_base_url: String,
+    /// SMTP relay to send email through; without one, emails are logged (`SMTP_URL`)
+    pub smtp_url: Option<String>,
+    /// Sender of every email (`MAIL_FROM`)
+    pub mail_from: String,
+    /// Directory logged emails are written to instead of stdout (`MAIL_DIR`)
+    pub mail_dir: Option<PathBuf>,
+    /// Shortest password accepted (`PASSWORD_MIN_LENGTH`)
+    pub password_min_length: usize,
+    /// Lowest estimated strength accepted, from 0 to 4 (`PASSWORD_MIN_SCORE`)
+    pub password_min_score: u8,
+    /// Directory of breached-password range files to refuse passwords from (`BREACHED_PASSWORDS_DIR`)
+    pub breached_passwords_dir: Option<PathBuf>,
+    /// Memory in KiB each Argon2id password hash uses (`ARGON2_MEMORY_KIB`)
+    pub argon2_memory_kib: u32,
+    /// Passes Argon2id makes over that memory (`ARGON2_ITERATIONS`)
+    pub argon2_iterations: u32,
+    /// Lanes Argon2id hashes in (`ARGON2_PARALLELISM`)
+    pub argon2_parallelism: u32,
+    /// OpenID Connect issuer staff sign in through; staff login is off without it (`OIDC_ISSUER_URL`)
+    pub oidc_issuer_url: Option<String>,
+    /// Client id the ledger is registered under with the issuer (`OIDC_CLIENT_ID`)
+    pub oidc_client_id: Option<String>,
+    /// Client secret, unless the ledger is registered as a public client (`OIDC_CLIENT_SECRET`)
+    pub oidc_client_secret: Option<String>,
+    /// Where the issuer sends staff back to, by default the callback under `APP_BASE_URL` (`OIDC_REDIRECT_URL`)
+    pub oidc_redirect_url: String,
+    /// ID token claim listing the groups or roles of a staff member (`OIDC_ROLE_CLAIM`)
+    pub oidc_role_claim: String,
+    /// Claim values and the ledger role each grants, as `value=role,...` (`OIDC_ROLE_MAP`)
+    pub oidc_role_map: Vec<(String, String)>,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        let bank_prefix: String = env_or("BANK_PREFIX", "7001".to_string());
+        let bank_prefix = if !bank_prefix.is_empty() && bank_prefix.chars().all(|c| c.is_ascii_digit()) {
+            bank_prefix
+        } else {
+            eprintln!("Ignoring non-numeric BANK_PREFIX {:?}", bank_prefix);
+            "7001".to_string()
+        };
+
+        let iban_country_code = env_or("IBAN_COUNTRY_CODE", "DE".to_string()).to_ascii_uppercase();
+        let iban_bank_code = env_or("IBAN_BANK_CODE", "70010000".to_string()).to_ascii_uppercase();
+        // Both must leave room for a 10 digit account sequence in the BBAN
+        let (iban_country_code, iban_bank_code) = match Iban::for_account(&iban_country_code, &iban_bank_code, "0000000000") {
+            Ok(_) => (iban_country_code, iban_bank_code),
+            Err(e) => {
+                eprintln!("Ignoring IBAN_COUNTRY_CODE {:?} and IBAN_BANK_CODE {:?}: {}", iban_country_code, iban_bank_code, e);
+                ("DE".to_string(), "70010000".to_string())
+            }
+        };
+
+        let password_min_score = env_or("PASSWORD_MIN_SCORE", 3);
+        let password_min_score = if password_min_score <= 4 {
+            password_min_score
+        } else {
+            eprintln!("Ignoring PASSWORD_MIN_SCORE {} above the highest score of 4", password_min_score);
+            3
+        };
+
+        // OWASP's recommended minimum for Argon2id
+        let argon2_memory_kib = env_or("ARGON2_MEMORY_KIB", 19_456);
+        let argon2_iterations = env_or("ARGON2_ITERATIONS", 2);
+        let argon2_parallelism = env_or("ARGON2_PARALLELISM", 1);
+        let (argon2_memory_kib, argon2_iterations, argon2_parallelism) = match Hasher::new(argon2_memory_kib, argon2_iterations, argon2_parallelism) {
+            Ok(_) => (argon2_memory_kib, argon2_iterations, argon2_parallelism),
+            Err(e) => {
+                eprintln!("Ignoring ARGON2_MEMORY_KIB {}, ARGON2_ITERATIONS {} and ARGON2_PARALLELISM {}: {}", argon2_memory_kib, argon2_iterations, argon2_parallelism, e);
+                (19_456, 2, 1)
+            }
+        };
+
+        let app_base_url = env_or("APP_BASE_URL", "http://localhost:3000".to_string());
+        let oidc_redirect_url = env_or("OIDC_REDIRECT_URL", format!("{}/api/v1/oidc/callback", app_base_url.trim_end_matches('/')));
+
+        Config {
+            bank_prefix,
+            iban_country_code,
+            iban_bank_code,
+            approval_threshold: env_or("APPROVAL_THRESHOLD", BigDecimal::from(10_000)),
+            approval_ttl: Duration::hours(env_or("APPROVAL_TTL_HOURS", 24)),
+            payee_cooling_off_limit: env_or("PAYEE_COOLING_OFF_LIMIT", BigDecimal::from(1_000)),
+            payee_cooling_off: Duration::hours(env_or("PAYEE_COOLING_OFF_HOURS", 24)),
+            payee_name_checks_per_day: env_or("PAYEE_NAME_CHECKS_PER_DAY", 10),
+            jwt_keys_dir: std::env::var("JWT_KEYS_DIR").ok().map(PathBuf::from),
+            jwt_signing_kid: std::env::var("JWT_SIGNING_KID").ok(),
+            access_token_ttl: Duration::minutes(env_or("ACCESS_TOKEN_TTL_MINUTES", 15)),
+            refresh_token_ttl: Duration::days(env_or("REFRESH_TOKEN_TTL_DAYS", 30)),
+            totp_issuer: env_or("TOTP_ISSUER", "Rusty Ledger".to_string()),
+            step_up_threshold: env_or("STEP_UP_THRESHOLD", BigDecimal::from(1_000)),
+            step_up_ttl: Duration::minutes(env_or("STEP_UP_TTL_MINUTES", 5)),
+            login_max_failures: env_or("LOGIN_MAX_FAILURES", 5),
+            login_lockout: Duration::minutes(env_or("LOGIN_LOCKOUT_MINUTES", 15)),
+            trusted_proxies: parse_ip_list("TRUSTED_PROXIES", &std::env::var("TRUSTED_PROXIES").unwrap_or_default()),
+            app_base_url,
+            smtp_url: std::env::var("SMTP_URL").ok(),
+            mail_from: env_or("MAIL_FROM", "Rusty Ledger <no-reply@rusty-ledger.local>".to_string()),
+            mail_dir: std::env::var("MAIL_DIR").ok().map(PathBuf::from),
+            password_min_length: env_or("PASSWORD_MIN_LENGTH", 10),
+            password_min_score,
+            breached_passwords_dir: std::env::var("BREACHED_PASSWORDS_DIR").ok().map(PathBuf::from),
+            argon2_memory_kib,
+            argon2_iterations,
+            argon2_parallelism,
+            oidc_issuer_url: std::env::var("OIDC_ISSUER_URL").ok(),
+            oidc_client_id: std::env::var("OIDC_CLIENT_ID").ok(),
+            oidc_client_secret: std::env::var("OIDC_CLIENT_SECRET").ok(),
+            oidc_redirect_url,
+            oidc_role_claim: env_or("OIDC_ROLE_CLAIM", "groups".to_string()),
+            oidc_role_map: oidc::parse_role_map(&env_or("OIDC_ROLE_MAP", "ledger-admins=admin".to_string())),
+        }
+    }
+
+    /// The IBAN of the `sequence`th account, the same sequence its account number uses.
+    pub fn iban_for(&self, sequence: i64) -> Iban {
+        // The country and bank code were checked to leave room for any sequence at startup
+        Iban::for_account(&self.iban_country_code, &self.iban_bank_code, &sequence.to_string())
+            .expect("IBAN settings validated at startup")
+    }
+
+    pub fn password_hasher(&self) -> Hasher {
+        Hasher::new(self.argon2_memory_kib, self.argon2_iterations, self.argon2_parallelism)
+            .expect("Argon2 settings validated at startup")
+    }
+
+    pub fn password_policy(&self) -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: self.password_min_length,
+            min_score: self.password_min_score,
+            breached_dir: self.breached_passwords_dir.clone(),
+        }
+    }
+}
+
+/// Parses a comma-separated list of IP addresses, reporting and skipping invalid ones.
+fn parse_ip_list(key: &str, value: &str) -> Vec<IpAddr> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|ip| !ip.is_empty())
+        .filter_map(|ip| ip.parse().map_err(|_| eprintln!("Ignoring invalid IP address {:?} in {}", ip, key)).ok())
+        .collect()
+}
+
+/// Reads and parses an environment variable, falling back to `default` when it is
+/// unset. An unparseable value is reported and also falls back.
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    match std::env::var(key) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            eprintln!("Ignoring invalid value {:?} for {}", value, key);
+            default
+        }),
+        Err(_) => default,
+    }
+}
@@ -0,0 +1,204 @@
+//! The error every handler returns, sent as RFC 7807 `application/problem+json`.
+//!
+//! Each problem carries a `code` clients can match on, which stays the same when the
+//! wording of `detail` changes, and the correlation id of the request. Internal errors
+//! keep their cause for the log: the client only gets the correlation id, which finds
+//! the cause there.
+
+use std::fmt;
+
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::middleware::correlation;
+
+#[derive(Debug)]
+pub enum LedgerError {
+    /// The request is malformed or contradicts itself
+    BadRequest(String),
+    /// Missing or wrong credentials
+    Unauthorized(String),
+    /// Authenticated, but not allowed to do this
+    Forbidden(String),
+    NotFound(String),
+    /// The request clashes with the current state, e.g. an approval already decided
+    Conflict(String),
+    AccountNotFound(String),
+    InsufficientFunds(String),
+    /// Refused by one of the ledger's rules, with a code and details of its own
+    Refused(Problem),
+    /// A failure of the ledger itself; its cause is logged, never sent
+    Internal(anyhow::Error),
+}
+
+/// A refusal with its own code, and any details as extension members.
+#[derive(Debug)]
+pub struct Problem {
+    status: StatusCode,
+    code: &'static str,
+    detail: String,
+    extensions: Map<String, Value>,
+    /// Seconds until trying again may succeed, sent as `Retry-After`
+    retry_after: Option<i64>,
+}
+
+impl Problem {
+    pub fn new(status: StatusCode, code: &'static str, detail: impl Into<String>) -> Self {
+        Problem { status, code, detail: detail.into(), extensions: Map::new(), retry_after: None }
+    }
+
+    /// Adds an extension member.
+    pub fn with(mut self, name: &str, value: impl Serialize) -> Self {
+        self.extensions.insert(name.to_string(), serde_json::to_value(value).unwrap_or_default());
+        self
+    }
+
+    pub fn retry_after(mut self, seconds: i64) -> Self {
+        self.retry_after = Some(seconds.max(1));
+        self
+    }
+}
+
+impl LedgerError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            LedgerError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            LedgerError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            LedgerError::Forbidden(_) => StatusCode::FORBIDDEN,
+            LedgerError::NotFound(_) | LedgerError::AccountNotFound(_) => StatusCode::NOT_FOUND,
+            LedgerError::Conflict(_) => StatusCode::CONFLICT,
+            LedgerError::InsufficientFunds(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            LedgerError::Refused(problem) => problem.status,
+            LedgerError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// The stable, machine-readable code of the error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            LedgerError::BadRequest(_) => "bad_request",
+            LedgerError::Unauthorized(_) => "unauthorized",
+            LedgerError::Forbidden(_) => "forbidden",
+            LedgerError::NotFound(_) => "not_found",
+            LedgerError::Conflict(_) => "conflict",
+            LedgerError::AccountNotFound(_) => "account_not_found",
+            LedgerError::InsufficientFunds(_) => "insufficient_funds",
+            LedgerError::Refused(problem) => problem.code,
+            LedgerError::Internal(_) => "internal_error",
+        }
+    }
+}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LedgerError::BadRequest(detail)
+            | LedgerError::Unauthorized(detail)
+            | LedgerError::Forbidden(detail)
+            | LedgerError::NotFound(detail)
+            | LedgerError::Conflict(detail)
+            | LedgerError::AccountNotFound(detail)
+            | LedgerError::InsufficientFunds(detail) => write!(f, "{}: {}", self.code(), detail),
+            LedgerError::Refused(problem) => write!(f, "{}: {}", problem.code, problem.detail),
+            LedgerError::Internal(e) => write!(f, "internal_error: {:#}", e),
+        }
+    }
+}
+
+impl From<Problem> for LedgerError {
+    fn from(problem: Problem) -> Self {
+        LedgerError::Refused(problem)
+    }
+}
+
+impl From<anyhow::Error> for LedgerError {
+    fn from(e: anyhow::Error) -> Self {
+        LedgerError::Internal(e)
+    }
+}
+
+impl From<sqlx::Error> for LedgerError {
+    fn from(e: sqlx::Error) -> Self {
+        LedgerError::Internal(e.into())
+    }
+}
+
+impl IntoResponse for LedgerError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let code = self.code();
+        let correlation_id = correlation::current();
+
+        let (detail, extensions, retry_after) = match self {
+            LedgerError::Internal(e) => {
+                eprintln!("[{}] Internal error: {:#}", correlation_id, e);
+                ("The ledger failed to handle the request".to_string(), Map::new(), None)
+            }
+            LedgerError::Refused(problem) => (problem.detail, problem.extensions, problem.retry_after),
+            LedgerError::BadRequest(detail)
+            | LedgerError::Unauthorized(detail)
+            | LedgerError::Forbidden(detail)
+            | LedgerError::NotFound(detail)
+            | LedgerError::Conflict(detail)
+            | LedgerError::AccountNotFound(detail)
+            | LedgerError::InsufficientFunds(detail) => (detail, Map::new(), None),
+        };
+
+        let mut body = extensions;
+        body.insert("type".to_string(), Value::from("about:blank"));
+        body.insert("title".to_string(), Value::from(status.canonical_reason().unwrap_or_default()));
+        body.insert("status".to_string(), Value::from(status.as_u16()));
+        body.insert("detail".to_string(), Value::from(detail));
+        body.insert("code".to_string(), Value::from(code));
+        body.insert("correlation_id".to_string(), Value::from(correlation_id));
+
+        let mut response = (status, Json(Value::Object(body))).into_response();
+        response.headers_mut().insert(header::CONTENT_TYPE, HeaderValue::from_static("application/problem+json"));
+        if let Some(seconds) = retry_after {
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+
+    async fn body(error: LedgerError) -> (Response<()>, Value) {
+        let (parts, body) = error.into_response().into_parts();
+        let bytes = body.collect().await.unwrap().to_bytes();
+        (Response::from_parts(parts, ()), serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn internal_errors_keep_their_cause_out_of_the_response() {
+        let (response, json) = body(anyhow::anyhow!("relation \"accounts\" does not exist").into()).await;
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/problem+json");
+        assert_eq!(json["code"], "internal_error");
+        assert!(!json["detail"].as_str().unwrap().contains("relation"));
+    }
+
+    #[tokio::test]
+    async fn refusals_carry_their_code_and_details() {
+        let problem = Problem::new(StatusCode::TOO_MANY_REQUESTS, "transfer_limit_exceeded", "Too many transfers")
+            .with("limit", "hourly_count")
+            .retry_after(90);
+        let (response, json) = body(problem.into()).await;
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "90");
+        assert_eq!(json["code"], "transfer_limit_exceeded");
+        assert_eq!(json["status"], 429);
+        assert_eq!(json["limit"], "hourly_count");
+        assert_eq!(json["detail"], "Too many transfers");
+    }
+}
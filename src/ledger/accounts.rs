@@ -0,0 +1,283 @@
+//! Accounts: opening them, finding them by id, number or IBAN, and correcting balances.
+
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::http::StatusCode;
+use bigdecimal::{BigDecimal, Signed};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::account_number;
+use crate::banking::iban::Iban;
+use crate::config::Config;
+use crate::error::{LedgerError, Problem};
+
+use super::repository::{AccountRecord, NewAccount, Repository, Store};
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub enum Types {
+    Savings,
+    Current,
+    Salary,
+    FD, // Fixed Deposit
+    RD, // Recurring Deposit
+}
+
+impl Types {
+    /// The type of an account as stored, see the [`fmt::Display`] impl.
+    pub(crate) fn from_stored(stored: &str) -> Types {
+        match stored {
+            "current" => Types::Current,
+            "salary" => Types::Salary,
+            "fd" => Types::FD,
+            "rd" => Types::RD,
+            _ => Types::Savings,
+        }
+    }
+}
+
+impl fmt::Display for Types {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result  {
+        match self {
+            Types::Savings => write!(f, "savings"),
+            Types::Current => write!(f, "current"),
+            Types::Salary => write!(f, "salary"),
+            Types::FD => write!(f, "fd"),
+            Types::RD => write!(f, "rd"),
+        }
+    }
+}
+
+/// An account with its balance.
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct Account {
+    account_id: Uuid,
+    account_number: String,
+    iban: Option<String>,
+    account_type: String,
+    #[schema(value_type = String)]
+    balance: BigDecimal,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct AccountBalance {
+    account_id: Uuid,
+    #[schema(value_type = String)]
+    balance: BigDecimal,
+}
+
+/// What a customer may see about someone else's account before paying into it.
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct AccountLookup {
+    account_id: Uuid,
+    account_number: String,
+    iban: Option<String>,
+    account_type: String,
+    owner_name: String,
+}
+
+/// A newly opened account.
+pub struct OpenedAccount {
+    pub account_id: Uuid,
+    pub account_number: String,
+    pub iban: String,
+}
+
+/// Masks a name down to the first letter of each part, e.g. "Jane Doe" becomes "J*** D***".
+fn mask_name(name: &str) -> String {
+    name.split_whitespace()
+        .map(|part| part.chars().next().map(|c| format!("{}***", c)).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn not_found(account_id: Uuid) -> LedgerError {
+    LedgerError::AccountNotFound(format!("Account with ID {} not found", account_id))
+}
+
+pub struct AccountService<R> {
+    repo: R,
+    config: Arc<Config>,
+}
+
+impl<R: Repository> AccountService<R> {
+    pub fn new(repo: R, config: Arc<Config>) -> Self {
+        AccountService { repo, config }
+    }
+
+    /// Opens an account for `user_id` in the caller's unit of work, numbered from the next
+    /// number in the sequence.
+    pub async fn open(&self, store: &mut R::Store, user_id: Uuid, account_type: &Types) -> Result<OpenedAccount, LedgerError> {
+        let sequence = store.next_account_sequence().await
+            .context("Failed to allocate account number")?;
+        let account_number = account_number::generate(&self.config.bank_prefix, sequence);
+        let iban = self.config.iban_for(sequence).electronic().to_string();
+
+        let account_id = store.insert_account(&NewAccount {
+            user_id,
+            account_type: account_type.to_string(),
+            account_number: account_number.clone(),
+            iban: Some(iban.clone()),
+        }).await
+         .context("Failed to create account")?;
+
+        Ok(OpenedAccount { account_id, account_number, iban })
+    }
+
+    async fn record(&self, account_id: Uuid) -> Result<AccountRecord, LedgerError> {
+        let mut conn = self.repo.acquire().await
+            .context("Failed to connect to the database")?;
+
+        conn.find_account(account_id).await
+            .context("Failed to fetch account")?
+            .ok_or_else(|| not_found(account_id))
+    }
+
+    pub async fn find(&self, account_id: Uuid) -> Result<Account, LedgerError> {
+        let account = self.record(account_id).await?;
+
+        Ok(Account {
+            account_id: account.id,
+            account_number: account.account_number,
+            iban: account.iban,
+            account_type: account.account_type,
+            balance: account.balance,
+        })
+    }
+
+    /// One of `user_id`'s accounts. Anyone else's is reported as not found, so that other
+    /// users' account ids cannot be probed.
+    pub async fn owned(&self, account_id: Uuid, user_id: Uuid) -> Result<AccountRecord, LedgerError> {
+        let account = self.record(account_id).await?;
+
+        if account.user_id != user_id {
+            return Err(not_found(account_id));
+        }
+
+        Ok(account)
+    }
+
+    /// Whether `account_id` is one of `user_id`'s accounts.
+    pub async fn is_owner(&self, account_id: Uuid, user_id: Uuid) -> Result<bool, LedgerError> {
+        let mut conn = self.repo.acquire().await
+            .context("Failed to connect to the database")?;
+
+        let account = conn.find_account(account_id).await
+            .context("Failed to fetch account")?;
+
+        Ok(account.is_some_and(|account| account.user_id == user_id))
+    }
+
+    pub async fn balance(&self, account_id: Uuid) -> Result<AccountBalance, LedgerError> {
+        let account = self.record(account_id).await?;
+
+        Ok(AccountBalance { account_id: account.id, balance: account.balance })
+    }
+
+    /// Parses an account number, rejecting mistyped ones by their check digit.
+    fn parse_number(&self, number: &str) -> Result<String, LedgerError> {
+        account_number::validate(number, self.config.bank_prefix.len())
+            .map_err(|e| Problem::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_account_number", e.to_string()).into())
+    }
+
+    async fn record_for_number(&self, number: &str) -> Result<AccountRecord, LedgerError> {
+        let number = self.parse_number(number)?;
+
+        let mut conn = self.repo.acquire().await
+            .context("Failed to connect to the database")?;
+
+        conn.find_account_by_number(&number).await
+            .context("Failed to look up account number")?
+            .ok_or_else(|| LedgerError::AccountNotFound(format!("Account number {} not found", account_number::format(&number))))
+    }
+
+    /// Resolves an account number to its account and masked holder name. Mistyped numbers
+    /// are rejected by their check digit before storage is queried.
+    pub async fn lookup(&self, number: &str) -> Result<AccountLookup, LedgerError> {
+        let account = self.record_for_number(number).await?;
+
+        Ok(AccountLookup {
+            account_id: account.id,
+            account_number: account.account_number,
+            iban: account.iban,
+            account_type: account.account_type,
+            owner_name: mask_name(&account.owner_name),
+        })
+    }
+
+    pub async fn id_for_number(&self, number: &str) -> Result<Uuid, LedgerError> {
+        Ok(self.record_for_number(number).await?.id)
+    }
+
+    /// Resolves an IBAN to its account, rejecting ones that fail their mod-97 check.
+    pub async fn id_for_iban(&self, iban: &str) -> Result<Uuid, LedgerError> {
+        let iban = Iban::parse(iban)
+            .map_err(|e| Problem::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_iban", e.to_string()))?;
+
+        let mut conn = self.repo.acquire().await
+            .context("Failed to connect to the database")?;
+
+        let account = conn.find_account_by_iban(iban.electronic()).await
+            .context("Failed to look up IBAN")?
+            .ok_or_else(|| LedgerError::AccountNotFound(format!("IBAN {} not found", iban)))?;
+
+        Ok(account.id)
+    }
+
+    /// A user's first account, the one they are signed in to.
+    pub async fn primary(&self, user_id: Uuid) -> Result<AccountRecord, LedgerError> {
+        let mut conn = self.repo.acquire().await
+            .context("Failed to connect to the database")?;
+
+        let accounts = conn.accounts_of(user_id).await
+            .context("Failed to fetch account")?;
+
+        accounts.into_iter()
+            .next()
+            .ok_or_else(|| LedgerError::AccountNotFound(format!("No account found for user {}", user_id)))
+    }
+
+    /// Changes an account's balance by a signed amount in the caller's unit of work. The
+    /// balance may not go negative.
+    pub async fn adjust(&self, store: &mut R::Store, account_id: Uuid, amount: &BigDecimal) -> Result<AccountBalance, LedgerError> {
+        let current = store.lock_balances(&[account_id]).await
+            .context("Failed to fetch account balance")?
+            .pop()
+            .ok_or_else(|| not_found(account_id))?;
+
+        let new_balance = current.balance + amount;
+
+        // Don't allow negative balances
+        if new_balance.is_negative() {
+            return Err(LedgerError::InsufficientFunds("Account balance cannot be negative".to_string()));
+        }
+
+        store.set_balance(account_id, &new_balance).await
+            .context("Failed to update account balance")?;
+
+        Ok(AccountBalance { account_id, balance: new_balance })
+    }
+
+    /// Gives every account opened before IBANs were introduced its IBAN, derived from the
+    /// sequence in its account number. Returns how many accounts were updated.
+    pub async fn assign_missing_ibans(&self) -> anyhow::Result<usize> {
+        let mut tx = self.repo.begin().await?;
+
+        let accounts = tx.accounts_without_iban().await?;
+
+        for account in &accounts {
+            let sequence = account_number::sequence(&account.account_number)
+                .ok_or_else(|| anyhow::anyhow!("Malformed account number {}", account.account_number))?;
+            let iban = self.config.iban_for(sequence);
+
+            tx.set_iban(account.id, iban.electronic()).await?;
+        }
+
+        R::commit(tx).await?;
+
+        Ok(accounts.len())
+    }
+}
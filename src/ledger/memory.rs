@@ -0,0 +1,474 @@
+//! The ledger's storage in memory, for tests and tools that need no database.
+//!
+//! Units of work take turns: [`MemoryRepository::begin`] waits for the one before to end,
+//! then works on its own copy of the tables, which replaces the shared ones on commit. So a
+//! unit of work is atomic, others never see it half done, and the balances it locks cannot
+//! change under it, the guarantees PostgreSQL gives the ledger with row locks, if coarser.
+//! A write outside a unit of work takes a turn of its own.
+//!
+//! A task holding a unit of work must not also write through a connection, since that
+//! write waits for the unit of work to end.
+
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use async_trait::async_trait;
+use bigdecimal::{BigDecimal, Signed};
+use tokio::sync::{Mutex as Turn, OwnedMutexGuard};
+use uuid::Uuid;
+
+use super::repository::{now, numeric, AccountRecord, Balance, NewAccount, NewTransaction, NewUser, Repository, Store, UserChanges, UserRecord};
+use super::transfers::Transaction;
+
+#[derive(Clone, Default)]
+pub struct MemoryRepository {
+    tables: Arc<Mutex<Tables>>,
+    turn: Arc<Turn<()>>,
+    /// Like a database sequence, numbers taken by a rolled back unit of work are not reused
+    sequence: Arc<AtomicI64>,
+}
+
+#[derive(Clone, Default)]
+struct Tables {
+    users: Vec<UserRecord>,
+    /// In the order they were opened
+    accounts: Vec<StoredAccount>,
+    balances: HashMap<Uuid, BigDecimal>,
+    transactions: Vec<Transaction>,
+}
+
+#[derive(Clone)]
+struct StoredAccount {
+    id: Uuid,
+    user_id: Uuid,
+    account_type: String,
+    account_number: String,
+    iban: Option<String>,
+}
+
+impl MemoryRepository {
+    fn tables(&self) -> MutexGuard<'_, Tables> {
+        // The tables are only replaced whole, so a panic elsewhere cannot leave them half written
+        self.tables.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[async_trait]
+impl Repository for MemoryRepository {
+    type Store = MemoryStore;
+    type Conn = Box<MemoryStore>;
+    type Tx = MemoryTx;
+
+    async fn acquire(&self) -> anyhow::Result<Self::Conn> {
+        Ok(Box::new(MemoryStore { repo: self.clone(), work: None }))
+    }
+
+    async fn begin(&self) -> anyhow::Result<Self::Tx> {
+        let turn = self.turn.clone().lock_owned().await;
+        let work = self.tables().clone();
+
+        Ok(MemoryTx {
+            store: MemoryStore { repo: self.clone(), work: Some(work) },
+            _turn: turn,
+        })
+    }
+
+    async fn commit(mut tx: Self::Tx) -> anyhow::Result<()> {
+        if let Some(work) = tx.store.work.take() {
+            *tx.store.repo.tables() = work;
+        }
+
+        Ok(())
+    }
+}
+
+/// A unit of work, rolled back by dropping its copy of the tables.
+pub struct MemoryTx {
+    store: MemoryStore,
+    _turn: OwnedMutexGuard<()>,
+}
+
+impl Deref for MemoryTx {
+    type Target = MemoryStore;
+
+    fn deref(&self) -> &MemoryStore {
+        &self.store
+    }
+}
+
+impl DerefMut for MemoryTx {
+    fn deref_mut(&mut self) -> &mut MemoryStore {
+        &mut self.store
+    }
+}
+
+pub struct MemoryStore {
+    repo: MemoryRepository,
+    /// The unit of work's own copy of the tables, or `None` to use the shared ones
+    work: Option<Tables>,
+}
+
+impl MemoryStore {
+    fn read<T>(&self, f: impl FnOnce(&Tables) -> T) -> T {
+        match &self.work {
+            Some(work) => f(work),
+            None => f(&self.repo.tables()),
+        }
+    }
+
+    /// Applies one statement. Outside a unit of work it takes a turn, and is applied to a
+    /// copy so that a failed statement leaves nothing behind.
+    async fn write<T>(&mut self, f: impl FnOnce(&mut Tables) -> anyhow::Result<T> + Send) -> anyhow::Result<T> {
+        if let Some(work) = &mut self.work {
+            return f(work);
+        }
+
+        let _turn = self.repo.turn.lock().await;
+        let mut tables = self.repo.tables();
+
+        let mut copy = tables.clone();
+        let result = f(&mut copy)?;
+        *tables = copy;
+
+        Ok(result)
+    }
+}
+
+impl Tables {
+    fn user_mut(&mut self, id: Uuid) -> Option<&mut UserRecord> {
+        self.users.iter_mut().find(|user| user.id == id)
+    }
+
+    fn account_mut(&mut self, id: Uuid) -> Option<&mut StoredAccount> {
+        self.accounts.iter_mut().find(|account| account.id == id)
+    }
+
+    /// An account joined with its owner and balance, as the database's query would.
+    fn record(&self, account: &StoredAccount) -> Option<AccountRecord> {
+        let owner = self.users.iter().find(|user| user.id == account.user_id)?;
+        let balance = self.balances.get(&account.id)?;
+
+        Some(AccountRecord {
+            id: account.id,
+            user_id: account.user_id,
+            account_number: account.account_number.clone(),
+            iban: account.iban.clone(),
+            account_type: account.account_type.clone(),
+            owner_name: owner.full_name.clone(),
+            balance: balance.clone(),
+        })
+    }
+
+    fn records(&self, filter: impl Fn(&StoredAccount) -> bool) -> Vec<AccountRecord> {
+        self.accounts.iter()
+            .filter(|account| filter(account))
+            .filter_map(|account| self.record(account))
+            .collect()
+    }
+
+    fn has_account(&self, id: Uuid) -> bool {
+        self.accounts.iter().any(|account| account.id == id)
+    }
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    async fn email_taken(&mut self, email: &str) -> anyhow::Result<bool> {
+        Ok(self.read(|tables| tables.users.iter().any(|user| user.email == email)))
+    }
+
+    async fn insert_user(&mut self, user: &NewUser) -> anyhow::Result<Uuid> {
+        let id = Uuid::new_v4();
+
+        self.write(|tables| {
+            if tables.users.iter().any(|existing| existing.email == user.email) {
+                anyhow::bail!("duplicate key value violates unique constraint \"users_email_key\"");
+            }
+
+            tables.users.push(UserRecord {
+                id,
+                full_name: user.full_name.clone(),
+                email: user.email.clone(),
+                pending_email: None,
+                email_verified: false,
+                password_hash: user.password_hash.clone(),
+            });
+
+            Ok(id)
+        }).await
+    }
+
+    async fn find_user(&mut self, id: Uuid) -> anyhow::Result<Option<UserRecord>> {
+        Ok(self.read(|tables| tables.users.iter().find(|user| user.id == id).cloned()))
+    }
+
+    async fn find_user_by_email(&mut self, email: &str) -> anyhow::Result<Option<UserRecord>> {
+        Ok(self.read(|tables| tables.users.iter().find(|user| user.email == email).cloned()))
+    }
+
+    async fn has_external_identity(&mut self, _user_id: Uuid) -> anyhow::Result<bool> {
+        // Identities are linked by the OIDC login in the api layer, which needs a database
+        Ok(false)
+    }
+
+    async fn update_user(&mut self, id: Uuid, changes: &UserChanges) -> anyhow::Result<()> {
+        self.write(|tables| {
+            if let Some(user) = tables.user_mut(id) {
+                if let Some(full_name) = &changes.full_name {
+                    user.full_name = full_name.clone();
+                }
+                if let Some(pending_email) = &changes.pending_email {
+                    user.pending_email = Some(pending_email.clone());
+                }
+                if let Some(password_hash) = &changes.password_hash {
+                    user.password_hash = password_hash.clone();
+                }
+            }
+
+            Ok(())
+        }).await
+    }
+
+    async fn replace_password_hash(&mut self, id: Uuid, old_hash: &str, new_hash: &str) -> anyhow::Result<bool> {
+        self.write(|tables| {
+            match tables.user_mut(id) {
+                Some(user) if user.password_hash == old_hash => {
+                    user.password_hash = new_hash.to_string();
+                    Ok(true)
+                }
+                _ => Ok(false),
+            }
+        }).await
+    }
+
+    async fn next_account_sequence(&mut self) -> anyhow::Result<i64> {
+        Ok(self.repo.sequence.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+
+    async fn insert_account(&mut self, account: &NewAccount) -> anyhow::Result<Uuid> {
+        let id = Uuid::new_v4();
+        let zero = numeric(&BigDecimal::from(0))?;
+
+        self.write(|tables| {
+            if !tables.users.iter().any(|user| user.id == account.user_id) {
+                anyhow::bail!("insert on table \"accounts\" violates foreign key constraint \"accounts_user_id_fkey\"");
+            }
+            if tables.accounts.iter().any(|existing| existing.account_number == account.account_number) {
+                anyhow::bail!("duplicate key value violates unique constraint \"accounts_account_number_key\"");
+            }
+            if account.iban.is_some() && tables.accounts.iter().any(|existing| existing.iban == account.iban) {
+                anyhow::bail!("duplicate key value violates unique constraint \"accounts_iban_key\"");
+            }
+
+            tables.accounts.push(StoredAccount {
+                id,
+                user_id: account.user_id,
+                account_type: account.account_type.clone(),
+                account_number: account.account_number.clone(),
+                iban: account.iban.clone(),
+            });
+            tables.balances.insert(id, zero);
+
+            Ok(id)
+        }).await
+    }
+
+    async fn find_account(&mut self, id: Uuid) -> anyhow::Result<Option<AccountRecord>> {
+        Ok(self.read(|tables| tables.records(|account| account.id == id).pop()))
+    }
+
+    async fn find_account_by_number(&mut self, number: &str) -> anyhow::Result<Option<AccountRecord>> {
+        Ok(self.read(|tables| tables.records(|account| account.account_number == number).pop()))
+    }
+
+    async fn find_account_by_iban(&mut self, iban: &str) -> anyhow::Result<Option<AccountRecord>> {
+        Ok(self.read(|tables| tables.records(|account| account.iban.as_deref() == Some(iban)).pop()))
+    }
+
+    async fn accounts_of(&mut self, user_id: Uuid) -> anyhow::Result<Vec<AccountRecord>> {
+        Ok(self.read(|tables| tables.records(|account| account.user_id == user_id)))
+    }
+
+    async fn accounts_without_iban(&mut self) -> anyhow::Result<Vec<AccountRecord>> {
+        Ok(self.read(|tables| tables.records(|account| account.iban.is_none())))
+    }
+
+    async fn set_iban(&mut self, account_id: Uuid, iban: &str) -> anyhow::Result<()> {
+        self.write(|tables| {
+            if tables.accounts.iter().any(|existing| existing.id != account_id && existing.iban.as_deref() == Some(iban)) {
+                anyhow::bail!("duplicate key value violates unique constraint \"accounts_iban_key\"");
+            }
+
+            if let Some(account) = tables.account_mut(account_id) {
+                account.iban = Some(iban.to_string());
+            }
+
+            Ok(())
+        }).await
+    }
+
+    async fn lock_balances(&mut self, account_ids: &[Uuid]) -> anyhow::Result<Vec<Balance>> {
+        // Holding the turn already locks every balance
+        let mut balances = self.read(|tables| {
+            account_ids.iter()
+                .filter_map(|id| tables.balances.get(id).map(|balance| Balance { account_id: *id, balance: balance.clone() }))
+                .collect::<Vec<_>>()
+        });
+
+        balances.sort_by_key(|balance| balance.account_id);
+        balances.dedup_by_key(|balance| balance.account_id);
+
+        Ok(balances)
+    }
+
+    async fn set_balance(&mut self, account_id: Uuid, balance: &BigDecimal) -> anyhow::Result<()> {
+        let balance = numeric(balance)?;
+
+        self.write(|tables| {
+            if let Some(stored) = tables.balances.get_mut(&account_id) {
+                *stored = balance;
+            }
+
+            Ok(())
+        }).await
+    }
+
+    async fn insert_transaction(&mut self, transaction: &NewTransaction) -> anyhow::Result<Uuid> {
+        let id = Uuid::new_v4();
+        let amount = numeric(&transaction.amount)?;
+
+        self.write(|tables| {
+            if !amount.is_positive() {
+                anyhow::bail!("new row for relation \"transactions\" violates check constraint \"transactions_amount_check\"");
+            }
+            if !tables.has_account(transaction.from_account_id) || !tables.has_account(transaction.to_account_id) {
+                anyhow::bail!("insert on table \"transactions\" violates a foreign key constraint");
+            }
+
+            tables.transactions.push(Transaction {
+                id,
+                from_account_id: transaction.from_account_id,
+                to_account_id: transaction.to_account_id,
+                amount,
+                created_at: Some(now()),
+                category: transaction.category.clone(),
+            });
+
+            Ok(id)
+        }).await
+    }
+
+    async fn find_transaction(&mut self, id: Uuid) -> anyhow::Result<Option<Transaction>> {
+        Ok(self.read(|tables| tables.transactions.iter().find(|transaction| transaction.id == id).cloned()))
+    }
+
+    async fn transactions_of(&mut self, account_id: Uuid) -> anyhow::Result<Vec<Transaction>> {
+        Ok(self.read(|tables| {
+            tables.transactions.iter()
+                .filter(|transaction| transaction.from_account_id == account_id || transaction.to_account_id == account_id)
+                .cloned()
+                .collect()
+        }))
+    }
+
+    async fn all_transactions(&mut self) -> anyhow::Result<Vec<Transaction>> {
+        Ok(self.read(|tables| tables.transactions.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn new_user(email: &str) -> NewUser {
+        NewUser { full_name: "Test User".to_string(), email: email.to_string(), password_hash: "hash".to_string() }
+    }
+
+    #[tokio::test]
+    async fn dropped_units_of_work_leave_nothing_behind() {
+        let repo = MemoryRepository::default();
+
+        let mut tx = repo.begin().await.unwrap();
+        tx.insert_user(&new_user("dropped@example.com")).await.unwrap();
+
+        // Not visible to others before it is committed
+        let mut conn = repo.acquire().await.unwrap();
+        assert!(!conn.email_taken("dropped@example.com").await.unwrap());
+
+        drop(tx);
+        assert!(!conn.email_taken("dropped@example.com").await.unwrap());
+
+        let mut tx = repo.begin().await.unwrap();
+        tx.insert_user(&new_user("kept@example.com")).await.unwrap();
+        MemoryRepository::commit(tx).await.unwrap();
+        assert!(conn.email_taken("kept@example.com").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn units_of_work_take_turns() {
+        let repo = MemoryRepository::default();
+        let tx = repo.begin().await.unwrap();
+
+        let waiting = tokio::spawn({
+            let repo = repo.clone();
+            async move { repo.begin().await.map(|_| ()) }
+        });
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+
+        drop(tx);
+        waiting.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_statements_change_nothing() {
+        let repo = MemoryRepository::default();
+        let mut conn = repo.acquire().await.unwrap();
+
+        let user_id = conn.insert_user(&new_user("owner@example.com")).await.unwrap();
+        assert!(conn.insert_user(&new_user("owner@example.com")).await.is_err());
+
+        let account = NewAccount {
+            user_id,
+            account_type: "savings".to_string(),
+            account_number: "70010000000018".to_string(),
+            iban: None,
+        };
+        let account_id = conn.insert_account(&account).await.unwrap();
+        assert!(conn.insert_account(&account).await.is_err());
+        assert_eq!(conn.accounts_of(user_id).await.unwrap().len(), 1);
+
+        let stranger = NewAccount { user_id: Uuid::new_v4(), account_number: "70010000000026".to_string(), ..account };
+        assert!(conn.insert_account(&stranger).await.is_err());
+
+        let zero = NewTransaction { from_account_id: account_id, to_account_id: account_id, amount: BigDecimal::from(0), category: None };
+        assert!(conn.insert_transaction(&zero).await.is_err());
+        assert!(conn.all_transactions().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn balances_are_stored_as_numeric() {
+        let repo = MemoryRepository::default();
+        let mut conn = repo.acquire().await.unwrap();
+
+        let user_id = conn.insert_user(&new_user("numeric@example.com")).await.unwrap();
+        let account_id = conn.insert_account(&NewAccount {
+            user_id,
+            account_type: "savings".to_string(),
+            account_number: "70010000000018".to_string(),
+            iban: None,
+        }).await.unwrap();
+
+        let balance = |conn: &mut MemoryStore| {
+            conn.read(|tables| tables.balances[&account_id].as_bigint_and_exponent())
+        };
+        assert_eq!(balance(&mut conn), (0.into(), 4));
+
+        conn.set_balance(account_id, &"12.34565".parse().unwrap()).await.unwrap();
+        assert_eq!(balance(&mut conn), (123457.into(), 4));
+    }
+}
@@ -0,0 +1,22 @@
+//! The ledger's domain: users, their accounts, and transfers between accounts.
+//!
+//! The services here hold the rules and reach storage only through a [`Repository`], so
+//! they can be called from the HTTP handlers in [`crate::api`], the command line or a
+//! worker alike. PostgreSQL is the repository the ledger runs on, see [`postgres`]; the
+//! one in [`memory`] gives the same guarantees without a database, for tests, and with the
+//! `sqlite` feature, `sqlite` keeps the ledger in a SQLite file.
+
+pub mod accounts;
+pub mod memory;
+pub mod postgres;
+pub mod repository;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod transfers;
+pub mod users;
+
+pub use accounts::AccountService;
+pub use memory::MemoryRepository;
+pub use repository::{Repository, Store};
+pub use transfers::TransferService;
+pub use users::UserService;
@@ -0,0 +1,330 @@
+//! The ledger's storage in PostgreSQL: a pool is the [`Repository`], and its connections
+//! and transactions the [`Store`].
+
+use async_trait::async_trait;
+use bigdecimal::BigDecimal;
+use sqlx::{pool::PoolConnection, PgConnection, Pool, Postgres};
+use uuid::Uuid;
+
+use super::repository::{AccountRecord, Balance, NewAccount, NewTransaction, NewUser, Repository, Store, UserChanges, UserRecord};
+use super::transfers::Transaction;
+
+#[async_trait]
+impl Repository for Pool<Postgres> {
+    type Store = PgConnection;
+    type Conn = PoolConnection<Postgres>;
+    type Tx = sqlx::Transaction<'static, Postgres>;
+
+    async fn acquire(&self) -> anyhow::Result<Self::Conn> {
+        Ok(Pool::acquire(self).await?)
+    }
+
+    async fn begin(&self) -> anyhow::Result<Self::Tx> {
+        Ok(Pool::begin(self).await?)
+    }
+
+    async fn commit(tx: Self::Tx) -> anyhow::Result<()> {
+        Ok(tx.commit().await?)
+    }
+}
+
+#[async_trait]
+impl Store for PgConnection {
+    async fn email_taken(&mut self, email: &str) -> anyhow::Result<bool> {
+        let taken = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM users WHERE email = $1) AS "taken!""#,
+            email
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(taken)
+    }
+
+    async fn insert_user(&mut self, user: &NewUser) -> anyhow::Result<Uuid> {
+        let id = sqlx::query_scalar!(
+            "INSERT INTO users (full_name, email, password_hash) VALUES ($1, $2, $3) RETURNING id",
+            user.full_name,
+            user.email,
+            user.password_hash
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(id)
+    }
+
+    async fn find_user(&mut self, id: Uuid) -> anyhow::Result<Option<UserRecord>> {
+        let user = sqlx::query_as!(
+            UserRecord,
+            r#"
+            SELECT id, full_name, email, pending_email, email_verified_at IS NOT NULL AS "email_verified!", password_hash
+            FROM users WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(user)
+    }
+
+    async fn find_user_by_email(&mut self, email: &str) -> anyhow::Result<Option<UserRecord>> {
+        let user = sqlx::query_as!(
+            UserRecord,
+            r#"
+            SELECT id, full_name, email, pending_email, email_verified_at IS NOT NULL AS "email_verified!", password_hash
+            FROM users WHERE email = $1
+            "#,
+            email
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(user)
+    }
+
+    async fn has_external_identity(&mut self, user_id: Uuid) -> anyhow::Result<bool> {
+        let external = sqlx::query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM oidc_identities WHERE user_id = $1) AS "external!""#,
+            user_id
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(external)
+    }
+
+    async fn update_user(&mut self, id: Uuid, changes: &UserChanges) -> anyhow::Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE users SET
+                full_name = COALESCE($2, full_name),
+                pending_email = COALESCE($3, pending_email),
+                password_hash = COALESCE($4, password_hash)
+            WHERE id = $1
+            "#,
+            id,
+            changes.full_name,
+            changes.pending_email,
+            changes.password_hash
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn replace_password_hash(&mut self, id: Uuid, old_hash: &str, new_hash: &str) -> anyhow::Result<bool> {
+        let result = sqlx::query!(
+            "UPDATE users SET password_hash = $3 WHERE id = $1 AND password_hash = $2",
+            id,
+            old_hash,
+            new_hash
+        )
+        .execute(self)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn next_account_sequence(&mut self) -> anyhow::Result<i64> {
+        let sequence = sqlx::query_scalar!(r#"SELECT nextval('account_number_seq') AS "seq!""#)
+            .fetch_one(self)
+            .await?;
+
+        Ok(sequence)
+    }
+
+    async fn insert_account(&mut self, account: &NewAccount) -> anyhow::Result<Uuid> {
+        let id = sqlx::query_scalar!(
+            "INSERT INTO accounts (user_id, account_type, account_number, iban) VALUES ($1, $2, $3, $4) RETURNING id",
+            account.user_id,
+            account.account_type,
+            account.account_number,
+            account.iban
+        )
+        .fetch_one(&mut *self)
+        .await?;
+
+        sqlx::query!("INSERT INTO account_balances (account_id) VALUES ($1)", id)
+            .execute(&mut *self)
+            .await?;
+
+        Ok(id)
+    }
+
+    async fn find_account(&mut self, id: Uuid) -> anyhow::Result<Option<AccountRecord>> {
+        let account = sqlx::query_as!(
+            AccountRecord,
+            r#"
+            SELECT a.id, a.user_id, a.account_number, a.iban, a.account_type, u.full_name AS owner_name, b.balance
+            FROM accounts a
+            JOIN users u ON u.id = a.user_id
+            JOIN account_balances b ON b.account_id = a.id
+            WHERE a.id = $1
+            "#,
+            id
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(account)
+    }
+
+    async fn find_account_by_number(&mut self, number: &str) -> anyhow::Result<Option<AccountRecord>> {
+        let account = sqlx::query_as!(
+            AccountRecord,
+            r#"
+            SELECT a.id, a.user_id, a.account_number, a.iban, a.account_type, u.full_name AS owner_name, b.balance
+            FROM accounts a
+            JOIN users u ON u.id = a.user_id
+            JOIN account_balances b ON b.account_id = a.id
+            WHERE a.account_number = $1
+            "#,
+            number
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(account)
+    }
+
+    async fn find_account_by_iban(&mut self, iban: &str) -> anyhow::Result<Option<AccountRecord>> {
+        let account = sqlx::query_as!(
+            AccountRecord,
+            r#"
+            SELECT a.id, a.user_id, a.account_number, a.iban, a.account_type, u.full_name AS owner_name, b.balance
+            FROM accounts a
+            JOIN users u ON u.id = a.user_id
+            JOIN account_balances b ON b.account_id = a.id
+            WHERE a.iban = $1
+            "#,
+            iban
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(account)
+    }
+
+    async fn accounts_of(&mut self, user_id: Uuid) -> anyhow::Result<Vec<AccountRecord>> {
+        let accounts = sqlx::query_as!(
+            AccountRecord,
+            r#"
+            SELECT a.id, a.user_id, a.account_number, a.iban, a.account_type, u.full_name AS owner_name, b.balance
+            FROM accounts a
+            JOIN users u ON u.id = a.user_id
+            JOIN account_balances b ON b.account_id = a.id
+            WHERE a.user_id = $1
+            ORDER BY a.created_at
+            "#,
+            user_id
+        )
+        .fetch_all(self)
+        .await?;
+
+        Ok(accounts)
+    }
+
+    async fn accounts_without_iban(&mut self) -> anyhow::Result<Vec<AccountRecord>> {
+        let accounts = sqlx::query_as!(
+            AccountRecord,
+            r#"
+            SELECT a.id, a.user_id, a.account_number, a.iban, a.account_type, u.full_name AS owner_name, b.balance
+            FROM accounts a
+            JOIN users u ON u.id = a.user_id
+            JOIN account_balances b ON b.account_id = a.id
+            WHERE a.iban IS NULL
+            "#
+        )
+        .fetch_all(self)
+        .await?;
+
+        Ok(accounts)
+    }
+
+    async fn set_iban(&mut self, account_id: Uuid, iban: &str) -> anyhow::Result<()> {
+        sqlx::query!("UPDATE accounts SET iban = $1 WHERE id = $2", iban, account_id)
+            .execute(self)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn lock_balances(&mut self, account_ids: &[Uuid]) -> anyhow::Result<Vec<Balance>> {
+        let balances = sqlx::query_as!(
+            Balance,
+            "SELECT account_id, balance FROM account_balances WHERE account_id = ANY($1) ORDER BY account_id FOR UPDATE",
+            account_ids
+        )
+        .fetch_all(self)
+        .await?;
+
+        Ok(balances)
+    }
+
+    async fn set_balance(&mut self, account_id: Uuid, balance: &BigDecimal) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE account_balances SET balance = $1, updated_at = now() WHERE account_id = $2",
+            balance,
+            account_id
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn insert_transaction(&mut self, transaction: &NewTransaction) -> anyhow::Result<Uuid> {
+        let id = sqlx::query_scalar!(
+            "INSERT INTO transactions (from_account_id, to_account_id, amount, category) VALUES ($1, $2, $3, $4) RETURNING id",
+            transaction.from_account_id,
+            transaction.to_account_id,
+            transaction.amount,
+            transaction.category
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(id)
+    }
+
+    async fn find_transaction(&mut self, id: Uuid) -> anyhow::Result<Option<Transaction>> {
+        let transaction = sqlx::query_as!(
+            Transaction,
+            "SELECT id, from_account_id, to_account_id, amount, created_at, category FROM transactions WHERE id = $1",
+            id
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(transaction)
+    }
+
+    async fn transactions_of(&mut self, account_id: Uuid) -> anyhow::Result<Vec<Transaction>> {
+        let transactions = sqlx::query_as!(
+            Transaction,
+            r#"
+            SELECT id, from_account_id, to_account_id, amount, created_at, category FROM transactions
+            WHERE from_account_id = $1 OR to_account_id = $1
+            "#,
+            account_id
+        )
+        .fetch_all(self)
+        .await?;
+
+        Ok(transactions)
+    }
+
+    async fn all_transactions(&mut self) -> anyhow::Result<Vec<Transaction>> {
+        let transactions = sqlx::query_as!(
+            Transaction,
+            "SELECT id, from_account_id, to_account_id, amount, created_at, category FROM transactions"
+        )
+        .fetch_all(self)
+        .await?;
+
+        Ok(transactions)
+    }
+}
@@ -0,0 +1,188 @@
+//! The storage the ledger's services work through.
+//!
+//! A [`Repository`] hands out units of work. Everything read and written through one
+//! [`Tx`](Repository::Tx) is committed together or not at all, and balances locked with
+//! [`Store::lock_balances`] stay locked until then, so concurrent transfers out of the same
+//! account are serialized.
+
+use std::ops::DerefMut;
+
+use async_trait::async_trait;
+use bigdecimal::{BigDecimal, RoundingMode};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::transfers::Transaction;
+
+/// Amounts and balances are stored as `NUMERIC(20, 4)`: rounded half away from zero to four
+/// decimal places, with at most sixteen digits before the point. Backends without that type
+/// store what this returns, and refuse what it refuses.
+pub fn numeric(value: &BigDecimal) -> anyhow::Result<BigDecimal> {
+    let rounded = value.with_scale_round(4, RoundingMode::HalfUp);
+    let limit = BigDecimal::from(10_i64.pow(16));
+
+    if rounded.abs() >= limit {
+        anyhow::bail!("numeric field overflow: {} does not fit NUMERIC(20, 4)", value);
+    }
+
+    Ok(rounded)
+}
+
+/// The current time as precisely as PostgreSQL keeps it, to the microsecond, for backends
+/// that timestamp rows themselves.
+pub(crate) fn now() -> OffsetDateTime {
+    let now = OffsetDateTime::now_utc();
+
+    now.replace_nanosecond(now.nanosecond() / 1_000 * 1_000).unwrap_or(now)
+}
+
+#[async_trait]
+pub trait Repository: Clone + Send + Sync + 'static {
+    type Store: Store + ?Sized;
+    /// A connection for reads that need no unit of work
+    type Conn: DerefMut<Target = Self::Store> + Send;
+    /// A unit of work, rolled back if dropped without [`Repository::commit`]
+    type Tx: DerefMut<Target = Self::Store> + Send;
+
+    async fn acquire(&self) -> anyhow::Result<Self::Conn>;
+
+    async fn begin(&self) -> anyhow::Result<Self::Tx>;
+
+    async fn commit(tx: Self::Tx) -> anyhow::Result<()>;
+}
+
+/// Reads and writes of the ledger's users, accounts, balances and transactions.
+#[async_trait]
+pub trait Store: Send {
+    async fn email_taken(&mut self, email: &str) -> anyhow::Result<bool>;
+
+    async fn insert_user(&mut self, user: &NewUser) -> anyhow::Result<Uuid>;
+
+    async fn find_user(&mut self, id: Uuid) -> anyhow::Result<Option<UserRecord>>;
+
+    async fn find_user_by_email(&mut self, email: &str) -> anyhow::Result<Option<UserRecord>>;
+
+    /// Whether the user signs in through an identity provider rather than with a password.
+    async fn has_external_identity(&mut self, user_id: Uuid) -> anyhow::Result<bool>;
+
+    /// Applies the changes that are `Some`, leaving the other fields as they are.
+    async fn update_user(&mut self, id: Uuid, changes: &UserChanges) -> anyhow::Result<()>;
+
+    /// Replaces a password hash, unless it is no longer `old_hash`. Returns whether it was replaced.
+    async fn replace_password_hash(&mut self, id: Uuid, old_hash: &str, new_hash: &str) -> anyhow::Result<bool>;
+
+    /// The next number in the sequence account numbers and IBANs are derived from.
+    async fn next_account_sequence(&mut self) -> anyhow::Result<i64>;
+
+    /// Creates an account with a zero balance.
+    async fn insert_account(&mut self, account: &NewAccount) -> anyhow::Result<Uuid>;
+
+    async fn find_account(&mut self, id: Uuid) -> anyhow::Result<Option<AccountRecord>>;
+
+    async fn find_account_by_number(&mut self, number: &str) -> anyhow::Result<Option<AccountRecord>>;
+
+    async fn find_account_by_iban(&mut self, iban: &str) -> anyhow::Result<Option<AccountRecord>>;
+
+    /// A user's accounts, oldest first.
+    async fn accounts_of(&mut self, user_id: Uuid) -> anyhow::Result<Vec<AccountRecord>>;
+
+    async fn accounts_without_iban(&mut self) -> anyhow::Result<Vec<AccountRecord>>;
+
+    async fn set_iban(&mut self, account_id: Uuid, iban: &str) -> anyhow::Result<()>;
+
+    /// Locks the balances of the given accounts until the unit of work ends, always in
+    /// account id order so opposing transfers cannot deadlock. Unknown accounts are left out.
+    async fn lock_balances(&mut self, account_ids: &[Uuid]) -> anyhow::Result<Vec<Balance>>;
+
+    async fn set_balance(&mut self, account_id: Uuid, balance: &BigDecimal) -> anyhow::Result<()>;
+
+    async fn insert_transaction(&mut self, transaction: &NewTransaction) -> anyhow::Result<Uuid>;
+
+    async fn find_transaction(&mut self, id: Uuid) -> anyhow::Result<Option<Transaction>>;
+
+    /// Transactions into or out of an account.
+    async fn transactions_of(&mut self, account_id: Uuid) -> anyhow::Result<Vec<Transaction>>;
+
+    async fn all_transactions(&mut self) -> anyhow::Result<Vec<Transaction>>;
+}
+
+pub struct NewUser {
+    pub full_name: String,
+    pub email: String,
+    pub password_hash: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct UserRecord {
+    pub id: Uuid,
+    pub full_name: String,
+    pub email: String,
+    /// An email change waiting to be verified
+    pub pending_email: Option<String>,
+    pub email_verified: bool,
+    pub password_hash: String,
+}
+
+#[derive(Default)]
+pub struct UserChanges {
+    pub full_name: Option<String>,
+    pub pending_email: Option<String>,
+    pub password_hash: Option<String>,
+}
+
+pub struct NewAccount {
+    pub user_id: Uuid,
+    pub account_type: String,
+    pub account_number: String,
+    pub iban: Option<String>,
+}
+
+/// An account with its owner's name and current balance.
+#[derive(Clone, Debug)]
+pub struct AccountRecord {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub account_number: String,
+    pub iban: Option<String>,
+    pub account_type: String,
+    pub owner_name: String,
+    pub balance: BigDecimal,
+}
+
+#[derive(Clone, Debug)]
+pub struct Balance {
+    pub account_id: Uuid,
+    pub balance: BigDecimal,
+}
+
+pub struct NewTransaction {
+    pub from_account_id: Uuid,
+    pub to_account_id: Uuid,
+    pub amount: BigDecimal,
+    pub category: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn decimal(value: &str) -> BigDecimal {
+        BigDecimal::from_str(value).unwrap()
+    }
+
+    #[test]
+    fn numeric_rounds_half_away_from_zero() {
+        assert_eq!(numeric(&decimal("1.00005")).unwrap().to_string(), "1.0001");
+        assert_eq!(numeric(&decimal("-1.00005")).unwrap().to_string(), "-1.0001");
+        assert_eq!(numeric(&decimal("1.00004")).unwrap().to_string(), "1.0000");
+        assert_eq!(numeric(&decimal("7")).unwrap().to_string(), "7.0000");
+    }
+
+    #[test]
+    fn numeric_refuses_more_than_sixteen_integer_digits() {
+        assert!(numeric(&decimal("9999999999999999.9999")).is_ok());
+        assert!(numeric(&decimal("9999999999999999.99995")).is_err());
+        assert!(numeric(&decimal("-10000000000000000")).is_err());
+    }
+}
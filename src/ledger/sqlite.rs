@@ -0,0 +1,449 @@
+//! The ledger's storage in SQLite, for tools that embed the ledger on a single node: a pool
+//! is the [`Repository`], and its connections and transactions the [`Store`].
+//!
+//! Its schema is in `migrations/sqlite`, applied by [`connect`]. Amounts are kept as text,
+//! written by [`numeric_text`] and read back exactly, so balances match what PostgreSQL's
+//! `NUMERIC(20, 4)` would hold to the last digit.
+//!
+//! Units of work begin with `BEGIN IMMEDIATE`, which takes SQLite's write lock up front.
+//! That serializes them, as locking balance rows does in PostgreSQL, only for the whole
+//! database.
+//!
+//! Timestamps are text too, always written by [`timestamp`] or [`NOW`] in one fixed-width
+//! format, so that comparing them in SQL compares the times they hold.
+//!
+//! Only the ledger's own storage lives here. The api layer's, which shares these
+//! connections and units of work, is in `src/store/`, next to its PostgreSQL queries.
+
+use std::str::FromStr;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use bigdecimal::num_bigint::BigInt;
+use bigdecimal::{BigDecimal, Zero};
+use sqlx::pool::PoolConnection;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteRow};
+use sqlx::{Pool, Row, Sqlite, SqliteConnection};
+use time::{OffsetDateTime, UtcOffset};
+use uuid::Uuid;
+
+use super::repository::{now, numeric, AccountRecord, Balance, NewAccount, NewTransaction, NewUser, Repository, Store, UserChanges, UserRecord};
+use super::transfers::Transaction;
+
+/// Opens the SQLite database at `url`, e.g. `sqlite://ledger.db`, creating it if missing,
+/// and brings its schema up to date.
+pub async fn connect(url: &str) -> anyhow::Result<Pool<Sqlite>> {
+    let options = SqliteConnectOptions::from_str(url)
+        .with_context(|| format!("Invalid SQLite URL {}", url))?
+        .create_if_missing(true)
+        .journal_mode(SqliteJournalMode::Wal)
+        .foreign_keys(true);
+
+    let pool = SqlitePoolOptions::new()
+        .connect_with(options)
+        .await
+        .context("Failed to open SQLite database")?;
+
+    sqlx::migrate!("migrations/sqlite")
+        .run(&pool)
+        .await
+        .context("Failed to migrate SQLite database")?;
+
+    Ok(pool)
+}
+
+/// An amount as stored: a `NUMERIC(20, 4)` value written out in full, with exactly four
+/// decimal places and never in exponent notation.
+pub fn numeric_text(value: &BigDecimal) -> anyhow::Result<String> {
+    let (digits, scale) = numeric(value)?.as_bigint_and_exponent();
+    debug_assert_eq!(scale, 4);
+
+    let sign = if digits.sign() == bigdecimal::num_bigint::Sign::Minus { "-" } else { "" };
+    let digits = format!("{:05}", digits.magnitude());
+    let (whole, fraction) = digits.split_at(digits.len() - 4);
+
+    Ok(format!("{}{}.{}", sign, whole, fraction))
+}
+
+/// An amount as read back, in the form PostgreSQL hands out a `NUMERIC`: without the
+/// base-10000 digit groups of zeros at its end, so `100.0000` reads as `100` and `12.5000`
+/// as it is.
+pub(crate) fn decimal(text: &str) -> anyhow::Result<BigDecimal> {
+    let value = BigDecimal::from_str(text).with_context(|| format!("Malformed amount {:?} in the database", text))?;
+    let (mut digits, mut scale) = value.into_bigint_and_exponent();
+
+    if digits.is_zero() {
+        return Ok(BigDecimal::zero());
+    }
+
+    let group = BigInt::from(10_000);
+    while (&digits % &group).is_zero() {
+        digits /= &group;
+        scale -= 4;
+    }
+
+    Ok(BigDecimal::new(digits, scale))
+}
+
+/// The current time in SQL, as [`timestamp`] writes it, for rows SQLite timestamps itself.
+pub(crate) const NOW: &str = "strftime('%Y-%m-%dT%H:%M:%f000Z', 'now')";
+
+/// A time as stored: in UTC, to the microsecond and always the same width, so that
+/// timestamps sort and compare as text.
+pub(crate) fn timestamp(time: OffsetDateTime) -> String {
+    let time = time.to_offset(UtcOffset::UTC);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        time.year(),
+        u8::from(time.month()),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second(),
+        time.microsecond()
+    )
+}
+
+#[async_trait]
+impl Repository for Pool<Sqlite> {
+    type Store = SqliteConnection;
+    type Conn = PoolConnection<Sqlite>;
+    type Tx = sqlx::Transaction<'static, Sqlite>;
+
+    async fn acquire(&self) -> anyhow::Result<Self::Conn> {
+        Ok(Pool::acquire(self).await?)
+    }
+
+    async fn begin(&self) -> anyhow::Result<Self::Tx> {
+        Ok(Pool::begin_with(self, "BEGIN IMMEDIATE").await?)
+    }
+
+    async fn commit(tx: Self::Tx) -> anyhow::Result<()> {
+        Ok(tx.commit().await?)
+    }
+}
+
+const USER_COLUMNS: &str = "id, full_name, email, pending_email, email_verified_at IS NOT NULL AS email_verified, password_hash";
+
+const ACCOUNT_COLUMNS: &str = r#"
+    SELECT a.id, a.user_id, a.account_number, a.iban, a.account_type, u.full_name AS owner_name, b.balance
+    FROM accounts a
+    JOIN users u ON u.id = a.user_id
+    JOIN account_balances b ON b.account_id = a.id
+"#;
+
+const TRANSACTION_COLUMNS: &str = "SELECT id, from_account_id, to_account_id, amount, created_at, category FROM transactions";
+
+fn user(row: SqliteRow) -> anyhow::Result<UserRecord> {
+    Ok(UserRecord {
+        id: row.try_get("id")?,
+        full_name: row.try_get("full_name")?,
+        email: row.try_get("email")?,
+        pending_email: row.try_get("pending_email")?,
+        email_verified: row.try_get("email_verified")?,
+        password_hash: row.try_get("password_hash")?,
+    })
+}
+
+fn account(row: SqliteRow) -> anyhow::Result<AccountRecord> {
+    Ok(AccountRecord {
+        id: row.try_get("id")?,
+        user_id: row.try_get("user_id")?,
+        account_number: row.try_get("account_number")?,
+        iban: row.try_get("iban")?,
+        account_type: row.try_get("account_type")?,
+        owner_name: row.try_get("owner_name")?,
+        balance: decimal(row.try_get("balance")?)?,
+    })
+}
+
+fn transaction(row: SqliteRow) -> anyhow::Result<Transaction> {
+    Ok(Transaction {
+        id: row.try_get("id")?,
+        from_account_id: row.try_get("from_account_id")?,
+        to_account_id: row.try_get("to_account_id")?,
+        amount: decimal(row.try_get("amount")?)?,
+        created_at: row.try_get("created_at")?,
+        category: row.try_get("category")?,
+    })
+}
+
+#[async_trait]
+impl Store for SqliteConnection {
+    async fn email_taken(&mut self, email: &str) -> anyhow::Result<bool> {
+        let taken = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM users WHERE email = ?)")
+            .bind(email)
+            .fetch_one(self)
+            .await?;
+
+        Ok(taken)
+    }
+
+    async fn insert_user(&mut self, user: &NewUser) -> anyhow::Result<Uuid> {
+        let id = Uuid::new_v4();
+
+        sqlx::query("INSERT INTO users (id, full_name, email, password_hash) VALUES (?, ?, ?, ?)")
+            .bind(id)
+            .bind(&user.full_name)
+            .bind(&user.email)
+            .bind(&user.password_hash)
+            .execute(self)
+            .await?;
+
+        Ok(id)
+    }
+
+    async fn find_user(&mut self, id: Uuid) -> anyhow::Result<Option<UserRecord>> {
+        sqlx::query(&format!("SELECT {} FROM users WHERE id = ?", USER_COLUMNS))
+            .bind(id)
+            .fetch_optional(self)
+            .await?
+            .map(user)
+            .transpose()
+    }
+
+    async fn find_user_by_email(&mut self, email: &str) -> anyhow::Result<Option<UserRecord>> {
+        sqlx::query(&format!("SELECT {} FROM users WHERE email = ?", USER_COLUMNS))
+            .bind(email)
+            .fetch_optional(self)
+            .await?
+            .map(user)
+            .transpose()
+    }
+
+    async fn has_external_identity(&mut self, user_id: Uuid) -> anyhow::Result<bool> {
+        let external = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM oidc_identities WHERE user_id = ?)")
+            .bind(user_id)
+            .fetch_one(self)
+            .await?;
+
+        Ok(external)
+    }
+
+    async fn update_user(&mut self, id: Uuid, changes: &UserChanges) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE users SET
+                full_name = COALESCE(?, full_name),
+                pending_email = COALESCE(?, pending_email),
+                password_hash = COALESCE(?, password_hash)
+            WHERE id = ?
+            "#
+        )
+        .bind(&changes.full_name)
+        .bind(&changes.pending_email)
+        .bind(&changes.password_hash)
+        .bind(id)
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn replace_password_hash(&mut self, id: Uuid, old_hash: &str, new_hash: &str) -> anyhow::Result<bool> {
+        let result = sqlx::query("UPDATE users SET password_hash = ? WHERE id = ? AND password_hash = ?")
+            .bind(new_hash)
+            .bind(id)
+            .bind(old_hash)
+            .execute(self)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn next_account_sequence(&mut self) -> anyhow::Result<i64> {
+        let sequence = sqlx::query_scalar("UPDATE account_number_seq SET value = value + 1 RETURNING value")
+            .fetch_one(self)
+            .await?;
+
+        Ok(sequence)
+    }
+
+    async fn insert_account(&mut self, account: &NewAccount) -> anyhow::Result<Uuid> {
+        let id = Uuid::new_v4();
+
+        sqlx::query("INSERT INTO accounts (id, user_id, account_type, account_number, iban) VALUES (?, ?, ?, ?, ?)")
+            .bind(id)
+            .bind(account.user_id)
+            .bind(&account.account_type)
+            .bind(&account.account_number)
+            .bind(&account.iban)
+            .execute(&mut *self)
+            .await?;
+
+        sqlx::query("INSERT INTO account_balances (account_id) VALUES (?)")
+            .bind(id)
+            .execute(&mut *self)
+            .await?;
+
+        Ok(id)
+    }
+
+    async fn find_account(&mut self, id: Uuid) -> anyhow::Result<Option<AccountRecord>> {
+        sqlx::query(&format!("{} WHERE a.id = ?", ACCOUNT_COLUMNS))
+            .bind(id)
+            .fetch_optional(self)
+            .await?
+            .map(account)
+            .transpose()
+    }
+
+    async fn find_account_by_number(&mut self, number: &str) -> anyhow::Result<Option<AccountRecord>> {
+        sqlx::query(&format!("{} WHERE a.account_number = ?", ACCOUNT_COLUMNS))
+            .bind(number)
+            .fetch_optional(self)
+            .await?
+            .map(account)
+            .transpose()
+    }
+
+    async fn find_account_by_iban(&mut self, iban: &str) -> anyhow::Result<Option<AccountRecord>> {
+        sqlx::query(&format!("{} WHERE a.iban = ?", ACCOUNT_COLUMNS))
+            .bind(iban)
+            .fetch_optional(self)
+            .await?
+            .map(account)
+            .transpose()
+    }
+
+    async fn accounts_of(&mut self, user_id: Uuid) -> anyhow::Result<Vec<AccountRecord>> {
+        // Rowids grow in the order accounts were opened, even within one millisecond
+        sqlx::query(&format!("{} WHERE a.user_id = ? ORDER BY a.rowid", ACCOUNT_COLUMNS))
+            .bind(user_id)
+            .fetch_all(self)
+            .await?
+            .into_iter()
+            .map(account)
+            .collect()
+    }
+
+    async fn accounts_without_iban(&mut self) -> anyhow::Result<Vec<AccountRecord>> {
+        sqlx::query(&format!("{} WHERE a.iban IS NULL", ACCOUNT_COLUMNS))
+            .fetch_all(self)
+            .await?
+            .into_iter()
+            .map(account)
+            .collect()
+    }
+
+    async fn set_iban(&mut self, account_id: Uuid, iban: &str) -> anyhow::Result<()> {
+        sqlx::query("UPDATE accounts SET iban = ? WHERE id = ?")
+            .bind(iban)
+            .bind(account_id)
+            .execute(self)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn lock_balances(&mut self, account_ids: &[Uuid]) -> anyhow::Result<Vec<Balance>> {
+        // A unit of work already holds the write lock, see BEGIN IMMEDIATE above
+        let mut balances = Vec::new();
+
+        for account_id in account_ids {
+            let balance: Option<String> = sqlx::query_scalar("SELECT balance FROM account_balances WHERE account_id = ?")
+                .bind(account_id)
+                .fetch_optional(&mut *self)
+                .await?;
+
+            if let Some(balance) = balance {
+                balances.push(Balance { account_id: *account_id, balance: decimal(&balance)? });
+            }
+        }
+
+        balances.sort_by_key(|balance| balance.account_id);
+        balances.dedup_by_key(|balance| balance.account_id);
+
+        Ok(balances)
+    }
+
+    async fn set_balance(&mut self, account_id: Uuid, balance: &BigDecimal) -> anyhow::Result<()> {
+        sqlx::query(&format!("UPDATE account_balances SET balance = ?, updated_at = {} WHERE account_id = ?", NOW))
+            .bind(numeric_text(balance)?)
+            .bind(account_id)
+            .execute(self)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn insert_transaction(&mut self, transaction: &NewTransaction) -> anyhow::Result<Uuid> {
+        let id = Uuid::new_v4();
+
+        sqlx::query("INSERT INTO transactions (id, from_account_id, to_account_id, amount, category, created_at) VALUES (?, ?, ?, ?, ?, ?)")
+            .bind(id)
+            .bind(transaction.from_account_id)
+            .bind(transaction.to_account_id)
+            .bind(numeric_text(&transaction.amount)?)
+            .bind(&transaction.category)
+            .bind(timestamp(now()))
+            .execute(self)
+            .await?;
+
+        Ok(id)
+    }
+
+    async fn find_transaction(&mut self, id: Uuid) -> anyhow::Result<Option<Transaction>> {
+        sqlx::query(&format!("{} WHERE id = ?", TRANSACTION_COLUMNS))
+            .bind(id)
+            .fetch_optional(self)
+            .await?
+            .map(transaction)
+            .transpose()
+    }
+
+    async fn transactions_of(&mut self, account_id: Uuid) -> anyhow::Result<Vec<Transaction>> {
+        sqlx::query(&format!("{} WHERE from_account_id = ?1 OR to_account_id = ?1", TRANSACTION_COLUMNS))
+            .bind(account_id)
+            .fetch_all(self)
+            .await?
+            .into_iter()
+            .map(transaction)
+            .collect()
+    }
+
+    async fn all_transactions(&mut self) -> anyhow::Result<Vec<Transaction>> {
+        sqlx::query(TRANSACTION_COLUMNS)
+            .fetch_all(self)
+            .await?
+            .into_iter()
+            .map(transaction)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(value: &str) -> String {
+        numeric_text(&BigDecimal::from_str(value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn amounts_are_written_out_in_full() {
+        assert_eq!(text("0"), "0.0000");
+        assert_eq!(text("0.0001"), "0.0001");
+        assert_eq!(text("-0.5"), "-0.5000");
+        assert_eq!(text("12.34565"), "12.3457");
+        assert_eq!(text("1e3"), "1000.0000");
+        assert_eq!(text("9999999999999999.9999"), "9999999999999999.9999");
+        assert!(numeric_text(&BigDecimal::from_str("1e16").unwrap()).is_err());
+    }
+
+    #[test]
+    fn amounts_read_back_as_postgres_returns_them() {
+        assert_eq!(decimal("100.0000").unwrap().to_string(), "100");
+        assert_eq!(decimal("12.5000").unwrap().to_string(), "12.5000");
+        assert_eq!(decimal("0.0000").unwrap().to_string(), "0");
+        assert_eq!(decimal("-3.0100").unwrap().to_string(), "-3.0100");
+    }
+
+    #[test]
+    fn amounts_read_back_exactly() {
+        for value in ["0.0001", "-7.1000", "123456789012.3456", "0.1000"] {
+            assert_eq!(decimal(&text(value)).unwrap(), BigDecimal::from_str(value).unwrap());
+        }
+    }
+}
@@ -0,0 +1,173 @@
+//! Transfers between accounts: resolving who is paid, and moving the money.
+//!
+//! Posting is split in two so that callers can check a transfer against other rules, such
+//! as limits, while both balances are locked: [`TransferService::lock`] takes the locks and
+//! [`TransferService::post`] records the transfer and moves the money, in the same unit of work.
+
+use std::sync::Arc;
+
+use anyhow::Context;
+use bigdecimal::{BigDecimal, Signed};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use utoipa::ToSchema;
+use uuid::Uuid;
+use validator::{ValidationError, ValidationErrors};
+
+use crate::config::Config;
+use crate::error::LedgerError;
+use crate::openapi;
+
+use super::accounts::AccountService;
+use super::repository::{Balance, NewTransaction, Repository, Store};
+
+/// A transfer with its counterparty resolved to an account, ready to be posted.
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct Transfer {
+    pub(crate) from_account_id: Uuid,
+    pub(crate) to_account_id: Uuid,
+    #[schema(value_type = String)]
+    pub(crate) amount: BigDecimal,
+    pub(crate) category: Option<String>,
+}
+
+/// A posted transfer.
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct Transaction {
+    pub(crate) id: Uuid,
+    pub(crate) from_account_id: Uuid,
+    pub(crate) to_account_id: Uuid,
+    #[schema(value_type = String)]
+    pub(crate) amount: BigDecimal,
+    #[schema(value_type = Option<openapi::CompactTimestamp>)]
+    pub(crate) created_at: Option<OffsetDateTime>,
+    pub(crate) category: Option<String>,
+}
+
+/// Who a transfer pays, as the payer named them.
+pub enum Counterparty {
+    Account(Uuid),
+    /// An account number, checked by its check digit
+    AccountNumber(String),
+    /// An IBAN, checked by mod-97
+    Iban(String),
+}
+
+/// Both balances of a transfer, locked by [`TransferService::lock`].
+pub struct Locked {
+    from: Balance,
+    to: Balance,
+}
+
+pub(crate) fn same_account() -> ValidationError {
+    ValidationError::new("same_account").with_message("Cannot transfer from an account to itself".into())
+}
+
+pub struct TransferService<R> {
+    repo: R,
+    config: Arc<Config>,
+}
+
+impl<R: Repository> TransferService<R> {
+    pub fn new(repo: R, config: Arc<Config>) -> Self {
+        TransferService { repo, config }
+    }
+
+    /// Resolves the counterparty of a transfer to an account.
+    pub async fn prepare(
+        &self,
+        from_account_id: Uuid,
+        to: Counterparty,
+        amount: BigDecimal,
+        category: Option<String>
+    ) -> Result<Transfer, LedgerError> {
+        let accounts = AccountService::new(self.repo.clone(), self.config.clone());
+
+        let to_account_id = match to {
+            Counterparty::Account(account_id) => account_id,
+            Counterparty::AccountNumber(number) => accounts.id_for_number(&number).await?,
+            Counterparty::Iban(iban) => accounts.id_for_iban(&iban).await?,
+        };
+
+        // An account number or IBAN can name the sending account as well
+        if to_account_id == from_account_id {
+            let mut errors = ValidationErrors::new();
+            errors.add("__all__", same_account());
+            return Err(errors.into());
+        }
+
+        Ok(Transfer { from_account_id, to_account_id, amount, category })
+    }
+
+    /// Locks the balance rows of both sides of a transfer for the rest of the unit of work.
+    pub async fn lock(&self, store: &mut R::Store, transfer: &Transfer) -> Result<Locked, LedgerError> {
+        let mut balances = store.lock_balances(&[transfer.from_account_id, transfer.to_account_id]).await
+            .context("Failed to fetch account balances")?;
+
+        let from_pos = balances.iter().position(|b| b.account_id == transfer.from_account_id)
+            .ok_or_else(|| LedgerError::AccountNotFound(format!("Source account {} not found", transfer.from_account_id)))?;
+        let from = balances.remove(from_pos);
+
+        let to = balances.into_iter().find(|b| b.account_id == transfer.to_account_id)
+            .ok_or_else(|| LedgerError::AccountNotFound(format!("Destination account {} not found", transfer.to_account_id)))?;
+
+        Ok(Locked { from, to })
+    }
+
+    /// Records a transfer and moves the money between the balances locked for it. Both
+    /// balances must stay above zero.
+    pub async fn post(&self, store: &mut R::Store, locked: Locked, transfer: &Transfer) -> Result<Uuid, LedgerError> {
+        let new_from_balance = locked.from.balance - &transfer.amount;
+        let new_to_balance = locked.to.balance + &transfer.amount;
+
+        if !new_from_balance.is_positive() || !new_to_balance.is_positive() {
+            return Err(LedgerError::InsufficientFunds(format!("Insufficient balance for transaction. From account balance would be {new_from_balance}, to account balance would be {new_to_balance}")));
+        }
+
+        let transaction_id = store.insert_transaction(&NewTransaction {
+            from_account_id: transfer.from_account_id,
+            to_account_id: transfer.to_account_id,
+            amount: transfer.amount.clone(),
+            category: transfer.category.clone(),
+        }).await
+         .context("Failed to create transaction")?;
+
+        store.set_balance(locked.from.account_id, &new_from_balance).await
+            .context("Failed to update source account balance")?;
+        store.set_balance(locked.to.account_id, &new_to_balance).await
+            .context("Failed to update destination account balance")?;
+
+        Ok(transaction_id)
+    }
+
+    pub async fn find(&self, id: Uuid) -> Result<Option<Transaction>, LedgerError> {
+        let mut conn = self.repo.acquire().await
+            .context("Failed to connect to the database")?;
+
+        let transaction = conn.find_transaction(id).await
+            .context("Failed to fetch transaction")?;
+
+        Ok(transaction)
+    }
+
+    /// Every transaction into or out of an account.
+    pub async fn for_account(&self, account_id: Uuid) -> Result<Vec<Transaction>, LedgerError> {
+        let mut conn = self.repo.acquire().await
+            .context("Failed to connect to the database")?;
+
+        let transactions = conn.transactions_of(account_id).await
+            .context("Failed to fetch transactions")?;
+
+        Ok(transactions)
+    }
+
+    pub async fn all(&self) -> Result<Vec<Transaction>, LedgerError> {
+        let mut conn = self.repo.acquire().await
+            .context("Failed to connect to the database")?;
+
+        let transactions = conn.all_transactions().await
+            .context("Failed to fetch transactions")?;
+
+        Ok(transactions)
+    }
+}
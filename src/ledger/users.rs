@@ -0,0 +1,273 @@
+//! Users: registering them with their first account, checking their passwords, and
+//! changing their profiles.
+
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::error::{LedgerError, Problem};
+use crate::password::hash;
+
+use super::accounts::{AccountService, OpenedAccount, Types};
+use super::repository::{NewUser, Repository, Store, UserChanges, UserRecord};
+
+/// Checks a password a user is choosing against the configured policy. `user_inputs`
+/// are their name, email and the like, which make poor passwords. A weak password is
+/// refused with every rule it breaks.
+pub(crate) async fn check_password(config: &Config, password: &str, user_inputs: &[&str]) -> Result<(), LedgerError> {
+    let violations = config.password_policy().check(password, user_inputs).await
+        .context("Failed to check breached passwords")?;
+
+    if violations.is_empty() {
+        return Ok(());
+    }
+
+    let violations: Vec<_> = violations.iter().map(|violation| {
+        let mut detail = serde_json::to_value(violation).unwrap_or_default();
+        detail["message"] = json!(violation.to_string());
+        detail
+    }).collect();
+
+    Err(Problem::new(StatusCode::UNPROCESSABLE_ENTITY, "weak_password", "Password does not meet the password policy")
+        .with("violations", violations)
+        .into())
+}
+
+pub struct Registration {
+    pub full_name: String,
+    pub email: String,
+    pub password: String,
+    pub account_type: Types,
+}
+
+pub struct Registered {
+    pub user_id: Uuid,
+    pub account: OpenedAccount,
+}
+
+/// A user whose password was right.
+pub struct Authenticated {
+    pub user_id: Uuid,
+    /// Staff sign in through the identity provider, not with a password
+    pub staff: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ProfileAccount {
+    account_id: Uuid,
+    account_number: String,
+    iban: Option<String>,
+    account_type: Types,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct Profile {
+    user_id: Uuid,
+    full_name: String,
+    email: String,
+    /// An email change waiting to be verified
+    pending_email: Option<String>,
+    email_verified: bool,
+    accounts: Vec<ProfileAccount>,
+}
+
+/// Changes to a user's profile. A new email only becomes pending; it replaces the current
+/// one once verified.
+#[derive(Default)]
+pub struct ProfileUpdate {
+    pub full_name: Option<String>,
+    pub new_email: Option<String>,
+    pub password: Option<String>,
+}
+
+pub struct UserService<R> {
+    repo: R,
+    config: Arc<Config>,
+}
+
+impl<R: Repository> UserService<R> {
+    pub fn new(repo: R, config: Arc<Config>) -> Self {
+        UserService { repo, config }
+    }
+
+    /// Registers a user together with their first account.
+    pub async fn register(&self, registration: Registration) -> Result<Registered, LedgerError> {
+        let Registration { full_name, email, password, account_type } = registration;
+
+        let mut conn = self.repo.acquire().await
+            .context("Failed to connect to the database")?;
+        let taken = conn.email_taken(&email).await
+            .context("Database error")?;
+        drop(conn);
+
+        if taken {
+            return Err(LedgerError::Conflict(format!("User with email {} already exists", email)));
+        }
+
+        check_password(&self.config, &password, &[&full_name, &email]).await?;
+
+        let password_hash = self.config.password_hasher().hash_blocking(&password).await
+            .context("Failed to hash password")?;
+
+        let mut tx = self.repo.begin().await
+            .context("Failed to start transaction")?;
+
+        let user_id = tx.insert_user(&NewUser { full_name, email, password_hash }).await
+            .context("Failed to create user")?;
+
+        let account = AccountService::new(self.repo.clone(), self.config.clone())
+            .open(&mut tx, user_id, &account_type).await?;
+
+        R::commit(tx).await
+            .context("Failed to commit transaction")?;
+
+        Ok(Registered { user_id, account })
+    }
+
+    /// Checks an email and password, upgrading the stored hash if it is outdated. Returns
+    /// `None` for a wrong password and an unknown email alike.
+    pub async fn authenticate(&self, email: &str, password: &str) -> Result<Option<Authenticated>, LedgerError> {
+        let mut conn = self.repo.acquire().await
+            .context("Failed to connect to the database")?;
+
+        let user = conn.find_user_by_email(email).await
+            .context("Database error")?;
+
+        let hasher = self.config.password_hasher();
+
+        // Unknown emails still pay for hashing, so timing does not reveal which exist
+        let verified = match &user {
+            Some(user) => hash::verify_blocking(password, &user.password_hash).await,
+            None => hasher.hash_blocking(password).await.map(|_| false),
+        }
+        .context("Failed to verify password")?;
+
+        let user = match user {
+            Some(user) if verified => user,
+            _ => return Ok(None),
+        };
+
+        let staff = conn.has_external_identity(user.id).await
+            .context("Database error")?;
+
+        // The password is at hand, so upgrade bcrypt or outdated Argon2 hashes now
+        if hasher.needs_rehash(&user.password_hash) {
+            self.rehash(&mut conn, &user, password).await;
+        }
+
+        Ok(Some(Authenticated { user_id: user.id, staff }))
+    }
+
+    /// Replaces the user's hash with a current one, unless the password changed meanwhile.
+    /// A failure is only logged: the old hash still works, and the next login tries again.
+    async fn rehash(&self, store: &mut R::Store, user: &UserRecord, password: &str) {
+        let new_hash = match self.config.password_hasher().hash_blocking(password).await {
+            Ok(new_hash) => new_hash,
+            Err(e) => {
+                eprintln!("Failed to rehash password for user {}: {}", user.id, e);
+                return;
+            }
+        };
+
+        if let Err(e) = store.replace_password_hash(user.id, &user.password_hash, &new_hash).await {
+            eprintln!("Failed to store rehashed password for user {}: {}", user.id, e);
+        }
+    }
+
+    pub async fn find(&self, user_id: Uuid) -> Result<UserRecord, LedgerError> {
+        let mut conn = self.repo.acquire().await
+            .context("Failed to connect to the database")?;
+
+        let user = conn.find_user(user_id).await
+            .context("Database error")?
+            .ok_or_else(|| LedgerError::NotFound(format!("User {} not found", user_id)))?;
+
+        Ok(user)
+    }
+
+    pub async fn verify_password(&self, user: &UserRecord, password: &str) -> Result<bool, LedgerError> {
+        let verified = hash::verify_blocking(password, &user.password_hash).await
+            .context("Failed to verify password")?;
+
+        Ok(verified)
+    }
+
+    /// Checks changes to `user`'s profile against the password policy and the emails in
+    /// use, and hashes a new password, ready for [`UserService::apply_update`].
+    pub async fn prepare_update(&self, user: &UserRecord, update: ProfileUpdate) -> Result<UserChanges, LedgerError> {
+        if let Some(password) = &update.password {
+            let full_name = update.full_name.as_deref().unwrap_or(&user.full_name);
+            let email = update.new_email.as_deref().unwrap_or(&user.email);
+            check_password(&self.config, password, &[full_name, email, &user.email]).await?;
+        }
+
+        if let Some(email) = &update.new_email {
+            let mut conn = self.repo.acquire().await
+                .context("Failed to connect to the database")?;
+
+            let taken = conn.email_taken(email).await
+                .context("Database error")?;
+
+            if taken {
+                return Err(LedgerError::Conflict(format!("User with email {} already exists", email)));
+            }
+        }
+
+        let password_hash = match &update.password {
+            Some(password) => Some(
+                self.config.password_hasher().hash_blocking(password).await
+                    .context("Failed to hash password")?
+            ),
+            None => None,
+        };
+
+        Ok(UserChanges {
+            full_name: update.full_name,
+            pending_email: update.new_email,
+            password_hash,
+        })
+    }
+
+    /// Stores changes from [`UserService::prepare_update`] in the caller's unit of work.
+    pub async fn apply_update(&self, store: &mut R::Store, user_id: Uuid, changes: &UserChanges) -> Result<(), LedgerError> {
+        store.update_user(user_id, changes).await
+            .context("Failed to update user")?;
+
+        Ok(())
+    }
+
+    /// A user's profile and accounts.
+    pub async fn profile(&self, user_id: Uuid) -> Result<Profile, LedgerError> {
+        let mut conn = self.repo.acquire().await
+            .context("Failed to connect to the database")?;
+
+        let user = conn.find_user(user_id).await
+            .context("Database error")?
+            .ok_or_else(|| LedgerError::NotFound(format!("User {} not found", user_id)))?;
+
+        let accounts = conn.accounts_of(user_id).await
+            .context("Failed to fetch accounts")?;
+
+        let accounts = accounts.into_iter().map(|account| ProfileAccount {
+            account_id: account.id,
+            account_number: account.account_number,
+            iban: account.iban,
+            account_type: Types::from_stored(&account.account_type),
+        }).collect();
+
+        Ok(Profile {
+            user_id,
+            full_name: user.full_name,
+            email: user.email,
+            pending_email: user.pending_email,
+            email_verified: user.email_verified,
+            accounts,
+        })
+    }
+}
@@ -1,25 +1,89 @@
-use axum::{
-    routing::{get, post},
-    Router,
-};
+use axum::{routing::get, Router};
+use utoipa::OpenApi;
+use utoipa_axum::{router::OpenApiRouter, routes};
+use utoipa_swagger_ui::SwaggerUi;
 
+pub mod account_number;
 pub mod api;
+pub mod banking;
+pub mod cli;
+pub mod config;
+pub mod error;
+pub mod keys;
+pub mod ledger;
+pub mod mailer;
 pub mod middleware;
+pub mod oidc;
+pub mod openapi;
+pub mod password;
+pub mod risk;
+pub mod service_accounts;
 pub mod state;
+pub mod store;
+pub mod totp;
 
 pub fn app(state: state::AppState) -> Router {
+    let (api, openapi) = OpenApiRouter::with_openapi(openapi::ApiDoc::openapi())
+        .routes(routes!(api::user::register))
+        .routes(routes!(api::user::get_me, api::user::update_me))
+        .routes(routes!(api::user::login))
+        .routes(routes!(api::user::login_2fa))
+        .routes(routes!(api::two_factor::enroll))
+        .routes(routes!(api::two_factor::confirm))
+        .routes(routes!(api::two_factor::disable))
+        .routes(routes!(api::two_factor::step_up))
+        .routes(routes!(api::verification::forgot_password))
+        .routes(routes!(api::verification::reset_password))
+        .routes(routes!(api::verification::verify_email))
+        .routes(routes!(api::verification::resend_verification))
+        .routes(routes!(api::oidc::login))
+        .routes(routes!(api::oidc::callback))
+        .routes(routes!(api::session::refresh))
+        .routes(routes!(api::session::logout))
+        .routes(routes!(api::session::get_all))
+        .routes(routes!(api::session::revoke))
+        .routes(routes!(api::session::revoke_all_others))
+        .routes(routes!(api::transaction::create))
+        .routes(routes!(api::transaction::get_all))
+        .routes(routes!(api::transaction::query))
+        .routes(routes!(api::account::check_balance))
+        .routes(routes!(api::account::lookup))
+        .routes(routes!(api::limit::get))
+        .routes(routes!(api::account::adjust_balance))
+        .routes(routes!(api::approval::get_all))
+        .routes(routes!(api::approval::approve))
+        .routes(routes!(api::approval::reject))
+        .routes(routes!(api::limit::set))
+        .routes(routes!(api::limit::create_override))
+        .routes(routes!(api::login_throttle::unlock))
+        .routes(routes!(api::review::get_all))
+        .routes(routes!(api::review::approve))
+        .routes(routes!(api::review::reject))
+        .routes(routes!(api::budget::create))
+        .routes(routes!(api::budget::get_all))
+        .routes(routes!(api::budget::delete))
+        .routes(routes!(api::payee::create))
+        .routes(routes!(api::payee::get_all))
+        .routes(routes!(api::payee::update))
+        .routes(routes!(api::payee::delete))
+        .routes(routes!(api::notification::get_all))
+        .routes(routes!(api::notification::mark_read))
+        .routes(routes!(api::v2::create_transfer))
+        .routes(routes!(api::v2::get_transfer))
+        .routes(routes!(api::v2::get_account))
+        .routes(routes!(api::v2::list_account_transactions))
+        .routes(routes!(api::v2::update_me))
+        .split_for_parts();
+
     Router::new()
         .route("/", get(|| async { "Hello, World!" }))
-        .route("/api/v1/user/register", post(api::user::register))
-        .route("/api/v1/user/updateProfile", get(api::user::update_profile))
-        .route("/api/v1/user/login", post(api::user::login))
-        .route("/api/v1/transaction/create", post(api::transaction::create))
-        .route("/api/v1/transaction/all", get(api::transaction::get_all))
-        .route("/api/v1/transaction/query", get(api::transaction::query))
-        .route("/api/v1/account/checkBalance", get(api::account::check_balance))
-        .route("/api/v1/account/updateBalance", post(api::account::update_balance))
-        .with_state(state)
-        .layer(axum::middleware::from_fn(
+        .route("/.well-known/jwks.json", get(api::session::jwks))
+        .merge(api)
+        .merge(SwaggerUi::new(openapi::DOCS_PATH).url(openapi::SPEC_PATH, openapi::with_problems(openapi)))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
             middleware::auth::auth,
         ))
-}
\ No newline at end of file
+        .layer(axum::middleware::from_fn(middleware::correlation::correlate))
+        .with_state(state)
+}
@@ -1,6 +1,9 @@
+use std::net::SocketAddr;
+
+use clap::Parser;
 use dotenv::dotenv;
-use rusty_ledger::{app, state};
-use sqlx::postgres::PgPoolOptions;
+use rusty_ledger::{app, cli, state};
+use rusty_ledger::store::Database;
 
 
 #[tokio::main]
@@ -8,13 +11,25 @@ async fn main() -> anyhow::Result<()> {
 
     dotenv().ok();
 
+    let cli = cli::Cli::parse();
+
     let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL is not defined");
 
-    let db = PgPoolOptions::new().max_connections(5).connect(&database_url).await?;
+    let db = Database::connect(&database_url).await?;
+
+    match cli.command {
+        None | Some(cli::Command::Serve) => {}
+        Some(command) => return cli::run(command, &db).await,
+    }
+
+    let state = state::AppState::new(db);
 
-    let state = state::AppState{db};
+    let assigned = state.accounts().assign_missing_ibans().await?;
+    if assigned > 0 {
+        println!("Assigned IBANs to {} existing accounts", assigned);
+    }
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app(state)).await.unwrap();
+    axum::serve(listener, app(state).into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
     Ok(())
 }
\ No newline at end of file
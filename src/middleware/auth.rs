@@ -1,71 +1,212 @@
+use std::convert::Infallible;
+
+use anyhow::Context;
 use axum::{
-    body::Body, http::{header, Request, StatusCode}, middleware::Next, response::Response
+    body::Body, extract::{FromRequestParts, OptionalFromRequestParts, State}, http::{header, request::Parts, Request}, middleware::Next, response::Response
 };
-use jsonwebtoken::{decode, DecodingKey, Validation};
 use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::error::LedgerError;
+use crate::ledger::Repository;
+use crate::openapi;
+use crate::service_accounts::{self, Scope, KEY_PREFIX};
+use crate::state::AppState;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,  // user id
     pub exp: usize,   // expiration time
+    pub jti: Uuid,    // token id, checked against the revocation list
+    pub sid: Uuid,    // session the token was issued for
+}
+
+/// The session behind the access token of the current request.
+#[derive(Debug, Clone, Copy)]
+pub struct CurrentSession {
+    pub id: Uuid,
+    pub jti: Uuid,
+    pub expires_at: OffsetDateTime,
 }
 
+/// Paths reachable without an access token.
+const PUBLIC_PATHS: &[&str] = &[
+    "/",
+    "/api/v1/user/register",
+    "/api/v1/user/login",
+    "/api/v1/user/login/2fa",
+    "/api/v1/user/refresh",
+    "/api/v1/user/forgotPassword",
+    "/api/v1/user/resetPassword",
+    "/api/v1/user/verifyEmail",
+    "/api/v1/oidc/login",
+    "/api/v1/oidc/callback",
+    "/.well-known/jwks.json",
+    openapi::SPEC_PATH,
+];
+
 pub async fn auth(
+    State(state): State<AppState>,
     req: Request<Body>,
     next: Next,
-) -> Result<Response, StatusCode> {
-    // Skip auth for registration and login
+) -> Result<Response, LedgerError> {
+    // Skip auth for registration, login and refreshing, which carry their own credentials,
+    // and for the API docs
     let path = req.uri().path();
-    if path == "/api/v1/user/register" || path == "/api/v1/user/login" || path == "/" {
+    if PUBLIC_PATHS.contains(&path) || path.starts_with(openapi::DOCS_PATH) {
         return Ok(next.run(req).await);
     }
 
-    // Print all headers for debugging
-    println!("Received headers:");
-    for (name, value) in req.headers() {
-        println!("  {}: {}", name, value.to_str().unwrap_or("<invalid>"));
-    }
-    
     // Get the authorization header
     let auth_header = req
         .headers()
         .get(header::AUTHORIZATION)
-        .ok_or_else(|| {
-            println!("headers: {:?}", req.headers().keys());
-            let has = req.headers().contains_key(header::AUTHORIZATION);
-            println!("has: {:?}", has);
-            println!("No authorization header found");
-            StatusCode::UNAUTHORIZED
-        })?
+        .ok_or_else(|| LedgerError::Unauthorized("Missing authorization header".to_string()))?
         .to_str()
-        .map_err(|_| {
-            println!("Invalid authorization header value");
-            StatusCode::UNAUTHORIZED
-        })?;
+        .map_err(|_| LedgerError::Unauthorized("Invalid authorization header".to_string()))?;
 
     // Extract the token from the Bearer header
     let token = auth_header
         .strip_prefix("Bearer ")
-        .ok_or_else(|| {
-            println!("No Bearer token found in authorization header");
-            StatusCode::UNAUTHORIZED
-        })?;
+        .ok_or_else(|| LedgerError::Unauthorized("Expected a bearer token".to_string()))?;
+
+    // API keys of service accounts, only on the routes their scopes cover
+    if token.starts_with(KEY_PREFIX) {
+        let principal = service_accounts::authenticate(&state.db, token).await
+            .context("Failed to check API key")?
+            .ok_or_else(|| LedgerError::Unauthorized("Unknown, expired or revoked API key".to_string()))?;
+
+        let required = Scope::required_for(req.method().as_str(), req.uri().path());
+        if !required.is_some_and(|scope| principal.scopes.contains(&scope)) {
+            return Err(LedgerError::Forbidden("The API key has no scope for this route".to_string()));
+        }
+
+        let mut req = req;
+        req.extensions_mut().insert(principal.owner_id.to_string());
+        req.extensions_mut().insert(principal);
+        return Ok(next.run(req).await);
+    }
 
     // Decode and validate the token
-    let claims = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(std::env::var("JWT_SECRET_KEY").expect("JWT_SECRET_KEY is not defined").as_bytes()),
-        &Validation::default(),
-    )
-    // .unwrap()
-    .map_err(|_| {
-        println!("Invalid token");
-        StatusCode::UNAUTHORIZED
-    })?.claims;
-
-    // Add the user ID to the request extensions
+    let claims: Claims = state.keys.verify(token, None)
+        .map_err(|_| LedgerError::Unauthorized("Invalid or expired token".to_string()))?;
+
+    // Tokens stop working as soon as they or their session are revoked
+    let mut conn = state.db.acquire().await
+        .context("Database error")?;
+
+    let revoked = conn.access_revoked(claims.jti, claims.sid).await
+        .context("Failed to check token revocation")?;
+
+    if revoked {
+        return Err(LedgerError::Unauthorized("The token has been revoked".to_string()));
+    }
+
+    // Coarse last-seen tracking, so busy sessions do not write on every request
+    conn.touch_session(claims.sid).await
+        .context("Failed to update session last seen")?;
+
+    drop(conn);
+
+    let session = CurrentSession {
+        id: claims.sid,
+        jti: claims.jti,
+        expires_at: OffsetDateTime::from_unix_timestamp(claims.exp as i64).unwrap_or_else(|_| OffsetDateTime::now_utc()),
+    };
+
+    // Add the user ID and session to the request extensions
     let mut req = req;
     req.extensions_mut().insert(claims.sub);
+    req.extensions_mut().insert(session);
 
     Ok(next.run(req).await)
-}
\ No newline at end of file
+}
+
+/// The authenticated user, as placed in the request extensions by [`auth`].
+#[derive(Debug, Clone, Copy)]
+pub struct AuthUser(pub Uuid);
+
+impl<S: Send + Sync> FromRequestParts<S> for AuthUser {
+    type Rejection = LedgerError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let sub = parts
+            .extensions
+            .get::<String>()
+            .ok_or(LedgerError::Unauthorized("Missing authenticated user".to_string()))?;
+
+        let user_id = Uuid::parse_str(sub)
+            .map_err(|_| LedgerError::Unauthorized("Invalid user id in token".to_string()))?;
+
+        Ok(AuthUser(user_id))
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for CurrentSession {
+    type Rejection = LedgerError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<CurrentSession>()
+            .copied()
+            .ok_or(LedgerError::Unauthorized("Missing authenticated session".to_string()))
+    }
+}
+
+/// `None` for service accounts, which call with API keys rather than in a session.
+impl<S: Send + Sync> OptionalFromRequestParts<S> for CurrentSession {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Option<Self>, Self::Rejection> {
+        Ok(parts.extensions.get::<CurrentSession>().copied())
+    }
+}
+
+/// An authenticated user with the `admin` role.
+#[derive(Debug, Clone, Copy)]
+pub struct AdminUser(pub Uuid);
+
+impl FromRequestParts<AppState> for AdminUser {
+    type Rejection = LedgerError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let AuthUser(user_id) = AuthUser::from_request_parts(parts, state).await?;
+
+        let mut conn = state.db.acquire().await
+            .context("Database error")?;
+
+        let role = conn.role_of(user_id).await
+            .context("Database error")?;
+
+        match role.as_deref() {
+            Some("admin") => Ok(AdminUser(user_id)),
+            _ => Err(LedgerError::Forbidden("Admin privileges required".to_string())),
+        }
+    }
+}
+
+/// An authenticated user who has verified their email address.
+#[derive(Debug, Clone, Copy)]
+pub struct VerifiedUser(pub Uuid);
+
+impl FromRequestParts<AppState> for VerifiedUser {
+    type Rejection = LedgerError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let AuthUser(user_id) = AuthUser::from_request_parts(parts, state).await?;
+
+        let mut conn = state.db.acquire().await
+            .context("Database error")?;
+
+        let verified = conn.find_user(user_id).await
+            .context("Database error")?
+            .map(|user| user.email_verified);
+
+        match verified {
+            Some(true) => Ok(VerifiedUser(user_id)),
+            _ => Err(LedgerError::Forbidden("Verify your email address first".to_string())),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts}, http::{header, request::Parts}
+};
+
+use crate::state::AppState;
+
+/// Where a request came from, as recorded against new sessions and counted by the login
+/// throttle.
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+impl FromRequestParts<AppState> for ClientInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let user_agent = parts
+            .headers
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+
+        let forwarded_for = parts
+            .headers
+            .get("x-forwarded-for")
+            .and_then(|value| value.to_str().ok());
+
+        let ip_address = client_ip(peer, forwarded_for, &state.config.trusted_proxies)
+            .map(|ip| ip.to_string());
+
+        Ok(ClientInfo { user_agent, ip_address })
+    }
+}
+
+/// The client's address. Behind a trusted proxy the peer is the proxy itself, so the
+/// client is the last address in `X-Forwarded-For` that no trusted proxy added; anything
+/// before it was sent by the client and could be made up. Anyone else's `X-Forwarded-For`
+/// is ignored.
+fn client_ip(peer: Option<IpAddr>, forwarded_for: Option<&str>, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+    let peer = peer?;
+    if !trusted_proxies.contains(&peer) {
+        return Some(peer);
+    }
+
+    let Some(forwarded_for) = forwarded_for else {
+        return Some(peer);
+    };
+
+    let mut client = peer;
+    for hop in forwarded_for.rsplit(',') {
+        let Ok(hop) = hop.trim().parse::<IpAddr>() else {
+            break;
+        };
+        client = hop;
+        if !trusted_proxies.contains(&hop) {
+            break;
+        }
+    }
+
+    Some(client)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_from_untrusted_peers() {
+        let trusted = [ip("10.0.0.1")];
+
+        assert_eq!(client_ip(Some(ip("198.51.100.9")), Some("203.0.113.7"), &trusted), Some(ip("198.51.100.9")));
+        assert_eq!(client_ip(Some(ip("198.51.100.9")), Some("203.0.113.7"), &[]), Some(ip("198.51.100.9")));
+        assert_eq!(client_ip(None, Some("203.0.113.7"), &trusted), None);
+    }
+
+    #[test]
+    fn trusted_proxies_report_the_client() {
+        let trusted = [ip("10.0.0.1"), ip("10.0.0.2")];
+
+        assert_eq!(client_ip(Some(ip("10.0.0.1")), Some("203.0.113.7"), &trusted), Some(ip("203.0.113.7")));
+        assert_eq!(client_ip(Some(ip("10.0.0.1")), None, &trusted), Some(ip("10.0.0.1")));
+        // A chain of trusted proxies, each adding the address it was called from
+        assert_eq!(client_ip(Some(ip("10.0.0.1")), Some("203.0.113.7, 10.0.0.2"), &trusted), Some(ip("203.0.113.7")));
+    }
+
+    #[test]
+    fn addresses_made_up_by_the_client_are_skipped() {
+        let trusted = [ip("10.0.0.1")];
+
+        assert_eq!(client_ip(Some(ip("10.0.0.1")), Some("192.0.2.1, 203.0.113.7"), &trusted), Some(ip("203.0.113.7")));
+        assert_eq!(client_ip(Some(ip("10.0.0.1")), Some("not an ip, 203.0.113.7"), &trusted), Some(ip("203.0.113.7")));
+        assert_eq!(client_ip(Some(ip("10.0.0.1")), Some("203.0.113.7, not an ip"), &trusted), Some(ip("10.0.0.1")));
+    }
+}
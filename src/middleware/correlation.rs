@@ -0,0 +1,42 @@
+use axum::{
+    body::Body, http::{HeaderValue, Request}, middleware::Next, response::Response
+};
+use uuid::Uuid;
+
+/// Carries the id both ways: a caller may send one, and every response names it.
+pub const HEADER: &str = "x-correlation-id";
+
+tokio::task_local! {
+    static CORRELATION_ID: String;
+}
+
+/// The correlation id of the request being handled.
+pub fn current() -> String {
+    CORRELATION_ID.try_with(Clone::clone).unwrap_or_else(|_| "none".to_string())
+}
+
+/// Ids from callers are kept, so their logs line up with ours, unless they could
+/// garble a log line.
+fn acceptable(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Gives each request a correlation id, for errors to report and logs to carry.
+pub async fn correlate(req: Request<Body>, next: Next) -> Response {
+    let id = req
+        .headers()
+        .get(HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| acceptable(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let mut response = CORRELATION_ID.scope(id.clone(), next.run(req)).await;
+
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(HEADER, value);
+    }
+    response
+}
@@ -0,0 +1,282 @@
+//! Extractors that validate request bodies and query strings before handlers see them.
+//!
+//! Request structs derive [`Validate`], and every rule a request breaks is reported at
+//! once: a `422` problem with code `validation_failed` and one entry in `violations` per
+//! broken rule. Bodies, query strings and path parameters that do not even parse are a
+//! `malformed_request`.
+
+use std::borrow::Cow;
+
+use axum::{
+    extract::{FromRequest, FromRequestParts, Path, Query, Request},
+    http::{request::Parts, StatusCode},
+    Json,
+};
+use bigdecimal::{BigDecimal, Signed, ToPrimitive, Zero};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use validator::{Validate, ValidationError, ValidationErrors, ValidationErrorsKind};
+
+use crate::error::{LedgerError, Problem};
+
+/// Amounts are stored as `NUMERIC(20, 4)`.
+const AMOUNT_SCALE: i64 = 4;
+const AMOUNT_DIGITS: u64 = 16;
+
+/// A JSON body that passed its validation rules.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidatedJson<T>(pub T);
+
+/// A query string that passed its validation rules.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidatedQuery<T>(pub T);
+
+/// Path parameters, such as the id in `/accounts/{id}`. They carry no rules of their own,
+/// but ones that do not parse are reported like any other malformed request.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PathParams<T>(pub T);
+
+impl<T, S> FromRequest<S> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = LedgerError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state).await
+            .map_err(|rejection| Problem::new(rejection.status(), "malformed_request", rejection.body_text()))?;
+        value.validate()?;
+        Ok(ValidatedJson(value))
+    }
+}
+
+impl<T, S> FromRequestParts<S> for ValidatedQuery<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = LedgerError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(value) = Query::<T>::from_request_parts(parts, state).await
+            .map_err(|rejection| Problem::new(rejection.status(), "malformed_request", rejection.body_text()))?;
+        value.validate()?;
+        Ok(ValidatedQuery(value))
+    }
+}
+
+impl<T, S> FromRequestParts<S> for PathParams<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+{
+    type Rejection = LedgerError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(value) = Path::<T>::from_request_parts(parts, state).await
+            .map_err(|rejection| Problem::new(rejection.status(), "malformed_request", rejection.body_text()))?;
+        Ok(PathParams(value))
+    }
+}
+
+impl From<ValidationErrors> for LedgerError {
+    fn from(errors: ValidationErrors) -> Self {
+        let mut violations = Vec::new();
+        collect(&errors, None, &mut violations);
+        violations.sort_by(|a, b| a["field"].as_str().cmp(&b["field"].as_str()));
+
+        Problem::new(StatusCode::UNPROCESSABLE_ENTITY, "validation_failed", "The request failed validation")
+            .with("violations", violations)
+            .into()
+    }
+}
+
+/// Flattens nested errors into violations, naming fields by their path, e.g. `payees[0].nickname`.
+fn collect(errors: &ValidationErrors, prefix: Option<&str>, violations: &mut Vec<Value>) {
+    for (field, kind) in errors.errors() {
+        // Rules over the whole struct are filed under `__all__`, and belong to no one field
+        let path = match (prefix, field.as_ref()) {
+            (prefix, "__all__") => prefix.map(str::to_string),
+            (Some(prefix), field) => Some(format!("{}.{}", prefix, field)),
+            (None, field) => Some(field.to_string()),
+        };
+
+        match kind {
+            ValidationErrorsKind::Field(field_errors) => {
+                violations.extend(field_errors.iter().map(|error| violation(path.as_deref(), error)));
+            }
+            ValidationErrorsKind::Struct(nested) => collect(nested, path.as_deref(), violations),
+            ValidationErrorsKind::List(items) => {
+                for (index, nested) in items {
+                    let path = format!("{}[{}]", path.as_deref().unwrap_or_default(), index);
+                    collect(nested, Some(&path), violations);
+                }
+            }
+        }
+    }
+}
+
+fn violation(field: Option<&str>, error: &ValidationError) -> Value {
+    let mut violation = json!({ "code": error.code, "message": message(error) });
+    if let Some(field) = field {
+        violation["field"] = json!(field);
+    }
+    // The rejected value is left out, as it may be a password
+    for (name, value) in error.params.iter().filter(|(name, _)| *name != "value") {
+        violation[name.as_ref()] = value.clone();
+    }
+    violation
+}
+
+/// The rule's own message, or one made up from its parameters for the built-in rules.
+fn message(error: &ValidationError) -> String {
+    if let Some(message) = &error.message {
+        return message.to_string();
+    }
+
+    let min = error.params.get("min");
+    let max = error.params.get("max");
+    match (error.code.as_ref(), min, max) {
+        ("email", _, _) => "Must be a valid email address".to_string(),
+        ("ip", _, _) => "Must be a valid IP address".to_string(),
+        ("length", Some(min), Some(max)) => format!("Must be between {} and {} characters", min, max),
+        ("length", Some(min), None) => format!("Must be at least {} characters", min),
+        ("length", None, Some(max)) => format!("Must be at most {} characters", max),
+        ("range", Some(min), Some(max)) => format!("Must be between {} and {}", min, max),
+        ("range", Some(min), None) => format!("Must be at least {}", min),
+        ("range", None, Some(max)) => format!("Must be at most {}", max),
+        (code, _, _) => format!("Failed the {} rule", code),
+    }
+}
+
+fn invalid(code: &'static str, message: &'static str) -> ValidationError {
+    ValidationError::new(code).with_message(Cow::Borrowed(message))
+}
+
+/// Whether `value` can be stored as an amount without rounding or overflowing.
+fn storable(value: &BigDecimal) -> Result<(), ValidationError> {
+    let value = value.normalized();
+    if value.fractional_digit_count() > AMOUNT_SCALE {
+        return Err(invalid("too_precise", "Must have at most 4 decimal places"));
+    }
+    if value.abs().with_scale(0).digits() > AMOUNT_DIGITS {
+        return Err(invalid("too_large", "Must be less than 10^16"));
+    }
+    Ok(())
+}
+
+/// A positive amount the ledger can store.
+pub fn amount(value: &BigDecimal) -> Result<(), ValidationError> {
+    if *value <= BigDecimal::zero() {
+        return Err(invalid("not_positive", "Must be greater than zero"));
+    }
+    storable(value)
+}
+
+/// A non-zero change to a balance the ledger can store.
+pub fn adjustment(value: &BigDecimal) -> Result<(), ValidationError> {
+    if value.is_zero() {
+        return Err(invalid("zero", "Must not be zero"));
+    }
+    storable(value)
+}
+
+/// A whole number of things, such as transfers, that may be zero.
+pub fn count(value: &BigDecimal) -> Result<(), ValidationError> {
+    if value.is_negative() {
+        return Err(invalid("negative", "Must not be negative"));
+    }
+    if !value.is_integer() {
+        return Err(invalid("not_integer", "Must be a whole number"));
+    }
+    if value.to_i32().is_none() {
+        return Err(invalid("too_large", "Must be at most 2147483647"));
+    }
+    Ok(())
+}
+
+/// Text with something other than whitespace in it.
+pub fn not_blank(value: &str) -> Result<(), ValidationError> {
+    if value.trim().is_empty() {
+        return Err(invalid("blank", "Must not be blank"));
+    }
+    Ok(())
+}
+
+/// One of the `allowed` values.
+pub fn one_of(value: &str, allowed: &[&str]) -> Result<(), ValidationError> {
+    if allowed.contains(&value) {
+        return Ok(());
+    }
+    let mut error = ValidationError::new("unknown_value")
+        .with_message(Cow::Owned(format!("Must be one of {}", allowed.join(", "))));
+    error.add_param(Cow::Borrowed("allowed"), &allowed);
+    Err(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn decimal(value: &str) -> BigDecimal {
+        BigDecimal::from_str(value).unwrap()
+    }
+
+    #[test]
+    fn amounts_must_fit_the_ledger() {
+        assert!(amount(&decimal("0.0001")).is_ok());
+        assert!(amount(&decimal("12.50000")).is_ok());
+        assert!(amount(&decimal("9999999999999999.9999")).is_ok());
+
+        assert_eq!(amount(&decimal("0")).unwrap_err().code, "not_positive");
+        assert_eq!(amount(&decimal("-5")).unwrap_err().code, "not_positive");
+        assert_eq!(amount(&decimal("0.00001")).unwrap_err().code, "too_precise");
+        assert_eq!(amount(&decimal("10000000000000000")).unwrap_err().code, "too_large");
+
+        assert!(adjustment(&decimal("-5")).is_ok());
+        assert_eq!(adjustment(&decimal("0.0000")).unwrap_err().code, "zero");
+    }
+
+    #[test]
+    fn counts_are_whole_numbers() {
+        assert!(count(&decimal("0")).is_ok());
+        assert!(count(&decimal("3.000")).is_ok());
+
+        assert_eq!(count(&decimal("2.5")).unwrap_err().code, "not_integer");
+        assert_eq!(count(&decimal("-1")).unwrap_err().code, "negative");
+        assert_eq!(count(&decimal("2147483648")).unwrap_err().code, "too_large");
+    }
+
+    #[derive(Validate)]
+    #[validate(schema(function = "different", skip_on_field_errors = false))]
+    struct Signup {
+        #[validate(email)]
+        email: String,
+        #[validate(length(min = 8), custom(function = "not_blank"))]
+        password: String,
+    }
+
+    fn different(signup: &Signup) -> Result<(), ValidationError> {
+        if signup.email == signup.password {
+            return Err(invalid("same", "Must differ"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn every_violation_is_reported_without_the_value() {
+        let errors = Signup { email: "   ".to_string(), password: "   ".to_string() }.validate().unwrap_err();
+        let mut violations = Vec::new();
+        collect(&errors, None, &mut violations);
+        violations.sort_by(|a, b| (a["field"].as_str(), a["code"].as_str()).cmp(&(b["field"].as_str(), b["code"].as_str())));
+
+        assert_eq!(violations, vec![
+            json!({ "code": "same", "message": "Must differ" }),
+            json!({ "field": "email", "code": "email", "message": "Must be a valid email address" }),
+            json!({ "field": "password", "code": "blank", "message": "Must not be blank" }),
+            json!({ "field": "password", "code": "length", "message": "Must be at least 8 characters", "min": 8 }),
+        ]);
+    }
+}
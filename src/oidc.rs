@@ -0,0 +1,315 @@
+//! Staff login through the company's OpenID Connect identity provider.
+//!
+//! The authorization code flow with PKCE: the browser is sent to the provider with the
+//! hash of a secret verifier, and comes back with a code that only the holder of that
+//! verifier can exchange for an ID token. The ID token is checked against the provider's
+//! published keys, and one of its claims, `groups` by default, decides the ledger role.
+//! Staff whose claim maps to no role are not let in.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use data_encoding::BASE64URL_NOPAD;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tokio::sync::OnceCell;
+
+use crate::api::session::random_token;
+use crate::config::Config;
+
+/// Roles the ledger knows, from most to least privileged.
+const ROLES: &[&str] = &["admin", "customer"];
+
+/// Signatures accepted on ID tokens; never the HMAC ones, whose key would be the client secret.
+const ID_TOKEN_ALGORITHMS: &[Algorithm] = &[
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// Why a login could not be completed.
+#[derive(Debug)]
+pub enum LoginError {
+    /// The provider could not be reached or answered nonsense
+    Provider(String),
+    /// The provider refused the code, or its ID token failed verification
+    Rejected(String),
+}
+
+impl fmt::Display for LoginError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoginError::Provider(message) => write!(f, "identity provider error: {}", message),
+            LoginError::Rejected(message) => write!(f, "login rejected: {}", message),
+        }
+    }
+}
+
+fn provider_error(e: impl fmt::Display) -> LoginError {
+    LoginError::Provider(e.to_string())
+}
+
+/// The endpoints the provider publishes in its discovery document.
+#[derive(Debug, Deserialize)]
+struct Provider {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenRes {
+    id_token: String,
+}
+
+/// The claims of a verified ID token the ledger uses.
+#[derive(Debug, Deserialize)]
+pub struct Identity {
+    /// The provider's id for the user, stable across email changes
+    pub sub: String,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub name: Option<String>,
+    nonce: Option<String>,
+    #[serde(flatten)]
+    other: HashMap<String, Value>,
+}
+
+/// A login on its way to the provider. Everything but the URL stays with the ledger
+/// until the browser comes back.
+pub struct PendingLogin {
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    pub authorization_url: String,
+}
+
+pub struct OidcClient {
+    issuer_url: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_url: String,
+    role_claim: String,
+    role_map: Vec<(String, String)>,
+    http: reqwest::Client,
+    /// Fetched on first use, so the ledger starts while the provider is down
+    provider: OnceCell<Provider>,
+}
+
+impl OidcClient {
+    /// A client for the configured provider, or `None` when staff login is not set up.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        Some(OidcClient {
+            issuer_url: config.oidc_issuer_url.clone()?.trim_end_matches('/').to_string(),
+            client_id: config.oidc_client_id.clone()?,
+            client_secret: config.oidc_client_secret.clone(),
+            redirect_url: config.oidc_redirect_url.clone(),
+            role_claim: config.oidc_role_claim.clone(),
+            role_map: config.oidc_role_map.clone(),
+            http: reqwest::Client::new(),
+            provider: OnceCell::new(),
+        })
+    }
+
+    /// The issuer identities are recorded under.
+    pub fn issuer(&self) -> &str {
+        &self.issuer_url
+    }
+
+    async fn provider(&self) -> Result<&Provider, LoginError> {
+        self.provider.get_or_try_init(|| async {
+            let url = format!("{}/.well-known/openid-configuration", self.issuer_url);
+            let provider: Provider = self.http.get(&url).send().await
+                .and_then(|res| res.error_for_status())
+                .map_err(provider_error)?
+                .json().await
+                .map_err(provider_error)?;
+
+            // A document naming another issuer could make the ledger trust its tokens
+            if provider.issuer.trim_end_matches('/') != self.issuer_url {
+                return Err(LoginError::Provider(format!("discovery document is for issuer {}", provider.issuer)));
+            }
+            Ok(provider)
+        }).await
+    }
+
+    /// Starts a login, returning where to send the browser.
+    pub async fn start_login(&self) -> Result<PendingLogin, LoginError> {
+        let provider = self.provider().await?;
+
+        let state = random_token();
+        let nonce = random_token();
+        let code_verifier = random_token();
+
+        let mut url = Url::parse(&provider.authorization_endpoint).map_err(provider_error)?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.client_id)
+            .append_pair("redirect_uri", &self.redirect_url)
+            .append_pair("scope", &format!("openid email profile {}", self.role_claim))
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &code_challenge(&code_verifier))
+            .append_pair("code_challenge_method", "S256");
+
+        Ok(PendingLogin { state, nonce, code_verifier, authorization_url: url.into() })
+    }
+
+    /// Exchanges the code the browser came back with, and verifies the ID token it buys.
+    pub async fn finish_login(&self, code: &str, code_verifier: &str, nonce: &str) -> Result<Identity, LoginError> {
+        let provider = self.provider().await?;
+
+        let mut request = self.http.post(&provider.token_endpoint).form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.redirect_url),
+            ("client_id", &self.client_id),
+            ("code_verifier", code_verifier),
+        ]);
+        if let Some(secret) = &self.client_secret {
+            request = request.basic_auth(&self.client_id, Some(secret));
+        }
+
+        let res = request.send().await.map_err(provider_error)?;
+        if res.status().is_client_error() {
+            let body = res.text().await.unwrap_or_default();
+            return Err(LoginError::Rejected(format!("token endpoint refused the code: {}", body)));
+        }
+        let token: TokenRes = res.error_for_status()
+            .map_err(provider_error)?
+            .json().await
+            .map_err(provider_error)?;
+
+        let identity = self.verify_id_token(provider, &token.id_token).await?;
+        if identity.nonce.as_deref() != Some(nonce) {
+            return Err(LoginError::Rejected("ID token was issued for another login".to_string()));
+        }
+
+        Ok(identity)
+    }
+
+    async fn verify_id_token(&self, provider: &Provider, id_token: &str) -> Result<Identity, LoginError> {
+        let rejected = |e: jsonwebtoken::errors::Error| LoginError::Rejected(format!("invalid ID token: {}", e));
+
+        let header = decode_header(id_token).map_err(rejected)?;
+        if !ID_TOKEN_ALGORITHMS.contains(&header.alg) {
+            return Err(LoginError::Rejected(format!("ID token signed with {:?}", header.alg)));
+        }
+
+        // Fetched per login rather than cached, so the provider's key rotations need no restart
+        let jwks: JwkSet = self.http.get(&provider.jwks_uri).send().await
+            .and_then(|res| res.error_for_status())
+            .map_err(provider_error)?
+            .json().await
+            .map_err(provider_error)?;
+
+        let jwk = match &header.kid {
+            Some(kid) => jwks.find(kid),
+            None if jwks.keys.len() == 1 => jwks.keys.first(),
+            None => None,
+        }
+        .ok_or_else(|| LoginError::Rejected("ID token signed with an unknown key".to_string()))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&provider.issuer]);
+        validation.set_audience(&[&self.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        let key = DecodingKey::from_jwk(jwk).map_err(rejected)?;
+        Ok(decode::<Identity>(id_token, &key, &validation).map_err(rejected)?.claims)
+    }
+
+    /// The ledger role the identity's role claim grants, the most privileged if several do.
+    pub fn role_for(&self, identity: &Identity) -> Option<&'static str> {
+        let values: Vec<&str> = match identity.other.get(&self.role_claim) {
+            Some(Value::String(value)) => vec![value.as_str()],
+            Some(Value::Array(values)) => values.iter().filter_map(Value::as_str).collect(),
+            _ => return None,
+        };
+
+        ROLES.iter().copied().find(|role| {
+            self.role_map.iter().any(|(value, mapped)| mapped == role && values.contains(&value.as_str()))
+        })
+    }
+}
+
+/// The S256 PKCE challenge for `verifier`.
+pub fn code_challenge(verifier: &str) -> String {
+    BASE64URL_NOPAD.encode(&Sha256::digest(verifier.as_bytes()))
+}
+
+/// Parses `OIDC_ROLE_MAP`, `value=role` pairs separated by commas, skipping malformed
+/// pairs and unknown roles.
+pub fn parse_role_map(map: &str) -> Vec<(String, String)> {
+    map.split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .filter_map(|pair| match pair.split_once('=') {
+            Some((value, role)) if ROLES.contains(&role.trim()) && !value.trim().is_empty() => {
+                Some((value.trim().to_string(), role.trim().to_string()))
+            }
+            _ => {
+                eprintln!("Ignoring OIDC_ROLE_MAP entry {:?}, expected value=admin or value=customer", pair);
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(role_map: &str) -> OidcClient {
+        OidcClient {
+            issuer_url: "https://id.example.com".to_string(),
+            client_id: "ledger".to_string(),
+            client_secret: None,
+            redirect_url: "https://ledger.example.com/api/v1/oidc/callback".to_string(),
+            role_claim: "groups".to_string(),
+            role_map: parse_role_map(role_map),
+            http: reqwest::Client::new(),
+            provider: OnceCell::new(),
+        }
+    }
+
+    fn identity(groups: Value) -> Identity {
+        serde_json::from_value(serde_json::json!({ "sub": "staff-1", "groups": groups })).unwrap()
+    }
+
+    #[test]
+    fn role_map_skips_malformed_entries() {
+        assert_eq!(
+            parse_role_map("ledger-admins=admin, support = customer,owners=root,broken,"),
+            vec![
+                ("ledger-admins".to_string(), "admin".to_string()),
+                ("support".to_string(), "customer".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn most_privileged_mapped_role_wins() {
+        let client = client("ledger-admins=admin,support=customer");
+
+        assert_eq!(client.role_for(&identity(serde_json::json!(["support", "ledger-admins"]))), Some("admin"));
+        assert_eq!(client.role_for(&identity(serde_json::json!("support"))), Some("customer"));
+        assert_eq!(client.role_for(&identity(serde_json::json!(["sales"]))), None);
+        assert_eq!(client.role_for(&identity(Value::Null)), None);
+    }
+
+    #[test]
+    fn challenge_matches_rfc_7636_example() {
+        assert_eq!(code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"), "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM");
+    }
+}
@@ -0,0 +1,117 @@
+//! The OpenAPI document of the `/api/v1` and `/api/v2` routes, generated from the handlers
+//! and their types.
+//!
+//! Each handler's `#[utoipa::path]` names its route, and [`crate::app`] registers the
+//! handlers from those annotations, so the document cannot list a route the ledger does not
+//! serve or miss one it does. It is served at `/api/v1/openapi.json`, with Swagger UI at
+//! `/api/v1/docs`.
+
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::{ContentBuilder, Ref, ResponseBuilder};
+use utoipa::{Modify, OpenApi, ToSchema};
+
+pub const SPEC_PATH: &str = "/api/v1/openapi.json";
+pub const DOCS_PATH: &str = "/api/v1/docs";
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Rusty Ledger", description = "Accounts, transfers and the controls around them."),
+    modifiers(&Security),
+    security(("bearer" = [])),
+    components(schemas(ProblemDetails)),
+    tags(
+        (name = "user", description = "Registration, login and the signed-in user"),
+        (name = "session", description = "Sessions and their tokens"),
+        (name = "two_factor", description = "Two-factor authentication"),
+        (name = "oidc", description = "Staff login through the company identity provider"),
+        (name = "account", description = "Balances and account lookup"),
+        (name = "transaction", description = "Transfers"),
+        (name = "limit", description = "Transfer limits"),
+        (name = "payee", description = "Saved payees"),
+        (name = "budget", description = "Budgets and their alerts"),
+        (name = "notification", description = "Notifications"),
+        (name = "admin", description = "Operations for users with the admin role"),
+    )
+)]
+pub struct ApiDoc;
+
+/// An error, as RFC 7807 problem details. Some problems add members of their own, such as
+/// `violations`.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct ProblemDetails {
+    r#type: String,
+    title: String,
+    status: u16,
+    detail: String,
+    /// Stable, machine-readable code of the error
+    code: String,
+    /// Finds the request in the ledger's logs; sent back as `X-Correlation-Id` too
+    correlation_id: String,
+}
+
+/// A timestamp without an explicit format, as the `time` crate sends it: year, day of the
+/// year, hour, minute, second, nanosecond, and the UTC offset's hours, minutes and seconds.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct CompactTimestamp(Vec<i64>);
+
+/// A date without an explicit format, as the `time` crate sends it: year and day of the year.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct CompactDate(Vec<i32>);
+
+/// Access tokens and API keys are both sent as bearer tokens.
+struct Security;
+
+impl Modify for Security {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some("An access token, or a service account's API key"))
+                    .build(),
+            ),
+        );
+    }
+}
+
+/// Gives every operation a problem as its default response, as any of them can fail. The
+/// operations are only known once the handlers are registered, so this runs on the finished
+/// document rather than as a modifier of [`ApiDoc`].
+pub fn with_problems(mut openapi: utoipa::openapi::OpenApi) -> utoipa::openapi::OpenApi {
+    Problems.modify(&mut openapi);
+    openapi
+}
+
+struct Problems;
+
+impl Modify for Problems {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let problem = ResponseBuilder::new()
+            .description("The request failed; see `code` for why")
+            .content(
+                "application/problem+json",
+                ContentBuilder::new().schema(Some(Ref::from_schema_name("ProblemDetails"))).build(),
+            )
+            .build();
+
+        for item in openapi.paths.paths.values_mut() {
+            for operation in [
+                &mut item.get,
+                &mut item.post,
+                &mut item.put,
+                &mut item.patch,
+                &mut item.delete,
+            ]
+            .into_iter()
+            .flatten()
+            {
+                operation.responses.responses.entry("default".to_string()).or_insert_with(|| problem.clone().into());
+            }
+        }
+    }
+}
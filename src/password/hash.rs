@@ -0,0 +1,107 @@
+//! Password hashing. New hashes use Argon2id; bcrypt hashes from before the switch still
+//! verify, and [`Hasher::needs_rehash`] says when a stored hash should be replaced.
+//!
+//! Both algorithms are slow on purpose, so the async functions run them on the blocking
+//! thread pool rather than stalling the runtime.
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::Rng;
+
+/// Hashes passwords with Argon2id using the configured cost.
+#[derive(Debug, Clone)]
+pub struct Hasher {
+    params: Params,
+}
+
+impl Hasher {
+    /// `memory_kib` of memory, `iterations` passes over it and `parallelism` lanes.
+    pub fn new(memory_kib: u32, iterations: u32, parallelism: u32) -> Result<Self, argon2::Error> {
+        Ok(Hasher { params: Params::new(memory_kib, iterations, parallelism, None)? })
+    }
+
+    fn argon2(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+
+    /// A PHC string (`$argon2id$v=19$m=...`) for `password` with a fresh random salt.
+    pub fn hash(&self, password: &str) -> anyhow::Result<String> {
+        let salt = SaltString::encode_b64(&rand::rng().random::<[u8; 16]>())?;
+        Ok(self.argon2().hash_password(password.as_bytes(), &salt)?.to_string())
+    }
+
+    pub async fn hash_blocking(&self, password: &str) -> anyhow::Result<String> {
+        let (hasher, password) = (self.clone(), password.to_string());
+        tokio::task::spawn_blocking(move || hasher.hash(&password)).await?
+    }
+
+    /// Whether `stored` should be replaced by a new hash: it is bcrypt, or Argon2 with
+    /// anything but the current algorithm and cost.
+    pub fn needs_rehash(&self, stored: &str) -> bool {
+        let Ok(parsed) = PasswordHash::new(stored) else {
+            return true;
+        };
+        let Ok(params) = Params::try_from(&parsed) else {
+            return true;
+        };
+
+        parsed.algorithm != Algorithm::Argon2id.ident()
+            || parsed.version != Some(Version::V0x13.into())
+            || params.m_cost() != self.params.m_cost()
+            || params.t_cost() != self.params.t_cost()
+            || params.p_cost() != self.params.p_cost()
+    }
+}
+
+/// Checks `password` against a stored Argon2 or bcrypt hash.
+pub fn verify(password: &str, stored: &str) -> anyhow::Result<bool> {
+    if stored.starts_with("$2") {
+        return Ok(bcrypt::verify(password, stored)?);
+    }
+
+    let parsed = PasswordHash::new(stored)?;
+    match Argon2::default().verify_password(password.as_bytes(), &parsed) {
+        Ok(()) => Ok(true),
+        Err(argon2::password_hash::Error::Password) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn verify_blocking(password: &str, stored: &str) -> anyhow::Result<bool> {
+    let (password, stored) = (password.to_string(), stored.to_string());
+    tokio::task::spawn_blocking(move || verify(&password, &stored)).await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hasher() -> Hasher {
+        // Cheap parameters; the cost does not change the behaviour under test
+        Hasher::new(1024, 1, 1).unwrap()
+    }
+
+    #[test]
+    fn argon2_hashes_verify() {
+        let stored = hasher().hash("plum-orbit-canyon-42").unwrap();
+        assert!(stored.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+        assert!(verify("plum-orbit-canyon-42", &stored).unwrap());
+        assert!(!verify("plum-orbit-canyon-43", &stored).unwrap());
+        assert!(!hasher().needs_rehash(&stored));
+    }
+
+    #[test]
+    fn bcrypt_hashes_verify_and_need_rehash() {
+        let stored = bcrypt::hash("plum-orbit-canyon-42", 4).unwrap();
+        assert!(verify("plum-orbit-canyon-42", &stored).unwrap());
+        assert!(!verify("plum-orbit-canyon-43", &stored).unwrap());
+        assert!(hasher().needs_rehash(&stored));
+    }
+
+    #[test]
+    fn changed_cost_needs_rehash() {
+        let stored = hasher().hash("plum-orbit-canyon-42").unwrap();
+        assert!(Hasher::new(2048, 1, 1).unwrap().needs_rehash(&stored));
+        assert!(Hasher::new(1024, 2, 1).unwrap().needs_rehash(&stored));
+    }
+}
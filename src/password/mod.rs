@@ -0,0 +1,4 @@
+//! Rules for the passwords users choose, and how they are stored.
+
+pub mod hash;
+pub mod policy;
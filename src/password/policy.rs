@@ -0,0 +1,345 @@
+//! What makes a password acceptable: a minimum length, a minimum strength estimated the
+//! way zxcvbn does it (how many guesses an attacker who knows common passwords and the
+//! user's own details would need), and not appearing in a breach corpus.
+//!
+//! Breached passwords are looked up offline in a directory of k-anonymity range files,
+//! the format of the Pwned Passwords range API: `<PREFIX>.txt` holds one
+//! `<SUFFIX>:<COUNT>` line for every breached password whose upper case SHA-1 hex digest
+//! starts with the five character `PREFIX`.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+
+/// Passwords and words attackers try first, most common first.
+const COMMON: &[&str] = &[
+    "password", "123456", "qwerty", "letmein", "welcome", "admin", "iloveyou", "monkey",
+    "dragon", "football", "baseball", "sunshine", "princess", "master", "shadow", "superman",
+    "trustno1", "login", "secret", "hello", "freedom", "whatever", "passw0rd", "starwars",
+    "michael", "jennifer", "charlie", "computer", "internet", "summer", "winter", "spring",
+    "autumn", "flower", "cookie", "banana", "orange", "purple", "money", "bank", "ledger",
+    "account", "access", "changeme", "default", "batman", "soccer", "hockey", "killer",
+    "ginger", "pepper", "hunter", "ranger", "buster", "thomas", "robert", "jordan", "love",
+    "test", "guest", "user", "abc123", "asdfgh", "zxcvbn", "qazwsx", "mustang", "maggie",
+];
+
+/// Rows of a US keyboard, for spotting walks like `qwerty` or `asdf`.
+const KEYBOARD_ROWS: &[&str] = &["1234567890", "qwertyuiop", "asdfghjkl", "zxcvbnm"];
+
+/// Years a password is likely to contain, 1900 to 2099.
+const YEAR_GUESSES: f64 = 200.0;
+
+/// Guesses (as a power of ten) below which a password gets each score, as in zxcvbn.
+const SCORE_THRESHOLDS: [f64; 4] = [3.0, 6.0, 8.0, 10.0];
+
+/// How strong a password is estimated to be.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Strength {
+    /// 0 (guessable in a handful of tries) to 4 (very unguessable)
+    pub score: u8,
+    pub guesses_log10: f64,
+    /// What would make the password stronger, worst problem first
+    pub suggestions: Vec<&'static str>,
+}
+
+/// One way a password fails the policy.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum PolicyViolation {
+    TooShort { min_length: usize },
+    TooWeak { score: u8, min_score: u8, suggestions: Vec<&'static str> },
+    /// Seen this many times in the breach corpus
+    Breached { occurrences: u64 },
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PolicyViolation::TooShort { min_length } => write!(f, "Password must be at least {} characters", min_length),
+            PolicyViolation::TooWeak { .. } => write!(f, "Password is too easy to guess"),
+            PolicyViolation::Breached { .. } => write!(f, "Password has appeared in a data breach"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    /// Lowest acceptable [`Strength::score`]
+    pub min_score: u8,
+    /// Directory of breached-password range files; no breach check without one
+    pub breached_dir: Option<PathBuf>,
+}
+
+impl PasswordPolicy {
+    /// Every way `password` fails the policy, none if it is acceptable. `user_inputs` are
+    /// the user's own details, such as their name and email, which make poor passwords.
+    pub async fn check(&self, password: &str, user_inputs: &[&str]) -> io::Result<Vec<PolicyViolation>> {
+        let mut violations = Vec::new();
+
+        if password.chars().count() < self.min_length {
+            violations.push(PolicyViolation::TooShort { min_length: self.min_length });
+        }
+
+        let strength = estimate(password, user_inputs);
+        if strength.score < self.min_score {
+            violations.push(PolicyViolation::TooWeak {
+                score: strength.score,
+                min_score: self.min_score,
+                suggestions: strength.suggestions,
+            });
+        }
+
+        if let Some(dir) = &self.breached_dir {
+            let occurrences = breach_count(dir, password).await?;
+            if occurrences > 0 {
+                violations.push(PolicyViolation::Breached { occurrences });
+            }
+        }
+
+        Ok(violations)
+    }
+}
+
+/// How often `password` appears in the range files in `dir`. Prefixes without a file
+/// count as not breached.
+pub async fn breach_count(dir: &Path, password: &str) -> io::Result<u64> {
+    let digest = hex::encode_upper(Sha1::digest(password.as_bytes()));
+    let (prefix, suffix) = digest.split_at(5);
+
+    let range = match tokio::fs::read_to_string(dir.join(format!("{}.txt", prefix))).await {
+        Ok(range) => range,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    // Padding entries have a count of 0, so they never match as breached
+    Ok(range
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(candidate, _)| candidate.trim().eq_ignore_ascii_case(suffix))
+        .and_then(|(_, count)| count.trim().parse().ok())
+        .unwrap_or(0))
+}
+
+/// Undoes common substitutions so `p@ssw0rd` matches `password`.
+fn unleet(c: char) -> char {
+    match c.to_ascii_lowercase() {
+        '@' | '4' => 'a',
+        '3' => 'e',
+        '1' | '!' => 'i',
+        '0' => 'o',
+        '$' | '5' => 's',
+        '7' => 't',
+        c => c,
+    }
+}
+
+/// Whether typing `b` straight after `a` follows a sequence (`abc`, `321`) or a
+/// keyboard walk (`qwe`).
+fn follows(a: char, b: char) -> bool {
+    let (a, b) = (a.to_ascii_lowercase(), b.to_ascii_lowercase());
+    if (a as i32 - b as i32).abs() == 1 && a.is_ascii_alphanumeric() && b.is_ascii_alphanumeric() {
+        return true;
+    }
+    KEYBOARD_ROWS.iter().any(|row| {
+        match (row.find(a), row.find(b)) {
+            (Some(i), Some(j)) => i.abs_diff(j) == 1,
+            _ => false,
+        }
+    })
+}
+
+fn charset_size(password: &str) -> f64 {
+    let mut size = 0;
+    if password.chars().any(|c| c.is_ascii_lowercase()) {
+        size += 26;
+    }
+    if password.chars().any(|c| c.is_ascii_uppercase()) {
+        size += 26;
+    }
+    if password.chars().any(|c| c.is_ascii_digit()) {
+        size += 10;
+    }
+    if password.chars().any(|c| c.is_ascii_punctuation() || c == ' ') {
+        size += 33;
+    }
+    if !password.is_ascii() {
+        size += 100;
+    }
+    f64::from(size.max(1))
+}
+
+/// Estimates how many guesses `password` would take. Parts matching a common password
+/// or one of `user_inputs` cost about as many guesses as their rank in the word list,
+/// years a couple of hundred, and repeated characters, sequences and keyboard walks next
+/// to nothing. Everything else costs a full guess from the character classes used.
+pub fn estimate(password: &str, user_inputs: &[&str]) -> Strength {
+    let chars: Vec<char> = password.chars().collect();
+    let normalised: Vec<char> = chars.iter().map(|&c| unleet(c)).collect();
+
+    // Words the user's own details are made of rank first: they are the first guesses
+    // of anyone targeting this account
+    let mut user_words: Vec<String> = user_inputs
+        .iter()
+        .flat_map(|input| input.split(|c: char| !c.is_alphanumeric()))
+        .filter(|word| word.chars().count() >= 3)
+        .map(|word| word.chars().map(unleet).collect())
+        .collect();
+    user_words.sort();
+    user_words.dedup();
+
+    // (word, rank, is a user input), longest first so the longest match wins
+    let mut dictionary: Vec<(String, f64, bool)> = user_words
+        .into_iter()
+        .map(|word| (word, 1.0, true))
+        .chain(COMMON.iter().enumerate().map(|(rank, word)| {
+            (word.chars().map(unleet).collect(), (rank + 2) as f64, false)
+        }))
+        .collect();
+    dictionary.sort_by_key(|(word, _, _)| std::cmp::Reverse(word.chars().count()));
+
+    let mut covered = vec![false; chars.len()];
+    let mut bits = 0.0;
+    let (mut used_common, mut used_user_input) = (false, false);
+
+    for (word, rank, is_user_input) in dictionary {
+        let word: Vec<char> = word.chars().collect();
+        if word.len() > chars.len() {
+            continue;
+        }
+        for start in 0..=chars.len() - word.len() {
+            let span = start..start + word.len();
+            if normalised[span.clone()] != word[..] || covered[span.clone()].iter().any(|&c| c) {
+                continue;
+            }
+            bits += rank.log2() + 1.0;
+            if chars[span.clone()].iter().any(|c| c.is_ascii_uppercase()) {
+                bits += 1.0;
+            }
+            if chars[span.clone()].iter().any(|&c| c.to_ascii_lowercase() != unleet(c)) {
+                bits += 1.0;
+            }
+            covered[span].iter_mut().for_each(|c| *c = true);
+            used_common |= !is_user_input;
+            used_user_input |= is_user_input;
+        }
+    }
+
+    // Years are guessed from a range of a couple of hundred
+    let mut years = false;
+    for start in 0..chars.len().saturating_sub(3) {
+        let span = start..start + 4;
+        let year: String = chars[span.clone()].iter().collect();
+        if covered[span.clone()].iter().any(|&c| c) || !matches!(year.parse::<u32>(), Ok(1900..=2099)) {
+            continue;
+        }
+        bits += YEAR_GUESSES.log2();
+        covered[span].iter_mut().for_each(|c| *c = true);
+        years = true;
+    }
+
+    let per_char = charset_size(password).log2();
+    let (mut repeats, mut sequences) = (false, false);
+    for (i, &c) in chars.iter().enumerate() {
+        if covered[i] {
+            continue;
+        }
+        match i.checked_sub(1).map(|prev| chars[prev]) {
+            Some(prev) if prev == c => {
+                bits += 1.0;
+                repeats = true;
+            }
+            Some(prev) if follows(prev, c) => {
+                bits += 1.0;
+                sequences = true;
+            }
+            _ => bits += per_char,
+        }
+    }
+
+    let guesses_log10 = bits * std::f64::consts::LOG10_2;
+    let score = SCORE_THRESHOLDS.iter().take_while(|&&threshold| guesses_log10 >= threshold).count() as u8;
+
+    let mut suggestions = Vec::new();
+    if score < 3 {
+        if used_user_input {
+            suggestions.push("Avoid your name and email address");
+        }
+        if used_common {
+            suggestions.push("Avoid common passwords and words, even with symbols for letters");
+        }
+        if years {
+            suggestions.push("Avoid years and dates");
+        }
+        if repeats {
+            suggestions.push("Avoid repeated characters");
+        }
+        if sequences {
+            suggestions.push("Avoid sequences like abc, 1234 or qwerty");
+        }
+        let classes: HashSet<_> = chars.iter().map(|c| (c.is_ascii_lowercase(), c.is_ascii_uppercase(), c.is_ascii_digit())).collect();
+        if classes.len() == 1 {
+            suggestions.push("Mix in other kinds of characters");
+        }
+        suggestions.push("Add another word or two; uncommon words are better");
+    }
+
+    Strength { score, guesses_log10, suggestions }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn common_passwords_score_low() {
+        for password in ["password123", "P@ssw0rd!", "qwertyuiop", "aaaaaaaaaaaa", "abcdefgh1234", "Summer2024!"] {
+            let strength = estimate(password, &[]);
+            assert!(strength.score < 3, "{} scored {}", password, strength.score);
+            assert!(!strength.suggestions.is_empty());
+        }
+    }
+
+    #[test]
+    fn unrelated_words_score_high() {
+        for password in ["plum-orbit-canyon-42", "Xk9#mQ2$vL", "violet harbor quartz"] {
+            let strength = estimate(password, &[]);
+            assert_eq!(strength.score, 4, "{} scored {}", password, strength.score);
+            assert!(strength.suggestions.is_empty());
+        }
+    }
+
+    #[test]
+    fn user_details_weaken_a_password() {
+        let alone = estimate("jonathanwhitfield9", &[]);
+        let personal = estimate("jonathanwhitfield9", &["Jonathan Whitfield", "jonathan.whitfield@example.com"]);
+        assert_eq!(alone.score, 4);
+        assert!(personal.score < 3);
+        assert_eq!(personal.suggestions[0], "Avoid your name and email address");
+    }
+
+    #[tokio::test]
+    async fn breached_passwords_are_found_by_range() {
+        let dir = std::env::temp_dir().join(format!("rusty-ledger-breaches-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // SHA-1 of "correct horse battery staple" is ABF7AAD6438836DBE526AA231ABDE2D0EEF74D42
+        std::fs::write(dir.join("ABF7A.txt"), "0000000000000000000000000000000000A:0\r\nAD6438836DBE526AA231ABDE2D0EEF74D42:3645\r\n").unwrap();
+
+        assert_eq!(breach_count(&dir, "correct horse battery staple").await.unwrap(), 3645);
+        assert_eq!(breach_count(&dir, "plum-orbit-canyon-42").await.unwrap(), 0);
+
+        let policy = PasswordPolicy { min_length: 30, min_score: 3, breached_dir: Some(dir.clone()) };
+        let violations = policy.check("correct horse battery staple", &[]).await.unwrap();
+        assert_eq!(violations, vec![
+            PolicyViolation::TooShort { min_length: 30 },
+            PolicyViolation::Breached { occurrences: 3645 },
+        ]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,175 @@
+use async_trait::async_trait;
+use bigdecimal::{BigDecimal, Zero};
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::store::Storage;
+
+/// A transfer about to be posted, as seen by the risk checks.
+#[derive(Clone, Debug)]
+pub struct TransferContext {
+    pub from_account_id: Uuid,
+    pub to_account_id: Uuid,
+    pub amount: BigDecimal,
+}
+
+/// Why a risk check held a transfer for review.
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct RiskFlag {
+    pub rule: String,
+    pub reason: String,
+}
+
+/// A screening rule run against every transfer before it is posted.
+///
+/// Checks run inside the transfer's database transaction, after the balance rows have
+/// been locked, so they see a consistent view of the sender's history.
+#[async_trait]
+pub trait RiskCheck: Send + Sync {
+    async fn check(&self, conn: &mut dyn Storage, transfer: &TransferContext) -> anyhow::Result<Option<RiskFlag>>;
+}
+
+/// Flags the first transfer between two accounts when it is above a threshold.
+pub struct NewPayeeCheck {
+    pub threshold: BigDecimal,
+}
+
+#[async_trait]
+impl RiskCheck for NewPayeeCheck {
+    async fn check(&self, conn: &mut dyn Storage, transfer: &TransferContext) -> anyhow::Result<Option<RiskFlag>> {
+        if transfer.amount < self.threshold {
+            return Ok(None);
+        }
+
+        if conn.has_paid(transfer.from_account_id, transfer.to_account_id).await? {
+            return Ok(None);
+        }
+
+        Ok(Some(RiskFlag {
+            rule: "new_payee".to_string(),
+            reason: format!("First transfer to account {} is at least {}", transfer.to_account_id, self.threshold),
+        }))
+    }
+}
+
+/// Flags an account making many transfers in quick succession.
+pub struct RapidTransfersCheck {
+    /// Transfers already made within the window before this one is flagged
+    pub max_transfers: i64,
+    pub window: Duration,
+}
+
+#[async_trait]
+impl RiskCheck for RapidTransfersCheck {
+    async fn check(&self, conn: &mut dyn Storage, transfer: &TransferContext) -> anyhow::Result<Option<RiskFlag>> {
+        let since = OffsetDateTime::now_utc() - self.window;
+
+        let recent = conn.transfers_since(transfer.from_account_id, since).await?;
+
+        if recent < self.max_transfers {
+            return Ok(None);
+        }
+
+        Ok(Some(RiskFlag {
+            rule: "rapid_transfers".to_string(),
+            reason: format!("{} transfers in the last {} minutes", recent, self.window.whole_minutes()),
+        }))
+    }
+}
+
+/// Flags large transfers of suspiciously round amounts.
+pub struct RoundAmountCheck {
+    pub min_amount: BigDecimal,
+    pub multiple: BigDecimal,
+}
+
+#[async_trait]
+impl RiskCheck for RoundAmountCheck {
+    async fn check(&self, _conn: &mut dyn Storage, transfer: &TransferContext) -> anyhow::Result<Option<RiskFlag>> {
+        if transfer.amount < self.min_amount || !(&transfer.amount % &self.multiple).is_zero() {
+            return Ok(None);
+        }
+
+        Ok(Some(RiskFlag {
+            rule: "round_amount".to_string(),
+            reason: format!("Amount {} is a round multiple of {}", transfer.amount, self.multiple),
+        }))
+    }
+}
+
+/// Flags recently opened accounts moving large sums.
+pub struct NewAccountCheck {
+    pub max_age: Duration,
+    pub threshold: BigDecimal,
+}
+
+#[async_trait]
+impl RiskCheck for NewAccountCheck {
+    async fn check(&self, conn: &mut dyn Storage, transfer: &TransferContext) -> anyhow::Result<Option<RiskFlag>> {
+        if transfer.amount < self.threshold {
+            return Ok(None);
+        }
+
+        let created_at = conn.account_opened_at(transfer.from_account_id).await?;
+
+        match created_at {
+            Some(created_at) if OffsetDateTime::now_utc() - created_at < self.max_age => Ok(Some(RiskFlag {
+                rule: "new_account".to_string(),
+                reason: format!("Account opened less than {} days ago is moving {}", self.max_age.whole_days(), transfer.amount),
+            })),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// The set of risk checks run before posting a transfer.
+pub struct RiskEngine {
+    checks: Vec<Box<dyn RiskCheck>>,
+}
+
+impl RiskEngine {
+    /// An engine with no checks, which lets every transfer through.
+    pub fn empty() -> Self {
+        RiskEngine { checks: Vec::new() }
+    }
+
+    pub fn with_check(mut self, check: impl RiskCheck + 'static) -> Self {
+        self.checks.push(Box::new(check));
+        self
+    }
+
+    /// Runs every check and returns the flags raised, if any.
+    pub async fn screen(&self, conn: &mut dyn Storage, transfer: &TransferContext) -> anyhow::Result<Vec<RiskFlag>> {
+        let mut flags = Vec::new();
+        for check in &self.checks {
+            if let Some(flag) = check.check(conn, transfer).await? {
+                flags.push(flag);
+            }
+        }
+        Ok(flags)
+    }
+}
+
+impl Default for RiskEngine {
+    /// The built-in rules with their default thresholds.
+    fn default() -> Self {
+        RiskEngine::empty()
+            .with_check(NewPayeeCheck {
+                threshold: BigDecimal::from(1_000),
+            })
+            .with_check(RapidTransfersCheck {
+                max_transfers: 5,
+                window: Duration::minutes(5),
+            })
+            .with_check(RoundAmountCheck {
+                min_amount: BigDecimal::from(5_000),
+                multiple: BigDecimal::from(1_000),
+            })
+            .with_check(NewAccountCheck {
+                max_age: Duration::days(7),
+                threshold: BigDecimal::from(5_000),
+            })
+    }
+}
@@ -0,0 +1,241 @@
+//! Service accounts and their API keys, for backend services calling the ledger.
+//!
+//! A service account belongs to a user and acts on that user's accounts. Each API key
+//! carries scopes naming what it may do, and only the routes those scopes cover accept
+//! it; everything else, including admin routes, needs a user's token. Keys look like
+//! `rl_<prefix>_<secret>` and are sent as `Authorization: Bearer <key>`.
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sqlx::error::ErrorKind;
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use crate::api::session::{hash_token, random_token};
+use crate::ledger::Repository;
+use crate::store::service_accounts::NewApiKey;
+use crate::store::{self, Database};
+
+/// Marks a bearer token as an API key rather than a JWT.
+pub const KEY_PREFIX: &str = "rl_";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scope {
+    #[serde(rename = "accounts:read")]
+    AccountsRead,
+    #[serde(rename = "transactions:read")]
+    TransactionsRead,
+    #[serde(rename = "transactions:write")]
+    TransactionsWrite,
+    #[serde(rename = "payees:read")]
+    PayeesRead,
+    #[serde(rename = "payees:write")]
+    PayeesWrite,
+}
+
+/// The routes API keys may call, and the scope each needs. Reads of balances and
+/// transactions are only on the v2 routes, which show nothing but the owner's accounts.
+const SCOPED_ROUTES: &[(&str, &str, Scope)] = &[
+    ("GET", "/api/v1/account/lookup", Scope::AccountsRead),
+    ("GET", "/api/v1/account/limits", Scope::AccountsRead),
+    ("POST", "/api/v1/transaction/create", Scope::TransactionsWrite),
+    ("GET", "/api/v1/payee/all", Scope::PayeesRead),
+    ("POST", "/api/v1/payee/create", Scope::PayeesWrite),
+    ("POST", "/api/v1/payee/update", Scope::PayeesWrite),
+    ("POST", "/api/v1/payee/delete", Scope::PayeesWrite),
+    ("GET", "/api/v2/accounts/{id}", Scope::AccountsRead),
+    ("GET", "/api/v2/accounts/{id}/transactions", Scope::TransactionsRead),
+    ("POST", "/api/v2/transfers", Scope::TransactionsWrite),
+    ("GET", "/api/v2/transfers/{id}", Scope::TransactionsRead),
+];
+
+/// Whether `path` is one the route template matches, where `{name}` stands for any one
+/// segment.
+fn matches_route(template: &str, path: &str) -> bool {
+    let mut segments = path.split('/');
+    template.split('/').all(|expected| match segments.next() {
+        Some(segment) if expected.starts_with('{') => !segment.is_empty(),
+        Some(segment) => segment == expected,
+        None => false,
+    }) && segments.next().is_none()
+}
+
+impl Scope {
+    pub const ALL: [Scope; 5] = [
+        Scope::AccountsRead,
+        Scope::TransactionsRead,
+        Scope::TransactionsWrite,
+        Scope::PayeesRead,
+        Scope::PayeesWrite,
+    ];
+
+    /// The scope an API key needs to call `path`, or `None` if API keys cannot call it.
+    pub fn required_for(method: &str, path: &str) -> Option<Scope> {
+        SCOPED_ROUTES
+            .iter()
+            .find(|(route_method, route_path, _)| *route_method == method && matches_route(route_path, path))
+            .map(|(_, _, scope)| *scope)
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Scope::AccountsRead => write!(f, "accounts:read"),
+            Scope::TransactionsRead => write!(f, "transactions:read"),
+            Scope::TransactionsWrite => write!(f, "transactions:write"),
+            Scope::PayeesRead => write!(f, "payees:read"),
+            Scope::PayeesWrite => write!(f, "payees:write"),
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Scope::ALL
+            .into_iter()
+            .find(|scope| scope.to_string() == s)
+            .ok_or_else(|| anyhow!("unknown scope {}, expected one of {}", s, Scope::ALL.map(|scope| scope.to_string()).join(", ")))
+    }
+}
+
+/// A service account authenticated by one of its API keys.
+#[derive(Debug, Clone)]
+pub struct ServicePrincipal {
+    pub service_account_id: Uuid,
+    pub api_key_id: Uuid,
+    /// The user whose accounts the service acts on
+    pub owner_id: Uuid,
+    pub scopes: Vec<Scope>,
+}
+
+/// An API key as listed, without its secret.
+#[derive(Debug, Clone)]
+pub struct ApiKeyInfo {
+    pub prefix: String,
+    pub service_account: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<OffsetDateTime>,
+    pub last_used_at: Option<OffsetDateTime>,
+    pub revoked_at: Option<OffsetDateTime>,
+}
+
+/// Creates a service account acting on the accounts of the user with `owner_email`.
+pub async fn create(db: &Database, name: &str, owner_email: &str) -> anyhow::Result<Uuid> {
+    let mut conn = db.acquire().await?;
+
+    let owner_id = conn.find_user_by_email(owner_email).await?
+        .ok_or_else(|| anyhow!("no user with email {}", owner_email))?
+        .id;
+
+    let id = conn.insert_service_account(name, owner_id).await
+        .map_err(|e| match e {
+            e if store::violates(&e, ErrorKind::UniqueViolation) => anyhow!("service account {} already exists", name),
+            e => e,
+        })?;
+
+    Ok(id)
+}
+
+/// Mints a key for the named service account. The key is only ever returned here.
+pub async fn mint_key(db: &Database, service_account: &str, scopes: &[Scope], ttl: Option<Duration>) -> anyhow::Result<String> {
+    if scopes.is_empty() {
+        bail!("an API key needs at least one scope");
+    }
+
+    let mut conn = db.acquire().await?;
+
+    let service_account_id = conn.service_account_id(service_account).await?
+        .ok_or_else(|| anyhow!("no service account named {}", service_account))?;
+
+    let prefix = hex::encode(rand::rng().random::<[u8; 4]>());
+    let key = format!("{}{}_{}", KEY_PREFIX, prefix, random_token());
+    let scopes: Vec<String> = scopes.iter().map(Scope::to_string).collect();
+
+    conn.insert_api_key(&NewApiKey {
+        service_account_id,
+        prefix: &prefix,
+        key_hash: &hash_token(&key),
+        scopes: &scopes,
+        expires_at: ttl.map(|ttl| OffsetDateTime::now_utc() + ttl),
+    }).await?;
+
+    Ok(key)
+}
+
+/// Revokes the key with `prefix`, effective immediately.
+pub async fn revoke_key(db: &Database, prefix: &str) -> anyhow::Result<()> {
+    let mut conn = db.acquire().await?;
+
+    if !conn.revoke_api_key(prefix).await? {
+        bail!("no active API key with prefix {}", prefix);
+    }
+    Ok(())
+}
+
+pub async fn list_keys(db: &Database) -> anyhow::Result<Vec<ApiKeyInfo>> {
+    let mut conn = db.acquire().await?;
+
+    conn.api_keys().await
+}
+
+/// The service account behind `key`, if it is a live key. Records that it was used.
+pub async fn authenticate(db: &Database, key: &str) -> anyhow::Result<Option<ServicePrincipal>> {
+    let mut conn = db.acquire().await?;
+
+    let found = conn.live_api_key(&hash_token(key)).await?;
+
+    let Some(found) = found else {
+        return Ok(None);
+    };
+
+    // Coarse, like session last-seen tracking, so busy services do not write on every call
+    if found.last_used_at.is_none_or(|at| OffsetDateTime::now_utc() - at > Duration::minutes(1)) {
+        conn.touch_api_key(found.id).await?;
+    }
+
+    Ok(Some(ServicePrincipal {
+        service_account_id: found.service_account_id,
+        api_key_id: found.id,
+        owner_id: found.owner_id,
+        // Scopes dropped from a later version stop being honoured rather than failing
+        scopes: found.scopes.iter().filter_map(|scope| scope.parse().ok()).collect(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scopes_round_trip() {
+        for scope in Scope::ALL {
+            assert_eq!(scope.to_string().parse::<Scope>().unwrap(), scope);
+        }
+        assert!("transactions:delete".parse::<Scope>().is_err());
+    }
+
+    #[test]
+    fn only_listed_routes_take_api_keys() {
+        assert_eq!(Scope::required_for("POST", "/api/v1/transaction/create"), Some(Scope::TransactionsWrite));
+        assert_eq!(Scope::required_for("GET", "/api/v1/transaction/create"), None);
+        assert_eq!(Scope::required_for("POST", "/api/v1/admin/account/adjustBalance"), None);
+        assert_eq!(Scope::required_for("GET", "/api/v1/user/sessions"), None);
+    }
+
+    #[test]
+    fn templated_routes_match_one_segment_per_parameter() {
+        let id = "0b6f3a52-4d6f-4c1e-9a57-7b1c2a1f8e0d";
+        assert_eq!(Scope::required_for("GET", &format!("/api/v2/accounts/{}", id)), Some(Scope::AccountsRead));
+        assert_eq!(Scope::required_for("GET", &format!("/api/v2/accounts/{}/transactions", id)), Some(Scope::TransactionsRead));
+        assert_eq!(Scope::required_for("GET", "/api/v2/accounts/"), None);
+        assert_eq!(Scope::required_for("GET", &format!("/api/v2/accounts/{}/limits", id)), None);
+        assert_eq!(Scope::required_for("PATCH", "/api/v2/users/me"), None);
+    }
+}
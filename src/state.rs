@@ -1,5 +1,88 @@
-use sqlx::{Pool, Postgres};
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::keys::KeyStore;
+use crate::ledger::{AccountService, TransferService, UserService};
+use crate::mailer::{LogMailer, Mailer, SmtpMailer};
+use crate::oidc::OidcClient;
+use crate::risk::RiskEngine;
+use crate::store::Database;
+
 #[derive(Clone)]
 pub struct AppState {
-    pub db: Pool<Postgres>,
+    pub db: Database,
+    pub config: Arc<Config>,
+    pub risk: Arc<RiskEngine>,
+    pub mailer: Arc<dyn Mailer>,
+    /// Signs and verifies access tokens
+    pub keys: Arc<KeyStore>,
+    /// The identity provider staff sign in through, if one is configured
+    pub oidc: Option<Arc<OidcClient>>,
+}
+
+impl AppState {
+    pub fn new(db: impl Into<Database>) -> Self {
+        let config = Config::from_env();
+
+        let mailer: Arc<dyn Mailer> = match &config.smtp_url {
+            Some(url) => match SmtpMailer::new(url, &config.mail_from) {
+                Ok(mailer) => Arc::new(mailer),
+                Err(e) => {
+                    eprintln!("Invalid SMTP settings, logging emails instead: {}", e);
+                    Arc::new(LogMailer::new(config.mail_dir.clone()))
+                }
+            },
+            None => Arc::new(LogMailer::new(config.mail_dir.clone())),
+        };
+
+        // Tokens cannot be issued without keys, so a broken key setup stops startup
+        let keys = match &config.jwt_keys_dir {
+            Some(dir) => Arc::new(
+                KeyStore::load(dir, config.jwt_signing_kid.as_deref())
+                    .unwrap_or_else(|e| panic!("Failed to load JWT keys: {:#}", e))
+            ),
+            None => KeyStore::ephemeral(),
+        };
+
+        let oidc = OidcClient::from_config(&config).map(Arc::new);
+
+        AppState {
+            db: db.into(),
+            config: Arc::new(config),
+            risk: Arc::new(RiskEngine::default()),
+            mailer,
+            keys,
+            oidc,
+        }
+    }
+
+    pub fn accounts(&self) -> AccountService<Database> {
+        AccountService::new(self.db.clone(), self.config.clone())
+    }
+
+    pub fn transfers(&self) -> TransferService<Database> {
+        TransferService::new(self.db.clone(), self.config.clone())
+    }
+
+    pub fn users(&self) -> UserService<Database> {
+        UserService::new(self.db.clone(), self.config.clone())
+    }
+
+    /// Replaces the mailer, e.g. with a [`LogMailer`] writing to a known directory.
+    pub fn with_mailer(mut self, mailer: Arc<dyn Mailer>) -> Self {
+        self.mailer = mailer;
+        self
+    }
+
+    pub fn with_keys(mut self, keys: Arc<KeyStore>) -> Self {
+        self.keys = keys;
+        self
+    }
+
+    /// Replaces the configuration, and the identity provider client built from it.
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.oidc = OidcClient::from_config(&config).map(Arc::new);
+        self.config = Arc::new(config);
+        self
+    }
 }
@@ -0,0 +1,238 @@
+//! Operations waiting for a second user's approval.
+
+use async_trait::async_trait;
+use sqlx::PgConnection;
+#[cfg(feature = "sqlite")]
+use sqlx::{sqlite::SqliteRow, Row, SqliteConnection};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::api::approval::PendingApproval;
+#[cfg(feature = "sqlite")]
+use crate::ledger::sqlite::{timestamp, NOW};
+
+pub struct NewApproval<'a> {
+    pub kind: &'a str,
+    pub payload: serde_json::Value,
+    pub requested_by: Uuid,
+    pub expires_at: OffsetDateTime,
+}
+
+/// How an approval was decided.
+pub struct ApprovalDecision<'a> {
+    pub status: &'a str,
+    pub decided_by: Uuid,
+    pub note: Option<&'a str>,
+    pub transaction_id: Option<Uuid>,
+    pub review_id: Option<Uuid>,
+}
+
+#[async_trait]
+pub trait ApprovalStore: Send {
+    async fn insert_approval(&mut self, approval: &NewApproval<'_>) -> anyhow::Result<PendingApproval>;
+
+    /// Marks pending approvals that have lapsed as expired.
+    async fn expire_approvals(&mut self) -> anyhow::Result<()>;
+
+    /// Approvals with `status`, oldest first.
+    async fn approvals_with_status(&mut self, status: &str) -> anyhow::Result<Vec<PendingApproval>>;
+
+    /// An approval, locked until the unit of work ends.
+    async fn lock_approval(&mut self, id: Uuid) -> anyhow::Result<Option<PendingApproval>>;
+
+    async fn expire_approval(&mut self, id: Uuid) -> anyhow::Result<()>;
+
+    async fn decide_approval(&mut self, id: Uuid, decision: &ApprovalDecision<'_>) -> anyhow::Result<PendingApproval>;
+}
+
+#[async_trait]
+impl ApprovalStore for PgConnection {
+    async fn insert_approval(&mut self, approval: &NewApproval<'_>) -> anyhow::Result<PendingApproval> {
+        let pending = sqlx::query_as!(
+            PendingApproval,
+            r#"
+            INSERT INTO pending_approvals (kind, payload, requested_by, expires_at)
+            VALUES ($1, $2, $3, $4)
+            RETURNING *
+            "#,
+            approval.kind,
+            approval.payload,
+            approval.requested_by,
+            approval.expires_at
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(pending)
+    }
+
+    async fn expire_approvals(&mut self) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE pending_approvals SET status = 'expired' WHERE status = 'pending' AND expires_at <= now()"
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn approvals_with_status(&mut self, status: &str) -> anyhow::Result<Vec<PendingApproval>> {
+        let approvals = sqlx::query_as!(
+            PendingApproval,
+            "SELECT * FROM pending_approvals WHERE status = $1 ORDER BY created_at",
+            status
+        )
+        .fetch_all(self)
+        .await?;
+
+        Ok(approvals)
+    }
+
+    async fn lock_approval(&mut self, id: Uuid) -> anyhow::Result<Option<PendingApproval>> {
+        let pending = sqlx::query_as!(
+            PendingApproval,
+            "SELECT * FROM pending_approvals WHERE id = $1 FOR UPDATE",
+            id
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(pending)
+    }
+
+    async fn expire_approval(&mut self, id: Uuid) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE pending_approvals SET status = 'expired' WHERE id = $1",
+            id
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn decide_approval(&mut self, id: Uuid, decision: &ApprovalDecision<'_>) -> anyhow::Result<PendingApproval> {
+        let decided = sqlx::query_as!(
+            PendingApproval,
+            r#"
+            UPDATE pending_approvals
+            SET status = $2, decided_by = $3, decision_note = $4, transaction_id = $5, review_id = $6, decided_at = now()
+            WHERE id = $1
+            RETURNING *
+            "#,
+            id,
+            decision.status,
+            decision.decided_by,
+            decision.note,
+            decision.transaction_id,
+            decision.review_id
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(decided)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+const APPROVAL_COLUMNS: &str = "id, kind, payload, status, requested_by, decided_by, decision_note, transaction_id, expires_at, decided_at, created_at, review_id";
+
+#[cfg(feature = "sqlite")]
+fn pending_approval(row: SqliteRow) -> anyhow::Result<PendingApproval> {
+    Ok(PendingApproval {
+        id: row.try_get("id")?,
+        kind: row.try_get("kind")?,
+        payload: serde_json::from_str(row.try_get("payload")?)?,
+        status: row.try_get("status")?,
+        requested_by: row.try_get("requested_by")?,
+        decided_by: row.try_get("decided_by")?,
+        decision_note: row.try_get("decision_note")?,
+        transaction_id: row.try_get("transaction_id")?,
+        expires_at: row.try_get("expires_at")?,
+        decided_at: row.try_get("decided_at")?,
+        created_at: row.try_get("created_at")?,
+        review_id: row.try_get("review_id")?,
+    })
+}
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl ApprovalStore for SqliteConnection {
+    async fn insert_approval(&mut self, approval: &NewApproval<'_>) -> anyhow::Result<PendingApproval> {
+        let row = sqlx::query(&format!(
+            "INSERT INTO pending_approvals (id, kind, payload, requested_by, expires_at) VALUES (?, ?, ?, ?, ?) RETURNING {}",
+            APPROVAL_COLUMNS
+        ))
+        .bind(Uuid::new_v4())
+        .bind(approval.kind)
+        .bind(approval.payload.to_string())
+        .bind(approval.requested_by)
+        .bind(timestamp(approval.expires_at))
+        .fetch_one(self)
+        .await?;
+
+        pending_approval(row)
+    }
+
+    async fn expire_approvals(&mut self) -> anyhow::Result<()> {
+        sqlx::query(&format!(
+            "UPDATE pending_approvals SET status = 'expired' WHERE status = 'pending' AND expires_at <= {}",
+            NOW
+        ))
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn approvals_with_status(&mut self, status: &str) -> anyhow::Result<Vec<PendingApproval>> {
+        sqlx::query(&format!("SELECT {} FROM pending_approvals WHERE status = ? ORDER BY created_at", APPROVAL_COLUMNS))
+            .bind(status)
+            .fetch_all(self)
+            .await?
+            .into_iter()
+            .map(pending_approval)
+            .collect()
+    }
+
+    async fn lock_approval(&mut self, id: Uuid) -> anyhow::Result<Option<PendingApproval>> {
+        // A unit of work already holds the write lock, see `ledger::sqlite`
+        sqlx::query(&format!("SELECT {} FROM pending_approvals WHERE id = ?", APPROVAL_COLUMNS))
+            .bind(id)
+            .fetch_optional(self)
+            .await?
+            .map(pending_approval)
+            .transpose()
+    }
+
+    async fn expire_approval(&mut self, id: Uuid) -> anyhow::Result<()> {
+        sqlx::query("UPDATE pending_approvals SET status = 'expired' WHERE id = ?")
+            .bind(id)
+            .execute(self)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn decide_approval(&mut self, id: Uuid, decision: &ApprovalDecision<'_>) -> anyhow::Result<PendingApproval> {
+        let row = sqlx::query(&format!(
+            r#"
+            UPDATE pending_approvals
+            SET status = ?, decided_by = ?, decision_note = ?, transaction_id = ?, review_id = ?, decided_at = {}
+            WHERE id = ?
+            RETURNING {}
+            "#,
+            NOW, APPROVAL_COLUMNS
+        ))
+        .bind(decision.status)
+        .bind(decision.decided_by)
+        .bind(decision.note)
+        .bind(decision.transaction_id)
+        .bind(decision.review_id)
+        .bind(id)
+        .fetch_one(self)
+        .await?;
+
+        pending_approval(row)
+    }
+}
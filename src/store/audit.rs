@@ -0,0 +1,64 @@
+//! The audit log, for changes the database does not record by itself.
+
+use async_trait::async_trait;
+use sqlx::PgConnection;
+#[cfg(feature = "sqlite")]
+use sqlx::SqliteConnection;
+use uuid::Uuid;
+
+pub struct AuditEntry<'a> {
+    pub entity_type: &'a str,
+    pub entity_id: Uuid,
+    pub operation: &'a str,
+    pub performed_by: Option<Uuid>,
+    pub after_state: serde_json::Value,
+}
+
+#[async_trait]
+pub trait AuditStore: Send {
+    async fn insert_audit_log(&mut self, entry: &AuditEntry<'_>) -> anyhow::Result<()>;
+}
+
+#[async_trait]
+impl AuditStore for PgConnection {
+    async fn insert_audit_log(&mut self, entry: &AuditEntry<'_>) -> anyhow::Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO audit_logs (entity_type, entity_id, operation, performed_by, after_state)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            entry.entity_type,
+            entry.entity_id,
+            entry.operation,
+            entry.performed_by,
+            entry.after_state
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl AuditStore for SqliteConnection {
+    async fn insert_audit_log(&mut self, entry: &AuditEntry<'_>) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO audit_logs (id, entity_type, entity_id, operation, performed_by, after_state)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#
+        )
+        .bind(Uuid::new_v4())
+        .bind(entry.entity_type)
+        .bind(entry.entity_id)
+        .bind(entry.operation)
+        .bind(entry.performed_by)
+        .bind(entry.after_state.to_string())
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+}
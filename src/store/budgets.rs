@@ -0,0 +1,345 @@
+//! Monthly budgets and the alerts, shown to users as notifications, raised when spending
+//! crosses them.
+
+use async_trait::async_trait;
+use bigdecimal::BigDecimal;
+use sqlx::PgConnection;
+#[cfg(feature = "sqlite")]
+use sqlx::{sqlite::SqliteRow, Row, SqliteConnection};
+use time::Date;
+use uuid::Uuid;
+
+use crate::api::budget::Budget;
+use crate::api::notification::Notification;
+#[cfg(feature = "sqlite")]
+use crate::ledger::sqlite::{decimal, numeric_text, timestamp, NOW};
+
+pub struct NewBudget<'a> {
+    pub user_id: Uuid,
+    pub account_id: Uuid,
+    pub category: Option<&'a str>,
+    pub amount: &'a BigDecimal,
+}
+
+/// A budget with what has been spent against it in a period.
+pub struct BudgetSpending {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub amount: BigDecimal,
+    pub spent: BigDecimal,
+}
+
+pub struct NewAlert<'a> {
+    pub budget_id: Uuid,
+    pub user_id: Uuid,
+    pub threshold: i32,
+    pub period_start: Date,
+    pub spent: &'a BigDecimal,
+}
+
+#[async_trait]
+pub trait BudgetStore: Send {
+    /// Creates the budget for an account and category, or replaces its amount.
+    async fn upsert_budget(&mut self, budget: &NewBudget<'_>) -> anyhow::Result<Budget>;
+
+    /// A user's budgets, oldest first.
+    async fn budgets_of(&mut self, user_id: Uuid) -> anyhow::Result<Vec<Budget>>;
+
+    /// Deletes one of `user_id`'s budgets, returning whether they had it.
+    async fn delete_budget(&mut self, user_id: Uuid, id: Uuid) -> anyhow::Result<bool>;
+
+    /// The budgets on an account, with what was sent out of it in their category since `period_start`.
+    async fn budget_spending(&mut self, account_id: Uuid, period_start: Date) -> anyhow::Result<Vec<BudgetSpending>>;
+
+    /// Records an alert, unless one was already raised for the budget, period and threshold.
+    async fn insert_alert(&mut self, alert: &NewAlert<'_>) -> anyhow::Result<()>;
+
+    /// A user's alerts, newest first.
+    async fn notifications_of(&mut self, user_id: Uuid) -> anyhow::Result<Vec<Notification>>;
+
+    /// Marks one of `user_id`'s alerts read, returning whether they had it.
+    async fn mark_notification_read(&mut self, user_id: Uuid, id: Uuid) -> anyhow::Result<bool>;
+}
+
+#[async_trait]
+impl BudgetStore for PgConnection {
+    async fn upsert_budget(&mut self, budget: &NewBudget<'_>) -> anyhow::Result<Budget> {
+        let budget = sqlx::query_as!(
+            Budget,
+            r#"
+            INSERT INTO budgets (user_id, account_id, category, amount)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (account_id, COALESCE(category, '')) DO UPDATE SET amount = EXCLUDED.amount
+            RETURNING id, account_id, category, amount, created_at
+            "#,
+            budget.user_id,
+            budget.account_id,
+            budget.category,
+            budget.amount
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(budget)
+    }
+
+    async fn budgets_of(&mut self, user_id: Uuid) -> anyhow::Result<Vec<Budget>> {
+        let budgets = sqlx::query_as!(
+            Budget,
+            "SELECT id, account_id, category, amount, created_at FROM budgets WHERE user_id = $1 ORDER BY created_at",
+            user_id
+        )
+        .fetch_all(self)
+        .await?;
+
+        Ok(budgets)
+    }
+
+    async fn delete_budget(&mut self, user_id: Uuid, id: Uuid) -> anyhow::Result<bool> {
+        let deleted = sqlx::query!(
+            "DELETE FROM budgets WHERE id = $1 AND user_id = $2",
+            id,
+            user_id
+        )
+        .execute(self)
+        .await?;
+
+        Ok(deleted.rows_affected() > 0)
+    }
+
+    async fn budget_spending(&mut self, account_id: Uuid, period_start: Date) -> anyhow::Result<Vec<BudgetSpending>> {
+        let budgets = sqlx::query_as!(
+            BudgetSpending,
+            r#"
+            SELECT b.id, b.user_id, b.amount,
+                   COALESCE(SUM(t.amount), 0) AS "spent!"
+            FROM budgets b
+            LEFT JOIN transactions t
+                ON t.from_account_id = b.account_id
+               AND t.created_at >= $2::date
+               AND (b.category IS NULL OR t.category = b.category)
+            WHERE b.account_id = $1
+            GROUP BY b.id
+            "#,
+            account_id,
+            period_start
+        )
+        .fetch_all(self)
+        .await?;
+
+        Ok(budgets)
+    }
+
+    async fn insert_alert(&mut self, alert: &NewAlert<'_>) -> anyhow::Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO budget_alerts (budget_id, user_id, threshold, period_start, spent)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (budget_id, period_start, threshold) DO NOTHING
+            "#,
+            alert.budget_id,
+            alert.user_id,
+            alert.threshold,
+            alert.period_start,
+            alert.spent
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn notifications_of(&mut self, user_id: Uuid) -> anyhow::Result<Vec<Notification>> {
+        let notifications = sqlx::query_as!(
+            Notification,
+            r#"
+            SELECT a.id, a.budget_id, b.account_id, b.category, b.amount AS budget_amount,
+                   a.threshold, a.spent, a.period_start, a.read_at, a.created_at
+            FROM budget_alerts a
+            JOIN budgets b ON b.id = a.budget_id
+            WHERE a.user_id = $1
+            ORDER BY a.created_at DESC
+            "#,
+            user_id
+        )
+        .fetch_all(self)
+        .await?;
+
+        Ok(notifications)
+    }
+
+    async fn mark_notification_read(&mut self, user_id: Uuid, id: Uuid) -> anyhow::Result<bool> {
+        let updated = sqlx::query!(
+            "UPDATE budget_alerts SET read_at = COALESCE(read_at, now()) WHERE id = $1 AND user_id = $2",
+            id,
+            user_id
+        )
+        .execute(self)
+        .await?;
+
+        Ok(updated.rows_affected() > 0)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn budget(row: SqliteRow) -> anyhow::Result<Budget> {
+    Ok(Budget {
+        id: row.try_get("id")?,
+        account_id: row.try_get("account_id")?,
+        category: row.try_get("category")?,
+        amount: decimal(row.try_get("amount")?)?,
+        created_at: row.try_get("created_at")?,
+    })
+}
+
+#[cfg(feature = "sqlite")]
+fn notification(row: SqliteRow) -> anyhow::Result<Notification> {
+    Ok(Notification {
+        id: row.try_get("id")?,
+        budget_id: row.try_get("budget_id")?,
+        account_id: row.try_get("account_id")?,
+        category: row.try_get("category")?,
+        budget_amount: decimal(row.try_get("budget_amount")?)?,
+        threshold: row.try_get("threshold")?,
+        spent: decimal(row.try_get("spent")?)?,
+        period_start: row.try_get("period_start")?,
+        read_at: row.try_get("read_at")?,
+        created_at: row.try_get("created_at")?,
+    })
+}
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl BudgetStore for SqliteConnection {
+    async fn upsert_budget(&mut self, budget: &NewBudget<'_>) -> anyhow::Result<Budget> {
+        let row = sqlx::query(
+            r#"
+            INSERT INTO budgets (id, user_id, account_id, category, amount)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT (account_id, COALESCE(category, '')) DO UPDATE SET amount = excluded.amount
+            RETURNING id, account_id, category, amount, created_at
+            "#
+        )
+        .bind(Uuid::new_v4())
+        .bind(budget.user_id)
+        .bind(budget.account_id)
+        .bind(budget.category)
+        .bind(numeric_text(budget.amount)?)
+        .fetch_one(self)
+        .await?;
+
+        self::budget(row)
+    }
+
+    async fn budgets_of(&mut self, user_id: Uuid) -> anyhow::Result<Vec<Budget>> {
+        sqlx::query("SELECT id, account_id, category, amount, created_at FROM budgets WHERE user_id = ? ORDER BY created_at")
+            .bind(user_id)
+            .fetch_all(self)
+            .await?
+            .into_iter()
+            .map(budget)
+            .collect()
+    }
+
+    async fn delete_budget(&mut self, user_id: Uuid, id: Uuid) -> anyhow::Result<bool> {
+        let deleted = sqlx::query("DELETE FROM budgets WHERE id = ? AND user_id = ?")
+            .bind(id)
+            .bind(user_id)
+            .execute(self)
+            .await?;
+
+        Ok(deleted.rows_affected() > 0)
+    }
+
+    async fn budget_spending(&mut self, account_id: Uuid, period_start: Date) -> anyhow::Result<Vec<BudgetSpending>> {
+        let budgets = sqlx::query("SELECT id, user_id, category, amount FROM budgets WHERE account_id = ?")
+            .bind(account_id)
+            .fetch_all(&mut *self)
+            .await?;
+
+        if budgets.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Amounts are text, so they are added up here rather than by SUM
+        let sent = sqlx::query("SELECT amount, category FROM transactions WHERE from_account_id = ? AND created_at >= ?")
+            .bind(account_id)
+            .bind(timestamp(period_start.midnight().assume_utc()))
+            .fetch_all(self)
+            .await?
+            .into_iter()
+            .map(|row| Ok((decimal(row.try_get("amount")?)?, row.try_get::<Option<String>, _>("category")?)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        budgets
+            .into_iter()
+            .map(|row| {
+                let category: Option<String> = row.try_get("category")?;
+                let spent = sent
+                    .iter()
+                    .filter(|(_, sent_category)| category.is_none() || *sent_category == category)
+                    .map(|(amount, _)| amount)
+                    .sum();
+
+                Ok(BudgetSpending {
+                    id: row.try_get("id")?,
+                    user_id: row.try_get("user_id")?,
+                    amount: decimal(row.try_get("amount")?)?,
+                    spent,
+                })
+            })
+            .collect()
+    }
+
+    async fn insert_alert(&mut self, alert: &NewAlert<'_>) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO budget_alerts (id, budget_id, user_id, threshold, period_start, spent)
+            VALUES (?, ?, ?, ?, ?, ?)
+            ON CONFLICT (budget_id, period_start, threshold) DO NOTHING
+            "#
+        )
+        .bind(Uuid::new_v4())
+        .bind(alert.budget_id)
+        .bind(alert.user_id)
+        .bind(alert.threshold)
+        .bind(alert.period_start)
+        .bind(numeric_text(alert.spent)?)
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn notifications_of(&mut self, user_id: Uuid) -> anyhow::Result<Vec<Notification>> {
+        sqlx::query(
+            r#"
+            SELECT a.id, a.budget_id, b.account_id, b.category, b.amount AS budget_amount,
+                   a.threshold, a.spent, a.period_start, a.read_at, a.created_at
+            FROM budget_alerts a
+            JOIN budgets b ON b.id = a.budget_id
+            WHERE a.user_id = ?
+            ORDER BY a.created_at DESC
+            "#
+        )
+        .bind(user_id)
+        .fetch_all(self)
+        .await?
+        .into_iter()
+        .map(notification)
+        .collect()
+    }
+
+    async fn mark_notification_read(&mut self, user_id: Uuid, id: Uuid) -> anyhow::Result<bool> {
+        let updated = sqlx::query(&format!(
+            "UPDATE budget_alerts SET read_at = COALESCE(read_at, {}) WHERE id = ? AND user_id = ?",
+            NOW
+        ))
+        .bind(id)
+        .bind(user_id)
+        .execute(self)
+        .await?;
+
+        Ok(updated.rows_affected() > 0)
+    }
+}
@@ -0,0 +1,244 @@
+//! Single-use links sent by email, and what following them changes.
+
+use async_trait::async_trait;
+use sqlx::PgConnection;
+#[cfg(feature = "sqlite")]
+use sqlx::{Row, SqliteConnection};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+#[cfg(feature = "sqlite")]
+use crate::ledger::sqlite::{timestamp, NOW};
+
+pub struct NewEmailToken<'a> {
+    pub user_id: Uuid,
+    pub purpose: &'a str,
+    pub email: &'a str,
+    pub token_hash: &'a str,
+    pub expires_at: OffsetDateTime,
+}
+
+/// The user and address a token was issued for.
+pub struct Redeemed {
+    pub user_id: Uuid,
+    pub email: String,
+}
+
+#[async_trait]
+pub trait EmailTokenStore: Send {
+    /// Stores a token, using up the user's earlier unused tokens with the same purpose.
+    async fn insert_email_token(&mut self, token: &NewEmailToken<'_>) -> anyhow::Result<()>;
+
+    /// The user an unused, unexpired token was issued to, leaving the token as it is.
+    async fn email_token_user(&mut self, token_hash: &str, purpose: &str) -> anyhow::Result<Option<Uuid>>;
+
+    /// Uses up an unused, unexpired token.
+    async fn redeem_email_token(&mut self, token_hash: &str, purpose: &str) -> anyhow::Result<Option<Redeemed>>;
+
+    /// Replaces a password from a reset link, which also verifies `email` if it is still
+    /// the user's address.
+    async fn reset_password(&mut self, user_id: Uuid, password_hash: &str, email: &str) -> anyhow::Result<()>;
+
+    /// Marks `email` verified, making it the user's address if it was pending. Returns
+    /// whether it was still either.
+    async fn verify_email(&mut self, user_id: Uuid, email: &str) -> anyhow::Result<bool>;
+}
+
+#[async_trait]
+impl EmailTokenStore for PgConnection {
+    async fn insert_email_token(&mut self, token: &NewEmailToken<'_>) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE email_tokens SET used_at = now() WHERE user_id = $1 AND purpose = $2 AND used_at IS NULL",
+            token.user_id,
+            token.purpose
+        )
+        .execute(&mut *self)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO email_tokens (user_id, purpose, email, token_hash, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            token.user_id,
+            token.purpose,
+            token.email,
+            token.token_hash,
+            token.expires_at
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn email_token_user(&mut self, token_hash: &str, purpose: &str) -> anyhow::Result<Option<Uuid>> {
+        let user_id = sqlx::query_scalar!(
+            r#"
+            SELECT user_id FROM email_tokens
+            WHERE token_hash = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > now()
+            "#,
+            token_hash,
+            purpose
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(user_id)
+    }
+
+    async fn redeem_email_token(&mut self, token_hash: &str, purpose: &str) -> anyhow::Result<Option<Redeemed>> {
+        let redeemed = sqlx::query_as!(
+            Redeemed,
+            r#"
+            UPDATE email_tokens SET used_at = now()
+            WHERE token_hash = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > now()
+            RETURNING user_id, email
+            "#,
+            token_hash,
+            purpose
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(redeemed)
+    }
+
+    async fn reset_password(&mut self, user_id: Uuid, password_hash: &str, email: &str) -> anyhow::Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE users SET password_hash = $2,
+                email_verified_at = CASE WHEN email = $3 THEN COALESCE(email_verified_at, now()) ELSE email_verified_at END
+            WHERE id = $1
+            "#,
+            user_id,
+            password_hash,
+            email
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn verify_email(&mut self, user_id: Uuid, email: &str) -> anyhow::Result<bool> {
+        let verified = sqlx::query!(
+            r#"
+            UPDATE users SET
+                email = $2,
+                pending_email = CASE WHEN pending_email = $2 THEN NULL ELSE pending_email END,
+                email_verified_at = now()
+            WHERE id = $1 AND (email = $2 OR pending_email = $2)
+            "#,
+            user_id,
+            email
+        )
+        .execute(self)
+        .await?;
+
+        Ok(verified.rows_affected() > 0)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl EmailTokenStore for SqliteConnection {
+    async fn insert_email_token(&mut self, token: &NewEmailToken<'_>) -> anyhow::Result<()> {
+        sqlx::query(&format!(
+            "UPDATE email_tokens SET used_at = {} WHERE user_id = ? AND purpose = ? AND used_at IS NULL",
+            NOW
+        ))
+        .bind(token.user_id)
+        .bind(token.purpose)
+        .execute(&mut *self)
+        .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO email_tokens (id, user_id, purpose, email, token_hash, expires_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#
+        )
+        .bind(Uuid::new_v4())
+        .bind(token.user_id)
+        .bind(token.purpose)
+        .bind(token.email)
+        .bind(token.token_hash)
+        .bind(timestamp(token.expires_at))
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn email_token_user(&mut self, token_hash: &str, purpose: &str) -> anyhow::Result<Option<Uuid>> {
+        let user_id = sqlx::query_scalar(&format!(
+            r#"
+            SELECT user_id FROM email_tokens
+            WHERE token_hash = ? AND purpose = ? AND used_at IS NULL AND expires_at > {}
+            "#,
+            NOW
+        ))
+        .bind(token_hash)
+        .bind(purpose)
+        .fetch_optional(self)
+        .await?;
+
+        Ok(user_id)
+    }
+
+    async fn redeem_email_token(&mut self, token_hash: &str, purpose: &str) -> anyhow::Result<Option<Redeemed>> {
+        let row = sqlx::query(&format!(
+            r#"
+            UPDATE email_tokens SET used_at = {0}
+            WHERE token_hash = ? AND purpose = ? AND used_at IS NULL AND expires_at > {0}
+            RETURNING user_id, email
+            "#,
+            NOW
+        ))
+        .bind(token_hash)
+        .bind(purpose)
+        .fetch_optional(self)
+        .await?;
+
+        row.map(|row| Ok(Redeemed { user_id: row.try_get("user_id")?, email: row.try_get("email")? }))
+            .transpose()
+    }
+
+    async fn reset_password(&mut self, user_id: Uuid, password_hash: &str, email: &str) -> anyhow::Result<()> {
+        sqlx::query(&format!(
+            r#"
+            UPDATE users SET password_hash = ?2,
+                email_verified_at = CASE WHEN email = ?3 THEN COALESCE(email_verified_at, {}) ELSE email_verified_at END
+            WHERE id = ?1
+            "#,
+            NOW
+        ))
+        .bind(user_id)
+        .bind(password_hash)
+        .bind(email)
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn verify_email(&mut self, user_id: Uuid, email: &str) -> anyhow::Result<bool> {
+        let verified = sqlx::query(&format!(
+            r#"
+            UPDATE users SET
+                email = ?2,
+                pending_email = CASE WHEN pending_email = ?2 THEN NULL ELSE pending_email END,
+                email_verified_at = {}
+            WHERE id = ?1 AND (email = ?2 OR pending_email = ?2)
+            "#,
+            NOW
+        ))
+        .bind(user_id)
+        .bind(email)
+        .execute(self)
+        .await?;
+
+        Ok(verified.rows_affected() > 0)
+    }
+}
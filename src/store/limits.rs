@@ -0,0 +1,338 @@
+//! Transfer limits, their temporary overrides, and the transfers counted against them.
+
+use async_trait::async_trait;
+use bigdecimal::BigDecimal;
+use sqlx::PgConnection;
+#[cfg(feature = "sqlite")]
+use sqlx::{sqlite::SqliteRow, Row, SqliteConnection};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::api::limit::{LimitOverride, Limits};
+#[cfg(feature = "sqlite")]
+use crate::ledger::sqlite::{decimal, numeric_text, timestamp, NOW};
+
+/// Who a set of limits applies to.
+pub enum LimitTarget<'a> {
+    Account(Uuid),
+    AccountType(&'a str),
+}
+
+pub struct NewOverride<'a> {
+    pub account_id: Uuid,
+    pub limit_kind: &'a str,
+    pub value: Option<&'a BigDecimal>,
+    pub expires_at: OffsetDateTime,
+    pub created_by: Uuid,
+}
+
+/// An override in force, the most recent of its kind.
+pub struct ActiveOverride {
+    pub limit_kind: String,
+    pub value: Option<BigDecimal>,
+}
+
+/// What an account has sent in the windows its limits apply to.
+pub struct Usage {
+    pub daily: BigDecimal,
+    pub weekly: BigDecimal,
+    pub hourly: i64,
+}
+
+#[async_trait]
+pub trait LimitStore: Send {
+    /// The limits set on an account, falling back to those on its type limit by limit.
+    /// `None` for an unknown account.
+    async fn limits_of(&mut self, account_id: Uuid) -> anyhow::Result<Option<Limits>>;
+
+    async fn active_overrides(&mut self, account_id: Uuid) -> anyhow::Result<Vec<ActiveOverride>>;
+
+    /// Replaces the limits set on an account or account type.
+    async fn set_limits(&mut self, target: &LimitTarget<'_>, limits: &Limits) -> anyhow::Result<()>;
+
+    async fn insert_override(&mut self, limit_override: &NewOverride<'_>) -> anyhow::Result<LimitOverride>;
+
+    /// Transfers out of an account since `week_start`, summed since `day_start` and in
+    /// all, and counted since `hour_start`.
+    async fn usage(&mut self, account_id: Uuid, hour_start: OffsetDateTime, day_start: OffsetDateTime, week_start: OffsetDateTime) -> anyhow::Result<Usage>;
+}
+
+#[async_trait]
+impl LimitStore for PgConnection {
+    async fn limits_of(&mut self, account_id: Uuid) -> anyhow::Result<Option<Limits>> {
+        let limits = sqlx::query_as!(
+            Limits,
+            r#"
+            SELECT COALESCE(a.per_transaction, t.per_transaction) AS per_transaction,
+                   COALESCE(a.daily_amount, t.daily_amount) AS daily_amount,
+                   COALESCE(a.weekly_amount, t.weekly_amount) AS weekly_amount,
+                   COALESCE(a.hourly_count, t.hourly_count) AS hourly_count
+            FROM accounts acc
+            LEFT JOIN transfer_limits a ON a.account_id = acc.id
+            LEFT JOIN transfer_limits t ON t.account_type = acc.account_type
+            WHERE acc.id = $1
+            "#,
+            account_id
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(limits)
+    }
+
+    async fn active_overrides(&mut self, account_id: Uuid) -> anyhow::Result<Vec<ActiveOverride>> {
+        let overrides = sqlx::query_as!(
+            ActiveOverride,
+            r#"
+            SELECT DISTINCT ON (limit_kind) limit_kind, value
+            FROM limit_overrides
+            WHERE account_id = $1 AND expires_at > now()
+            ORDER BY limit_kind, created_at DESC
+            "#,
+            account_id
+        )
+        .fetch_all(self)
+        .await?;
+
+        Ok(overrides)
+    }
+
+    async fn set_limits(&mut self, target: &LimitTarget<'_>, limits: &Limits) -> anyhow::Result<()> {
+        match target {
+            LimitTarget::Account(account_id) => sqlx::query!(
+                r#"
+                INSERT INTO transfer_limits (account_id, per_transaction, daily_amount, weekly_amount, hourly_count)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (account_id) DO UPDATE SET
+                    per_transaction = EXCLUDED.per_transaction,
+                    daily_amount = EXCLUDED.daily_amount,
+                    weekly_amount = EXCLUDED.weekly_amount,
+                    hourly_count = EXCLUDED.hourly_count,
+                    updated_at = now()
+                "#,
+                account_id,
+                limits.per_transaction,
+                limits.daily_amount,
+                limits.weekly_amount,
+                limits.hourly_count
+            )
+            .execute(self)
+            .await?,
+            LimitTarget::AccountType(account_type) => sqlx::query!(
+                r#"
+                INSERT INTO transfer_limits (account_type, per_transaction, daily_amount, weekly_amount, hourly_count)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (account_type) DO UPDATE SET
+                    per_transaction = EXCLUDED.per_transaction,
+                    daily_amount = EXCLUDED.daily_amount,
+                    weekly_amount = EXCLUDED.weekly_amount,
+                    hourly_count = EXCLUDED.hourly_count,
+                    updated_at = now()
+                "#,
+                account_type,
+                limits.per_transaction,
+                limits.daily_amount,
+                limits.weekly_amount,
+                limits.hourly_count
+            )
+            .execute(self)
+            .await?,
+        };
+
+        Ok(())
+    }
+
+    async fn insert_override(&mut self, limit_override: &NewOverride<'_>) -> anyhow::Result<LimitOverride> {
+        let created = sqlx::query_as!(
+            LimitOverride,
+            r#"
+            INSERT INTO limit_overrides (account_id, limit_kind, value, expires_at, created_by)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, account_id, limit_kind, value, expires_at
+            "#,
+            limit_override.account_id,
+            limit_override.limit_kind,
+            limit_override.value,
+            limit_override.expires_at,
+            limit_override.created_by
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(created)
+    }
+
+    async fn usage(&mut self, account_id: Uuid, hour_start: OffsetDateTime, day_start: OffsetDateTime, week_start: OffsetDateTime) -> anyhow::Result<Usage> {
+        let usage = sqlx::query_as!(
+            Usage,
+            r#"
+            SELECT COALESCE(SUM(amount) FILTER (WHERE created_at >= $2), 0) AS "daily!",
+                   COALESCE(SUM(amount), 0) AS "weekly!",
+                   COUNT(*) FILTER (WHERE created_at >= $3) AS "hourly!"
+            FROM transactions
+            WHERE from_account_id = $1 AND created_at >= $4
+            "#,
+            account_id,
+            day_start,
+            hour_start,
+            week_start
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(usage)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn optional_decimal(row: &SqliteRow, column: &str) -> anyhow::Result<Option<BigDecimal>> {
+    row.try_get::<Option<&str>, _>(column)?.map(decimal).transpose()
+}
+
+#[cfg(feature = "sqlite")]
+fn optional_text(value: Option<&BigDecimal>) -> anyhow::Result<Option<String>> {
+    value.map(numeric_text).transpose()
+}
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl LimitStore for SqliteConnection {
+    async fn limits_of(&mut self, account_id: Uuid) -> anyhow::Result<Option<Limits>> {
+        let row = sqlx::query(
+            r#"
+            SELECT COALESCE(a.per_transaction, t.per_transaction) AS per_transaction,
+                   COALESCE(a.daily_amount, t.daily_amount) AS daily_amount,
+                   COALESCE(a.weekly_amount, t.weekly_amount) AS weekly_amount,
+                   COALESCE(a.hourly_count, t.hourly_count) AS hourly_count
+            FROM accounts acc
+            LEFT JOIN transfer_limits a ON a.account_id = acc.id
+            LEFT JOIN transfer_limits t ON t.account_type = acc.account_type
+            WHERE acc.id = ?
+            "#
+        )
+        .bind(account_id)
+        .fetch_optional(self)
+        .await?;
+
+        row.map(|row| {
+            Ok(Limits {
+                per_transaction: optional_decimal(&row, "per_transaction")?,
+                daily_amount: optional_decimal(&row, "daily_amount")?,
+                weekly_amount: optional_decimal(&row, "weekly_amount")?,
+                hourly_count: row.try_get("hourly_count")?,
+            })
+        })
+        .transpose()
+    }
+
+    async fn active_overrides(&mut self, account_id: Uuid) -> anyhow::Result<Vec<ActiveOverride>> {
+        let mut overrides = sqlx::query(&format!(
+            r#"
+            SELECT limit_kind, value
+            FROM limit_overrides
+            WHERE account_id = ? AND expires_at > {}
+            ORDER BY limit_kind, created_at DESC, rowid DESC
+            "#,
+            NOW
+        ))
+        .bind(account_id)
+        .fetch_all(self)
+        .await?
+        .into_iter()
+        .map(|row| Ok(ActiveOverride { limit_kind: row.try_get("limit_kind")?, value: optional_decimal(&row, "value")? }))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+        // The most recent of each kind, as DISTINCT ON picks in PostgreSQL
+        overrides.dedup_by(|later, first| later.limit_kind == first.limit_kind);
+
+        Ok(overrides)
+    }
+
+    async fn set_limits(&mut self, target: &LimitTarget<'_>, limits: &Limits) -> anyhow::Result<()> {
+        let column = match target {
+            LimitTarget::Account(_) => "account_id",
+            LimitTarget::AccountType(_) => "account_type",
+        };
+        let sql = format!(
+            r#"
+            INSERT INTO transfer_limits (id, {0}, per_transaction, daily_amount, weekly_amount, hourly_count)
+            VALUES (?, ?, ?, ?, ?, ?)
+            ON CONFLICT ({0}) DO UPDATE SET
+                per_transaction = excluded.per_transaction,
+                daily_amount = excluded.daily_amount,
+                weekly_amount = excluded.weekly_amount,
+                hourly_count = excluded.hourly_count,
+                updated_at = {1}
+            "#,
+            column, NOW
+        );
+
+        let query = sqlx::query(&sql).bind(Uuid::new_v4());
+        let query = match target {
+            LimitTarget::Account(account_id) => query.bind(*account_id),
+            LimitTarget::AccountType(account_type) => query.bind(*account_type),
+        };
+
+        query
+            .bind(optional_text(limits.per_transaction.as_ref())?)
+            .bind(optional_text(limits.daily_amount.as_ref())?)
+            .bind(optional_text(limits.weekly_amount.as_ref())?)
+            .bind(limits.hourly_count)
+            .execute(self)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn insert_override(&mut self, limit_override: &NewOverride<'_>) -> anyhow::Result<LimitOverride> {
+        let row = sqlx::query(
+            r#"
+            INSERT INTO limit_overrides (id, account_id, limit_kind, value, expires_at, created_by)
+            VALUES (?, ?, ?, ?, ?, ?)
+            RETURNING id, account_id, limit_kind, value, expires_at
+            "#
+        )
+        .bind(Uuid::new_v4())
+        .bind(limit_override.account_id)
+        .bind(limit_override.limit_kind)
+        .bind(optional_text(limit_override.value)?)
+        .bind(timestamp(limit_override.expires_at))
+        .bind(limit_override.created_by)
+        .fetch_one(self)
+        .await?;
+
+        Ok(LimitOverride {
+            id: row.try_get("id")?,
+            account_id: row.try_get("account_id")?,
+            limit_kind: row.try_get("limit_kind")?,
+            value: optional_decimal(&row, "value")?,
+            expires_at: row.try_get("expires_at")?,
+        })
+    }
+
+    async fn usage(&mut self, account_id: Uuid, hour_start: OffsetDateTime, day_start: OffsetDateTime, week_start: OffsetDateTime) -> anyhow::Result<Usage> {
+        let sent = sqlx::query("SELECT amount, created_at FROM transactions WHERE from_account_id = ? AND created_at >= ?")
+            .bind(account_id)
+            .bind(timestamp(week_start))
+            .fetch_all(self)
+            .await?;
+
+        // Amounts are text, so they are added up here rather than by SUM
+        let mut usage = Usage { daily: BigDecimal::from(0), weekly: BigDecimal::from(0), hourly: 0 };
+
+        for row in sent {
+            let amount = decimal(row.try_get("amount")?)?;
+            let created_at: OffsetDateTime = row.try_get("created_at")?;
+
+            if created_at >= hour_start {
+                usage.hourly += 1;
+            }
+            if created_at >= day_start {
+                usage.daily += &amount;
+            }
+            usage.weekly += amount;
+        }
+
+        Ok(usage)
+    }
+}
@@ -0,0 +1,224 @@
+//! Storage for the HTTP api: sessions, two-factor logins, throttles, limits, payees,
+//! approvals, reviews, budgets, service accounts and emailed tokens, next to the ledger's
+//! own users, accounts and transactions.
+//!
+//! Each area has a trait here, implemented for a PostgreSQL connection and, with the
+//! `sqlite` feature, a SQLite one. [`Storage`] is all of them together with the ledger's
+//! [`Store`], and [`Database`] the [`Repository`] handing it out, so the handlers and the
+//! ledger's services share connections and units of work whichever database the server
+//! runs on.
+
+use std::ops::{Deref, DerefMut};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use sqlx::error::ErrorKind;
+use sqlx::pool::PoolConnection;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::{PgConnection, Pool, Postgres};
+#[cfg(feature = "sqlite")]
+use sqlx::{Sqlite, SqliteConnection};
+
+use crate::ledger::{Repository, Store};
+
+pub mod approvals;
+pub mod audit;
+pub mod budgets;
+pub mod email_tokens;
+pub mod limits;
+pub mod payees;
+pub mod reviews;
+pub mod risk;
+pub mod service_accounts;
+pub mod sessions;
+pub mod staff;
+pub mod throttles;
+pub mod two_factor;
+
+pub use approvals::ApprovalStore;
+pub use audit::AuditStore;
+pub use budgets::BudgetStore;
+pub use email_tokens::EmailTokenStore;
+pub use limits::LimitStore;
+pub use payees::PayeeStore;
+pub use reviews::ReviewStore;
+pub use risk::RiskStore;
+pub use service_accounts::ServiceAccountStore;
+pub use sessions::SessionStore;
+pub use staff::StaffStore;
+pub use throttles::ThrottleStore;
+pub use two_factor::TwoFactorStore;
+
+/// Everything the server stores, on one connection or in one unit of work.
+pub trait Storage:
+    Store
+    + AuditStore
+    + ApprovalStore
+    + BudgetStore
+    + EmailTokenStore
+    + LimitStore
+    + PayeeStore
+    + ReviewStore
+    + RiskStore
+    + ServiceAccountStore
+    + SessionStore
+    + StaffStore
+    + ThrottleStore
+    + TwoFactorStore
+{
+}
+
+impl<T> Storage for T where
+    T: Store
+        + AuditStore
+        + ApprovalStore
+        + BudgetStore
+        + EmailTokenStore
+        + LimitStore
+        + PayeeStore
+        + ReviewStore
+        + RiskStore
+        + ServiceAccountStore
+        + SessionStore
+        + StaffStore
+        + ThrottleStore
+        + TwoFactorStore
+{
+}
+
+/// The database the server runs on.
+#[derive(Clone)]
+pub enum Database {
+    Postgres(Pool<Postgres>),
+    #[cfg(feature = "sqlite")]
+    Sqlite(Pool<Sqlite>),
+}
+
+impl Database {
+    /// Connects to the database at `url`: PostgreSQL, or with the `sqlite` feature, a
+    /// `sqlite:` file, created and migrated if need be.
+    pub async fn connect(url: &str) -> anyhow::Result<Self> {
+        #[cfg(feature = "sqlite")]
+        if url.starts_with("sqlite:") {
+            return Ok(Database::Sqlite(crate::ledger::sqlite::connect(url).await?));
+        }
+
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect(url)
+            .await
+            .context("Failed to connect to PostgreSQL")?;
+
+        Ok(Database::Postgres(pool))
+    }
+}
+
+impl From<Pool<Postgres>> for Database {
+    fn from(pool: Pool<Postgres>) -> Self {
+        Database::Postgres(pool)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<Pool<Sqlite>> for Database {
+    fn from(pool: Pool<Sqlite>) -> Self {
+        Database::Sqlite(pool)
+    }
+}
+
+/// A connection to either database.
+pub enum Conn {
+    Postgres(PoolConnection<Postgres>),
+    #[cfg(feature = "sqlite")]
+    Sqlite(PoolConnection<Sqlite>),
+}
+
+impl Deref for Conn {
+    type Target = dyn Storage;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Conn::Postgres(conn) => &**conn as &PgConnection,
+            #[cfg(feature = "sqlite")]
+            Conn::Sqlite(conn) => &**conn as &SqliteConnection,
+        }
+    }
+}
+
+impl DerefMut for Conn {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            Conn::Postgres(conn) => &mut **conn as &mut PgConnection,
+            #[cfg(feature = "sqlite")]
+            Conn::Sqlite(conn) => &mut **conn as &mut SqliteConnection,
+        }
+    }
+}
+
+/// A unit of work in either database.
+pub enum Tx {
+    Postgres(sqlx::Transaction<'static, Postgres>),
+    #[cfg(feature = "sqlite")]
+    Sqlite(sqlx::Transaction<'static, Sqlite>),
+}
+
+impl Deref for Tx {
+    type Target = dyn Storage;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Tx::Postgres(tx) => &**tx as &PgConnection,
+            #[cfg(feature = "sqlite")]
+            Tx::Sqlite(tx) => &**tx as &SqliteConnection,
+        }
+    }
+}
+
+impl DerefMut for Tx {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            Tx::Postgres(tx) => &mut **tx as &mut PgConnection,
+            #[cfg(feature = "sqlite")]
+            Tx::Sqlite(tx) => &mut **tx as &mut SqliteConnection,
+        }
+    }
+}
+
+#[async_trait]
+impl Repository for Database {
+    type Store = dyn Storage;
+    type Conn = Conn;
+    type Tx = Tx;
+
+    async fn acquire(&self) -> anyhow::Result<Self::Conn> {
+        match self {
+            Database::Postgres(pool) => Ok(Conn::Postgres(Repository::acquire(pool).await?)),
+            #[cfg(feature = "sqlite")]
+            Database::Sqlite(pool) => Ok(Conn::Sqlite(Repository::acquire(pool).await?)),
+        }
+    }
+
+    async fn begin(&self) -> anyhow::Result<Self::Tx> {
+        match self {
+            Database::Postgres(pool) => Ok(Tx::Postgres(Repository::begin(pool).await?)),
+            #[cfg(feature = "sqlite")]
+            Database::Sqlite(pool) => Ok(Tx::Sqlite(Repository::begin(pool).await?)),
+        }
+    }
+
+    async fn commit(tx: Self::Tx) -> anyhow::Result<()> {
+        match tx {
+            Tx::Postgres(tx) => <Pool<Postgres> as Repository>::commit(tx).await,
+            #[cfg(feature = "sqlite")]
+            Tx::Sqlite(tx) => <Pool<Sqlite> as Repository>::commit(tx).await,
+        }
+    }
+}
+
+/// Whether `e` is the database refusing a write for breaking a constraint of `kind`.
+pub fn violates(e: &anyhow::Error, kind: ErrorKind) -> bool {
+    match e.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::Database(db)) => db.kind() == kind,
+        _ => false,
+    }
+}
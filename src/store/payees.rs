@@ -0,0 +1,330 @@
+//! Users' saved payees, the checks of account holder names made while adding them, and
+//! what users have sent to each recipient.
+
+use async_trait::async_trait;
+use bigdecimal::BigDecimal;
+use sqlx::PgConnection;
+#[cfg(feature = "sqlite")]
+use sqlx::{sqlite::SqliteRow, Row, SqliteConnection};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::api::payee::Payee;
+#[cfg(feature = "sqlite")]
+use crate::ledger::sqlite::{decimal, timestamp, NOW};
+
+pub struct NewPayee<'a> {
+    pub user_id: Uuid,
+    pub account_id: Uuid,
+    pub nickname: &'a str,
+    pub verification_status: &'a str,
+}
+
+/// What a user has had to do with a recipient account.
+pub struct RecipientHistory {
+    /// Whether the account is the user's own
+    pub own: bool,
+    /// When the user saved it as a payee, if they have
+    pub added_at: Option<OffsetDateTime>,
+    pub first_paid_at: Option<OffsetDateTime>,
+    /// Everything the user's accounts have sent it
+    pub sent: BigDecimal,
+}
+
+#[async_trait]
+pub trait PayeeStore: Send {
+    async fn insert_payee(&mut self, payee: &NewPayee<'_>) -> anyhow::Result<Payee>;
+
+    /// A user's payees by nickname.
+    async fn payees_of(&mut self, user_id: Uuid) -> anyhow::Result<Vec<Payee>>;
+
+    /// Renames one of `user_id`'s payees, returning it, or `None` if they have no such payee.
+    async fn rename_payee(&mut self, user_id: Uuid, id: Uuid, nickname: &str) -> anyhow::Result<Option<Payee>>;
+
+    /// Deletes one of `user_id`'s payees, returning whether they had it.
+    async fn delete_payee(&mut self, user_id: Uuid, id: Uuid) -> anyhow::Result<bool>;
+
+    /// The account behind one of `user_id`'s payees.
+    async fn payee_account(&mut self, user_id: Uuid, id: Uuid) -> anyhow::Result<Option<Uuid>>;
+
+    async fn record_name_check(&mut self, user_id: Uuid, account_id: Uuid) -> anyhow::Result<()>;
+
+    async fn name_checks_since(&mut self, user_id: Uuid, since: OffsetDateTime) -> anyhow::Result<i64>;
+
+    async fn recipient_history(&mut self, user_id: Uuid, account_id: Uuid) -> anyhow::Result<RecipientHistory>;
+}
+
+#[async_trait]
+impl PayeeStore for PgConnection {
+    async fn insert_payee(&mut self, payee: &NewPayee<'_>) -> anyhow::Result<Payee> {
+        let payee = sqlx::query_as!(
+            Payee,
+            r#"
+            INSERT INTO payees (user_id, account_id, nickname, verification_status)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, account_id, nickname, verification_status, created_at, updated_at
+            "#,
+            payee.user_id,
+            payee.account_id,
+            payee.nickname,
+            payee.verification_status
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(payee)
+    }
+
+    async fn payees_of(&mut self, user_id: Uuid) -> anyhow::Result<Vec<Payee>> {
+        let payees = sqlx::query_as!(
+            Payee,
+            r#"
+            SELECT id, account_id, nickname, verification_status, created_at, updated_at
+            FROM payees WHERE user_id = $1 ORDER BY nickname
+            "#,
+            user_id
+        )
+        .fetch_all(self)
+        .await?;
+
+        Ok(payees)
+    }
+
+    async fn rename_payee(&mut self, user_id: Uuid, id: Uuid, nickname: &str) -> anyhow::Result<Option<Payee>> {
+        let payee = sqlx::query_as!(
+            Payee,
+            r#"
+            UPDATE payees SET nickname = $1, updated_at = now()
+            WHERE id = $2 AND user_id = $3
+            RETURNING id, account_id, nickname, verification_status, created_at, updated_at
+            "#,
+            nickname,
+            id,
+            user_id
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(payee)
+    }
+
+    async fn delete_payee(&mut self, user_id: Uuid, id: Uuid) -> anyhow::Result<bool> {
+        let deleted = sqlx::query!(
+            "DELETE FROM payees WHERE id = $1 AND user_id = $2",
+            id,
+            user_id
+        )
+        .execute(self)
+        .await?;
+
+        Ok(deleted.rows_affected() > 0)
+    }
+
+    async fn payee_account(&mut self, user_id: Uuid, id: Uuid) -> anyhow::Result<Option<Uuid>> {
+        let account_id = sqlx::query_scalar!(
+            "SELECT account_id FROM payees WHERE id = $1 AND user_id = $2",
+            id,
+            user_id
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(account_id)
+    }
+
+    async fn record_name_check(&mut self, user_id: Uuid, account_id: Uuid) -> anyhow::Result<()> {
+        sqlx::query!(
+            "INSERT INTO payee_name_checks (user_id, account_id) VALUES ($1, $2)",
+            user_id,
+            account_id
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn name_checks_since(&mut self, user_id: Uuid, since: OffsetDateTime) -> anyhow::Result<i64> {
+        let checks = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM payee_name_checks WHERE user_id = $1 AND checked_at > $2"#,
+            user_id,
+            since
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(checks)
+    }
+
+    async fn recipient_history(&mut self, user_id: Uuid, account_id: Uuid) -> anyhow::Result<RecipientHistory> {
+        let history = sqlx::query_as!(
+            RecipientHistory,
+            r#"
+            SELECT COALESCE((SELECT user_id = $1 FROM accounts WHERE id = $2), false) AS "own!",
+                   (SELECT created_at FROM payees WHERE user_id = $1 AND account_id = $2) AS added_at,
+                   MIN(t.created_at) AS first_paid_at,
+                   COALESCE(SUM(t.amount), 0) AS "sent!"
+            FROM transactions t
+            JOIN accounts a ON a.id = t.from_account_id
+            WHERE a.user_id = $1 AND t.to_account_id = $2
+            "#,
+            user_id,
+            account_id
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(history)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+const PAYEE_COLUMNS: &str = "id, account_id, nickname, verification_status, created_at, updated_at";
+
+#[cfg(feature = "sqlite")]
+fn payee(row: SqliteRow) -> anyhow::Result<Payee> {
+    Ok(Payee {
+        id: row.try_get("id")?,
+        account_id: row.try_get("account_id")?,
+        nickname: row.try_get("nickname")?,
+        verification_status: row.try_get("verification_status")?,
+        created_at: row.try_get("created_at")?,
+        updated_at: row.try_get("updated_at")?,
+    })
+}
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl PayeeStore for SqliteConnection {
+    async fn insert_payee(&mut self, payee: &NewPayee<'_>) -> anyhow::Result<Payee> {
+        let row = sqlx::query(&format!(
+            r#"
+            INSERT INTO payees (id, user_id, account_id, nickname, verification_status)
+            VALUES (?, ?, ?, ?, ?)
+            RETURNING {}
+            "#,
+            PAYEE_COLUMNS
+        ))
+        .bind(Uuid::new_v4())
+        .bind(payee.user_id)
+        .bind(payee.account_id)
+        .bind(payee.nickname)
+        .bind(payee.verification_status)
+        .fetch_one(self)
+        .await?;
+
+        self::payee(row)
+    }
+
+    async fn payees_of(&mut self, user_id: Uuid) -> anyhow::Result<Vec<Payee>> {
+        sqlx::query(&format!("SELECT {} FROM payees WHERE user_id = ? ORDER BY nickname", PAYEE_COLUMNS))
+            .bind(user_id)
+            .fetch_all(self)
+            .await?
+            .into_iter()
+            .map(payee)
+            .collect()
+    }
+
+    async fn rename_payee(&mut self, user_id: Uuid, id: Uuid, nickname: &str) -> anyhow::Result<Option<Payee>> {
+        sqlx::query(&format!(
+            r#"
+            UPDATE payees SET nickname = ?, updated_at = {}
+            WHERE id = ? AND user_id = ?
+            RETURNING {}
+            "#,
+            NOW, PAYEE_COLUMNS
+        ))
+        .bind(nickname)
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(self)
+        .await?
+        .map(payee)
+        .transpose()
+    }
+
+    async fn delete_payee(&mut self, user_id: Uuid, id: Uuid) -> anyhow::Result<bool> {
+        let deleted = sqlx::query("DELETE FROM payees WHERE id = ? AND user_id = ?")
+            .bind(id)
+            .bind(user_id)
+            .execute(self)
+            .await?;
+
+        Ok(deleted.rows_affected() > 0)
+    }
+
+    async fn payee_account(&mut self, user_id: Uuid, id: Uuid) -> anyhow::Result<Option<Uuid>> {
+        let account_id = sqlx::query_scalar("SELECT account_id FROM payees WHERE id = ? AND user_id = ?")
+            .bind(id)
+            .bind(user_id)
+            .fetch_optional(self)
+            .await?;
+
+        Ok(account_id)
+    }
+
+    async fn record_name_check(&mut self, user_id: Uuid, account_id: Uuid) -> anyhow::Result<()> {
+        sqlx::query("INSERT INTO payee_name_checks (id, user_id, account_id) VALUES (?, ?, ?)")
+            .bind(Uuid::new_v4())
+            .bind(user_id)
+            .bind(account_id)
+            .execute(self)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn name_checks_since(&mut self, user_id: Uuid, since: OffsetDateTime) -> anyhow::Result<i64> {
+        let checks = sqlx::query_scalar("SELECT COUNT(*) FROM payee_name_checks WHERE user_id = ? AND checked_at > ?")
+            .bind(user_id)
+            .bind(timestamp(since))
+            .fetch_one(self)
+            .await?;
+
+        Ok(checks)
+    }
+
+    async fn recipient_history(&mut self, user_id: Uuid, account_id: Uuid) -> anyhow::Result<RecipientHistory> {
+        let row = sqlx::query(
+            r#"
+            SELECT COALESCE((SELECT user_id = ?1 FROM accounts WHERE id = ?2), false) AS own,
+                   (SELECT created_at FROM payees WHERE user_id = ?1 AND account_id = ?2) AS added_at
+            "#
+        )
+        .bind(user_id)
+        .bind(account_id)
+        .fetch_one(&mut *self)
+        .await?;
+
+        let mut history = RecipientHistory {
+            own: row.try_get("own")?,
+            added_at: row.try_get("added_at")?,
+            first_paid_at: None,
+            sent: BigDecimal::from(0),
+        };
+
+        let payments = sqlx::query(
+            r#"
+            SELECT t.amount, t.created_at
+            FROM transactions t
+            JOIN accounts a ON a.id = t.from_account_id
+            WHERE a.user_id = ? AND t.to_account_id = ?
+            "#
+        )
+        .bind(user_id)
+        .bind(account_id)
+        .fetch_all(self)
+        .await?;
+
+        // Amounts are text, so they are added up here rather than by SUM
+        for payment in payments {
+            let paid_at: OffsetDateTime = payment.try_get("created_at")?;
+
+            history.first_paid_at = Some(history.first_paid_at.map_or(paid_at, |first| first.min(paid_at)));
+            history.sent += decimal(payment.try_get("amount")?)?;
+        }
+
+        Ok(history)
+    }
+}
@@ -0,0 +1,191 @@
+//! Transfers held by the risk checks until an admin decides them.
+
+use async_trait::async_trait;
+use sqlx::PgConnection;
+#[cfg(feature = "sqlite")]
+use sqlx::{sqlite::SqliteRow, Row, SqliteConnection};
+use uuid::Uuid;
+
+use crate::api::review::Review;
+#[cfg(feature = "sqlite")]
+use crate::ledger::sqlite::{decimal, numeric_text, NOW};
+use crate::ledger::transfers::Transfer;
+
+/// How a review was decided.
+pub struct ReviewDecision<'a> {
+    pub status: &'a str,
+    pub reviewed_by: Uuid,
+    pub note: Option<&'a str>,
+    pub transaction_id: Option<Uuid>,
+}
+
+#[async_trait]
+pub trait ReviewStore: Send {
+    /// Queues a transfer with the flags that held it, returning the review's id.
+    async fn insert_review(&mut self, transfer: &Transfer, flags: serde_json::Value, requested_by: Option<Uuid>) -> anyhow::Result<Uuid>;
+
+    /// Reviews with `status`, oldest first.
+    async fn reviews_with_status(&mut self, status: &str) -> anyhow::Result<Vec<Review>>;
+
+    /// A review, locked until the unit of work ends.
+    async fn lock_review(&mut self, id: Uuid) -> anyhow::Result<Option<Review>>;
+
+    async fn decide_review(&mut self, id: Uuid, decision: &ReviewDecision<'_>) -> anyhow::Result<Review>;
+}
+
+#[async_trait]
+impl ReviewStore for PgConnection {
+    async fn insert_review(&mut self, transfer: &Transfer, flags: serde_json::Value, requested_by: Option<Uuid>) -> anyhow::Result<Uuid> {
+        let id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO review_queue (from_account_id, to_account_id, amount, category, flags, requested_by)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id
+            "#,
+            transfer.from_account_id,
+            transfer.to_account_id,
+            transfer.amount,
+            transfer.category,
+            flags,
+            requested_by
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(id)
+    }
+
+    async fn reviews_with_status(&mut self, status: &str) -> anyhow::Result<Vec<Review>> {
+        let reviews = sqlx::query_as!(
+            Review,
+            "SELECT * FROM review_queue WHERE status = $1 ORDER BY created_at",
+            status
+        )
+        .fetch_all(self)
+        .await?;
+
+        Ok(reviews)
+    }
+
+    async fn lock_review(&mut self, id: Uuid) -> anyhow::Result<Option<Review>> {
+        let review = sqlx::query_as!(
+            Review,
+            "SELECT * FROM review_queue WHERE id = $1 FOR UPDATE",
+            id
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(review)
+    }
+
+    async fn decide_review(&mut self, id: Uuid, decision: &ReviewDecision<'_>) -> anyhow::Result<Review> {
+        let review = sqlx::query_as!(
+            Review,
+            r#"
+            UPDATE review_queue
+            SET status = $2, reviewed_by = $3, review_note = $4, transaction_id = $5, reviewed_at = now()
+            WHERE id = $1
+            RETURNING *
+            "#,
+            id,
+            decision.status,
+            decision.reviewed_by,
+            decision.note,
+            decision.transaction_id
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(review)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+const REVIEW_COLUMNS: &str = "id, from_account_id, to_account_id, amount, category, flags, status, requested_by, reviewed_by, review_note, transaction_id, reviewed_at, created_at";
+
+#[cfg(feature = "sqlite")]
+fn review(row: SqliteRow) -> anyhow::Result<Review> {
+    Ok(Review {
+        id: row.try_get("id")?,
+        from_account_id: row.try_get("from_account_id")?,
+        to_account_id: row.try_get("to_account_id")?,
+        amount: decimal(row.try_get("amount")?)?,
+        category: row.try_get("category")?,
+        flags: serde_json::from_str(row.try_get("flags")?)?,
+        status: row.try_get("status")?,
+        requested_by: row.try_get("requested_by")?,
+        reviewed_by: row.try_get("reviewed_by")?,
+        review_note: row.try_get("review_note")?,
+        transaction_id: row.try_get("transaction_id")?,
+        reviewed_at: row.try_get("reviewed_at")?,
+        created_at: row.try_get("created_at")?,
+    })
+}
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl ReviewStore for SqliteConnection {
+    async fn insert_review(&mut self, transfer: &Transfer, flags: serde_json::Value, requested_by: Option<Uuid>) -> anyhow::Result<Uuid> {
+        let id = Uuid::new_v4();
+
+        sqlx::query(
+            r#"
+            INSERT INTO review_queue (id, from_account_id, to_account_id, amount, category, flags, requested_by)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#
+        )
+        .bind(id)
+        .bind(transfer.from_account_id)
+        .bind(transfer.to_account_id)
+        .bind(numeric_text(&transfer.amount)?)
+        .bind(&transfer.category)
+        .bind(flags.to_string())
+        .bind(requested_by)
+        .execute(self)
+        .await?;
+
+        Ok(id)
+    }
+
+    async fn reviews_with_status(&mut self, status: &str) -> anyhow::Result<Vec<Review>> {
+        sqlx::query(&format!("SELECT {} FROM review_queue WHERE status = ? ORDER BY created_at", REVIEW_COLUMNS))
+            .bind(status)
+            .fetch_all(self)
+            .await?
+            .into_iter()
+            .map(review)
+            .collect()
+    }
+
+    async fn lock_review(&mut self, id: Uuid) -> anyhow::Result<Option<Review>> {
+        // A unit of work already holds the write lock, see `ledger::sqlite`
+        sqlx::query(&format!("SELECT {} FROM review_queue WHERE id = ?", REVIEW_COLUMNS))
+            .bind(id)
+            .fetch_optional(self)
+            .await?
+            .map(review)
+            .transpose()
+    }
+
+    async fn decide_review(&mut self, id: Uuid, decision: &ReviewDecision<'_>) -> anyhow::Result<Review> {
+        let row = sqlx::query(&format!(
+            r#"
+            UPDATE review_queue
+            SET status = ?, reviewed_by = ?, review_note = ?, transaction_id = ?, reviewed_at = {}
+            WHERE id = ?
+            RETURNING {}
+            "#,
+            NOW, REVIEW_COLUMNS
+        ))
+        .bind(decision.status)
+        .bind(decision.reviewed_by)
+        .bind(decision.note)
+        .bind(decision.transaction_id)
+        .bind(id)
+        .fetch_one(self)
+        .await?;
+
+        review(row)
+    }
+}
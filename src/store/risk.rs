@@ -0,0 +1,94 @@
+//! The history the risk checks screen transfers against.
+
+use async_trait::async_trait;
+use sqlx::PgConnection;
+#[cfg(feature = "sqlite")]
+use sqlx::SqliteConnection;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+#[cfg(feature = "sqlite")]
+use crate::ledger::sqlite::timestamp;
+
+#[async_trait]
+pub trait RiskStore: Send {
+    /// Whether `from_account_id` has sent anything to `to_account_id` before.
+    async fn has_paid(&mut self, from_account_id: Uuid, to_account_id: Uuid) -> anyhow::Result<bool>;
+
+    /// Transfers out of an account since `since`.
+    async fn transfers_since(&mut self, account_id: Uuid, since: OffsetDateTime) -> anyhow::Result<i64>;
+
+    async fn account_opened_at(&mut self, account_id: Uuid) -> anyhow::Result<Option<OffsetDateTime>>;
+}
+
+#[async_trait]
+impl RiskStore for PgConnection {
+    async fn has_paid(&mut self, from_account_id: Uuid, to_account_id: Uuid) -> anyhow::Result<bool> {
+        let paid = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM transactions WHERE from_account_id = $1 AND to_account_id = $2) AS "paid!""#,
+            from_account_id,
+            to_account_id
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(paid)
+    }
+
+    async fn transfers_since(&mut self, account_id: Uuid, since: OffsetDateTime) -> anyhow::Result<i64> {
+        let count = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM transactions WHERE from_account_id = $1 AND created_at >= $2"#,
+            account_id,
+            since
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(count)
+    }
+
+    async fn account_opened_at(&mut self, account_id: Uuid) -> anyhow::Result<Option<OffsetDateTime>> {
+        let created_at = sqlx::query_scalar!(
+            "SELECT created_at FROM accounts WHERE id = $1",
+            account_id
+        )
+        .fetch_optional(self)
+        .await?
+        .flatten();
+
+        Ok(created_at)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl RiskStore for SqliteConnection {
+    async fn has_paid(&mut self, from_account_id: Uuid, to_account_id: Uuid) -> anyhow::Result<bool> {
+        let paid = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM transactions WHERE from_account_id = ? AND to_account_id = ?)")
+            .bind(from_account_id)
+            .bind(to_account_id)
+            .fetch_one(self)
+            .await?;
+
+        Ok(paid)
+    }
+
+    async fn transfers_since(&mut self, account_id: Uuid, since: OffsetDateTime) -> anyhow::Result<i64> {
+        let count = sqlx::query_scalar("SELECT COUNT(*) FROM transactions WHERE from_account_id = ? AND created_at >= ?")
+            .bind(account_id)
+            .bind(timestamp(since))
+            .fetch_one(self)
+            .await?;
+
+        Ok(count)
+    }
+
+    async fn account_opened_at(&mut self, account_id: Uuid) -> anyhow::Result<Option<OffsetDateTime>> {
+        let created_at = sqlx::query_scalar("SELECT created_at FROM accounts WHERE id = ?")
+            .bind(account_id)
+            .fetch_optional(self)
+            .await?;
+
+        Ok(created_at)
+    }
+}
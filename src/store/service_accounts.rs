@@ -0,0 +1,255 @@
+//! Service accounts and their API keys.
+
+use async_trait::async_trait;
+use sqlx::PgConnection;
+#[cfg(feature = "sqlite")]
+use sqlx::{Row, SqliteConnection};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+#[cfg(feature = "sqlite")]
+use crate::ledger::sqlite::{timestamp, NOW};
+use crate::service_accounts::ApiKeyInfo;
+
+pub struct NewApiKey<'a> {
+    pub service_account_id: Uuid,
+    pub prefix: &'a str,
+    pub key_hash: &'a str,
+    pub scopes: &'a [String],
+    pub expires_at: Option<OffsetDateTime>,
+}
+
+/// A live API key and the service account it belongs to.
+pub struct ApiKeyRecord {
+    pub id: Uuid,
+    pub scopes: Vec<String>,
+    pub last_used_at: Option<OffsetDateTime>,
+    pub service_account_id: Uuid,
+    pub owner_id: Uuid,
+}
+
+#[async_trait]
+pub trait ServiceAccountStore: Send {
+    async fn insert_service_account(&mut self, name: &str, owner_id: Uuid) -> anyhow::Result<Uuid>;
+
+    async fn service_account_id(&mut self, name: &str) -> anyhow::Result<Option<Uuid>>;
+
+    async fn insert_api_key(&mut self, key: &NewApiKey<'_>) -> anyhow::Result<()>;
+
+    /// Revokes the live key with `prefix`, returning whether there was one.
+    async fn revoke_api_key(&mut self, prefix: &str) -> anyhow::Result<bool>;
+
+    /// Every key, by service account and then age.
+    async fn api_keys(&mut self) -> anyhow::Result<Vec<ApiKeyInfo>>;
+
+    /// The key with `key_hash`, unless it has expired or been revoked.
+    async fn live_api_key(&mut self, key_hash: &str) -> anyhow::Result<Option<ApiKeyRecord>>;
+
+    async fn touch_api_key(&mut self, id: Uuid) -> anyhow::Result<()>;
+}
+
+#[async_trait]
+impl ServiceAccountStore for PgConnection {
+    async fn insert_service_account(&mut self, name: &str, owner_id: Uuid) -> anyhow::Result<Uuid> {
+        let id = sqlx::query_scalar!(
+            "INSERT INTO service_accounts (name, owner_id) VALUES ($1, $2) RETURNING id",
+            name,
+            owner_id
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(id)
+    }
+
+    async fn service_account_id(&mut self, name: &str) -> anyhow::Result<Option<Uuid>> {
+        let id = sqlx::query_scalar!("SELECT id FROM service_accounts WHERE name = $1", name)
+            .fetch_optional(self)
+            .await?;
+
+        Ok(id)
+    }
+
+    async fn insert_api_key(&mut self, key: &NewApiKey<'_>) -> anyhow::Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO api_keys (service_account_id, prefix, key_hash, scopes, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            key.service_account_id,
+            key.prefix,
+            key.key_hash,
+            key.scopes,
+            key.expires_at
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn revoke_api_key(&mut self, prefix: &str) -> anyhow::Result<bool> {
+        let revoked = sqlx::query!(
+            "UPDATE api_keys SET revoked_at = now() WHERE prefix = $1 AND revoked_at IS NULL",
+            prefix
+        )
+        .execute(self)
+        .await?;
+
+        Ok(revoked.rows_affected() > 0)
+    }
+
+    async fn api_keys(&mut self) -> anyhow::Result<Vec<ApiKeyInfo>> {
+        let keys = sqlx::query_as!(
+            ApiKeyInfo,
+            r#"
+            SELECT k.prefix, s.name AS service_account, k.scopes, k.expires_at, k.last_used_at, k.revoked_at
+            FROM api_keys k JOIN service_accounts s ON s.id = k.service_account_id
+            ORDER BY s.name, k.created_at
+            "#
+        )
+        .fetch_all(self)
+        .await?;
+
+        Ok(keys)
+    }
+
+    async fn live_api_key(&mut self, key_hash: &str) -> anyhow::Result<Option<ApiKeyRecord>> {
+        let key = sqlx::query_as!(
+            ApiKeyRecord,
+            r#"
+            SELECT k.id, k.scopes, k.last_used_at, s.id AS service_account_id, s.owner_id
+            FROM api_keys k JOIN service_accounts s ON s.id = k.service_account_id
+            WHERE k.key_hash = $1 AND k.revoked_at IS NULL AND (k.expires_at IS NULL OR k.expires_at > now())
+            "#,
+            key_hash
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(key)
+    }
+
+    async fn touch_api_key(&mut self, id: Uuid) -> anyhow::Result<()> {
+        sqlx::query!("UPDATE api_keys SET last_used_at = now() WHERE id = $1", id)
+            .execute(self)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl ServiceAccountStore for SqliteConnection {
+    async fn insert_service_account(&mut self, name: &str, owner_id: Uuid) -> anyhow::Result<Uuid> {
+        let id = Uuid::new_v4();
+
+        sqlx::query("INSERT INTO service_accounts (id, name, owner_id) VALUES (?, ?, ?)")
+            .bind(id)
+            .bind(name)
+            .bind(owner_id)
+            .execute(self)
+            .await?;
+
+        Ok(id)
+    }
+
+    async fn service_account_id(&mut self, name: &str) -> anyhow::Result<Option<Uuid>> {
+        let id = sqlx::query_scalar("SELECT id FROM service_accounts WHERE name = ?")
+            .bind(name)
+            .fetch_optional(self)
+            .await?;
+
+        Ok(id)
+    }
+
+    async fn insert_api_key(&mut self, key: &NewApiKey<'_>) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO api_keys (id, service_account_id, prefix, key_hash, scopes, expires_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#
+        )
+        .bind(Uuid::new_v4())
+        .bind(key.service_account_id)
+        .bind(key.prefix)
+        .bind(key.key_hash)
+        .bind(serde_json::to_string(key.scopes)?)
+        .bind(key.expires_at.map(timestamp))
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn revoke_api_key(&mut self, prefix: &str) -> anyhow::Result<bool> {
+        let revoked = sqlx::query(&format!(
+            "UPDATE api_keys SET revoked_at = {} WHERE prefix = ? AND revoked_at IS NULL",
+            NOW
+        ))
+        .bind(prefix)
+        .execute(self)
+        .await?;
+
+        Ok(revoked.rows_affected() > 0)
+    }
+
+    async fn api_keys(&mut self) -> anyhow::Result<Vec<ApiKeyInfo>> {
+        sqlx::query(
+            r#"
+            SELECT k.prefix, s.name AS service_account, k.scopes, k.expires_at, k.last_used_at, k.revoked_at
+            FROM api_keys k JOIN service_accounts s ON s.id = k.service_account_id
+            ORDER BY s.name, k.created_at
+            "#
+        )
+        .fetch_all(self)
+        .await?
+        .into_iter()
+        .map(|row| {
+            Ok(ApiKeyInfo {
+                prefix: row.try_get("prefix")?,
+                service_account: row.try_get("service_account")?,
+                scopes: serde_json::from_str(row.try_get("scopes")?)?,
+                expires_at: row.try_get("expires_at")?,
+                last_used_at: row.try_get("last_used_at")?,
+                revoked_at: row.try_get("revoked_at")?,
+            })
+        })
+        .collect()
+    }
+
+    async fn live_api_key(&mut self, key_hash: &str) -> anyhow::Result<Option<ApiKeyRecord>> {
+        let row = sqlx::query(&format!(
+            r#"
+            SELECT k.id, k.scopes, k.last_used_at, s.id AS service_account_id, s.owner_id
+            FROM api_keys k JOIN service_accounts s ON s.id = k.service_account_id
+            WHERE k.key_hash = ? AND k.revoked_at IS NULL AND (k.expires_at IS NULL OR k.expires_at > {})
+            "#,
+            NOW
+        ))
+        .bind(key_hash)
+        .fetch_optional(self)
+        .await?;
+
+        row.map(|row| {
+            Ok(ApiKeyRecord {
+                id: row.try_get("id")?,
+                scopes: serde_json::from_str(row.try_get("scopes")?)?,
+                last_used_at: row.try_get("last_used_at")?,
+                service_account_id: row.try_get("service_account_id")?,
+                owner_id: row.try_get("owner_id")?,
+            })
+        })
+        .transpose()
+    }
+
+    async fn touch_api_key(&mut self, id: Uuid) -> anyhow::Result<()> {
+        sqlx::query(&format!("UPDATE api_keys SET last_used_at = {} WHERE id = ?", NOW))
+            .bind(id)
+            .execute(self)
+            .await?;
+
+        Ok(())
+    }
+}
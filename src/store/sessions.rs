@@ -0,0 +1,399 @@
+//! Signed-in sessions, their refresh tokens, and revoked access tokens.
+
+use async_trait::async_trait;
+use sqlx::PgConnection;
+#[cfg(feature = "sqlite")]
+use sqlx::{Row, SqliteConnection};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::api::session::ActiveSession;
+#[cfg(feature = "sqlite")]
+use crate::ledger::sqlite::{timestamp, NOW};
+
+pub struct NewSession<'a> {
+    pub user_id: Uuid,
+    pub expires_at: OffsetDateTime,
+    pub user_agent: Option<&'a str>,
+    pub ip_address: Option<&'a str>,
+}
+
+#[async_trait]
+pub trait SessionStore: Send {
+    async fn insert_session(&mut self, session: &NewSession<'_>) -> anyhow::Result<Uuid>;
+
+    async fn insert_refresh_token(&mut self, session_id: Uuid, token_hash: &str) -> anyhow::Result<()>;
+
+    /// Uses up a refresh token, returning its session, or `None` if it was unknown or used.
+    async fn use_refresh_token(&mut self, token_hash: &str) -> anyhow::Result<Option<Uuid>>;
+
+    /// Revokes the live session a refresh token was issued for, returning it if there was one.
+    async fn revoke_session_of_token(&mut self, token_hash: &str, reason: &str) -> anyhow::Result<Option<Uuid>>;
+
+    /// Extends a live session until `expires_at`, returning its user, or `None` if it has
+    /// expired or been revoked.
+    async fn extend_session(&mut self, id: Uuid, expires_at: OffsetDateTime) -> anyhow::Result<Option<Uuid>>;
+
+    /// Revokes a session if it is `user_id`'s and still live. Returns whether it was.
+    async fn revoke_session(&mut self, id: Uuid, user_id: Uuid, reason: &str) -> anyhow::Result<bool>;
+
+    /// Revokes every live session of `user_id` except `keep`, returning how many were revoked.
+    async fn revoke_sessions(&mut self, user_id: Uuid, keep: Option<Uuid>, reason: &str) -> anyhow::Result<u64>;
+
+    /// Refuses an access token until it expires, forgetting tokens that have.
+    async fn revoke_access_token(&mut self, jti: Uuid, expires_at: OffsetDateTime) -> anyhow::Result<()>;
+
+    /// Whether the access token, or the session it was issued for, has been revoked.
+    async fn access_revoked(&mut self, jti: Uuid, session_id: Uuid) -> anyhow::Result<bool>;
+
+    /// Records that a session was used, at most once a minute.
+    async fn touch_session(&mut self, id: Uuid) -> anyhow::Result<()>;
+
+    /// The live sessions of `user_id`, most recently used first.
+    async fn live_sessions(&mut self, user_id: Uuid, current: Uuid) -> anyhow::Result<Vec<ActiveSession>>;
+}
+
+#[async_trait]
+impl SessionStore for PgConnection {
+    async fn insert_session(&mut self, session: &NewSession<'_>) -> anyhow::Result<Uuid> {
+        let id = sqlx::query_scalar!(
+            "INSERT INTO sessions (user_id, expires_at, user_agent, ip_address) VALUES ($1, $2, $3, $4) RETURNING id",
+            session.user_id,
+            session.expires_at,
+            session.user_agent,
+            session.ip_address
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(id)
+    }
+
+    async fn insert_refresh_token(&mut self, session_id: Uuid, token_hash: &str) -> anyhow::Result<()> {
+        sqlx::query!(
+            "INSERT INTO refresh_tokens (session_id, token_hash) VALUES ($1, $2)",
+            session_id,
+            token_hash
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn use_refresh_token(&mut self, token_hash: &str) -> anyhow::Result<Option<Uuid>> {
+        let session_id = sqlx::query_scalar!(
+            "UPDATE refresh_tokens SET used_at = now() WHERE token_hash = $1 AND used_at IS NULL RETURNING session_id",
+            token_hash
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(session_id)
+    }
+
+    async fn revoke_session_of_token(&mut self, token_hash: &str, reason: &str) -> anyhow::Result<Option<Uuid>> {
+        let session_id = sqlx::query_scalar!(
+            r#"
+            UPDATE sessions SET revoked_at = now(), revoked_reason = $2
+            WHERE id = (SELECT session_id FROM refresh_tokens WHERE token_hash = $1) AND revoked_at IS NULL
+            RETURNING id
+            "#,
+            token_hash,
+            reason
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(session_id)
+    }
+
+    async fn extend_session(&mut self, id: Uuid, expires_at: OffsetDateTime) -> anyhow::Result<Option<Uuid>> {
+        let user_id = sqlx::query_scalar!(
+            r#"
+            UPDATE sessions SET expires_at = $2, last_seen_at = now()
+            WHERE id = $1 AND revoked_at IS NULL AND expires_at > now()
+            RETURNING user_id
+            "#,
+            id,
+            expires_at
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(user_id)
+    }
+
+    async fn revoke_session(&mut self, id: Uuid, user_id: Uuid, reason: &str) -> anyhow::Result<bool> {
+        let revoked = sqlx::query!(
+            r#"
+            UPDATE sessions SET revoked_at = now(), revoked_reason = $3
+            WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+            "#,
+            id,
+            user_id,
+            reason
+        )
+        .execute(self)
+        .await?;
+
+        Ok(revoked.rows_affected() > 0)
+    }
+
+    async fn revoke_sessions(&mut self, user_id: Uuid, keep: Option<Uuid>, reason: &str) -> anyhow::Result<u64> {
+        let revoked = sqlx::query!(
+            r#"
+            UPDATE sessions SET revoked_at = now(), revoked_reason = $3
+            WHERE user_id = $1 AND id IS DISTINCT FROM $2 AND revoked_at IS NULL
+            "#,
+            user_id,
+            keep,
+            reason
+        )
+        .execute(self)
+        .await?;
+
+        Ok(revoked.rows_affected())
+    }
+
+    async fn revoke_access_token(&mut self, jti: Uuid, expires_at: OffsetDateTime) -> anyhow::Result<()> {
+        sqlx::query!(
+            "INSERT INTO revoked_tokens (jti, expires_at) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+            jti,
+            expires_at
+        )
+        .execute(&mut *self)
+        .await?;
+
+        // Entries are only needed until the token would have expired anyway
+        sqlx::query!("DELETE FROM revoked_tokens WHERE expires_at <= now()")
+            .execute(self)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn access_revoked(&mut self, jti: Uuid, session_id: Uuid) -> anyhow::Result<bool> {
+        let revoked = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS(SELECT 1 FROM revoked_tokens WHERE jti = $1)
+                OR NOT EXISTS(SELECT 1 FROM sessions WHERE id = $2 AND revoked_at IS NULL) AS "revoked!"
+            "#,
+            jti,
+            session_id
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(revoked)
+    }
+
+    async fn touch_session(&mut self, id: Uuid) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE sessions SET last_seen_at = now() WHERE id = $1 AND last_seen_at < now() - interval '1 minute'",
+            id
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn live_sessions(&mut self, user_id: Uuid, current: Uuid) -> anyhow::Result<Vec<ActiveSession>> {
+        let sessions = sqlx::query_as!(
+            ActiveSession,
+            r#"
+            SELECT id, user_agent, ip_address, created_at, last_seen_at, id = $2 AS "current!"
+            FROM sessions
+            WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > now()
+            ORDER BY last_seen_at DESC
+            "#,
+            user_id,
+            current
+        )
+        .fetch_all(self)
+        .await?;
+
+        Ok(sessions)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl SessionStore for SqliteConnection {
+    async fn insert_session(&mut self, session: &NewSession<'_>) -> anyhow::Result<Uuid> {
+        let id = Uuid::new_v4();
+
+        sqlx::query("INSERT INTO sessions (id, user_id, expires_at, user_agent, ip_address) VALUES (?, ?, ?, ?, ?)")
+            .bind(id)
+            .bind(session.user_id)
+            .bind(timestamp(session.expires_at))
+            .bind(session.user_agent)
+            .bind(session.ip_address)
+            .execute(self)
+            .await?;
+
+        Ok(id)
+    }
+
+    async fn insert_refresh_token(&mut self, session_id: Uuid, token_hash: &str) -> anyhow::Result<()> {
+        sqlx::query("INSERT INTO refresh_tokens (id, session_id, token_hash) VALUES (?, ?, ?)")
+            .bind(Uuid::new_v4())
+            .bind(session_id)
+            .bind(token_hash)
+            .execute(self)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn use_refresh_token(&mut self, token_hash: &str) -> anyhow::Result<Option<Uuid>> {
+        let session_id = sqlx::query_scalar(&format!(
+            "UPDATE refresh_tokens SET used_at = {} WHERE token_hash = ? AND used_at IS NULL RETURNING session_id",
+            NOW
+        ))
+        .bind(token_hash)
+        .fetch_optional(self)
+        .await?;
+
+        Ok(session_id)
+    }
+
+    async fn revoke_session_of_token(&mut self, token_hash: &str, reason: &str) -> anyhow::Result<Option<Uuid>> {
+        let session_id = sqlx::query_scalar(&format!(
+            r#"
+            UPDATE sessions SET revoked_at = {}, revoked_reason = ?2
+            WHERE id = (SELECT session_id FROM refresh_tokens WHERE token_hash = ?1) AND revoked_at IS NULL
+            RETURNING id
+            "#,
+            NOW
+        ))
+        .bind(token_hash)
+        .bind(reason)
+        .fetch_optional(self)
+        .await?;
+
+        Ok(session_id)
+    }
+
+    async fn extend_session(&mut self, id: Uuid, expires_at: OffsetDateTime) -> anyhow::Result<Option<Uuid>> {
+        let user_id = sqlx::query_scalar(&format!(
+            r#"
+            UPDATE sessions SET expires_at = ?2, last_seen_at = {0}
+            WHERE id = ?1 AND revoked_at IS NULL AND expires_at > {0}
+            RETURNING user_id
+            "#,
+            NOW
+        ))
+        .bind(id)
+        .bind(timestamp(expires_at))
+        .fetch_optional(self)
+        .await?;
+
+        Ok(user_id)
+    }
+
+    async fn revoke_session(&mut self, id: Uuid, user_id: Uuid, reason: &str) -> anyhow::Result<bool> {
+        let revoked = sqlx::query(&format!(
+            r#"
+            UPDATE sessions SET revoked_at = {}, revoked_reason = ?3
+            WHERE id = ?1 AND user_id = ?2 AND revoked_at IS NULL
+            "#,
+            NOW
+        ))
+        .bind(id)
+        .bind(user_id)
+        .bind(reason)
+        .execute(self)
+        .await?;
+
+        Ok(revoked.rows_affected() > 0)
+    }
+
+    async fn revoke_sessions(&mut self, user_id: Uuid, keep: Option<Uuid>, reason: &str) -> anyhow::Result<u64> {
+        let revoked = sqlx::query(&format!(
+            r#"
+            UPDATE sessions SET revoked_at = {}, revoked_reason = ?3
+            WHERE user_id = ?1 AND id IS NOT ?2 AND revoked_at IS NULL
+            "#,
+            NOW
+        ))
+        .bind(user_id)
+        .bind(keep)
+        .bind(reason)
+        .execute(self)
+        .await?;
+
+        Ok(revoked.rows_affected())
+    }
+
+    async fn revoke_access_token(&mut self, jti: Uuid, expires_at: OffsetDateTime) -> anyhow::Result<()> {
+        sqlx::query("INSERT INTO revoked_tokens (jti, expires_at) VALUES (?, ?) ON CONFLICT DO NOTHING")
+            .bind(jti)
+            .bind(timestamp(expires_at))
+            .execute(&mut *self)
+            .await?;
+
+        // Entries are only needed until the token would have expired anyway
+        sqlx::query(&format!("DELETE FROM revoked_tokens WHERE expires_at <= {}", NOW))
+            .execute(self)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn access_revoked(&mut self, jti: Uuid, session_id: Uuid) -> anyhow::Result<bool> {
+        let revoked = sqlx::query_scalar(
+            r#"
+            SELECT EXISTS(SELECT 1 FROM revoked_tokens WHERE jti = ?)
+                OR NOT EXISTS(SELECT 1 FROM sessions WHERE id = ? AND revoked_at IS NULL)
+            "#
+        )
+        .bind(jti)
+        .bind(session_id)
+        .fetch_one(self)
+        .await?;
+
+        Ok(revoked)
+    }
+
+    async fn touch_session(&mut self, id: Uuid) -> anyhow::Result<()> {
+        sqlx::query(&format!(
+            "UPDATE sessions SET last_seen_at = {} WHERE id = ? AND last_seen_at < strftime('%Y-%m-%dT%H:%M:%f000Z', 'now', '-1 minute')",
+            NOW
+        ))
+        .bind(id)
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn live_sessions(&mut self, user_id: Uuid, current: Uuid) -> anyhow::Result<Vec<ActiveSession>> {
+        sqlx::query(&format!(
+            r#"
+            SELECT id, user_agent, ip_address, created_at, last_seen_at, id = ?2 AS "current"
+            FROM sessions
+            WHERE user_id = ?1 AND revoked_at IS NULL AND expires_at > {}
+            ORDER BY last_seen_at DESC
+            "#,
+            NOW
+        ))
+        .bind(user_id)
+        .bind(current)
+        .fetch_all(self)
+        .await?
+        .into_iter()
+        .map(|row| {
+            Ok(ActiveSession {
+                id: row.try_get("id")?,
+                user_agent: row.try_get("user_agent")?,
+                ip_address: row.try_get("ip_address")?,
+                created_at: row.try_get("created_at")?,
+                last_seen_at: row.try_get("last_seen_at")?,
+                current: row.try_get("current")?,
+            })
+        })
+        .collect()
+    }
+}
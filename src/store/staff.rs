@@ -0,0 +1,225 @@
+//! Roles, and the identity provider logins staff sign in with.
+
+use async_trait::async_trait;
+use sqlx::PgConnection;
+#[cfg(feature = "sqlite")]
+use sqlx::{Row, SqliteConnection};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::ledger::repository::NewUser;
+#[cfg(feature = "sqlite")]
+use crate::ledger::sqlite::{timestamp, NOW};
+
+pub struct NewOidcLogin<'a> {
+    pub state: &'a str,
+    pub code_verifier: &'a str,
+    pub nonce: &'a str,
+    pub expires_at: OffsetDateTime,
+}
+
+/// A login sent to the identity provider, as needed to finish it.
+pub struct OidcLogin {
+    pub code_verifier: String,
+    pub nonce: String,
+}
+
+#[async_trait]
+pub trait StaffStore: Send {
+    async fn role_of(&mut self, user_id: Uuid) -> anyhow::Result<Option<String>>;
+
+    async fn set_role(&mut self, user_id: Uuid, role: &str) -> anyhow::Result<()>;
+
+    /// Stores a login sent to the provider, forgetting those that have expired.
+    async fn insert_oidc_login(&mut self, login: &NewOidcLogin<'_>) -> anyhow::Result<()>;
+
+    /// Takes the unexpired login with `state`, so each is good for one callback.
+    async fn take_oidc_login(&mut self, state: &str) -> anyhow::Result<Option<OidcLogin>>;
+
+    /// The user linked to an identity at a provider.
+    async fn linked_user(&mut self, issuer: &str, subject: &str) -> anyhow::Result<Option<Uuid>>;
+
+    async fn link_identity(&mut self, issuer: &str, subject: &str, user_id: Uuid) -> anyhow::Result<()>;
+
+    /// Creates a user whose email the identity provider has verified.
+    async fn insert_verified_user(&mut self, user: &NewUser) -> anyhow::Result<Uuid>;
+}
+
+#[async_trait]
+impl StaffStore for PgConnection {
+    async fn role_of(&mut self, user_id: Uuid) -> anyhow::Result<Option<String>> {
+        let role = sqlx::query_scalar!("SELECT role FROM users WHERE id = $1", user_id)
+            .fetch_optional(self)
+            .await?;
+
+        Ok(role)
+    }
+
+    async fn set_role(&mut self, user_id: Uuid, role: &str) -> anyhow::Result<()> {
+        sqlx::query!("UPDATE users SET role = $2 WHERE id = $1", user_id, role)
+            .execute(self)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn insert_oidc_login(&mut self, login: &NewOidcLogin<'_>) -> anyhow::Result<()> {
+        // Logins abandoned at the provider are never called back for, so clear them here
+        sqlx::query!("DELETE FROM oidc_logins WHERE expires_at < now()")
+            .execute(&mut *self)
+            .await?;
+
+        sqlx::query!(
+            "INSERT INTO oidc_logins (state, code_verifier, nonce, expires_at) VALUES ($1, $2, $3, $4)",
+            login.state,
+            login.code_verifier,
+            login.nonce,
+            login.expires_at
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn take_oidc_login(&mut self, state: &str) -> anyhow::Result<Option<OidcLogin>> {
+        let login = sqlx::query_as!(
+            OidcLogin,
+            "DELETE FROM oidc_logins WHERE state = $1 AND expires_at > now() RETURNING code_verifier, nonce",
+            state
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(login)
+    }
+
+    async fn linked_user(&mut self, issuer: &str, subject: &str) -> anyhow::Result<Option<Uuid>> {
+        let user_id = sqlx::query_scalar!(
+            "SELECT user_id FROM oidc_identities WHERE issuer = $1 AND subject = $2",
+            issuer,
+            subject
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(user_id)
+    }
+
+    async fn link_identity(&mut self, issuer: &str, subject: &str, user_id: Uuid) -> anyhow::Result<()> {
+        sqlx::query!(
+            "INSERT INTO oidc_identities (issuer, subject, user_id) VALUES ($1, $2, $3)",
+            issuer,
+            subject,
+            user_id
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn insert_verified_user(&mut self, user: &NewUser) -> anyhow::Result<Uuid> {
+        let id = sqlx::query_scalar!(
+            "INSERT INTO users (full_name, email, password_hash, email_verified_at) VALUES ($1, $2, $3, now()) RETURNING id",
+            user.full_name,
+            user.email,
+            user.password_hash
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(id)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl StaffStore for SqliteConnection {
+    async fn role_of(&mut self, user_id: Uuid) -> anyhow::Result<Option<String>> {
+        let role = sqlx::query_scalar("SELECT role FROM users WHERE id = ?")
+            .bind(user_id)
+            .fetch_optional(self)
+            .await?;
+
+        Ok(role)
+    }
+
+    async fn set_role(&mut self, user_id: Uuid, role: &str) -> anyhow::Result<()> {
+        sqlx::query("UPDATE users SET role = ? WHERE id = ?")
+            .bind(role)
+            .bind(user_id)
+            .execute(self)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn insert_oidc_login(&mut self, login: &NewOidcLogin<'_>) -> anyhow::Result<()> {
+        // Logins abandoned at the provider are never called back for, so clear them here
+        sqlx::query(&format!("DELETE FROM oidc_logins WHERE expires_at < {}", NOW))
+            .execute(&mut *self)
+            .await?;
+
+        sqlx::query("INSERT INTO oidc_logins (state, code_verifier, nonce, expires_at) VALUES (?, ?, ?, ?)")
+            .bind(login.state)
+            .bind(login.code_verifier)
+            .bind(login.nonce)
+            .bind(timestamp(login.expires_at))
+            .execute(self)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn take_oidc_login(&mut self, state: &str) -> anyhow::Result<Option<OidcLogin>> {
+        let row = sqlx::query(&format!(
+            "DELETE FROM oidc_logins WHERE state = ? AND expires_at > {} RETURNING code_verifier, nonce",
+            NOW
+        ))
+        .bind(state)
+        .fetch_optional(self)
+        .await?;
+
+        row.map(|row| Ok(OidcLogin { code_verifier: row.try_get("code_verifier")?, nonce: row.try_get("nonce")? }))
+            .transpose()
+    }
+
+    async fn linked_user(&mut self, issuer: &str, subject: &str) -> anyhow::Result<Option<Uuid>> {
+        let user_id = sqlx::query_scalar("SELECT user_id FROM oidc_identities WHERE issuer = ? AND subject = ?")
+            .bind(issuer)
+            .bind(subject)
+            .fetch_optional(self)
+            .await?;
+
+        Ok(user_id)
+    }
+
+    async fn link_identity(&mut self, issuer: &str, subject: &str, user_id: Uuid) -> anyhow::Result<()> {
+        sqlx::query("INSERT INTO oidc_identities (issuer, subject, user_id) VALUES (?, ?, ?)")
+            .bind(issuer)
+            .bind(subject)
+            .bind(user_id)
+            .execute(self)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn insert_verified_user(&mut self, user: &NewUser) -> anyhow::Result<Uuid> {
+        let id = Uuid::new_v4();
+
+        sqlx::query(&format!(
+            "INSERT INTO users (id, full_name, email, password_hash, email_verified_at) VALUES (?, ?, ?, ?, {})",
+            NOW
+        ))
+        .bind(id)
+        .bind(&user.full_name)
+        .bind(&user.email)
+        .bind(&user.password_hash)
+        .execute(self)
+        .await?;
+
+        Ok(id)
+    }
+}
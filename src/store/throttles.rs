@@ -0,0 +1,218 @@
+//! Failed login counts per email and per IP address.
+
+use async_trait::async_trait;
+use sqlx::PgConnection;
+#[cfg(feature = "sqlite")]
+use sqlx::{Row, SqliteConnection};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+#[cfg(feature = "sqlite")]
+use crate::ledger::sqlite::timestamp;
+
+pub struct ThrottleRecord {
+    pub id: Uuid,
+    pub failures: i32,
+    pub last_failure_at: Option<OffsetDateTime>,
+    pub locked_until: Option<OffsetDateTime>,
+}
+
+#[async_trait]
+pub trait ThrottleStore: Send {
+    /// The throttle row for `scope` and `key`, created without failures if there is none,
+    /// and locked until the unit of work ends.
+    async fn lock_throttle(&mut self, scope: &str, key: &str) -> anyhow::Result<ThrottleRecord>;
+
+    async fn lock_out(&mut self, id: Uuid, until: OffsetDateTime) -> anyhow::Result<()>;
+
+    /// Sets the failures counted, as of the latest at `at`, and lifts any ended lockout.
+    async fn count_failures(&mut self, id: Uuid, failures: i32, at: OffsetDateTime) -> anyhow::Result<()>;
+
+    /// Takes back the failure counted at `counted_at`, putting back the time of the one
+    /// before unless another has been counted since.
+    async fn uncount_failure(&mut self, id: Uuid, counted_at: OffsetDateTime, previous_failure_at: Option<OffsetDateTime>) -> anyhow::Result<()>;
+
+    /// Deletes a throttle row, only if it counts no failures when `unused` is set.
+    async fn delete_throttle(&mut self, id: Uuid, unused: bool) -> anyhow::Result<()>;
+
+    /// Deletes the row for `scope` and `key`, returning it if there was one.
+    async fn remove_throttle(&mut self, scope: &str, key: &str) -> anyhow::Result<Option<Uuid>>;
+}
+
+#[async_trait]
+impl ThrottleStore for PgConnection {
+    async fn lock_throttle(&mut self, scope: &str, key: &str) -> anyhow::Result<ThrottleRecord> {
+        // A row to lock, for an email or IP that has not failed yet
+        sqlx::query!(
+            "INSERT INTO login_throttles (scope, key) VALUES ($1, $2) ON CONFLICT (scope, key) DO NOTHING",
+            scope,
+            key
+        )
+        .execute(&mut *self)
+        .await?;
+
+        let throttle = sqlx::query_as!(
+            ThrottleRecord,
+            "SELECT id, failures, last_failure_at, locked_until FROM login_throttles WHERE scope = $1 AND key = $2 FOR UPDATE",
+            scope,
+            key
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(throttle)
+    }
+
+    async fn lock_out(&mut self, id: Uuid, until: OffsetDateTime) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE login_throttles SET locked_until = $2 WHERE id = $1",
+            id,
+            until
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn count_failures(&mut self, id: Uuid, failures: i32, at: OffsetDateTime) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE login_throttles SET failures = $2, last_failure_at = $3, locked_until = NULL WHERE id = $1",
+            id,
+            failures,
+            at
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn uncount_failure(&mut self, id: Uuid, counted_at: OffsetDateTime, previous_failure_at: Option<OffsetDateTime>) -> anyhow::Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE login_throttles
+            SET failures = failures - 1,
+                last_failure_at = CASE WHEN last_failure_at = $2 THEN $3 ELSE last_failure_at END
+            WHERE id = $1 AND failures > 0
+            "#,
+            id,
+            counted_at,
+            previous_failure_at
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete_throttle(&mut self, id: Uuid, unused: bool) -> anyhow::Result<()> {
+        sqlx::query!(
+            "DELETE FROM login_throttles WHERE id = $1 AND (NOT $2 OR failures = 0)",
+            id,
+            unused
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn remove_throttle(&mut self, scope: &str, key: &str) -> anyhow::Result<Option<Uuid>> {
+        let id = sqlx::query_scalar!(
+            "DELETE FROM login_throttles WHERE scope = $1 AND key = $2 RETURNING id",
+            scope,
+            key
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(id)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl ThrottleStore for SqliteConnection {
+    async fn lock_throttle(&mut self, scope: &str, key: &str) -> anyhow::Result<ThrottleRecord> {
+        // A unit of work already holds the write lock, see `ledger::sqlite`
+        sqlx::query("INSERT INTO login_throttles (id, scope, key) VALUES (?, ?, ?) ON CONFLICT (scope, key) DO NOTHING")
+            .bind(Uuid::new_v4())
+            .bind(scope)
+            .bind(key)
+            .execute(&mut *self)
+            .await?;
+
+        let row = sqlx::query("SELECT id, failures, last_failure_at, locked_until FROM login_throttles WHERE scope = ? AND key = ?")
+            .bind(scope)
+            .bind(key)
+            .fetch_one(self)
+            .await?;
+
+        Ok(ThrottleRecord {
+            id: row.try_get("id")?,
+            failures: row.try_get("failures")?,
+            last_failure_at: row.try_get("last_failure_at")?,
+            locked_until: row.try_get("locked_until")?,
+        })
+    }
+
+    async fn lock_out(&mut self, id: Uuid, until: OffsetDateTime) -> anyhow::Result<()> {
+        sqlx::query("UPDATE login_throttles SET locked_until = ? WHERE id = ?")
+            .bind(timestamp(until))
+            .bind(id)
+            .execute(self)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn count_failures(&mut self, id: Uuid, failures: i32, at: OffsetDateTime) -> anyhow::Result<()> {
+        sqlx::query("UPDATE login_throttles SET failures = ?, last_failure_at = ?, locked_until = NULL WHERE id = ?")
+            .bind(failures)
+            .bind(timestamp(at))
+            .bind(id)
+            .execute(self)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn uncount_failure(&mut self, id: Uuid, counted_at: OffsetDateTime, previous_failure_at: Option<OffsetDateTime>) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE login_throttles
+            SET failures = failures - 1,
+                last_failure_at = CASE WHEN last_failure_at = ?2 THEN ?3 ELSE last_failure_at END
+            WHERE id = ?1 AND failures > 0
+            "#
+        )
+        .bind(id)
+        .bind(timestamp(counted_at))
+        .bind(previous_failure_at.map(timestamp))
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete_throttle(&mut self, id: Uuid, unused: bool) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM login_throttles WHERE id = ? AND (NOT ? OR failures = 0)")
+            .bind(id)
+            .bind(unused)
+            .execute(self)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn remove_throttle(&mut self, scope: &str, key: &str) -> anyhow::Result<Option<Uuid>> {
+        let id = sqlx::query_scalar("DELETE FROM login_throttles WHERE scope = ? AND key = ? RETURNING id")
+            .bind(scope)
+            .bind(key)
+            .fetch_optional(self)
+            .await?;
+
+        Ok(id)
+    }
+}
@@ -0,0 +1,391 @@
+//! TOTP secrets, recovery codes, login challenges and step-ups.
+
+use async_trait::async_trait;
+use sqlx::PgConnection;
+#[cfg(feature = "sqlite")]
+use sqlx::{Row, SqliteConnection};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+#[cfg(feature = "sqlite")]
+use crate::ledger::sqlite::{timestamp, NOW};
+
+/// A user's two-factor setup.
+pub struct Totp {
+    pub email: String,
+    /// Set at enrollment, before 2FA is enabled
+    pub secret: Option<String>,
+    pub enabled: bool,
+}
+
+pub struct StepUp {
+    pub totp_enabled: bool,
+    /// When the session last stepped up, if the session is the user's
+    pub step_up_at: Option<OffsetDateTime>,
+}
+
+#[async_trait]
+pub trait TwoFactorStore: Send {
+    async fn totp(&mut self, user_id: Uuid) -> anyhow::Result<Option<Totp>>;
+
+    /// Stores a new, not yet enabled secret.
+    async fn set_totp_secret(&mut self, user_id: Uuid, secret: &str) -> anyhow::Result<()>;
+
+    /// Records the time step of a TOTP code, unless it is not after the last one recorded.
+    /// Returns whether it was.
+    async fn record_totp_step(&mut self, user_id: Uuid, step: i64) -> anyhow::Result<bool>;
+
+    async fn enable_totp(&mut self, user_id: Uuid) -> anyhow::Result<()>;
+
+    /// Forgets the secret, which turns 2FA off.
+    async fn clear_totp(&mut self, user_id: Uuid) -> anyhow::Result<()>;
+
+    /// Replaces a user's recovery codes with the given hashes.
+    async fn set_recovery_codes(&mut self, user_id: Uuid, code_hashes: &[String]) -> anyhow::Result<()>;
+
+    /// Uses up an unused recovery code. Returns whether there was one.
+    async fn use_recovery_code(&mut self, user_id: Uuid, code_hash: &str) -> anyhow::Result<bool>;
+
+    /// Stores a login challenge, forgetting those that have expired.
+    async fn insert_login_challenge(&mut self, id: Uuid, user_id: Uuid, expires_at: OffsetDateTime) -> anyhow::Result<()>;
+
+    /// The email of the user an unexpired login challenge was issued to.
+    async fn login_challenge_email(&mut self, id: Uuid, user_id: Uuid) -> anyhow::Result<Option<String>>;
+
+    /// Counts a wrong code against a login challenge, returning the failures so far.
+    async fn fail_login_challenge(&mut self, id: Uuid) -> anyhow::Result<Option<i32>>;
+
+    /// Deletes a login challenge, returning whether it was still there.
+    async fn delete_login_challenge(&mut self, id: Uuid) -> anyhow::Result<bool>;
+
+    async fn record_step_up(&mut self, session_id: Uuid) -> anyhow::Result<()>;
+
+    async fn step_up(&mut self, user_id: Uuid, session_id: Option<Uuid>) -> anyhow::Result<StepUp>;
+}
+
+#[async_trait]
+impl TwoFactorStore for PgConnection {
+    async fn totp(&mut self, user_id: Uuid) -> anyhow::Result<Option<Totp>> {
+        let totp = sqlx::query_as!(
+            Totp,
+            r#"SELECT email, totp_secret AS secret, totp_enabled_at IS NOT NULL AS "enabled!" FROM users WHERE id = $1"#,
+            user_id
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(totp)
+    }
+
+    async fn set_totp_secret(&mut self, user_id: Uuid, secret: &str) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE users SET totp_secret = $2, totp_last_step = NULL WHERE id = $1",
+            user_id,
+            secret
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn record_totp_step(&mut self, user_id: Uuid, step: i64) -> anyhow::Result<bool> {
+        let recorded = sqlx::query!(
+            "UPDATE users SET totp_last_step = $2 WHERE id = $1 AND (totp_last_step IS NULL OR totp_last_step < $2)",
+            user_id,
+            step
+        )
+        .execute(self)
+        .await?;
+
+        Ok(recorded.rows_affected() == 1)
+    }
+
+    async fn enable_totp(&mut self, user_id: Uuid) -> anyhow::Result<()> {
+        sqlx::query!("UPDATE users SET totp_enabled_at = now() WHERE id = $1", user_id)
+            .execute(self)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn clear_totp(&mut self, user_id: Uuid) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE users SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_step = NULL WHERE id = $1",
+            user_id
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn set_recovery_codes(&mut self, user_id: Uuid, code_hashes: &[String]) -> anyhow::Result<()> {
+        sqlx::query!("DELETE FROM recovery_codes WHERE user_id = $1", user_id)
+            .execute(&mut *self)
+            .await?;
+
+        sqlx::query!(
+            "INSERT INTO recovery_codes (user_id, code_hash) SELECT $1, unnest($2::TEXT[])",
+            user_id,
+            code_hashes
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn use_recovery_code(&mut self, user_id: Uuid, code_hash: &str) -> anyhow::Result<bool> {
+        let used = sqlx::query!(
+            "UPDATE recovery_codes SET used_at = now() WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL",
+            user_id,
+            code_hash
+        )
+        .execute(self)
+        .await?;
+
+        Ok(used.rows_affected() == 1)
+    }
+
+    async fn insert_login_challenge(&mut self, id: Uuid, user_id: Uuid, expires_at: OffsetDateTime) -> anyhow::Result<()> {
+        // Challenges abandoned at the code step are never redeemed, so clear them here
+        sqlx::query!("DELETE FROM login_challenges WHERE expires_at < now()")
+            .execute(&mut *self)
+            .await?;
+
+        sqlx::query!(
+            "INSERT INTO login_challenges (id, user_id, expires_at) VALUES ($1, $2, $3)",
+            id,
+            user_id,
+            expires_at
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn login_challenge_email(&mut self, id: Uuid, user_id: Uuid) -> anyhow::Result<Option<String>> {
+        let email = sqlx::query_scalar!(
+            r#"
+            SELECT u.email FROM login_challenges c JOIN users u ON u.id = c.user_id
+            WHERE c.id = $1 AND c.user_id = $2 AND c.expires_at > now()
+            "#,
+            id,
+            user_id
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(email)
+    }
+
+    async fn fail_login_challenge(&mut self, id: Uuid) -> anyhow::Result<Option<i32>> {
+        let failures = sqlx::query_scalar!(
+            "UPDATE login_challenges SET failures = failures + 1 WHERE id = $1 RETURNING failures",
+            id
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(failures)
+    }
+
+    async fn delete_login_challenge(&mut self, id: Uuid) -> anyhow::Result<bool> {
+        let deleted = sqlx::query!("DELETE FROM login_challenges WHERE id = $1", id)
+            .execute(self)
+            .await?;
+
+        Ok(deleted.rows_affected() == 1)
+    }
+
+    async fn record_step_up(&mut self, session_id: Uuid) -> anyhow::Result<()> {
+        sqlx::query!("UPDATE sessions SET step_up_at = now() WHERE id = $1", session_id)
+            .execute(self)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn step_up(&mut self, user_id: Uuid, session_id: Option<Uuid>) -> anyhow::Result<StepUp> {
+        let step_up = sqlx::query_as!(
+            StepUp,
+            r#"
+            SELECT u.totp_enabled_at IS NOT NULL AS "totp_enabled!", s.step_up_at
+            FROM users u LEFT JOIN sessions s ON s.id = $2 AND s.user_id = u.id
+            WHERE u.id = $1
+            "#,
+            user_id,
+            session_id
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(step_up)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl TwoFactorStore for SqliteConnection {
+    async fn totp(&mut self, user_id: Uuid) -> anyhow::Result<Option<Totp>> {
+        let row = sqlx::query("SELECT email, totp_secret, totp_enabled_at IS NOT NULL AS enabled FROM users WHERE id = ?")
+            .bind(user_id)
+            .fetch_optional(self)
+            .await?;
+
+        row.map(|row| {
+            Ok(Totp {
+                email: row.try_get("email")?,
+                secret: row.try_get("totp_secret")?,
+                enabled: row.try_get("enabled")?,
+            })
+        })
+        .transpose()
+    }
+
+    async fn set_totp_secret(&mut self, user_id: Uuid, secret: &str) -> anyhow::Result<()> {
+        sqlx::query("UPDATE users SET totp_secret = ?, totp_last_step = NULL WHERE id = ?")
+            .bind(secret)
+            .bind(user_id)
+            .execute(self)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn record_totp_step(&mut self, user_id: Uuid, step: i64) -> anyhow::Result<bool> {
+        let recorded = sqlx::query("UPDATE users SET totp_last_step = ?2 WHERE id = ?1 AND (totp_last_step IS NULL OR totp_last_step < ?2)")
+            .bind(user_id)
+            .bind(step)
+            .execute(self)
+            .await?;
+
+        Ok(recorded.rows_affected() == 1)
+    }
+
+    async fn enable_totp(&mut self, user_id: Uuid) -> anyhow::Result<()> {
+        sqlx::query(&format!("UPDATE users SET totp_enabled_at = {} WHERE id = ?", NOW))
+            .bind(user_id)
+            .execute(self)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn clear_totp(&mut self, user_id: Uuid) -> anyhow::Result<()> {
+        sqlx::query("UPDATE users SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_step = NULL WHERE id = ?")
+            .bind(user_id)
+            .execute(self)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_recovery_codes(&mut self, user_id: Uuid, code_hashes: &[String]) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM recovery_codes WHERE user_id = ?")
+            .bind(user_id)
+            .execute(&mut *self)
+            .await?;
+
+        for code_hash in code_hashes {
+            sqlx::query("INSERT INTO recovery_codes (id, user_id, code_hash) VALUES (?, ?, ?)")
+                .bind(Uuid::new_v4())
+                .bind(user_id)
+                .bind(code_hash)
+                .execute(&mut *self)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn use_recovery_code(&mut self, user_id: Uuid, code_hash: &str) -> anyhow::Result<bool> {
+        let used = sqlx::query(&format!(
+            "UPDATE recovery_codes SET used_at = {} WHERE user_id = ? AND code_hash = ? AND used_at IS NULL",
+            NOW
+        ))
+        .bind(user_id)
+        .bind(code_hash)
+        .execute(self)
+        .await?;
+
+        Ok(used.rows_affected() == 1)
+    }
+
+    async fn insert_login_challenge(&mut self, id: Uuid, user_id: Uuid, expires_at: OffsetDateTime) -> anyhow::Result<()> {
+        // Challenges abandoned at the code step are never redeemed, so clear them here
+        sqlx::query(&format!("DELETE FROM login_challenges WHERE expires_at < {}", NOW))
+            .execute(&mut *self)
+            .await?;
+
+        sqlx::query("INSERT INTO login_challenges (id, user_id, expires_at) VALUES (?, ?, ?)")
+            .bind(id)
+            .bind(user_id)
+            .bind(timestamp(expires_at))
+            .execute(self)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn login_challenge_email(&mut self, id: Uuid, user_id: Uuid) -> anyhow::Result<Option<String>> {
+        let email = sqlx::query_scalar(&format!(
+            r#"
+            SELECT u.email FROM login_challenges c JOIN users u ON u.id = c.user_id
+            WHERE c.id = ? AND c.user_id = ? AND c.expires_at > {}
+            "#,
+            NOW
+        ))
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(self)
+        .await?;
+
+        Ok(email)
+    }
+
+    async fn fail_login_challenge(&mut self, id: Uuid) -> anyhow::Result<Option<i32>> {
+        let failures = sqlx::query_scalar("UPDATE login_challenges SET failures = failures + 1 WHERE id = ? RETURNING failures")
+            .bind(id)
+            .fetch_optional(self)
+            .await?;
+
+        Ok(failures)
+    }
+
+    async fn delete_login_challenge(&mut self, id: Uuid) -> anyhow::Result<bool> {
+        let deleted = sqlx::query("DELETE FROM login_challenges WHERE id = ?")
+            .bind(id)
+            .execute(self)
+            .await?;
+
+        Ok(deleted.rows_affected() == 1)
+    }
+
+    async fn record_step_up(&mut self, session_id: Uuid) -> anyhow::Result<()> {
+        sqlx::query(&format!("UPDATE sessions SET step_up_at = {} WHERE id = ?", NOW))
+            .bind(session_id)
+            .execute(self)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn step_up(&mut self, user_id: Uuid, session_id: Option<Uuid>) -> anyhow::Result<StepUp> {
+        let row = sqlx::query(
+            r#"
+            SELECT u.totp_enabled_at IS NOT NULL AS totp_enabled, s.step_up_at
+            FROM users u LEFT JOIN sessions s ON s.id = ?2 AND s.user_id = u.id
+            WHERE u.id = ?1
+            "#
+        )
+        .bind(user_id)
+        .bind(session_id)
+        .fetch_one(self)
+        .await?;
+
+        Ok(StepUp { totp_enabled: row.try_get("totp_enabled")?, step_up_at: row.try_get("step_up_at")? })
+    }
+}
@@ -0,0 +1,140 @@
+//! Time-based one-time passwords (RFC 6238) using HMAC-SHA1, as understood by common
+//! authenticator apps.
+
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha1::Sha1;
+
+/// Seconds each code is valid for.
+pub const STEP: i64 = 30;
+/// Digits in each code.
+pub const DIGITS: u32 = 6;
+/// Steps either side of the current one still accepted, to allow for clock drift.
+const SKEW: i64 = 1;
+
+/// A fresh 160 bit secret, the size RFC 4226 recommends for HMAC-SHA1.
+pub fn generate_secret() -> Vec<u8> {
+    rand::rng().random::<[u8; 20]>().to_vec()
+}
+
+/// The secret as the unpadded base32 authenticator apps expect.
+pub fn encode_secret(secret: &[u8]) -> String {
+    BASE32_NOPAD.encode(secret)
+}
+
+pub fn decode_secret(encoded: &str) -> Option<Vec<u8>> {
+    BASE32_NOPAD.decode(encoded.as_bytes()).ok()
+}
+
+/// The HOTP value (RFC 4226) for a counter, truncated to `digits` digits.
+fn hotp(secret: &[u8], counter: u64, digits: u32) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // Dynamic truncation: the low nibble of the last byte picks four bytes to use
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([hash[offset] & 0x7f, hash[offset + 1], hash[offset + 2], hash[offset + 3]]);
+
+    format!("{:0width$}", binary % 10u32.pow(digits), width = digits as usize)
+}
+
+/// The time step a unix timestamp falls in.
+pub fn step_at(unix_time: i64) -> i64 {
+    unix_time.div_euclid(STEP)
+}
+
+/// The code for the step containing `unix_time`.
+pub fn code_at(secret: &[u8], unix_time: i64, digits: u32) -> String {
+    hotp(secret, step_at(unix_time) as u64, digits)
+}
+
+/// Checks a code against the steps around `unix_time`. Returns the step it matched,
+/// which callers record so the same code cannot be used twice.
+pub fn verify(secret: &[u8], code: &str, unix_time: i64) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let current = step_at(unix_time);
+    (current - SKEW..=current + SKEW).find(|&step| {
+        step >= 0 && constant_time_eq(hotp(secret, step as u64, DIGITS).as_bytes(), code.as_bytes())
+    })
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// The `otpauth://` URI authenticator apps scan from a QR code.
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &[u8]) -> String {
+    let encode = |s: &str| {
+        s.bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+                _ => format!("%{:02X}", b),
+            })
+            .collect::<String>()
+    };
+
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        encode(issuer),
+        encode(account),
+        encode_secret(secret),
+        encode(issuer),
+        DIGITS,
+        STEP
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The SHA1 test vectors from RFC 6238 appendix B.
+    #[test]
+    fn rfc6238_vectors() {
+        let secret = b"12345678901234567890";
+        let vectors = [
+            (59, "94287082"),
+            (1111111109, "07081804"),
+            (1111111111, "14050471"),
+            (1234567890, "89005924"),
+            (2000000000, "69279037"),
+            (20000000000, "65353130"),
+        ];
+
+        for (time, expected) in vectors {
+            assert_eq!(code_at(secret, time, 8), expected, "at {}", time);
+        }
+    }
+
+    #[test]
+    fn verify_allows_one_step_of_drift() {
+        let secret = generate_secret();
+        let now = 1_700_000_000;
+
+        let previous = code_at(&secret, now - STEP, DIGITS);
+        assert_eq!(verify(&secret, &previous, now), Some(step_at(now) - 1));
+
+        let stale = code_at(&secret, now - 2 * STEP, DIGITS);
+        assert_eq!(verify(&secret, &stale, now), None);
+
+        assert_eq!(verify(&secret, "12345", now), None);
+        assert_eq!(verify(&secret, "abcdef", now), None);
+    }
+
+    #[test]
+    fn secrets_round_trip_through_base32() {
+        let secret = generate_secret();
+        let encoded = encode_secret(&secret);
+        assert_eq!(encoded.len(), 32);
+        assert_eq!(decode_secret(&encoded), Some(secret.clone()));
+
+        let uri = otpauth_uri("Rusty Ledger", "jane@example.com", &secret);
+        assert!(uri.starts_with("otpauth://totp/Rusty%20Ledger:jane%40example.com?secret="));
+    }
+}
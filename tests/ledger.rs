@@ -0,0 +1,467 @@
+//! The ledger's services, called directly rather than over HTTP.
+//!
+//! Every scenario runs against each repository, so the in-memory one is held to what
+//! PostgreSQL does. The property tests run in memory only, where a case takes a millisecond.
+
+use bigdecimal::{BigDecimal, Signed};
+use proptest::prelude::*;
+use rusty_ledger::config::Config;
+use rusty_ledger::error::LedgerError;
+use rusty_ledger::ledger::accounts::Types;
+use rusty_ledger::ledger::repository::NewUser;
+use rusty_ledger::ledger::transfers::Counterparty;
+use rusty_ledger::ledger::users::{ProfileUpdate, Registration};
+use rusty_ledger::ledger::{AccountService, MemoryRepository, Repository, Store, TransferService, UserService};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use uuid::Uuid;
+
+fn amount(value: &str) -> BigDecimal {
+    BigDecimal::from_str(value).unwrap()
+}
+
+/// The services over one repository. Password hashing is made cheap, since what it costs
+/// is not under test here.
+struct Ledger<R> {
+    repo: R,
+    accounts: AccountService<R>,
+    transfers: TransferService<R>,
+    users: UserService<R>,
+}
+
+impl<R: Repository> Ledger<R> {
+    fn new(repo: R) -> Self {
+        let mut config = Config::from_env();
+        config.argon2_memory_kib = 64;
+        config.argon2_iterations = 1;
+        let config = Arc::new(config);
+
+        Ledger {
+            accounts: AccountService::new(repo.clone(), config.clone()),
+            transfers: TransferService::new(repo.clone(), config.clone()),
+            users: UserService::new(repo.clone(), config),
+            repo,
+        }
+    }
+
+    async fn register(&self, email: &str) -> (Uuid, Uuid) {
+        let registered = self.users.register(Registration {
+            full_name: "Test User".to_string(),
+            email: email.to_string(),
+            password: "plum-orbit-canyon-42".to_string(),
+            account_type: Types::Savings,
+        }).await.unwrap_or_else(|_| panic!("failed to register {}", email));
+
+        (registered.user_id, registered.account.account_id)
+    }
+
+    /// Opens an account without going through registration, skipping the password checks.
+    async fn open_account(&self, balance: &BigDecimal) -> Uuid {
+        let mut tx = self.repo.begin().await.unwrap();
+
+        let user_id = tx.insert_user(&NewUser {
+            full_name: "Test User".to_string(),
+            email: format!("{}@example.com", Uuid::new_v4()),
+            password_hash: "not a hash".to_string(),
+        }).await.unwrap();
+        let account = self.accounts.open(&mut tx, user_id, &Types::Savings).await.ok().unwrap();
+        tx.set_balance(account.account_id, balance).await.unwrap();
+
+        R::commit(tx).await.unwrap();
+
+        account.account_id
+    }
+
+    async fn balance(&self, account_id: Uuid) -> BigDecimal {
+        let balance = serde_json::to_value(self.accounts.balance(account_id).await.ok().unwrap()).unwrap();
+
+        amount(balance["balance"].as_str().unwrap())
+    }
+
+    async fn deposit(&self, account_id: Uuid, value: &str) {
+        let mut tx = self.repo.begin().await.unwrap();
+        assert!(self.accounts.adjust(&mut tx, account_id, &amount(value)).await.is_ok());
+        R::commit(tx).await.unwrap();
+    }
+
+    /// Posts a transfer in a unit of work of its own, which is rolled back if it is refused.
+    async fn transfer(&self, from: Uuid, to: Uuid, value: &BigDecimal) -> Result<Uuid, LedgerError> {
+        let transfer = self.transfers.prepare(from, Counterparty::Account(to), value.clone(), None).await?;
+
+        let mut tx = self.repo.begin().await?;
+        let locked = self.transfers.lock(&mut tx, &transfer).await?;
+        let transaction_id = self.transfers.post(&mut tx, locked, &transfer).await?;
+        R::commit(tx).await?;
+
+        Ok(transaction_id)
+    }
+}
+
+async fn register_opens_an_account<R: Repository>(repo: R) {
+    let ledger = Ledger::new(repo);
+
+    let (user_id, account_id) = ledger.register("ledger@example.com").await;
+
+    let account = serde_json::to_value(ledger.accounts.find(account_id).await.ok().unwrap()).unwrap();
+    assert_eq!(account["account_type"], "savings");
+    assert_eq!(amount(account["balance"].as_str().unwrap()), amount("0"));
+    assert!(account["iban"].is_string());
+
+    let primary = ledger.accounts.primary(user_id).await.ok().unwrap();
+    assert_eq!(primary.id, account_id);
+    assert_eq!(primary.owner_name, "Test User");
+
+    let lookup = serde_json::to_value(ledger.accounts.lookup(&primary.account_number).await.ok().unwrap()).unwrap();
+    assert_eq!(lookup["owner_name"], "T*** U***");
+
+    // The same email cannot register twice
+    let again = ledger.users.register(Registration {
+        full_name: "Someone Else".to_string(),
+        email: "ledger@example.com".to_string(),
+        password: "plum-orbit-canyon-42".to_string(),
+        account_type: Types::Current,
+    }).await;
+    assert!(again.is_err());
+}
+
+async fn authenticate<R: Repository>(repo: R) {
+    let ledger = Ledger::new(repo);
+    let (user_id, _) = ledger.register("auth@example.com").await;
+
+    let user = ledger.users.authenticate("auth@example.com", "plum-orbit-canyon-42").await.ok().unwrap().unwrap();
+    assert_eq!(user.user_id, user_id);
+    assert!(!user.staff);
+
+    assert!(ledger.users.authenticate("auth@example.com", "wrong-password").await.ok().unwrap().is_none());
+    assert!(ledger.users.authenticate("nobody@example.com", "plum-orbit-canyon-42").await.ok().unwrap().is_none());
+}
+
+async fn transfer_moves_money<R: Repository>(repo: R) {
+    let ledger = Ledger::new(repo);
+    let (_, from) = ledger.register("from@example.com").await;
+    let (_, to) = ledger.register("to@example.com").await;
+    ledger.deposit(from, "100.00").await;
+    ledger.deposit(to, "1.00").await;
+
+    let transfers = &ledger.transfers;
+    let transfer = transfers.prepare(from, Counterparty::Account(to), amount("40.25"), Some("rent".to_string())).await.ok().unwrap();
+
+    let mut tx = ledger.repo.begin().await.unwrap();
+    let locked = transfers.lock(&mut tx, &transfer).await.ok().unwrap();
+    let transaction_id = transfers.post(&mut tx, locked, &transfer).await.ok().unwrap();
+    R::commit(tx).await.unwrap();
+
+    assert_eq!(ledger.balance(from).await, amount("59.75"));
+    assert_eq!(ledger.balance(to).await, amount("41.25"));
+
+    let posted = serde_json::to_value(transfers.find(transaction_id).await.ok().unwrap().unwrap()).unwrap();
+    assert_eq!(posted["category"], "rent");
+
+    let history: Vec<Value> = transfers.for_account(to).await.ok().unwrap()
+        .into_iter()
+        .map(|transaction| serde_json::to_value(transaction).unwrap())
+        .collect();
+    assert_eq!(history.len(), 1);
+}
+
+async fn transfer_rules<R: Repository>(repo: R) {
+    let ledger = Ledger::new(repo);
+    let (user_id, from) = ledger.register("rules-from@example.com").await;
+    let (_, to) = ledger.register("rules-to@example.com").await;
+    ledger.deposit(from, "10.00").await;
+    ledger.deposit(to, "1.00").await;
+
+    let transfers = &ledger.transfers;
+
+    // An account cannot pay itself, however it is named
+    let own_number = ledger.accounts.primary(user_id).await.ok().unwrap().account_number;
+    assert!(transfers.prepare(from, Counterparty::Account(from), amount("1.00"), None).await.is_err());
+    assert!(transfers.prepare(from, Counterparty::AccountNumber(own_number), amount("1.00"), None).await.is_err());
+
+    // Unknown counterparties are reported as such
+    let unknown = transfers.prepare(from, Counterparty::Account(Uuid::new_v4()), amount("1.00"), None).await.ok().unwrap();
+    let mut tx = ledger.repo.begin().await.unwrap();
+    let locked = transfers.lock(&mut tx, &unknown).await;
+    assert!(matches!(locked, Err(LedgerError::AccountNotFound(_))));
+    drop(tx);
+
+    // Nothing moves when the sender cannot cover the amount
+    let posted = ledger.transfer(from, to, &amount("25.00")).await;
+    assert!(matches!(posted, Err(LedgerError::InsufficientFunds(_))));
+
+    assert_eq!(ledger.balance(from).await, amount("10.00"));
+    assert_eq!(ledger.balance(to).await, amount("1.00"));
+}
+
+async fn amounts_are_kept_to_four_places<R: Repository>(repo: R) {
+    let ledger = Ledger::new(repo);
+    let from = ledger.open_account(&amount("1.00005")).await;
+    let to = ledger.open_account(&amount("0.99994")).await;
+
+    // Stored as NUMERIC(20, 4), rounding half away from zero
+    assert_eq!(ledger.balance(from).await, amount("1.0001"));
+    assert_eq!(ledger.balance(to).await, amount("0.9999"));
+
+    let transaction_id = ledger.transfer(from, to, &amount("0.1234")).await.ok().unwrap();
+
+    let posted = serde_json::to_value(ledger.transfers.find(transaction_id).await.ok().unwrap().unwrap()).unwrap();
+    assert_eq!(amount(posted["amount"].as_str().unwrap()), amount("0.1234"));
+    assert_eq!(ledger.balance(from).await, amount("0.8767"));
+    assert_eq!(ledger.balance(to).await, amount("1.1233"));
+}
+
+async fn large_amounts_stay_exact<R: Repository>(repo: R) {
+    let ledger = Ledger::new(repo);
+    let from = ledger.open_account(&amount("9999999999999999.9999")).await;
+    let to = ledger.open_account(&amount("0.0001")).await;
+
+    // Far beyond what a double keeps exactly
+    ledger.transfer(from, to, &amount("1234567890123456.7891")).await.ok().unwrap();
+
+    assert_eq!(ledger.balance(from).await, amount("8765432109876543.2108"));
+    assert_eq!(ledger.balance(to).await, amount("1234567890123456.7892"));
+
+    // NUMERIC(20, 4) holds no more, so nothing moves
+    let full = ledger.open_account(&amount("9999999999999999.9999")).await;
+    let overflow = ledger.transfer(to, full, &amount("1")).await;
+    assert!(overflow.is_err());
+    assert_eq!(ledger.balance(to).await, amount("1234567890123456.7892"));
+    assert_eq!(ledger.balance(full).await, amount("9999999999999999.9999"));
+}
+
+async fn adjust_rejects_negative_balances<R: Repository>(repo: R) {
+    let ledger = Ledger::new(repo);
+    let account_id = ledger.open_account(&amount("5.00")).await;
+
+    let mut tx = ledger.repo.begin().await.unwrap();
+    let adjusted = ledger.accounts.adjust(&mut tx, account_id, &amount("-5.01")).await;
+    assert!(matches!(adjusted, Err(LedgerError::InsufficientFunds(_))));
+    drop(tx);
+
+    ledger.deposit(account_id, "-5.00").await;
+    assert_eq!(ledger.balance(account_id).await, amount("0.00"));
+}
+
+async fn concurrent_transfers_lose_nothing<R: Repository>(repo: R) {
+    let ledger = Arc::new(Ledger::new(repo));
+    let a = ledger.open_account(&amount("100")).await;
+    let b = ledger.open_account(&amount("100")).await;
+
+    // Transfers in both directions at once, more than either account can cover
+    let tasks: Vec<_> = (0..40)
+        .map(|i| {
+            let ledger = ledger.clone();
+            let (from, to) = if i % 2 == 0 { (a, b) } else { (b, a) };
+            tokio::spawn(async move { ledger.transfer(from, to, &amount("7.5")).await.is_ok() })
+        })
+        .collect();
+
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    assert_eq!(ledger.balance(a).await + ledger.balance(b).await, amount("200"));
+
+    // Every posted transfer moved money, and no other money moved
+    let history: Vec<Value> = ledger.transfers.for_account(a).await.ok().unwrap()
+        .into_iter()
+        .map(|transaction| serde_json::to_value(transaction).unwrap())
+        .collect();
+    let into_a = history.iter().filter(|transaction| transaction["to_account_id"] == a.to_string()).count() as i64;
+    let out_of_a = history.len() as i64 - into_a;
+    assert_eq!(ledger.balance(a).await, amount("100") + amount("7.5") * BigDecimal::from(into_a - out_of_a));
+}
+
+async fn profile_update<R: Repository>(repo: R) {
+    let ledger = Ledger::new(repo);
+    let (user_id, _) = ledger.register("profile@example.com").await;
+    ledger.register("taken@example.com").await;
+
+    let users = &ledger.users;
+    let user = users.find(user_id).await.ok().unwrap();
+
+    let taken = users.prepare_update(&user, ProfileUpdate {
+        full_name: None,
+        new_email: Some("taken@example.com".to_string()),
+        password: None,
+    }).await;
+    assert!(taken.is_err());
+
+    let changes = users.prepare_update(&user, ProfileUpdate {
+        full_name: Some("Renamed User".to_string()),
+        new_email: Some("new@example.com".to_string()),
+        password: None,
+    }).await.ok().unwrap();
+
+    let mut tx = ledger.repo.begin().await.unwrap();
+    assert!(users.apply_update(&mut tx, user_id, &changes).await.is_ok());
+    R::commit(tx).await.unwrap();
+
+    // The new email waits for verification
+    let profile = serde_json::to_value(users.profile(user_id).await.ok().unwrap()).unwrap();
+    assert_eq!(profile["full_name"], "Renamed User");
+    assert_eq!(profile["email"], "profile@example.com");
+    assert_eq!(profile["pending_email"], "new@example.com");
+}
+
+/// Runs each scenario against PostgreSQL, in memory and, with the `sqlite` feature, SQLite.
+macro_rules! scenarios {
+    ($($name:ident),* $(,)?) => {
+        mod postgres {
+            $(
+                #[sqlx::test]
+                async fn $name(pool: sqlx::PgPool) {
+                    super::$name(pool).await
+                }
+            )*
+        }
+
+        mod memory {
+            $(
+                #[tokio::test]
+                async fn $name() {
+                    super::$name(super::MemoryRepository::default()).await
+                }
+            )*
+        }
+
+        #[cfg(feature = "sqlite")]
+        mod sqlite {
+            $(
+                #[tokio::test]
+                async fn $name() {
+                    let database = super::SqliteDatabase::create().await;
+                    super::$name(database.pool.clone()).await;
+                    database.remove().await;
+                }
+            )*
+        }
+    };
+}
+
+/// A SQLite database of its own in the temporary directory.
+#[cfg(feature = "sqlite")]
+struct SqliteDatabase {
+    pool: sqlx::SqlitePool,
+    path: std::path::PathBuf,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteDatabase {
+    async fn create() -> Self {
+        let path = std::env::temp_dir().join(format!("rusty-ledger-{}.db", Uuid::new_v4()));
+        let pool = rusty_ledger::ledger::sqlite::connect(&format!("sqlite://{}", path.display())).await.unwrap();
+
+        SqliteDatabase { pool, path }
+    }
+
+    async fn remove(self) {
+        self.pool.close().await;
+
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", self.path.display(), suffix));
+        }
+    }
+}
+
+scenarios!(
+    register_opens_an_account,
+    authenticate,
+    transfer_moves_money,
+    transfer_rules,
+    amounts_are_kept_to_four_places,
+    large_amounts_stay_exact,
+    adjust_rejects_negative_balances,
+    concurrent_transfers_lose_nothing,
+    profile_update,
+);
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap()
+}
+
+/// Amounts in cents up to 1,000.00.
+fn cents() -> impl Strategy<Value = BigDecimal> {
+    (0_i64..=100_000).prop_map(|cents| BigDecimal::new(cents.into(), 2))
+}
+
+proptest! {
+    /// Whatever transfers are asked for, a transfer is posted exactly when it leaves both
+    /// balances above zero, and money is neither made nor lost.
+    #[test]
+    fn transfers_follow_the_balance_rule(
+        opening in prop::collection::vec(cents(), 2..5),
+        transfers in prop::collection::vec((0_usize..5, 0_usize..5, cents()), 1..30),
+    ) {
+        runtime().block_on(async {
+            let ledger = Ledger::new(MemoryRepository::default());
+
+            let mut accounts = Vec::new();
+            let mut expected = HashMap::new();
+            for balance in &opening {
+                let account_id = ledger.open_account(balance).await;
+                accounts.push(account_id);
+                expected.insert(account_id, balance.clone());
+            }
+
+            for (from, to, value) in transfers {
+                let (from, to) = (accounts[from % accounts.len()], accounts[to % accounts.len()]);
+
+                let new_from = &expected[&from] - &value;
+                let new_to = &expected[&to] + &value;
+                let allowed = from != to && new_from.is_positive() && new_to.is_positive();
+
+                let posted = ledger.transfer(from, to, &value).await;
+                prop_assert_eq!(posted.is_ok(), allowed);
+
+                if allowed {
+                    expected.insert(from, new_from);
+                    expected.insert(to, new_to);
+                }
+            }
+
+            let mut total = BigDecimal::from(0);
+            for account_id in &accounts {
+                let balance = ledger.balance(*account_id).await;
+                prop_assert_eq!(&balance, &expected[account_id]);
+                total += balance;
+            }
+            prop_assert_eq!(total, opening.iter().sum::<BigDecimal>());
+
+            Ok(())
+        })?;
+    }
+
+    /// An adjustment applies exactly when the balance stays at or above zero.
+    #[test]
+    fn adjustments_never_go_negative(
+        opening in cents(),
+        adjustments in prop::collection::vec((any::<bool>(), cents()), 1..30),
+    ) {
+        runtime().block_on(async {
+            let ledger = Ledger::new(MemoryRepository::default());
+            let account_id = ledger.open_account(&opening).await;
+            let mut expected = opening;
+
+            for (credit, value) in adjustments {
+                let change = if credit { value } else { -value };
+                let allowed = !(&expected + &change).is_negative();
+
+                let mut tx = ledger.repo.begin().await.unwrap();
+                let adjusted = ledger.accounts.adjust(&mut tx, account_id, &change).await;
+                prop_assert_eq!(adjusted.is_ok(), allowed);
+
+                if allowed {
+                    MemoryRepository::commit(tx).await.unwrap();
+                    expected += change;
+                }
+            }
+
+            prop_assert_eq!(ledger.balance(account_id).await, expected);
+
+            Ok(())
+        })?;
+    }
+}